@@ -0,0 +1,114 @@
+use std::string::String;
+
+use error::{CalcError, CannotIsolate};
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall};
+
+/// Solves `equation`, an `lhs = rhs` tree, for `variable` by undoing the
+/// operations around its only occurrence one at a time, so `F = m*a`
+/// solved for `a` gives `a = F / m`.
+pub fn rearrange(equation: &ExprNode, variable: &str) -> Result<ExprNode, CalcError> {
+    let fail = || CannotIsolate { variable: String::from_str(variable) };
+
+    if equation.kind() != ExprBinary || equation.token() != "=" {
+        return Err(fail());
+    }
+
+    let (lhs, rhs) = (equation.children().get(0), equation.children().get(1));
+    let (mut side, mut other) = match (occurrences(lhs, variable), occurrences(rhs, variable)) {
+        (1, 0) => (lhs.clone(), rhs.clone()),
+        (0, 1) => (rhs.clone(), lhs.clone()),
+        _ => return Err(fail()),
+    };
+
+    while side.kind() != ExprVariable {
+        match undo(&side, other, variable) {
+            Some((inner, undone)) => {
+                side = inner;
+                other = undone;
+            },
+            None => return Err(fail()),
+        }
+    }
+
+    Ok(binary("=", side, other))
+}
+
+/// How many times `variable` appears in `node`.
+pub fn occurrences(node: &ExprNode, variable: &str) -> uint {
+    if node.kind() == ExprVariable && node.token() == variable {
+        return 1;
+    }
+    node.children().iter().map(|child| occurrences(child, variable)).fold(0, |a, b| a + b)
+}
+
+// peel one operation off the side holding the variable, applying its
+// inverse to the other side
+fn undo(side: &ExprNode, other: ExprNode, variable: &str) -> Option<(ExprNode, ExprNode)> {
+    let children = side.children();
+
+    match side.kind() {
+        ExprUnary => Some((children.get(0).clone(), negate(other))),
+        ExprBinary => {
+            let (l, r) = (children.get(0).clone(), children.get(1).clone());
+            let in_left = occurrences(&l, variable) > 0;
+
+            Some(match (side.token(), in_left) {
+                ("+", true) => (l, binary("-", other, r)),
+                ("+", false) => (r, binary("-", other, l)),
+                ("-", true) => (l, binary("+", other, r)),
+                ("-", false) => (r, binary("-", l, other)),
+                ("*", true) => (l, binary("/", other, r)),
+                ("*", false) => (r, binary("/", other, l)),
+                ("/", true) => (l, binary("*", other, r)),
+                ("/", false) => (r, binary("/", l, other)),
+                ("^", true) => (l, binary("^", other, binary("/", literal("1"), r))),
+                ("^", false) => (r, binary("/", call("ln", other), call("ln", l))),
+                _ => return None,
+            })
+        },
+        ExprCall if children.len() == 1 => {
+            let arg = children.get(0).clone();
+
+            Some(match side.token() {
+                "ln" => (arg, binary("^", literal("e"), other)),
+                "log" => (arg, binary("^", literal("10"), other)),
+                "lg" => (arg, binary("^", literal("2"), other)),
+                "sqrt" => (arg, binary("^", other, literal("2"))),
+                "neg" => (arg, negate(other)),
+                _ => return None,
+            })
+        },
+        _ => None,
+    }
+}
+
+fn binary(operator: &str, lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    ExprNode::new(operator, ExprBinary, vec![lhs, rhs])
+}
+
+fn negate(operand: ExprNode) -> ExprNode {
+    ExprNode::new("-", ExprUnary, vec![operand])
+}
+
+fn call(name: &str, arg: ExprNode) -> ExprNode {
+    ExprNode::new(name, ExprCall, vec![arg])
+}
+
+fn literal(text: &str) -> ExprNode {
+    ExprNode::new(text, ExprLiteral, vec![])
+}
+
+#[cfg(test)]
+mod test {
+    use exprtree::ExprTree;
+    use value::Expression;
+
+    #[test]
+    fn the_documented_example_rearranges() {
+        let solved = ExprTree::build("rearrange(\"F = m*a\", \"a\")").unwrap().eval();
+        match solved {
+            Ok(Expression(node)) => assert_eq!(format!("{}", node).as_slice(), "a = F / m"),
+            other => fail!("expected an expression, got {}", other),
+        }
+    }
+}
@@ -0,0 +1,787 @@
+use std::cmp::{max, Less, Equal, Greater};
+use std::collections::TreeMap;
+use std::f64;
+use std::rand::{Rng, SeedableRng, StdRng};
+use std::string::String;
+
+use builtins;
+use calculus;
+use compile::Program;
+use env::Environment;
+use error::{CalcError, DimensionMismatch, ShapeMismatch, TypeMismatch};
+use exprtree::{ExprTree, ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall};
+use exprtree::{ExprText, ExprUnit};
+use units;
+use units::{Dimensions, Measure};
+use value::{Value, Function};
+
+/// How a result responds to nudging one of its inputs.
+pub struct Sensitivity {
+    pub variable: String,
+    /// The input's value before it was nudged.
+    pub value: f64,
+    /// Change in the result when the input grows by the percentage.
+    pub change: f64,
+    /// `change` as a percentage of the original result.
+    pub percent: f64,
+}
+
+/// Grows each variable the expression reads, or only `variable`, by
+/// `delta_pct` percent one at a time and reports the effect on the result,
+/// largest first. A variable that is zero is moved by `delta_pct / 100`
+/// instead. Assignments are not analysed, since evaluating them again would
+/// change the environment.
+pub fn sensitivity(tree: &ExprTree, env: &mut Environment, variable: Option<&str>,
+                   delta_pct: f64) -> Result<Vec<Sensitivity>, CalcError> {
+    let root = match tree.root() {
+        Some(root) if !(root.kind() == ExprBinary && root.token() == "=") => root,
+        _ => return Ok(vec![]),
+    };
+
+    // the caller has already had this result, and the nudged ones below
+    // aren't real, so result hooks are told of none of them
+    env.mute_hooks(true);
+    let base = tree.eval_in(env).and_then(|v| v.number());
+    env.mute_hooks(false);
+    let base = try!(base);
+    let mut rows: Vec<Sensitivity> = vec![];
+
+    for name in variables(root).iter() {
+        if variable.map_or(false, |v| v != name.as_slice()) {
+            continue;
+        }
+
+        let original = match env.get(name.as_slice()) {
+            Some(original) => original,
+            None => continue,
+        };
+        // function values and other non-numbers have nothing to nudge
+        let value = match original.number() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+
+        let nudged = if value == 0_f64 {
+            delta_pct / 100_f64
+        } else {
+            value * (1_f64 + delta_pct / 100_f64)
+        };
+
+        let mode = env.mode;
+        env.set(name.as_slice(), Value::from_f64(nudged, mode));
+        env.mute_hooks(true);
+        let result = tree.eval_in(env).and_then(|v| v.number());
+        env.mute_hooks(false);
+        env.set(name.as_slice(), original);
+
+        let change = try!(result) - base;
+        rows.push(Sensitivity {
+            variable: name.clone(),
+            value: value,
+            change: change,
+            percent: change / base.abs() * 100_f64,
+        });
+    }
+
+    rows.sort_by(|a, b| {
+        let (x, y) = (a.change.abs(), b.change.abs());
+        if x > y { Less } else if x < y { Greater } else { Equal }
+    });
+    Ok(rows)
+}
+
+/// Lays the rows out as a table with a header line.
+pub fn sensitivity_table(rows: &[Sensitivity], delta_pct: f64) -> String {
+    let mut table = format!("{:<12} {:>14} {:>14} {:>10}\n",
+                            "variable", "value", format!("change at +{}%", delta_pct),
+                            "change %");
+    for row in rows.iter() {
+        table.push_str(format!("{:<12} {:>14} {:>14} {:>10}\n",
+                               row.variable, row.value, row.change,
+                               format!("{:.2}", row.percent)).as_slice());
+    }
+    table
+}
+
+/// How far an expression's result moves when its rounding is disturbed.
+pub struct Stability {
+    /// The result worked normally in doubles.
+    pub value: f64,
+    /// The result with every step rounded to single precision instead.
+    pub single: f64,
+    /// The smallest and largest results under stochastic rounding.
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub runs: uint,
+}
+
+impl Stability {
+    /// Decimal digits of the result that agree across every stochastic
+    /// run; a double carries about 15, and a formula that keeps far fewer
+    /// is losing them to cancellation or growth.
+    pub fn digits(&self) -> f64 {
+        let most = f64::DIGITS as f64;
+        let spread = self.max - self.min;
+        if spread == 0_f64 {
+            return most;
+        }
+        let scale = self.value.abs().max(f64::MIN_POS_VALUE);
+        (-(spread / scale).log10()).max(0_f64).min(most)
+    }
+}
+
+/// Works the expression `runs` times with every intermediate result
+/// rounded at random to within one unit in the last place, and once with
+/// every step rounded to single precision, and reports the spread. A
+/// stable formula gives nearly the same answer each time. The rounding
+/// draws on `env`'s random source, so a seeded environment gives the same
+/// report every time. Assignments are not analysed, and the expression
+/// must compile to a `Program`.
+pub fn stability(tree: &ExprTree, env: &mut Environment,
+                 runs: uint) -> Result<Option<Stability>, CalcError> {
+    let root = match tree.root() {
+        Some(root) if !(root.kind() == ExprBinary && root.token() == "=") => root,
+        _ => return Ok(None),
+    };
+
+    let mut program = try!(Program::compile(root));
+    let value = try!(program.eval_compiled(env));
+    let single = try!(program.eval_rounded(env, |n| n as f32 as f64));
+
+    // the rounding needs its own generator while `env` is lent to the
+    // program, so one is seeded from the environment's
+    let seed = [env.rng().next_u64() as uint];
+    let mut rng: StdRng = SeedableRng::from_seed(seed.as_slice());
+    let mut results: Vec<f64> = Vec::with_capacity(runs);
+    for _ in range(0, runs) {
+        results.push(try!(program.eval_rounded(env, |n| {
+            n * (1_f64 + rng.gen_range(-1_f64, 1_f64) * f64::EPSILON)
+        })));
+    }
+
+    let count = max(results.len(), 1) as f64;
+    let mean = results.iter().fold(0_f64, |sum, &n| sum + n) / count;
+    let variance = results.iter().fold(0_f64, |sum, &n| sum + (n - mean) * (n - mean)) / count;
+    Ok(Some(Stability {
+        value: value,
+        single: single,
+        min: results.iter().fold(value, |least, &n| least.min(n)),
+        max: results.iter().fold(value, |most, &n| most.max(n)),
+        mean: mean,
+        std_dev: variance.sqrt(),
+        runs: runs,
+    }))
+}
+
+/// Lays out the spread one figure per line, with a warning when fewer
+/// than half a double's digits survive.
+pub fn stability_report(stability: &Stability) -> String {
+    let digits = stability.digits();
+    let mut report = format!("double       {}\n\
+                              single       {}\n\
+                              range        {} to {} over {} runs\n\
+                              mean         {}\n\
+                              std dev      {}\n\
+                              digits       {:.1} of {}\n",
+                             stability.value, stability.single,
+                             stability.min, stability.max, stability.runs,
+                             stability.mean, stability.std_dev, digits, f64::DIGITS);
+    if digits < f64::DIGITS as f64 / 2_f64 {
+        report.push_str("warning: the result is sensitive to rounding\n");
+    }
+    report
+}
+
+/// A condition number past which a double result keeps fewer than half
+/// of its digits.
+pub static ILL_CONDITIONED: f64 = 1e8;
+
+/// How much an expression magnifies relative errors in its inputs.
+pub struct Condition {
+    pub value: f64,
+    /// The relative condition number, the sum over the inputs of
+    /// `|x * df/dx / f|`; the result can lose about its log10 in digits.
+    pub number: f64,
+    /// Each input's share of `number`, largest first.
+    pub inputs: Vec<(String, f64)>,
+}
+
+impl Condition {
+    /// Whether the result can't be trusted to half a double's digits,
+    /// including when the condition number is infinite or undefined.
+    pub fn is_ill_conditioned(&self) -> bool {
+        !(self.number <= ILL_CONDITIONED)
+    }
+}
+
+/// Estimates the relative condition number of the expression at the
+/// variables in `env`, taking each variable's derivative by forward-mode
+/// automatic differentiation. Literals count as exact. Assignments are not
+/// analysed, and functions `forward` can't differentiate give an error.
+pub fn condition(tree: &ExprTree, env: &Environment) -> Result<Option<Condition>, CalcError> {
+    let root = match tree.root() {
+        Some(root) if !(root.kind() == ExprBinary && root.token() == "=") => root,
+        _ => return Ok(None),
+    };
+
+    let mut value = 0_f64;
+    let mut inputs: Vec<(String, f64)> = vec![];
+    for name in variables(root).iter() {
+        let x = match env.get(name.as_slice()).map(|v| v.number()) {
+            Some(Ok(x)) => x,
+            _ => continue,
+        };
+        let dual = try!(calculus::forward(root, env, name.as_slice()));
+        value = dual.value;
+        let term = if x == 0_f64 || dual.derivative == 0_f64 {
+            0_f64
+        } else {
+            (x * dual.derivative / dual.value).abs()
+        };
+        inputs.push((name.clone(), term));
+    }
+
+    if inputs.is_empty() {
+        value = try!(calculus::forward(root, env, "")).value;
+    }
+    inputs.sort_by(|&(_, a), &(_, b)| if a > b { Less } else if a < b { Greater } else { Equal });
+    Ok(Some(Condition {
+        value: value,
+        number: inputs.iter().fold(0_f64, |sum, &(_, term)| sum + term),
+        inputs: inputs,
+    }))
+}
+
+// names the expression reads, in first-use order; call names, named
+// argument keys and `_` are not variables
+fn variables(node: &ExprNode) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    collect_variables(node, &mut names);
+    names
+}
+
+fn collect_variables(node: &ExprNode, names: &mut Vec<String>) {
+    if node.kind() == ExprVariable {
+        let name = String::from_str(node.token());
+        if name.as_slice() != "_" && !names.contains(&name) {
+            names.push(name);
+        }
+        return;
+    }
+
+    for child in node.children().iter() {
+        if child.kind() == ExprBinary && child.token() == "=" {
+            // `name=value` inside a call: only the value is read
+            collect_variables(child.children().get(1), names);
+        } else {
+            collect_variables(child, names);
+        }
+    }
+}
+
+/// Something that makes an expression unsafe to cache.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Impurity {
+    /// The expression assigns this variable or defines this function.
+    Assignment(String),
+    /// This function can give a different result for the same arguments.
+    Nondeterministic(String),
+}
+
+/// Reasons evaluating the expression in `env` would change `env` or could
+/// give a different result next time, each listed once. User functions are
+/// followed into their bodies, and functions passed as values count as
+/// called. An empty list means the result can be memoized for as long as
+/// the variables it reads are unchanged.
+pub fn impurities(tree: &ExprTree, env: &Environment) -> Vec<Impurity> {
+    let mut found: Vec<Impurity> = vec![];
+    let mut visited: Vec<String> = vec![];
+    match tree.root() {
+        Some(root) => collect_impurities(root, env, &mut visited, &mut found),
+        None => {},
+    }
+    found
+}
+
+/// Whether the expression neither writes to `env` nor calls anything
+/// nondeterministic; see `impurities`.
+pub fn is_pure(tree: &ExprTree, env: &Environment) -> bool {
+    impurities(tree, env).is_empty()
+}
+
+fn collect_impurities(node: &ExprNode, env: &Environment, visited: &mut Vec<String>,
+                      found: &mut Vec<Impurity>) {
+    match node.kind() {
+        ExprBinary if node.token() == "=" => {
+            let target = node.children().get(0);
+            note(found, Assignment(String::from_str(target.token())));
+            // a definition's body only runs when the function is called
+            if target.kind() != ExprCall {
+                collect_impurities(node.children().get(1), env, visited, found);
+            }
+            return;
+        },
+        ExprCall => callee_impurities(node.token(), env, visited, found),
+        ExprVariable => match env.get(node.token()) {
+            Some(Function(partial)) =>
+                callee_impurities(partial.name.as_slice(), env, visited, found),
+            Some(_) => {},
+            None => callee_impurities(node.token(), env, visited, found),
+        },
+        _ => {},
+    }
+
+    for child in node.children().iter() {
+        if node.kind() == ExprCall && child.kind() == ExprBinary && child.token() == "=" {
+            // `name=value` inside a call: only the value is evaluated
+            collect_impurities(child.children().get(1), env, visited, found);
+        } else {
+            collect_impurities(child, env, visited, found);
+        }
+    }
+}
+
+// what calling `name` may do; each user function is walked once, which
+// also stops recursion
+fn callee_impurities(name: &str, env: &Environment, visited: &mut Vec<String>,
+                     found: &mut Vec<Impurity>) {
+    if builtins::is_builtin(name) {
+        if !builtins::is_deterministic(name) {
+            note(found, Nondeterministic(String::from_str(name)));
+        }
+        return;
+    }
+
+    let function = match env.function(name) {
+        Some(function) => function,
+        // a registered function may keep state or read the outside world,
+        // and there is no body to look into
+        None if !env.registry().arities(name).is_empty() => {
+            note(found, Nondeterministic(String::from_str(name)));
+            return;
+        },
+        None => return,
+    };
+    let name = String::from_str(name);
+    if visited.contains(&name) {
+        return;
+    }
+    visited.push(name);
+
+    for default in function.defaults.iter().filter_map(|d| d.as_ref()) {
+        collect_impurities(default, env, visited, found);
+    }
+    collect_impurities(&*function.body, env, visited, found);
+}
+
+fn note(found: &mut Vec<Impurity>, impurity: Impurity) {
+    if !found.contains(&impurity) {
+        found.push(impurity);
+    }
+}
+
+/// What a variable holds, as declared to `analyze`.
+pub enum Kind {
+    ScalarKind,
+    ListKind,
+    /// A list of equally long lists.
+    MatrixKind,
+    /// A number with units of these dimensions.
+    QuantityKind(Dimensions),
+}
+
+// the dimensions are a fixed-size array, which can't derive these
+impl Clone for Kind {
+    fn clone(&self) -> Kind {
+        match *self {
+            ScalarKind => ScalarKind,
+            ListKind => ListKind,
+            MatrixKind => MatrixKind,
+            QuantityKind(dimensions) => QuantityKind(dimensions),
+        }
+    }
+}
+
+impl PartialEq for Kind {
+    fn eq(&self, other: &Kind) -> bool {
+        match (self, other) {
+            (&ScalarKind, &ScalarKind) | (&ListKind, &ListKind) | (&MatrixKind, &MatrixKind) =>
+                true,
+            (&QuantityKind(a), &QuantityKind(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A part of a formula that would fail to evaluate with the declared kinds.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Mismatch {
+    /// The failing subexpression, written as infix.
+    pub expression: String,
+    /// The error evaluating it would give.
+    pub error: CalcError,
+}
+
+/// Checks the kinds of value a formula combines without evaluating it,
+/// given the kinds of some of its variables, and lists each operation or
+/// built-in that would fail, innermost first: `dot(x, 3)` fails however
+/// `x` is declared, since `3` is not a list. Undeclared variables and user
+/// functions could be anything, so nothing that depends on them is
+/// reported.
+pub fn analyze(tree: &ExprTree, kinds: &TreeMap<String, Kind>) -> Vec<Mismatch> {
+    let mut found: Vec<Mismatch> = vec![];
+    match tree.root() {
+        Some(root) => { infer(root, kinds, &mut found); },
+        None => {},
+    }
+    found
+}
+
+// the base units of each dimension, for writing dimensions out
+static BASE_UNITS: [&'static str, ..5] = ["m", "kg", "s", "k", "b"];
+
+// the kind `node` gives, if the declarations tell; mismatches within it
+// are added to `found`, and a node that fails is taken to be unknown so
+// that each mistake is reported once
+fn infer(node: &ExprNode, kinds: &TreeMap<String, Kind>, found: &mut Vec<Mismatch>)
+         -> Option<Kind> {
+    if node.kind() == ExprBinary && node.token() == "=" {
+        let target = node.children().get(0);
+        if target.kind() != ExprCall {
+            return infer(node.children().get(1), kinds, found);
+        }
+        // a definition's parameters hide variables of the same names
+        let mut inner = kinds.clone();
+        for param in target.children().iter() {
+            inner.remove(&String::from_str(param.token()));
+        }
+        infer(node.children().get(1), &inner, found);
+        return None;
+    }
+
+    let mut args: Vec<Option<Kind>> = vec![];
+    for child in node.children().iter() {
+        args.push(if child.kind() == ExprBinary && child.token() == "=" {
+            // `name=value` inside a call: only the value is evaluated
+            infer(child.children().get(1), kinds, found)
+        } else {
+            infer(child, kinds, found)
+        });
+    }
+
+    let kind = match node.kind() {
+        ExprLiteral => Ok(Some(ScalarKind)),
+        ExprVariable => Ok(kinds.find(&String::from_str(node.token())).map(|k| k.clone())),
+        ExprText => Ok(None),
+        ExprUnary => Ok(args.get(0).clone()),
+        ExprUnit => match units::lookup(node.token()) {
+            Some(unit) =>
+                binary_kind("*", node, args.get(0), &Some(QuantityKind(unit.dimensions))),
+            None => Ok(None),
+        },
+        ExprBinary => binary_kind(node.token(), node, args.get(0), args.get(1)),
+        ExprCall => call_kind(node.token(), args.as_slice()),
+    };
+    match kind {
+        Ok(kind) => kind,
+        Err(error) => {
+            found.push(Mismatch { expression: format!("{}", node), error: error });
+            None
+        },
+    }
+}
+
+fn binary_kind(operator: &str, node: &ExprNode, lhs: &Option<Kind>, rhs: &Option<Kind>)
+               -> Result<Option<Kind>, CalcError> {
+    let (lhs, rhs) = match (lhs, rhs) {
+        (&Some(ref lhs), &Some(ref rhs)) => (lhs, rhs),
+        _ => return Ok(None),
+    };
+
+    // a matrix times a list is a list, and lists otherwise work element by
+    // element, so the result is the larger of the two
+    match (lhs, rhs) {
+        (&MatrixKind, &ListKind) | (&ListKind, &MatrixKind) if operator == "*" =>
+            return Ok(Some(ListKind)),
+        (&MatrixKind, _) | (_, &MatrixKind) => return Ok(Some(MatrixKind)),
+        (&ListKind, _) | (_, &ListKind) => return Ok(Some(ListKind)),
+        _ => {},
+    }
+
+    let (a, b) = (dimensions(lhs).unwrap(), dimensions(rhs).unwrap());
+    match operator {
+        "+"|"-"|"<"|">"|"<="|">="|"=="|"!=" => {
+            if a != b {
+                return Err(DimensionMismatch { left: unit_text(a), right: unit_text(b) });
+            }
+            Ok(Some(if operator == "+" || operator == "-" { lhs.clone() } else { ScalarKind }))
+        },
+        "*"|"/" => {
+            let sign = if operator == "*" { 1 } else { -1 };
+            let mut product = a;
+            for (total, &d) in product.mut_iter().zip(b.iter()) {
+                *total += sign * d;
+            }
+            Ok(Some(quantity(product)))
+        },
+        "^" => {
+            if b != [0i32, ..5] {
+                return Err(TypeMismatch { expected: "plain number", found: "quantity" });
+            }
+            // the power of a quantity is known when the exponent is
+            // written out
+            let exponent = node.children().get(1);
+            match from_str::<i32>(exponent.token()) {
+                Some(n) if exponent.kind() == ExprLiteral => {
+                    let mut power = a;
+                    for d in power.mut_iter() {
+                        *d *= n;
+                    }
+                    Ok(Some(quantity(power)))
+                },
+                _ if a == [0i32, ..5] => Ok(Some(ScalarKind)),
+                _ => Ok(None),
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+fn call_kind(name: &str, args: &[Option<Kind>]) -> Result<Option<Kind>, CalcError> {
+    let known = args.iter().all(|arg| arg.is_some());
+    match name {
+        // `[1, 2, 3]` is read as `list(1, 2, 3)`
+        "list" => Ok(if !known {
+            None
+        } else if !args.is_empty() && args.iter().all(|arg| *arg == Some(ListKind)) {
+            Some(MatrixKind)
+        } else if args.iter().all(|arg| dimensions(arg.get_ref()).is_some()) {
+            Some(ListKind)
+        } else {
+            None
+        }),
+        "dot"|"cross"|"transpose" => {
+            for arg in args.iter() {
+                match *arg {
+                    Some(ListKind) | None => {},
+                    Some(MatrixKind) if name == "transpose" => {},
+                    Some(ref other) => return Err(TypeMismatch {
+                        expected: "list of numbers",
+                        found: kind_name(other),
+                    }),
+                }
+            }
+            Ok(if !known {
+                None
+            } else {
+                Some(match name {
+                    "dot" => ScalarKind,
+                    "cross" => ListKind,
+                    _ => MatrixKind,
+                })
+            })
+        },
+        "det"|"inv" => match args.get(0) {
+            Some(&Some(MatrixKind)) =>
+                Ok(Some(if name == "det" { ScalarKind } else { MatrixKind })),
+            Some(&Some(ref other)) => Err(ShapeMismatch {
+                operation: String::from_str(name),
+                left: String::from_str(if *other == ListKind { "a list" } else { "a number" }),
+                right: String::from_str("a square matrix"),
+            }),
+            _ => Ok(None),
+        },
+        // the square root of anything but a list is worked out by the value
+        "sqrt" if args.len() == 1 && args[0] != Some(ListKind) => Ok(None),
+        _ if builtins::takes_numbers(name) => {
+            for arg in args.iter() {
+                match *arg {
+                    Some(QuantityKind(_)) =>
+                        return Err(TypeMismatch { expected: "number", found: "quantity" }),
+                    // lists are spread into their numbers, but the rows of
+                    // a matrix are lists
+                    Some(MatrixKind) =>
+                        return Err(TypeMismatch { expected: "number", found: "list" }),
+                    _ => {},
+                }
+            }
+            Ok(Some(ScalarKind))
+        },
+        _ => Ok(None),
+    }
+}
+
+// the dimensions of a number, all zero for a plain one; `None` for a list
+fn dimensions(kind: &Kind) -> Option<Dimensions> {
+    match *kind {
+        ScalarKind => Some([0i32, ..5]),
+        QuantityKind(dimensions) => Some(dimensions),
+        ListKind | MatrixKind => None,
+    }
+}
+
+fn quantity(dimensions: Dimensions) -> Kind {
+    if dimensions == [0i32, ..5] { ScalarKind } else { QuantityKind(dimensions) }
+}
+
+// dimensions written in base units, as `DimensionMismatch` gives them
+fn unit_text(dimensions: Dimensions) -> String {
+    let units = BASE_UNITS.iter().zip(dimensions.iter())
+        .filter(|&(_, &power)| power != 0)
+        .map(|(name, &power)| (String::from_str(*name), power))
+        .collect();
+    Measure { magnitude: 1_f64, units: units }.unit_text()
+}
+
+// what `Value::kind_name` calls a value of this kind
+fn kind_name(kind: &Kind) -> &'static str {
+    match *kind {
+        ScalarKind => "number",
+        ListKind | MatrixKind => "list",
+        QuantityKind(_) => "quantity",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::TreeMap;
+    use std::string::String;
+
+    use env::Environment;
+    use error::{CalcError, DimensionMismatch, ShapeMismatch, TypeMismatch};
+    use exprtree::ExprTree;
+    use value::Real;
+    use super::{Assignment, Nondeterministic};
+    use super::{Kind, ScalarKind, ListKind, MatrixKind, QuantityKind};
+    use super::{analyze, condition, impurities, is_pure, sensitivity, stability};
+    use super::stability_report;
+
+    // the stability report for `expression`, worked in an environment
+    // seeded with `seed`
+    fn report(expression: &str, seed: uint) -> String {
+        let mut env = Environment::new();
+        env.seed(seed);
+        let tree = ExprTree::build(expression).unwrap();
+        stability_report(&stability(&tree, &mut env, 50).unwrap().unwrap())
+    }
+
+    // the failing parts of `expression` and their errors, with `x` a list,
+    // `m` a matrix, `n` a number and `d` a length
+    fn mismatches(expression: &str) -> Vec<(String, CalcError)> {
+        let mut kinds: TreeMap<String, Kind> = TreeMap::new();
+        kinds.insert(String::from_str("x"), ListKind);
+        kinds.insert(String::from_str("m"), MatrixKind);
+        kinds.insert(String::from_str("n"), ScalarKind);
+        kinds.insert(String::from_str("d"), QuantityKind([1, 0, 0, 0, 0]));
+        let tree = ExprTree::build(expression).unwrap();
+        analyze(&tree, &kinds).move_iter().map(|m| (m.expression, m.error)).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_report() {
+        let expression = "(1 + 1e-10) - 1";
+        assert_eq!(report(expression, 7), report(expression, 7));
+        assert!(report(expression, 7) != report(expression, 8));
+    }
+
+    #[test]
+    fn inputs_are_ranked_by_their_effect() {
+        let mut env = Environment::new();
+        env.set("a", Real(2_f64));
+        env.set("b", Real(3_f64));
+        let tree = ExprTree::build("a^2 + b").unwrap();
+        let rows = sensitivity(&tree, &mut env, None, 10_f64).unwrap();
+
+        let names: Vec<&str> = rows.iter().map(|r| r.variable.as_slice()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert!((rows.get(0).change - 0.84).abs() < 1e-9);
+        assert!((rows.get(1).percent - 0.3 / 7_f64 * 100_f64).abs() < 1e-9);
+        // the inputs are put back afterwards
+        assert_eq!(env.get("a"), Some(Real(2_f64)));
+
+        let rows = sensitivity(&tree, &mut env, Some("b"), 10_f64).unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn user_functions_are_followed_for_impurities() {
+        let mut env = Environment::new();
+        ExprTree::build("noisy(x) = x + rand()").unwrap().eval_in(&mut env).unwrap();
+        ExprTree::build("calm(x) = 2 * x").unwrap().eval_in(&mut env).unwrap();
+
+        assert!(is_pure(&ExprTree::build("sin(y) + calm(y)").unwrap(), &env));
+        assert_eq!(impurities(&ExprTree::build("noisy(1) + noisy(2)").unwrap(), &env),
+                   vec![Nondeterministic(String::from_str("rand"))]);
+        assert_eq!(impurities(&ExprTree::build("y = calm(3)").unwrap(), &env),
+                   vec![Assignment(String::from_str("y"))]);
+    }
+
+    #[test]
+    fn cancellation_is_reported_as_unstable() {
+        assert!(report("(1 + 1e-10) - 1", 7).as_slice().contains("warning: "));
+        assert!(!report("2 * 3 + 1", 7).as_slice().contains("warning: "));
+
+        // assignments are not analysed
+        let mut env = Environment::new();
+        let tree = ExprTree::build("x = 1 / 3").unwrap();
+        assert!(stability(&tree, &mut env, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn powers_and_differences_condition_as_expected() {
+        let mut env = Environment::new();
+        env.set("a", Real(3_f64));
+        env.set("b", Real(2_f64));
+        let product = condition(&ExprTree::build("a^2 * b").unwrap(), &env).unwrap().unwrap();
+        assert!((product.value - 18_f64).abs() < 1e-9);
+        assert!((product.number - 3_f64).abs() < 1e-9);
+        let names: Vec<String> = product.inputs.iter().map(|&(ref name, _)| name.clone()).collect();
+        assert_eq!(names, vec![String::from_str("a"), String::from_str("b")]);
+        assert!(!product.is_ill_conditioned());
+
+        env.set("a", Real(1.0000000001));
+        env.set("b", Real(1_f64));
+        let difference = condition(&ExprTree::build("a - b").unwrap(), &env).unwrap().unwrap();
+        assert!(difference.is_ill_conditioned());
+    }
+
+    #[test]
+    fn matrix_built_ins_check_their_arguments() {
+        assert_eq!(mismatches("dot(x, 3)"), vec![(String::from_str("dot(x, 3)"), TypeMismatch {
+            expected: "list of numbers",
+            found: "number",
+        })]);
+        assert_eq!(mismatches("det(x) + inv(m * m) + dot(m * x, [1, n])"),
+                   vec![(String::from_str("det(x)"), ShapeMismatch {
+                       operation: String::from_str("det"),
+                       left: String::from_str("a list"),
+                       right: String::from_str("a square matrix"),
+                   })]);
+        assert!(mismatches("sum(x) + dot(transpose(m) * x, x) + sin(n)").is_empty());
+        assert_eq!(mismatches("sin(m)").len(), 1);
+
+        // nothing is known of undeclared variables or a function's own
+        // parameters
+        assert!(mismatches("dot(y, 3) + z").len() == 1);
+        assert!(mismatches("dot(y, z)").is_empty());
+        assert!(mismatches("f(x) = dot(x, 3)").len() == 1);
+        assert!(mismatches("f(n) = dot(n, x)").is_empty());
+    }
+
+    #[test]
+    fn declared_dimensions_must_agree() {
+        assert_eq!(mismatches("d * n + 1"), vec![(String::from_str("d * n + 1"),
+                                                  DimensionMismatch {
+                                                      left: String::from_str("m"),
+                                                      right: String::from_str(""),
+                                                  })]);
+        assert!(mismatches("d^2 / d - d < d").is_empty());
+        assert_eq!(mismatches("d^2 == d").len(), 1);
+        assert_eq!(mismatches("n^d").len(), 1);
+        // lists carry no dimensions, and a failed part is not reported again
+        assert!(mismatches("x + d").is_empty());
+        assert_eq!(mismatches("(d + n) * 2 + n").len(), 1);
+    }
+}
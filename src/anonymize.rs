@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use builtins;
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprBinary, ExprCall, ExprText};
+use exprtree::parse_number;
+
+/// Placeholders handed out so far, one table per kind of name, so the same
+/// name or number gets the same placeholder everywhere it appears.
+struct Placeholders {
+    variables: HashMap<String, String>,
+    functions: HashMap<String, String>,
+    numbers: HashMap<String, String>,
+    texts: HashMap<String, String>,
+}
+
+impl Placeholders {
+    fn new() -> Placeholders {
+        Placeholders {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            numbers: HashMap::new(),
+            texts: HashMap::new(),
+        }
+    }
+}
+
+// `prefix` and the order the name was first seen, counting from 1
+fn placeholder(table: &mut HashMap<String, String>, prefix: &str, name: &str) -> String {
+    let next = table.len() + 1;
+    table.find_or_insert_with(String::from_str(name), |_| format!("{}{}", prefix, next)).clone()
+}
+
+/// The tree with user data swapped for placeholders: variables become
+/// `a1`, `a2`, ..., user functions `f1`, ..., numbers `n1`, ... and
+/// strings `s1`, .... Operators, built-ins, named constants and units are
+/// kept, so `price * qty + round(price)` comes out as
+/// `a1 * a2 + round(a1)` and the shape of a formula can be logged without
+/// what was in it.
+pub fn anonymize(node: &ExprNode) -> ExprNode {
+    anonymize_node(node, &mut Placeholders::new())
+}
+
+fn anonymize_node(node: &ExprNode, names: &mut Placeholders) -> ExprNode {
+    let token = node.token();
+    match node.kind() {
+        ExprLiteral if parse_number(token).is_some() => {
+            let name = placeholder(&mut names.numbers, "n", token);
+            ExprNode::new(name.as_slice(), ExprVariable, vec![])
+        },
+        ExprVariable if token == "_" || builtins::is_builtin(token) => node.clone(),
+        ExprVariable => {
+            let name = placeholder(&mut names.variables, "a", token);
+            ExprNode::new(name.as_slice(), ExprVariable, vec![])
+        },
+        ExprText => {
+            let name = placeholder(&mut names.texts, "s", token);
+            ExprNode::new(name.as_slice(), ExprText, vec![])
+        },
+        // the right of a conversion names units, which are kept
+        ExprBinary if token == "to" || token == "in" => {
+            let lhs = anonymize_node(node.children().get(0), names);
+            ExprNode::new(token, ExprBinary, vec![lhs, node.children().get(1).clone()])
+        },
+        ExprCall => {
+            let name = if builtins::is_builtin(token) {
+                String::from_str(token)
+            } else {
+                placeholder(&mut names.functions, "f", token)
+            };
+            let children = node.children().iter()
+                .map(|child| anonymize_node(child, names))
+                .collect();
+            ExprNode::new(name.as_slice(), ExprCall, children)
+        },
+        kind => {
+            let children = node.children().iter()
+                .map(|child| anonymize_node(child, names))
+                .collect();
+            ExprNode::new(token, kind, children)
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+
+    fn anonymized(expression: &str) -> String {
+        format!("{}", ExprTree::build(expression).unwrap().anonymize().root().unwrap())
+    }
+
+    #[test]
+    fn the_same_name_gets_the_same_placeholder() {
+        assert_eq!(anonymized("price * qty + round(price)"),
+                   String::from_str("a1 * a2 + round(a1)"));
+        assert_eq!(anonymized("tax(price, 0.2) + 0.2 * pi"),
+                   String::from_str("f1(a1, n1) + n1 * pi"));
+        // the shape is all that is left
+        assert_eq!(anonymized("x^2 - y"), anonymized("b^7 - c"));
+    }
+}
@@ -0,0 +1,686 @@
+use std::fmt;
+
+use error::{CalcError, UnknownFunction, WrongArity, OutOfDomain};
+#[cfg(feature = "finance")]
+use finance;
+#[cfg(feature = "matrix")]
+use matrix;
+use registry;
+#[cfg(feature = "stats")]
+use stats::Summary;
+use value::Value;
+
+/// The unit trigonometric functions take their arguments in and inverse
+/// ones give their results in.
+#[deriving(Show, Clone, PartialEq)]
+pub enum AngleMode {
+    Radians,
+    Degrees,
+}
+
+/// How many arguments a function accepts.
+#[deriving(Clone, PartialEq)]
+pub enum Arity {
+    Exactly(uint),
+    AtLeast(uint),
+    Between(uint, uint),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: uint) -> bool {
+        match *self {
+            Exactly(n) => count == n,
+            AtLeast(n) => count >= n,
+            Between(min, max) => count >= min && count <= max,
+        }
+    }
+
+    /// Whether some number of arguments is accepted by both arities, so
+    /// overloads of one name with them could not be told apart.
+    pub fn overlaps(&self, other: &Arity) -> bool {
+        let (low, high) = self.bounds();
+        let (other_low, other_high) = other.bounds();
+        low <= other_high.unwrap_or(low) && other_low <= high.unwrap_or(other_low)
+    }
+
+    // the fewest and most arguments accepted, with no most for `AtLeast`
+    fn bounds(&self) -> (uint, Option<uint>) {
+        match *self {
+            Exactly(n) => (n, Some(n)),
+            AtLeast(n) => (n, None),
+            Between(min, max) => (min, Some(max)),
+        }
+    }
+}
+
+impl fmt::Show for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exactly(n) => write!(f, "{}", n),
+            AtLeast(n) => write!(f, "at least {}", n),
+            Between(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// A function the calculator has built in. The tables below are what
+/// `registry::builtin` looks names up in.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    /// How `call` works the function out from numbers, with angles in the
+    /// given unit; `None` for those that take functions or text, or
+    /// evaluate lazily, and are applied by the evaluator.
+    pub apply: Option<fn(&[f64], AngleMode) -> Result<f64, CalcError>>,
+}
+
+/// The built-ins of every build.
+pub static CORE: [Builtin, ..66] = [
+    Builtin { name: "ln", arity: Exactly(1), apply: Some(ln) },
+    Builtin { name: "lg", arity: Exactly(1), apply: Some(lg) },
+    // `log(x, base)` and `round(x, places)`; the base defaults to ten and
+    // the places to none
+    Builtin { name: "log", arity: Between(1, 2), apply: Some(log) },
+    Builtin { name: "sin", arity: Exactly(1), apply: Some(sin) },
+    Builtin { name: "cos", arity: Exactly(1), apply: Some(cos) },
+    Builtin { name: "tan", arity: Exactly(1), apply: Some(tan) },
+    Builtin { name: "csc", arity: Exactly(1), apply: Some(csc) },
+    Builtin { name: "sec", arity: Exactly(1), apply: Some(sec) },
+    Builtin { name: "cot", arity: Exactly(1), apply: Some(cot) },
+    Builtin { name: "asin", arity: Exactly(1), apply: Some(asin) },
+    Builtin { name: "acos", arity: Exactly(1), apply: Some(acos) },
+    Builtin { name: "atan", arity: Exactly(1), apply: Some(atan) },
+    Builtin { name: "acsc", arity: Exactly(1), apply: Some(acsc) },
+    Builtin { name: "asec", arity: Exactly(1), apply: Some(asec) },
+    Builtin { name: "acot", arity: Exactly(1), apply: Some(acot) },
+    Builtin { name: "neg", arity: Exactly(1), apply: Some(neg) },
+    Builtin { name: "sgn", arity: Exactly(1), apply: Some(sgn) },
+    Builtin { name: "sinh", arity: Exactly(1), apply: Some(sinh) },
+    Builtin { name: "cosh", arity: Exactly(1), apply: Some(cosh) },
+    Builtin { name: "tanh", arity: Exactly(1), apply: Some(tanh) },
+    Builtin { name: "sqrt", arity: Exactly(1), apply: Some(sqrt) },
+    Builtin { name: "cbrt", arity: Exactly(1), apply: Some(cbrt) },
+    Builtin { name: "exp", arity: Exactly(1), apply: Some(exp) },
+    Builtin { name: "abs", arity: Exactly(1), apply: Some(abs) },
+    Builtin { name: "round", arity: Between(1, 2), apply: Some(round) },
+    Builtin { name: "floor", arity: Exactly(1), apply: Some(floor) },
+    Builtin { name: "ceil", arity: Exactly(1), apply: Some(ceil) },
+    Builtin { name: "trunc", arity: Exactly(1), apply: Some(trunc) },
+    Builtin { name: "deg", arity: Exactly(1), apply: Some(deg) },
+    Builtin { name: "rad", arity: Exactly(1), apply: Some(rad) },
+    Builtin { name: "fact", arity: Exactly(1), apply: Some(fact) },
+    Builtin { name: "gamma", arity: Exactly(1), apply: Some(gamma_of) },
+    Builtin { name: "atan2", arity: Exactly(2), apply: Some(atan2) },
+    Builtin { name: "pow", arity: Exactly(2), apply: Some(pow) },
+    Builtin { name: "add", arity: Exactly(2), apply: Some(add) },
+    Builtin { name: "sub", arity: Exactly(2), apply: Some(sub) },
+    Builtin { name: "mul", arity: Exactly(2), apply: Some(mul) },
+    Builtin { name: "div", arity: Exactly(2), apply: Some(div) },
+    Builtin { name: "ncr", arity: Exactly(2), apply: Some(ncr) },
+    Builtin { name: "npr", arity: Exactly(2), apply: Some(npr) },
+    Builtin { name: "max", arity: AtLeast(1), apply: Some(max) },
+    Builtin { name: "min", arity: AtLeast(1), apply: Some(min) },
+    Builtin { name: "sum", arity: AtLeast(1), apply: Some(sum) },
+    Builtin { name: "prod", arity: AtLeast(1), apply: Some(prod) },
+    Builtin { name: "avg", arity: AtLeast(1), apply: Some(mean) },
+    Builtin { name: "mean", arity: AtLeast(1), apply: Some(mean) },
+    Builtin { name: "gcd", arity: AtLeast(1), apply: Some(gcd_of) },
+    Builtin { name: "lcm", arity: AtLeast(1), apply: Some(lcm_of) },
+    Builtin { name: "map", arity: AtLeast(1), apply: None },
+    Builtin { name: "list", arity: AtLeast(0), apply: None },
+    Builtin { name: "rand", arity: Between(0, 2), apply: None },
+    Builtin { name: "randint", arity: Exactly(2), apply: None },
+    Builtin { name: "normal", arity: Exactly(2), apply: None },
+    Builtin { name: "to_hex", arity: Exactly(1), apply: None },
+    Builtin { name: "to_oct", arity: Exactly(1), apply: None },
+    Builtin { name: "to_bin", arity: Exactly(1), apply: None },
+    Builtin { name: "reduce", arity: AtLeast(2), apply: None },
+    Builtin { name: "rearrange", arity: Exactly(2), apply: None },
+    Builtin { name: "derive", arity: Exactly(2), apply: None },
+    Builtin { name: "simplify", arity: Exactly(1), apply: None },
+    Builtin { name: "rate", arity: Exactly(2), apply: None },
+    Builtin { name: "goalseek", arity: Exactly(4), apply: None },
+    Builtin { name: "solve", arity: Between(2, 4), apply: None },
+    Builtin { name: "integrate", arity: Exactly(4), apply: None },
+    Builtin { name: "product", arity: Exactly(4), apply: None },
+    Builtin { name: "if", arity: Exactly(3), apply: None },
+];
+
+// the built-ins of the optional parts of the library are only there when
+// it is built with their features; see the Makefile
+
+/// The built-ins of the finance feature.
+#[cfg(feature = "finance")]
+pub static FINANCE: [Builtin, ..2] = [
+    // rate per period, number of periods, principal
+    Builtin { name: "pmt", arity: Exactly(3), apply: Some(pmt) },
+    // the amount, then limit and rate pairs, then an optional top rate
+    Builtin { name: "brackets", arity: AtLeast(2), apply: Some(brackets) },
+];
+
+#[cfg(not(feature = "finance"))]
+pub static FINANCE: [Builtin, ..0] = [];
+
+/// The built-ins of the stats feature.
+#[cfg(feature = "stats")]
+pub static STATS: [Builtin, ..2] = [
+    Builtin { name: "median", arity: AtLeast(1), apply: Some(median) },
+    Builtin { name: "stddev", arity: AtLeast(1), apply: Some(stddev) },
+];
+
+#[cfg(not(feature = "stats"))]
+pub static STATS: [Builtin, ..0] = [];
+
+/// The built-ins of the matrix feature, applied by the evaluator through
+/// `call_matrix`.
+#[cfg(feature = "matrix")]
+pub static MATRIX: [Builtin, ..5] = [
+    Builtin { name: "dot", arity: Exactly(2), apply: None },
+    Builtin { name: "cross", arity: Exactly(2), apply: None },
+    Builtin { name: "det", arity: Exactly(1), apply: None },
+    Builtin { name: "inv", arity: Exactly(1), apply: None },
+    Builtin { name: "transpose", arity: Exactly(1), apply: None },
+];
+
+#[cfg(not(feature = "matrix"))]
+pub static MATRIX: [Builtin, ..0] = [];
+
+/// The arity of the built-in function `name`, if there is one.
+pub fn arity(name: &str) -> Option<Arity> {
+    registry::builtin(name).map(|builtin| builtin.arity.clone())
+}
+
+/// Applies the matrix built-in `name` to `args`, whose count the caller
+/// has checked.
+#[cfg(feature = "matrix")]
+pub fn call_matrix(name: &str, args: &[Value]) -> Result<Value, CalcError> {
+    matrix::call(name, args)
+}
+
+#[cfg(not(feature = "matrix"))]
+pub fn call_matrix(name: &str, _: &[Value]) -> Result<Value, CalcError> {
+    Err(UnknownFunction(String::from_str(name)))
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    registry::builtin(name).is_some()
+}
+
+/// Whether the built-in `name` always gives the same result for the same
+/// arguments; those that draw from the environment's random source do not.
+pub fn is_deterministic(name: &str) -> bool {
+    match name {
+        "rand"|"randint"|"normal" => false,
+        _ => is_builtin(name),
+    }
+}
+
+/// Whether the built-in `name` reads or gives an angle, and so depends on
+/// the `AngleMode` it is called with.
+pub fn uses_angle(name: &str) -> bool {
+    match name {
+        "sin"|"cos"|"tan"|"csc"|"sec"|"cot"|"asin"|"acos"|"atan"|"acsc"|"asec"|"acot" |
+        "atan2"|"deg"|"rad" => true,
+        _ => false,
+    }
+}
+
+/// Roughly what one call to the built-in `name` costs, in units of about
+/// one addition, not counting its arguments; see `Program::estimated_cost`.
+pub fn cost(name: &str) -> uint {
+    match name {
+        "neg"|"sgn"|"abs"|"round"|"floor"|"ceil"|"trunc"|"deg"|"rad" |
+        "add"|"sub"|"mul"|"max"|"min"|"sum"|"prod"|"list" => 1,
+        "div"|"avg"|"mean"|"stddev"|"sqrt" => 4,
+        // a sort
+        "median" => 20,
+        "ln"|"lg"|"log"|"exp"|"cbrt"|"pow" => 20,
+        "sin"|"cos"|"tan"|"csc"|"sec"|"cot"|"sinh"|"cosh"|"tanh" => 25,
+        "asin"|"acos"|"atan"|"acsc"|"asec"|"acot"|"atan2" => 30,
+        "gcd"|"lcm"|"ncr"|"npr"|"pmt"|"brackets" => 50,
+        // up to 170 multiplications, and a reflection and series
+        "fact"|"gamma" => 170,
+        _ => 10,
+    }
+}
+
+/// Whether `call` can apply `name`; the other built-ins take functions or
+/// text, or evaluate lazily, and are applied by the evaluator.
+pub fn takes_numbers(name: &str) -> bool {
+    registry::builtin(name).map_or(false, |builtin| builtin.apply.is_some())
+}
+
+/// Checks that the built-in `name` exists and takes `count` arguments.
+pub fn check_arity(name: &str, count: uint) -> Result<(), CalcError> {
+    let arity = match arity(name) {
+        Some(arity) => arity,
+        None => return Err(UnknownFunction(String::from_str(name))),
+    };
+
+    if !arity.accepts(count) {
+        return Err(WrongArity {
+            function: String::from_str(name),
+            expected: arity,
+            found: count,
+        });
+    }
+    Ok(())
+}
+
+/// Applies the built-in `name` to already evaluated arguments, with angles
+/// in the unit `angle`.
+pub fn call(name: &str, args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    try!(check_arity(name, args.len()));
+    match registry::builtin(name).and_then(|builtin| builtin.apply) {
+        Some(apply) => apply(args, angle),
+        None => Err(UnknownFunction(String::from_str(name))),
+    }
+}
+
+// the domain checks below turn what would otherwise be a silent NaN into
+// an error naming the argument
+
+fn out_of_domain(function: &str, argument: f64, expected: &'static str)
+                 -> Result<f64, CalcError> {
+    Err(OutOfDomain {
+        function: String::from_str(function),
+        argument: argument,
+        expected: expected,
+    })
+}
+
+fn to_radians(x: f64, angle: AngleMode) -> f64 {
+    if angle == Degrees { x.to_radians() } else { x }
+}
+
+fn from_radians(x: f64, angle: AngleMode) -> f64 {
+    if angle == Degrees { x.to_degrees() } else { x }
+}
+
+fn ln(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if args[0] < 0_f64 {
+        return out_of_domain("ln", args[0], "numbers of at least 0");
+    }
+    Ok(args[0].ln())
+}
+
+fn lg(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if args[0] < 0_f64 {
+        return out_of_domain("lg", args[0], "numbers of at least 0");
+    }
+    Ok(args[0].log2())
+}
+
+fn log(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if args[0] < 0_f64 {
+        return out_of_domain("log", args[0], "numbers of at least 0");
+    }
+    if args.len() == 1 {
+        return Ok(args[0].log10());
+    }
+    if args[1] <= 0_f64 || args[1] == 1_f64 {
+        return out_of_domain("log", args[1], "bases above 0 other than 1");
+    }
+    Ok(args[0].ln() / args[1].ln())
+}
+
+fn sin(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(to_radians(args[0], angle).sin())
+}
+
+fn cos(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(to_radians(args[0], angle).cos())
+}
+
+fn tan(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(to_radians(args[0], angle).tan())
+}
+
+fn csc(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(1_f64 / to_radians(args[0], angle).sin())
+}
+
+fn sec(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(1_f64 / to_radians(args[0], angle).cos())
+}
+
+fn cot(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(1_f64 / to_radians(args[0], angle).tan())
+}
+
+fn asin(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    if args[0].abs() > 1_f64 {
+        return out_of_domain("asin", args[0], "numbers from -1 to 1");
+    }
+    Ok(from_radians(args[0].asin(), angle))
+}
+
+fn acos(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    if args[0].abs() > 1_f64 {
+        return out_of_domain("acos", args[0], "numbers from -1 to 1");
+    }
+    Ok(from_radians(args[0].acos(), angle))
+}
+
+fn atan(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(from_radians(args[0].atan(), angle))
+}
+
+fn acsc(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    if args[0].abs() < 1_f64 {
+        return out_of_domain("acsc", args[0], "numbers of at least 1 in size");
+    }
+    Ok(from_radians((1_f64 / args[0]).asin(), angle))
+}
+
+fn asec(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    if args[0].abs() < 1_f64 {
+        return out_of_domain("asec", args[0], "numbers of at least 1 in size");
+    }
+    Ok(from_radians((1_f64 / args[0]).acos(), angle))
+}
+
+fn acot(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(from_radians((1_f64 / args[0]).atan(), angle))
+}
+
+fn neg(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(-args[0])
+}
+
+fn sgn(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].signum())
+}
+
+fn sinh(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].sinh())
+}
+
+fn cosh(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].cosh())
+}
+
+fn tanh(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].tanh())
+}
+
+fn sqrt(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if args[0] < 0_f64 {
+        return out_of_domain("sqrt", args[0], "numbers of at least 0");
+    }
+    Ok(args[0].sqrt())
+}
+
+fn cbrt(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].cbrt())
+}
+
+fn exp(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].exp())
+}
+
+fn abs(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].abs())
+}
+
+// a negative number of places rounds to tens, hundreds and so on
+fn round(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if args.len() == 1 {
+        return Ok(args[0].round());
+    }
+    if !is_whole(args[1]) {
+        return out_of_domain("round", args[1], "whole numbers of places");
+    }
+    let scale = 10_f64.powi(args[1] as i32);
+    Ok((args[0] * scale).round() / scale)
+}
+
+fn floor(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].floor())
+}
+
+fn ceil(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].ceil())
+}
+
+fn trunc(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].trunc())
+}
+
+// these say what unit a value is in and convert it to the one angles are
+// being worked in, so `sin(deg(90))` is 1 either way
+
+fn deg(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(from_radians(args[0].to_radians(), angle))
+}
+
+fn rad(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(from_radians(args[0], angle))
+}
+
+fn fact(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if !is_whole(args[0]) || args[0] < 0_f64 {
+        return out_of_domain("fact", args[0], "whole numbers of at least 0");
+    }
+    Ok(factorial(args[0]))
+}
+
+fn gamma_of(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    if is_whole(args[0]) && args[0] <= 0_f64 {
+        return out_of_domain("gamma", args[0], "any number but 0 and the negative \
+                                                whole numbers");
+    }
+    Ok(gamma(args[0]))
+}
+
+fn atan2(args: &[f64], angle: AngleMode) -> Result<f64, CalcError> {
+    Ok(from_radians(args[0].atan2(args[1]), angle))
+}
+
+fn pow(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0].powf(args[1]))
+}
+
+fn add(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0] + args[1])
+}
+
+fn sub(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0] - args[1])
+}
+
+fn mul(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0] * args[1])
+}
+
+fn div(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args[0] / args[1])
+}
+
+fn ncr(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    match args.iter().find(|&&arg| !is_whole(arg) || arg < 0_f64) {
+        Some(&arg) => out_of_domain("ncr", arg, "whole numbers of at least 0"),
+        None => Ok(choose(args[0], args[1])),
+    }
+}
+
+fn npr(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    match args.iter().find(|&&arg| !is_whole(arg) || arg < 0_f64) {
+        Some(&arg) => out_of_domain("npr", arg, "whole numbers of at least 0"),
+        None => Ok(permutations(args[0], args[1])),
+    }
+}
+
+fn max(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args.iter().fold(args[0], |best, &arg| best.max(arg)))
+}
+
+fn min(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args.iter().fold(args[0], |best, &arg| best.min(arg)))
+}
+
+fn sum(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args.iter().fold(0_f64, |total, &arg| total + arg))
+}
+
+fn prod(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(args.iter().fold(1_f64, |total, &arg| total * arg))
+}
+
+fn mean(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    let total = args.iter().fold(0_f64, |total, &arg| total + arg);
+    Ok(total / args.len() as f64)
+}
+
+fn gcd_of(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    match args.iter().find(|&&arg| !is_whole(arg)) {
+        Some(&arg) => out_of_domain("gcd", arg, "whole numbers"),
+        None => Ok(args.iter().fold(0_f64, |a, &b| gcd(a, b))),
+    }
+}
+
+fn lcm_of(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    match args.iter().find(|&&arg| !is_whole(arg)) {
+        Some(&arg) => out_of_domain("lcm", arg, "whole numbers"),
+        None => Ok(args.iter().fold(1_f64, |a, &b| if a == 0_f64 || b == 0_f64 {
+            0_f64
+        } else {
+            (a * b / gcd(a, b)).abs()
+        })),
+    }
+}
+
+#[cfg(feature = "finance")]
+fn pmt(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(finance::pmt(args[0], args[1], args[2]))
+}
+
+#[cfg(feature = "finance")]
+fn brackets(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(finance::tiered(args[0], args.slice_from(1)))
+}
+
+#[cfg(feature = "stats")]
+fn median(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(summary_of(args).percentile(50_f64))
+}
+
+#[cfg(feature = "stats")]
+fn stddev(args: &[f64], _: AngleMode) -> Result<f64, CalcError> {
+    Ok(summary_of(args).stddev())
+}
+
+#[cfg(feature = "stats")]
+fn summary_of(args: &[f64]) -> Summary {
+    let mut summary = Summary::new();
+    for &arg in args.iter() {
+        summary.add(arg);
+    }
+    summary
+}
+
+fn is_whole(x: f64) -> bool {
+    x.is_finite() && x == x.trunc()
+}
+
+// Lanczos' approximation with g = 7, reflected for arguments below one half;
+// good to about 15 digits
+fn gamma(x: f64) -> f64 {
+    static COEFFICIENTS: [f64, ..9] = [
+        0.99999999999980993, 676.5203681218851, -1259.1392167224028,
+        771.32342877765313, -176.61502916214059, 12.507343278686905,
+        -0.13857109526572012, 9.9843695780195716e-6, 1.5056327351493116e-7,
+    ];
+
+    if x < 0.5 {
+        let pi: f64 = Float::pi();
+        return pi / ((pi * x).sin() * gamma(1_f64 - x));
+    }
+
+    let x = x - 1_f64;
+    let t = x + 7.5;
+    let mut sum = COEFFICIENTS[0];
+    for (i, &c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    let two_pi: f64 = Float::two_pi();
+    two_pi.sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+// `n!` overflows a double past 170
+fn factorial(n: f64) -> f64 {
+    if n > 170_f64 {
+        return Float::infinity();
+    }
+    range(1, n as uint + 1).fold(1_f64, |product, i| product * i as f64)
+}
+
+fn gcd(a: f64, b: f64) -> f64 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0_f64 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+// ways to pick `k` of `n` things; both are whole and not negative
+fn choose(n: f64, k: f64) -> f64 {
+    if k > n {
+        return 0_f64;
+    }
+    let k = k.min(n - k);
+    let mut ways = 1_f64;
+    for i in range(0, k as uint) {
+        ways = ways * (n - i as f64) / (i + 1) as f64;
+    }
+    ways.round()
+}
+
+// ordered ways to pick `k` of `n` things
+fn permutations(n: f64, k: f64) -> f64 {
+    if k > n {
+        return 0_f64;
+    }
+    range(0, k as uint).fold(1_f64, |ways, i| ways * (n - i as f64))
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use error::OutOfDomain;
+    use super::{call, Radians, Degrees};
+
+    #[test]
+    fn degrees_convert_at_the_edges() {
+        assert_eq!(call("sin", &[90_f64], Degrees), Ok(1_f64));
+        assert_eq!(call("cos", &[180_f64], Degrees), Ok(-1_f64));
+        assert_eq!(call("asin", &[1_f64], Degrees), Ok(90_f64));
+        // `deg` and `rad` say what unit a value is in
+        let pi: f64 = Float::pi();
+        assert_eq!(call("sin", &[90_f64.to_radians()], Radians), Ok(1_f64));
+        assert_eq!(call("deg", &[180_f64], Radians), Ok(pi));
+        assert_eq!(call("sin", &[call("deg", &[90_f64], Degrees).unwrap()], Degrees), Ok(1_f64));
+    }
+
+    #[test]
+    fn whole_number_built_ins_check_their_domain() {
+        assert_eq!(call("ncr", &[5_f64, 2_f64], Radians), Ok(10_f64));
+        assert_eq!(call("npr", &[5_f64, 2_f64], Radians), Ok(20_f64));
+        assert_eq!(call("ncr", &[2_f64, 5_f64], Radians), Ok(0_f64));
+        assert_eq!(call("fact", &[5_f64], Radians), Ok(120_f64));
+        assert_eq!(call("round", &[1234.5678, -2_f64], Radians), Ok(1200_f64));
+        assert_eq!(call("tanh", &[0_f64], Radians), Ok(0_f64));
+        assert_eq!(call("cosh", &[0_f64], Radians), Ok(1_f64));
+
+        assert_eq!(call("fact", &[-1_f64], Radians), Err(OutOfDomain {
+            function: String::from_str("fact"),
+            argument: -1_f64,
+            expected: "whole numbers of at least 0",
+        }));
+        assert!(call("gamma", &[0_f64], Radians).is_err());
+        assert!(call("acos", &[2_f64], Radians).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::f64::consts::PI;
+use std::string::String;
+
+use algebra::occurrences;
+use builtins;
+use builtins::Degrees;
+use env::Environment;
+use error::{CalcError, CannotDifferentiate, UnknownVariable};
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+use simplify::{numeric, number, add, sub, mul, div, pow, negate};
+
+/// The derivative of `node` with respect to `variable`, built by applying
+/// the sum, product, quotient, power and chain rules from the leaves up, so
+/// `x^2 + sin(x)` gives `2 * x + cos(x)`. Each node is simplified as it
+/// is built, so terms that come out as 0 or 1 are dropped.
+pub fn derive(node: &ExprNode, variable: &str) -> Result<ExprNode, CalcError> {
+    let children = node.children();
+
+    match node.kind() {
+        ExprLiteral => Ok(number(0_f64)),
+        ExprVariable => Ok(number(if node.token() == variable { 1_f64 } else { 0_f64 })),
+        ExprUnary => Ok(negate(try!(derive(children.get(0), variable)))),
+        ExprBinary => {
+            let (u, v) = (children.get(0), children.get(1));
+            let du = try!(derive(u, variable));
+            let dv = try!(derive(v, variable));
+
+            Ok(match node.token() {
+                "+" => add(du, dv),
+                "-" => sub(du, dv),
+                "*" => add(mul(du, v.clone()), mul(u.clone(), dv)),
+                "/" => div(sub(mul(du, v.clone()), mul(u.clone(), dv)),
+                           pow(v.clone(), number(2_f64))),
+                "^" if occurrences(v, variable) == 0 => {
+                    // n * u^(n - 1) * u'
+                    let lowered = match numeric(v) {
+                        Some(n) => number(n - 1_f64),
+                        None => sub(v.clone(), number(1_f64)),
+                    };
+                    mul(mul(v.clone(), pow(u.clone(), lowered)), du)
+                },
+                "^" if occurrences(u, variable) == 0 => {
+                    // a^v * ln(a) * v', where ln(e) is left out
+                    if u.kind() == ExprLiteral && u.token() == "e" {
+                        mul(node.clone(), dv)
+                    } else {
+                        mul(mul(node.clone(), call("ln", u.clone())), dv)
+                    }
+                },
+                "^" => {
+                    // u^v * (v' * ln(u) + v * u' / u)
+                    let inner = add(mul(dv, call("ln", u.clone())),
+                                    div(mul(v.clone(), du), u.clone()));
+                    mul(node.clone(), inner)
+                },
+                other => return Err(CannotDifferentiate {
+                    function: String::from_str(other),
+                }),
+            })
+        },
+        ExprCall if children.len() == 1 => {
+            let u = children.get(0);
+            let du = try!(derive(u, variable));
+
+            let outer = match node.token() {
+                "sin" => call("cos", u.clone()),
+                "cos" => negate(call("sin", u.clone())),
+                "tan" => pow(call("sec", u.clone()), number(2_f64)),
+                "csc" => negate(mul(call("csc", u.clone()), call("cot", u.clone()))),
+                "sec" => mul(call("sec", u.clone()), call("tan", u.clone())),
+                "cot" => negate(pow(call("csc", u.clone()), number(2_f64))),
+                "ln" => div(number(1_f64), u.clone()),
+                "log" => div(number(1_f64), mul(u.clone(), call("ln", number(10_f64)))),
+                "lg" => div(number(1_f64), mul(u.clone(), call("ln", number(2_f64)))),
+                "sqrt" => div(number(1_f64), mul(number(2_f64), call("sqrt", u.clone()))),
+                "neg" => number(-1_f64),
+                "abs" => call("sgn", u.clone()),
+                other => return Err(CannotDifferentiate {
+                    function: String::from_str(other),
+                }),
+            };
+            Ok(mul(outer, du))
+        },
+        ExprCall | ExprText | ExprUnit => Err(CannotDifferentiate {
+            function: String::from_str(node.token()),
+        }),
+    }
+}
+
+fn call(name: &str, arg: ExprNode) -> ExprNode {
+    ExprNode::new(name, ExprCall, vec![arg])
+}
+
+/// A value together with its derivative with respect to one input.
+#[deriving(Show, Clone, PartialEq)]
+pub struct Dual {
+    pub value: f64,
+    pub derivative: f64,
+}
+
+/// Works `node` out against the variables in `env` along with its
+/// derivative with respect to `variable`, by forward-mode automatic
+/// differentiation: each step carries its derivative alongside its value,
+/// so no derivative tree is built. Supports what `derive` does plus the
+/// inverse and hyperbolic functions, `exp` and `cbrt`.
+pub fn forward(node: &ExprNode, env: &Environment, variable: &str) -> Result<Dual, CalcError> {
+    let children = node.children();
+    let refuse = || CannotDifferentiate { function: String::from_str(node.token()) };
+
+    match node.kind() {
+        ExprLiteral => match node.value() {
+            Some(n) => Ok(Dual { value: n, derivative: 0_f64 }),
+            None => Err(refuse()),
+        },
+        ExprVariable => match env.get(node.token()) {
+            Some(value) => Ok(Dual {
+                value: try!(value.number()),
+                derivative: if node.token() == variable { 1_f64 } else { 0_f64 },
+            }),
+            None => Err(UnknownVariable(String::from_str(node.token()))),
+        },
+        ExprUnary => {
+            let u = try!(forward(children.get(0), env, variable));
+            Ok(Dual { value: -u.value, derivative: -u.derivative })
+        },
+        ExprBinary => {
+            let u = try!(forward(children.get(0), env, variable));
+            let v = try!(forward(children.get(1), env, variable));
+            match binary(node.token(), u, v) {
+                Some(dual) => Ok(dual),
+                None => Err(refuse()),
+            }
+        },
+        ExprCall if children.len() == 1 && builtins::takes_numbers(node.token()) => {
+            let u = try!(forward(children.get(0), env, variable));
+            let value = try!(builtins::call(node.token(), &[u.value], env.angle));
+            // trigonometric functions work in the angle unit, so their
+            // slopes carry its size in radians
+            let unit = if env.angle == Degrees { PI / 180_f64 } else { 1_f64 };
+            let x = u.value;
+            let slope = match node.token() {
+                "sin" => unit * (x * unit).cos(),
+                "cos" => -unit * (x * unit).sin(),
+                "tan" => unit / ((x * unit).cos() * (x * unit).cos()),
+                "csc" => -unit * value / (x * unit).tan(),
+                "sec" => unit * value * (x * unit).tan(),
+                "cot" => -unit * value * value - unit,
+                "asin" => 1_f64 / unit / (1_f64 - x * x).sqrt(),
+                "acos" => -1_f64 / unit / (1_f64 - x * x).sqrt(),
+                "atan" => 1_f64 / unit / (1_f64 + x * x),
+                "sinh" => x.cosh(),
+                "cosh" => x.sinh(),
+                "tanh" => 1_f64 - value * value,
+                "ln" => 1_f64 / x,
+                "log" => 1_f64 / (x * 10_f64.ln()),
+                "lg" => 1_f64 / (x * 2_f64.ln()),
+                "exp" => value,
+                "sqrt" => 0.5_f64 / value,
+                "cbrt" => 1_f64 / (3_f64 * value * value),
+                "neg" => -1_f64,
+                "abs" => x.signum(),
+                _ => return Err(refuse()),
+            };
+            Ok(Dual { value: value, derivative: slope * u.derivative })
+        },
+        ExprCall | ExprText | ExprUnit => Err(refuse()),
+    }
+}
+
+/// Applies the arithmetic operator `operator` to two duals, as `forward`
+/// does at each operator of a tree; `None` for operators it has no
+/// derivative for.
+pub fn binary(operator: &str, u: Dual, v: Dual) -> Option<Dual> {
+    let (value, derivative) = match operator {
+        "+" => (u.value + v.value, u.derivative + v.derivative),
+        "-" => (u.value - v.value, u.derivative - v.derivative),
+        "*" => (u.value * v.value, u.derivative * v.value + u.value * v.derivative),
+        "/" => (u.value / v.value,
+                (u.derivative * v.value - u.value * v.derivative) / (v.value * v.value)),
+        "^" => {
+            let value = u.value.powf(v.value);
+            // n * u^(n - 1) * u' when the exponent is constant, which also
+            // keeps a negative base from reaching ln
+            let derivative = if v.derivative == 0_f64 {
+                v.value * u.value.powf(v.value - 1_f64) * u.derivative
+            } else {
+                value * (v.derivative * u.value.ln() + v.value * u.derivative / u.value)
+            };
+            (value, derivative)
+        },
+        _ => return None,
+    };
+    Some(Dual { value: value, derivative: derivative })
+}
+
+/// The accuracy `integrate` aims for over the whole interval.
+pub static INTEGRATION_TOLERANCE: f64 = 1e-10;
+
+// halvings of any one piece `integrate` makes before settling for its
+// estimate, which bounds the work for integrands that never settle
+static MAX_HALVINGS: uint = 20;
+
+/// The integral of `f` from `a` to `b` by adaptive Simpson's rule. Each
+/// piece is halved until Simpson's rule on the halves agrees with the rule
+/// on the whole to within the piece's share of the tolerance, so the work
+/// goes where `f` bends most.
+pub fn integrate(f: |f64| -> Result<f64, CalcError>, a: f64, b: f64) -> Result<f64, CalcError> {
+    let mut f = f;
+    let m = a + (b - a) / 2_f64;
+    let (fa, fm, fb) = (try!(f(a)), try!(f(m)), try!(f(b)));
+    let whole = simpson(a, b, fa, fm, fb);
+    adaptive_simpson(&mut f, (a, b), (fa, fm, fb), whole, INTEGRATION_TOLERANCE, MAX_HALVINGS)
+}
+
+fn simpson(a: f64, b: f64, fa: f64, fm: f64, fb: f64) -> f64 {
+    (b - a) / 6_f64 * (fa + 4_f64 * fm + fb)
+}
+
+// `whole` is Simpson's rule over `(a, b)`, from `f` at a, the midpoint
+// and b
+fn adaptive_simpson(f: &mut |f64| -> Result<f64, CalcError>, (a, b): (f64, f64),
+                    (fa, fm, fb): (f64, f64, f64), whole: f64, tolerance: f64,
+                    halvings: uint) -> Result<f64, CalcError> {
+    let m = a + (b - a) / 2_f64;
+    let (left_mid, right_mid) = (a + (m - a) / 2_f64, m + (b - m) / 2_f64);
+    let (flm, frm) = (try!((*f)(left_mid)), try!((*f)(right_mid)));
+    let left = simpson(a, m, fa, flm, fm);
+    let right = simpson(m, b, fm, frm, fb);
+
+    // Richardson extrapolation: the halves' error is about a fifteenth of
+    // the difference
+    let difference = left + right - whole;
+    if halvings == 0 || !difference.is_finite() || difference.abs() <= 15_f64 * tolerance {
+        return Ok(left + right + difference / 15_f64);
+    }
+
+    let left = try!(adaptive_simpson(f, (a, m), (fa, flm, fm), left, tolerance / 2_f64,
+                                     halvings - 1));
+    let right = try!(adaptive_simpson(f, (m, b), (fm, frm, fb), right, tolerance / 2_f64,
+                                      halvings - 1));
+    Ok(left + right)
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+    use super::derive;
+
+    // the derivative of `expression` with respect to `variable`, printed
+    fn derivative(expression: &str, variable: &str) -> String {
+        let tree = ExprTree::build(expression).unwrap();
+        match derive(tree.root().unwrap(), variable) {
+            Ok(node) => format!("{}", node),
+            Err(error) => format!("error: {}", error),
+        }
+    }
+
+    #[test]
+    fn rules_apply_from_the_leaves_up() {
+        assert_eq!(derivative("x^2 + sin(x)", "x"), String::from_str("2 * x + cos(x)"));
+        assert_eq!(derivative("x^3", "x"), String::from_str("3 * x ^ 2"));
+        assert_eq!(derivative("ln(x)", "x"), String::from_str("1 / x"));
+        // other names are held constant
+        assert_eq!(derivative("x*y", "y"), String::from_str("x"));
+        assert_eq!(derivative("y^2", "x"), String::from_str("0"));
+    }
+
+    #[test]
+    fn unknown_functions_are_reported() {
+        assert_eq!(derivative("floor(x)", "x"),
+                   String::from_str("error: cannot differentiate 'floor'"));
+    }
+}
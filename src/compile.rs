@@ -0,0 +1,643 @@
+use std::cmp::max;
+use std::string::String;
+use std::uint;
+
+use builtins;
+use builtins::{AngleMode, Radians};
+use env::Environment;
+use error::{CalcError, NotCompilable, UnknownVariable};
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+use value::{Value, Real, List};
+
+/// One step of a compiled `Program`, working on a stack of doubles.
+#[deriving(Show, Clone, PartialEq)]
+pub enum Instruction {
+    PushNumber(f64),
+    /// Pushes the value in a variable slot; see `Program::var_slots`.
+    LoadSlot(uint),
+    NegateTop,
+    /// Pops two operands and pushes the result of the operator.
+    ApplyOperator(String),
+    /// Pops this many arguments and pushes the built-in's result.
+    CallBuiltin(String, uint),
+}
+
+/// An expression flattened to postfix order, for evaluating one tree many
+/// times with changing variables. Everything is worked in doubles, and the
+/// stack is kept between runs so evaluating allocates nothing. Variables
+/// are numbered when the program is compiled, and each run reads them from
+/// an array of slots rather than looking them up by name.
+pub struct Program {
+    code: Vec<Instruction>,
+    stack: Vec<f64>,
+    /// The variable each slot holds, in the order first read.
+    names: Vec<String>,
+    /// The value in each slot.
+    slots: Vec<f64>,
+    /// Index of the first instruction of the subexpression each
+    /// instruction finishes.
+    starts: Vec<uint>,
+    /// What each instruction left on top of the stack in the last run;
+    /// empty until the program has run once.
+    values: Vec<f64>,
+}
+
+impl Program {
+    /// Flattens the tree under `root`. Only arithmetic on numbers and
+    /// variables, and calls to the numeric built-ins, can be compiled;
+    /// assignments, pipes, text, user functions and the built-ins the
+    /// evaluator applies itself give `NotCompilable`.
+    pub fn compile(root: &ExprNode) -> Result<Program, CalcError> {
+        let mut code = vec![];
+        let mut names = vec![];
+        let depth = try!(emit(root, &mut code, &mut names));
+        let starts = subexpression_starts(code.as_slice());
+        let slots = Vec::from_elem(names.len(), 0_f64);
+        Ok(Program {
+            code: code,
+            stack: Vec::with_capacity(depth),
+            names: names,
+            slots: slots,
+            starts: starts,
+            values: vec![],
+        })
+    }
+
+    pub fn instructions<'a>(&'a self) -> &'a [Instruction] {
+        self.code.as_slice()
+    }
+
+    /// The variable read from each slot, indexed by slot number.
+    pub fn var_slots<'a>(&'a self) -> &'a [String] {
+        self.names.as_slice()
+    }
+
+    /// The slot `name` is read from, if the program reads it at all.
+    pub fn slot(&self, name: &str) -> Option<uint> {
+        self.names.iter().position(|slot| slot.as_slice() == name)
+    }
+
+    /// Puts `value` in `slot` for the runs that follow.
+    pub fn set_slot(&mut self, slot: uint, value: f64) {
+        *self.slots.get_mut(slot) = value;
+    }
+
+    /// A copy of the program with the variable `name` fixed at `value`, for
+    /// parameters that never change, such as `g` in a physics formula. Its
+    /// loads become the number itself, and every step that is then left
+    /// working only on numbers is done once, here, rather than on every
+    /// run. The copy has no slot for `name`; the other slots keep their
+    /// values but may be renumbered. Steps that fail, such as `sqrt` of a
+    /// negative number, are left in to fail when the copy runs.
+    pub fn bind_const(&self, name: &str, value: f64) -> Program {
+        let bound = self.slot(name);
+        let renumber = |slot: uint| match bound {
+            Some(b) if slot > b => slot - 1,
+            _ => slot,
+        };
+
+        let mut code: Vec<Instruction> = vec![];
+        // the first instruction of each operand on the stack, and its
+        // value if it is a known number
+        let mut operands: Vec<(uint, Option<f64>)> = vec![];
+        for instruction in self.code.iter() {
+            let count = operand_count(instruction);
+            let base = operands.len() - count;
+            let start = if count == 0 { code.len() } else { operands.get(base).val0() };
+            let known: Vec<f64> = operands.slice_from(base).iter()
+                .filter_map(|&(_, n)| n)
+                .collect();
+            operands.truncate(base);
+
+            let folded = match *instruction {
+                PushNumber(n) => Some(n),
+                LoadSlot(slot) if Some(slot) == bound => Some(value),
+                LoadSlot(_) => None,
+                // the angle unit is only known when the program runs
+                CallBuiltin(ref name, _) if builtins::uses_angle(name.as_slice()) => None,
+                _ if known.len() == count => {
+                    let mut stack = known;
+                    match execute(instruction, &mut stack, [], Radians) {
+                        Ok(()) => stack.pop(),
+                        Err(_) => None,
+                    }
+                },
+                _ => None,
+            };
+            match folded {
+                Some(n) => {
+                    code.truncate(start);
+                    code.push(PushNumber(n));
+                },
+                None => code.push(match *instruction {
+                    LoadSlot(slot) => LoadSlot(renumber(slot)),
+                    ref other => other.clone(),
+                }),
+            }
+            operands.push((start, folded));
+        }
+
+        let names = self.names.iter().enumerate()
+            .filter(|&(slot, _)| Some(slot) != bound)
+            .map(|(_, name)| name.clone())
+            .collect();
+        let slots = self.slots.iter().enumerate()
+            .filter(|&(slot, _)| Some(slot) != bound)
+            .map(|(_, &value)| value)
+            .collect();
+        let starts = subexpression_starts(code.as_slice());
+        Program {
+            code: code,
+            stack: Vec::with_capacity(self.stack.capacity()),
+            names: names,
+            slots: slots,
+            starts: starts,
+            values: vec![],
+        }
+    }
+
+    /// Fills every slot from the variables in `env`, each looked up once,
+    /// or from its registered constants as a tree would.
+    pub fn load(&mut self, env: &Environment) -> Result<(), CalcError> {
+        for (name, slot) in self.names.iter().zip(self.slots.mut_iter()) {
+            *slot = match env.vars().find(name) {
+                Some(value) => try!(value.number()),
+                None => match env.registry().constant(name.as_slice()) {
+                    Some(constant) => constant,
+                    None => return Err(UnknownVariable(name.clone())),
+                },
+            };
+        }
+        Ok(())
+    }
+
+    /// A rough measure of the work one run takes, in units of about one
+    /// addition, so callers can turn away or queue expensive formulas
+    /// before running them. The same program always gives the same cost.
+    pub fn estimated_cost(&self) -> uint {
+        self.code.iter().fold(0, |total, instruction| total + match *instruction {
+            PushNumber(_) | LoadSlot(_) | NegateTop => 1,
+            ApplyOperator(ref operator) => operator_cost(operator.as_slice()),
+            CallBuiltin(ref name, count) => builtins::cost(name.as_slice()) + count,
+        })
+    }
+
+    /// Runs the program against the variables in `env`. Built-ins are
+    /// called directly, even where a user function has taken the name.
+    pub fn eval_compiled(&mut self, env: &Environment) -> Result<f64, CalcError> {
+        try!(self.load(env));
+        self.eval_loaded(env.angle)
+    }
+
+    /// Runs the program with the slots as they are, as last filled by
+    /// `load` or `set_slot`. A loop that changes one input can set its slot
+    /// and run again without going through the environment at all.
+    pub fn eval_loaded(&mut self, angle: AngleMode) -> Result<f64, CalcError> {
+        self.stack.clear();
+        self.values.clear();
+
+        for instruction in self.code.iter() {
+            match execute(instruction, &mut self.stack, self.slots.as_slice(), angle) {
+                Ok(()) => self.values.push(*self.stack.last().unwrap()),
+                Err(e) => {
+                    self.values.clear();
+                    return Err(e);
+                },
+            }
+        }
+
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Runs the program with every intermediate result passed through
+    /// `round`, to see how the answer moves when each step rounds
+    /// differently. The run is not kept for `eval_incremental`.
+    pub fn eval_rounded(&mut self, env: &Environment,
+                        round: |f64| -> f64) -> Result<f64, CalcError> {
+        try!(self.load(env));
+        self.stack.clear();
+        self.values.clear();
+
+        for instruction in self.code.iter() {
+            try!(execute(instruction, &mut self.stack, self.slots.as_slice(), env.angle));
+            let top = self.stack.pop().unwrap();
+            self.stack.push(round(top));
+        }
+
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Runs the program again after only `changed` has changed in `env`,
+    /// redoing just the instructions whose result depends on it and
+    /// reusing the last run's results for the rest. Suited to a slider
+    /// bound to one input of a large formula; the first run, and any run
+    /// after an error, is a full one.
+    pub fn eval_incremental(&mut self, changed: &str,
+                            env: &Environment) -> Result<f64, CalcError> {
+        if self.values.len() != self.code.len() {
+            return self.eval_compiled(env);
+        }
+
+        try!(self.load(env));
+        let dirty = self.dirty(self.slot(changed));
+        let root = self.code.len() - 1;
+        self.stack.clear();
+        match self.recompute(root, dirty.as_slice(), env.angle) {
+            Ok(()) => Ok(self.stack.pop().unwrap()),
+            Err(e) => {
+                self.values.clear();
+                Err(e)
+            },
+        }
+    }
+
+    // which instructions read slot `changed`, directly or through an
+    // operand; none do when the program doesn't read it
+    fn dirty(&self, changed: Option<uint>) -> Vec<bool> {
+        let mut dirty: Vec<bool> = Vec::with_capacity(self.code.len());
+        let mut operands: Vec<bool> = vec![];
+        for instruction in self.code.iter() {
+            let count = operand_count(instruction);
+            let base = operands.len() - count;
+            let reads = match *instruction {
+                LoadSlot(slot) => Some(slot) == changed,
+                _ => operands.slice_from(base).iter().any(|&d| d),
+            };
+            operands.truncate(base);
+            operands.push(reads);
+            dirty.push(reads);
+        }
+        dirty
+    }
+
+    // leave the result of the subexpression finished by instruction `end`
+    // on the stack, working it out again only where it is dirty
+    fn recompute(&mut self, end: uint, dirty: &[bool], angle: AngleMode)
+                 -> Result<(), CalcError> {
+        if !dirty[end] {
+            self.stack.push(*self.values.get(end));
+            return Ok(());
+        }
+
+        // each operand finishes just before the next one starts
+        let mut ends: Vec<uint> = vec![];
+        let mut next = end;
+        for _ in range(0, operand_count(self.code.get(end))) {
+            ends.push(next - 1);
+            next = *self.starts.get(next - 1);
+        }
+        for &operand in ends.iter().rev() {
+            try!(self.recompute(operand, dirty, angle));
+        }
+
+        try!(execute(self.code.get(end), &mut self.stack, self.slots.as_slice(), angle));
+        *self.values.get_mut(end) = *self.stack.last().unwrap();
+        Ok(())
+    }
+}
+
+// carry out one instruction on `stack`
+fn execute(instruction: &Instruction, stack: &mut Vec<f64>, slots: &[f64],
+           angle: AngleMode) -> Result<(), CalcError> {
+    match *instruction {
+        PushNumber(n) => stack.push(n),
+        LoadSlot(slot) => stack.push(slots[slot]),
+        NegateTop => {
+            let top = stack.pop().unwrap();
+            stack.push(-top);
+        },
+        ApplyOperator(ref operator) => {
+            let rhs = stack.pop().unwrap();
+            let lhs = stack.pop().unwrap();
+            let result = try!(Value::binary(operator.as_slice(), &Real(lhs), &Real(rhs)));
+            stack.push(try!(result.number()));
+        },
+        CallBuiltin(ref name, count) => {
+            let base = stack.len() - count;
+            let result = try!(builtins::call(name.as_slice(), stack.slice_from(base), angle));
+            stack.truncate(base);
+            stack.push(result);
+        },
+    }
+    Ok(())
+}
+
+fn operand_count(instruction: &Instruction) -> uint {
+    match *instruction {
+        PushNumber(_) | LoadSlot(_) => 0,
+        NegateTop => 1,
+        ApplyOperator(_) => 2,
+        CallBuiltin(_, count) => count,
+    }
+}
+
+// the first instruction of each instruction's subexpression, found by
+// replaying the stack with start indices in place of values
+fn subexpression_starts(code: &[Instruction]) -> Vec<uint> {
+    let mut starts: Vec<uint> = Vec::with_capacity(code.len());
+    let mut operands: Vec<uint> = vec![];
+    for (i, instruction) in code.iter().enumerate() {
+        let count = operand_count(instruction);
+        let base = operands.len() - count;
+        let start = if count == 0 { i } else { *operands.get(base) };
+        operands.truncate(base);
+        operands.push(start);
+        starts.push(start);
+    }
+    starts
+}
+
+// the points Simpson's rule is taken at when it is halved five times over,
+// which a smooth integrand seldom needs more than
+static INTEGRAL_POINTS: uint = 65;
+
+/// What evaluating the tree under `root` takes, in the units of
+/// `Program::estimated_cost`, for trees that cannot be compiled as well as
+/// those that can. The body of `sum`, `product` or `integrate` counts once
+/// for each time it is worked out, and the matrix built-ins grow with the
+/// size of their matrices, written out or read from `env`. A bound that
+/// is neither is taken to run to `env.max_iterations`, and a matrix of
+/// unknown size costs what other built-ins do.
+pub fn tree_cost(root: &ExprNode, env: &Environment) -> uint {
+    let children = root.children();
+    let inner = || children.iter().fold(0, |total, child| add(total, tree_cost(child, env)));
+
+    match root.kind() {
+        ExprLiteral | ExprVariable | ExprText | ExprUnit => 1,
+        ExprUnary => add(inner(), 1),
+        // a definition costs nothing until its function is called
+        ExprBinary if root.token() == "=" && children.get(0).kind() == ExprCall => 1,
+        ExprBinary => add(inner(), operator_cost(root.token())),
+        ExprCall if root.is_binding() => {
+            let body = add(tree_cost(children.get(0), env), 1);
+            let steps = if root.token() == "integrate" {
+                INTEGRAL_POINTS
+            } else {
+                match (bound(children.get(2), env), bound(children.get(3), env)) {
+                    (Some(from), Some(to)) if to < from => 0,
+                    (Some(from), Some(to)) if to - from < env.max_iterations as f64 =>
+                        (to - from) as uint + 1,
+                    _ => env.max_iterations,
+                }
+            };
+            let bounds = add(tree_cost(children.get(2), env), tree_cost(children.get(3), env));
+            add(bounds, multiply(steps, body))
+        },
+        ExprCall => {
+            let call = match (root.token(), children.iter().next().and_then(|m| size(m, env))) {
+                ("det", Some((n, _))) => multiply(n, multiply(n, n)),
+                // eliminating on the matrix and the identity beside it
+                ("inv", Some((n, _))) => multiply(2, multiply(n, multiply(n, n))),
+                ("transpose", Some((rows, columns))) => multiply(rows, columns),
+                ("dot", Some((rows, columns))) => multiply(2, multiply(rows, columns)),
+                (name, _) => builtins::cost(name),
+            };
+            add(inner(), add(call, children.len()))
+        },
+    }
+}
+
+fn add(a: uint, b: uint) -> uint {
+    a.checked_add(&b).unwrap_or(uint::MAX)
+}
+
+fn multiply(a: uint, b: uint) -> uint {
+    a.checked_mul(&b).unwrap_or(uint::MAX)
+}
+
+// a bound of `sum` or `product` known without evaluating anything
+fn bound(node: &ExprNode, env: &Environment) -> Option<f64> {
+    match node.kind() {
+        ExprLiteral => node.value(),
+        ExprUnary => bound(node.children().get(0), env).map(|n| -n),
+        ExprVariable => env.get(node.token()).and_then(|value| value.number().ok()),
+        _ => None,
+    }
+}
+
+// the rows and columns of a matrix written out or held in a variable, and
+// of what `inv` or `transpose` make of one; a plain list is one row
+fn size(node: &ExprNode, env: &Environment) -> Option<(uint, uint)> {
+    match node.kind() {
+        ExprCall if node.token() == "list" => {
+            let children = node.children();
+            Some(match children.iter().next() {
+                Some(row) if row.kind() == ExprCall && row.token() == "list" =>
+                    (children.len(), row.children().len()),
+                _ => (1, children.len()),
+            })
+        },
+        ExprCall if node.token() == "inv" => node.children().iter().next()
+            .and_then(|m| size(m, env)),
+        ExprCall if node.token() == "transpose" => node.children().iter().next()
+            .and_then(|m| size(m, env)).map(|(rows, columns)| (columns, rows)),
+        ExprVariable => match env.get(node.token()) {
+            Some(List(ref items)) => Some(match items.iter().next() {
+                Some(&List(ref row)) => (items.len(), row.len()),
+                _ => (1, items.len()),
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn operator_cost(operator: &str) -> uint {
+    match operator {
+        "/"|"//"|"%" => 4,
+        "^" => 20,
+        _ => 1,
+    }
+}
+
+// append the code for `node` and return the stack depth it needs; each
+// variable is given a slot in `names` the first time it is read
+fn emit(node: &ExprNode, code: &mut Vec<Instruction>,
+        names: &mut Vec<String>) -> Result<uint, CalcError> {
+    let children = node.children();
+    let refuse = || NotCompilable { construct: String::from_str(node.token()) };
+
+    match node.kind() {
+        ExprLiteral => match node.value() {
+            Some(n) => code.push(PushNumber(n)),
+            None => return Err(refuse()),
+        },
+        ExprVariable if node.token() == "_" => return Err(refuse()),
+        ExprVariable => {
+            let slot = match names.iter().position(|name| name.as_slice() == node.token()) {
+                Some(slot) => slot,
+                None => {
+                    names.push(String::from_str(node.token()));
+                    names.len() - 1
+                },
+            };
+            code.push(LoadSlot(slot));
+        },
+        ExprUnary => {
+            let depth = try!(emit(children.get(0), code, names));
+            code.push(NegateTop);
+            return Ok(depth);
+        },
+        ExprBinary if node.token() == "=" || node.token() == "|>" => return Err(refuse()),
+        // unit conversions need quantities, which the stack does not hold
+        ExprBinary if node.token() == "to" || node.token() == "in" => return Err(refuse()),
+        ExprBinary => {
+            let lhs = try!(emit(children.get(0), code, names));
+            let rhs = try!(emit(children.get(1), code, names));
+            code.push(ApplyOperator(String::from_str(node.token())));
+            return Ok(max(lhs, rhs + 1));
+        },
+        ExprCall if builtins::takes_numbers(node.token()) && !node.is_binding() => {
+            try!(builtins::check_arity(node.token(), children.len()));
+
+            // each argument sits on the stack while the next is worked out
+            let mut depth = 0;
+            for (i, child) in children.iter().enumerate() {
+                depth = max(depth, i + try!(emit(child, code, names)));
+            }
+            code.push(CallBuiltin(String::from_str(node.token()), children.len()));
+            return Ok(depth);
+        },
+        ExprCall | ExprText | ExprUnit => return Err(refuse()),
+    }
+    Ok(1)
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use builtins::Radians;
+    use env::Environment;
+    use error::{NotCompilable, UnknownVariable};
+    use exprtree::ExprTree;
+    use value::{Real, List};
+    use super::{Program, PushNumber, LoadSlot, ApplyOperator, CallBuiltin};
+
+    fn compile(expression: &str) -> Program {
+        ExprTree::build(expression).unwrap().compile().unwrap()
+    }
+
+    fn tree_cost(expression: &str, env: &Environment) -> uint {
+        ExprTree::build(expression).unwrap().estimated_cost_in(env)
+    }
+
+    #[test]
+    fn programs_agree_with_the_tree() {
+        let expression = "x^2 - 3*x + sin(x) / 2";
+        let tree = ExprTree::build(expression).unwrap();
+        let mut program = compile(expression);
+        let mut env = Environment::new();
+        for step in range(-20i, 21) {
+            env.set("x", Real(step as f64 / 4_f64));
+            let expected = tree.eval_in(&mut env).and_then(|v| v.number());
+            assert_eq!(program.eval_compiled(&env), expected);
+        }
+    }
+
+    #[test]
+    fn only_arithmetic_compiles() {
+        for expression in ["x = 2", "f(x)", "\"text\"", "4 |> sqrt"].iter() {
+            match ExprTree::build(*expression).unwrap().compile() {
+                Err(NotCompilable { .. }) => {},
+                other => fail!("{} compiled: {}", expression, other.is_ok()),
+            }
+        }
+    }
+
+    #[test]
+    fn costs_follow_the_instructions() {
+        assert_eq!(compile("x + 1").estimated_cost(), 3);
+        // a load, a sine of one argument, a push and a power
+        assert_eq!(compile("sin(x) ^ 2").estimated_cost(), 1 + 26 + 1 + 20);
+        assert!(compile("x / y").estimated_cost() > compile("x * y").estimated_cost());
+    }
+
+    #[test]
+    fn tree_costs_grow_with_ranges_and_sizes() {
+        let mut env = Environment::new();
+        assert_eq!(tree_cost("sin(x) ^ 2", &env), compile("sin(x) ^ 2").estimated_cost());
+        // the bounds, then a load, a push, a power and the running total
+        // for each of a thousand terms
+        assert_eq!(tree_cost("sum(k^2, k, 1, 1000)", &env), 2 + 1000 * 23);
+        assert_eq!(tree_cost("sum(k, k, 1, n)", &env), 2 + env.max_iterations * 2);
+        env.set("n", Real(10_f64));
+        assert_eq!(tree_cost("sum(k, k, 1, n)", &env), 2 + 10 * 2);
+        assert_eq!(tree_cost("sum(k, k, 5, 1)", &env), 2);
+
+        let row = List(Vec::from_elem(20, Real(1_f64)));
+        env.set("m", List(Vec::from_elem(20, row)));
+        assert_eq!(tree_cost("det(m)", &env), 1 + 20 * 20 * 20 + 1);
+        assert_eq!(tree_cost("det(transpose(m))", &env), 1 + 400 + 1 + 8000 + 1);
+        assert!(tree_cost("det([[1, 2], [3, 4]])", &env) < tree_cost("det(m)", &env));
+    }
+
+    #[test]
+    fn incremental_runs_match_full_ones() {
+        let expression = "a * sin(b) + b^2 / (a + 1)";
+        let mut program = compile(expression);
+        let mut env = Environment::new();
+        env.set("a", Real(2_f64));
+        env.set("b", Real(0.5));
+        program.eval_incremental("a", &env).unwrap();
+
+        for &(name, value) in [("a", 3_f64), ("b", -1.5), ("a", -1_f64), ("c", 7_f64)].iter() {
+            env.set(name, Real(value));
+            let full = compile(expression).eval_compiled(&env);
+            assert_eq!(program.eval_incremental(name, &env), full);
+        }
+    }
+
+    #[test]
+    fn variables_get_a_slot_each() {
+        let mut program = compile("x * y + x");
+        assert_eq!(program.var_slots(), &[String::from_str("x"), String::from_str("y")]);
+        assert_eq!(program.slot("y"), Some(1));
+        assert_eq!(program.slot("z"), None);
+        assert_eq!(program.instructions(), &[
+            LoadSlot(0),
+            LoadSlot(1),
+            ApplyOperator(String::from_str("*")),
+            LoadSlot(0),
+            ApplyOperator(String::from_str("+")),
+        ]);
+
+        let mut env = Environment::new();
+        env.set("x", Real(3_f64));
+        assert_eq!(program.load(&env), Err(UnknownVariable(String::from_str("y"))));
+        env.set("y", Real(2_f64));
+        assert_eq!(program.eval_compiled(&env), Ok(9_f64));
+        // a changed slot is seen without going back to the environment
+        program.set_slot(1, 10_f64);
+        assert_eq!(program.eval_loaded(Radians), Ok(33_f64));
+    }
+
+    #[test]
+    fn bound_constants_fold_away() {
+        let program = compile("x * (g / 2) + y").bind_const("g", 9.81);
+        assert_eq!(program.var_slots(), &[String::from_str("x"), String::from_str("y")]);
+        assert_eq!(program.instructions(), &[
+            LoadSlot(0),
+            PushNumber(9.81 / 2_f64),
+            ApplyOperator(String::from_str("*")),
+            LoadSlot(1),
+            ApplyOperator(String::from_str("+")),
+        ]);
+
+        let mut program = program;
+        program.set_slot(0, 2_f64);
+        program.set_slot(1, 1_f64);
+        assert_eq!(program.eval_loaded(Radians), Ok(10.81));
+    }
+
+    #[test]
+    fn angles_and_failures_wait_for_the_run() {
+        let program = compile("sin(a) + sqrt(b)").bind_const("a", 90_f64).bind_const("b", -1_f64);
+        assert!(program.var_slots().is_empty());
+        assert!(program.instructions().contains(&CallBuiltin(String::from_str("sin"), 1)));
+        assert!(program.instructions().contains(&CallBuiltin(String::from_str("sqrt"), 1)));
+        let mut program = program;
+        assert!(program.eval_loaded(Radians).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use std::cmp::max;
+use std::fmt;
+use std::num::{pow, Float, Zero, Signed, ToPrimitive};
+use std::string::String;
+
+use num::bigint::{BigInt, ToBigInt};
+use num::rational::{BigRational, Ratio};
+
+/// Decimal places kept when a quotient does not terminate.
+pub static DIVISION_PLACES: uint = 32;
+
+/// Exponents beyond this are left to f64 rather than building huge integers.
+pub static MAX_EXPONENT: i64 = 4096;
+
+// the named constants to more places than any quotient keeps, so cutting
+// them to the working precision leaves every digit correct
+static CONSTANTS: [(&'static str, &'static str), ..8] = [
+    ("pi", "3.141592653589793238462643383279502884197169399375"),
+    ("e", "2.718281828459045235360287471352662497757247093699"),
+    ("tau", "6.283185307179586476925286766559005768394338798750"),
+    ("phi", "1.618033988749894848204586834365638117720309179805"),
+    ("sqrt2", "1.414213562373095048801688724209698078569671875376"),
+    ("ln2", "0.693147180559945309417232121458176568075500134360"),
+    ("ln10", "2.302585092994045684017991454684364207601101488628"),
+    ("euler_gamma", "0.577215664901532860606512090082402431042159335939"),
+];
+
+/// An exact decimal number, `mantissa * 10^-scale`. Values are kept with
+/// trailing fractional zeros stripped, so equal numbers compare equal.
+#[deriving(Clone, PartialEq)]
+pub struct BigDecimal {
+    mantissa: BigInt,
+    scale: uint,
+}
+
+fn ten_pow(n: uint) -> BigInt {
+    pow(10i.to_bigint().unwrap(), n)
+}
+
+impl BigDecimal {
+    pub fn new(mantissa: BigInt, scale: uint) -> BigDecimal {
+        BigDecimal { mantissa: mantissa, scale: scale }.normalized()
+    }
+
+    /// Reads a decimal literal such as `12.5`, `-0.25`, `1_000` or
+    /// `6.022e23`.
+    pub fn parse(text: &str) -> Option<BigDecimal> {
+        let cleaned: String = text.chars().filter(|&c| c != '_').collect();
+        let (negative, cleaned) = if cleaned.as_slice().starts_with("-") {
+            (true, cleaned.as_slice().slice_from(1))
+        } else {
+            (false, cleaned.as_slice())
+        };
+
+        let (number, exponent) = match cleaned.find(|c: char| c == 'e' || c == 'E') {
+            Some(i) => {
+                let exponent = cleaned.slice_from(i + 1).trim_left_chars('+');
+                match from_str::<i64>(exponent) {
+                    Some(e) if e.abs() <= MAX_EXPONENT => (cleaned.slice_to(i), e),
+                    _ => return None,
+                }
+            },
+            None => (cleaned, 0),
+        };
+
+        let (whole, fraction) = match number.find('.') {
+            Some(i) => (number.slice_to(i), number.slice_from(i + 1)),
+            None => (number, ""),
+        };
+
+        let mut digits = String::from_str(whole);
+        digits.push_str(fraction);
+        if digits.is_empty() || !digits.as_slice().chars().all(|c| c.is_digit()) {
+            return None;
+        }
+
+        let mantissa: BigInt = match from_str(digits.as_slice()) {
+            Some(m) => m,
+            None => return None,
+        };
+        let mantissa = if negative { -mantissa } else { mantissa };
+        Some(BigDecimal::with_scale(mantissa, fraction.len() as i64 - exponent))
+    }
+
+    /// The named constant `name`, such as `pi` or `ln2`, truncated to
+    /// `DIVISION_PLACES` decimal places like any other irrational result.
+    pub fn constant(name: &str) -> Option<BigDecimal> {
+        CONSTANTS.iter()
+            .find(|&&(constant, _)| constant == name)
+            .and_then(|&(_, digits)| BigDecimal::parse(digits))
+            .map(|value| value.truncated(DIVISION_PLACES))
+    }
+
+    /// The exact decimal expansion of a double, or None for NaN and
+    /// infinities.
+    pub fn from_f64(value: f64) -> Option<BigDecimal> {
+        if value.is_nan() || value.is_infinite() {
+            return None;
+        }
+        BigDecimal::parse(format!("{}", value).as_slice())
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        from_str::<f64>(format!("{}", self).as_slice()).unwrap_or(Float::nan())
+    }
+
+    /// A fraction written out to `places` decimal places, truncated.
+    pub fn from_rational(ratio: &BigRational, places: uint) -> BigDecimal {
+        let numer = BigDecimal::new(ratio.numer().clone(), 0);
+        let denom = BigDecimal::new(ratio.denom().clone(), 0);
+        numer.div(&denom, places).unwrap()
+    }
+
+    /// The same number as an exact fraction.
+    pub fn to_rational(&self) -> BigRational {
+        Ratio::new(self.mantissa.clone(), ten_pow(self.scale))
+    }
+
+    /// The value as an integer, if it is one and fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.scale == 0 { self.mantissa.to_i64() } else { None }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    pub fn add(&self, other: &BigDecimal) -> BigDecimal {
+        let (a, b, scale) = self.align(other);
+        BigDecimal::new(a + b, scale)
+    }
+
+    pub fn sub(&self, other: &BigDecimal) -> BigDecimal {
+        let (a, b, scale) = self.align(other);
+        BigDecimal::new(a - b, scale)
+    }
+
+    pub fn mul(&self, other: &BigDecimal) -> BigDecimal {
+        BigDecimal::new(self.mantissa * other.mantissa, self.scale + other.scale)
+    }
+
+    pub fn neg(&self) -> BigDecimal {
+        BigDecimal::new(-self.mantissa, self.scale)
+    }
+
+    /// Divides, truncating a quotient that does not terminate after `places`
+    /// decimal places. None when `other` is zero.
+    pub fn div(&self, other: &BigDecimal, places: uint) -> Option<BigDecimal> {
+        if other.is_zero() {
+            return None;
+        }
+
+        // (a / 10^sa) / (b / 10^sb) = (a * 10^(places + sb) / b) / 10^(places + sa)
+        let numerator = self.mantissa * ten_pow(places + other.scale);
+        Some(BigDecimal::new(numerator / other.mantissa, places + self.scale))
+    }
+
+    /// Raises to an integer power; negative powers divide as `div` does.
+    /// None for a zero base with a negative power, or a power too large to
+    /// expand.
+    pub fn powi(&self, exponent: i64, places: uint) -> Option<BigDecimal> {
+        if exponent.abs() > MAX_EXPONENT {
+            return None;
+        }
+
+        let n = exponent.abs() as uint;
+        let magnitude = BigDecimal::new(pow(self.mantissa.clone(), n), self.scale * n);
+        if exponent >= 0 {
+            Some(magnitude)
+        } else {
+            BigDecimal::new(1i.to_bigint().unwrap(), 0).div(&magnitude, places)
+        }
+    }
+
+    /// Drops the digits after `places` decimal places.
+    pub fn truncated(&self, places: uint) -> BigDecimal {
+        if self.scale <= places {
+            return self.clone();
+        }
+        BigDecimal::new(self.mantissa / ten_pow(self.scale - places), places)
+    }
+
+    // both mantissas brought to the larger of the two scales
+    fn align(&self, other: &BigDecimal) -> (BigInt, BigInt, uint) {
+        let scale = max(self.scale, other.scale);
+        (self.mantissa * ten_pow(scale - self.scale),
+         other.mantissa * ten_pow(scale - other.scale),
+         scale)
+    }
+
+    // a negative scale is folded into the mantissa
+    fn with_scale(mantissa: BigInt, scale: i64) -> BigDecimal {
+        if scale >= 0 {
+            BigDecimal::new(mantissa, scale as uint)
+        } else {
+            BigDecimal::new(mantissa * ten_pow((-scale) as uint), 0)
+        }
+    }
+
+    fn normalized(self) -> BigDecimal {
+        let ten = 10i.to_bigint().unwrap();
+        let mut mantissa = self.mantissa;
+        let mut scale = self.scale;
+
+        while scale > 0 && (mantissa % ten).is_zero() {
+            mantissa = mantissa / ten;
+            scale -= 1;
+        }
+        if mantissa.is_zero() {
+            scale = 0;
+        }
+
+        BigDecimal { mantissa: mantissa, scale: scale }
+    }
+}
+
+impl fmt::Show for BigDecimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.mantissa.is_negative() { "-" } else { "" };
+        let digits = format!("{}", self.mantissa.abs());
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+
+        // pad with zeros so there is a digit before the point
+        let mut padded = String::new();
+        if digits.len() <= self.scale {
+            padded.push_str(String::from_char(self.scale + 1 - digits.len(), '0').as_slice());
+        }
+        padded.push_str(digits.as_slice());
+
+        let point = padded.len() - self.scale;
+        write!(f, "{}{}.{}", sign, padded.as_slice().slice_to(point),
+               padded.as_slice().slice_from(point))
+    }
+}
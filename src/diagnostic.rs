@@ -0,0 +1,183 @@
+use std::cmp::{max, min};
+use std::string::String;
+
+use error::CalcError;
+use messages::Catalog;
+
+/// Renders `error`, met on line `index` (counting from zero) of the script
+/// at `path`, the way compilers do: the message from `catalog` with its
+/// error code, the location, and the line with a caret under the span,
+/// between the lines above and below it.
+///
+/// ```text
+/// error[E0012]: malformed number '1.2.3' at position 4
+///  --> rates.calc:3:5
+///   |
+/// 2 | x = 1
+/// 3 | y = 1.2.3 + x
+///   |     ^^^^^
+/// 4 | y * 2
+/// ```
+pub fn render(error: &CalcError, catalog: &Catalog, path: &str, lines: &[String],
+              index: uint) -> String {
+    let line = lines[index].as_slice();
+    // positions count from the first character evaluated, and leading tabs
+    // are copied so the caret lines up under them
+    let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+    let evaluated = line.slice_from(indent.len());
+    let column = error.position().map(|position| {
+        let before = evaluated.char_indices().take_while(|&(i, _)| i < position).count();
+        indent.as_slice().char_len() + before
+    });
+
+    let first = if index > 0 { index - 1 } else { 0 };
+    let last = min(index + 1, lines.len() - 1);
+    let width = format!("{}", last + 1).len();
+    let blank = String::from_char(width, ' ');
+
+    let mut text = format!("error[{}]: {}\n", error.code(), catalog.message(error));
+    text.push_str(format!("{}--> {}:{}:{}\n", blank, path, index + 1,
+                          column.map_or(1, |c| c + 1)).as_slice());
+    text.push_str(format!("{} |\n", blank).as_slice());
+    for i in range(first, last + 1) {
+        text.push_str(format!("{:>2$} | {}\n", i + 1, lines[i], width).as_slice());
+        match error.position() {
+            Some(_) if i == index => {
+                text.push_str(format!("{} | {}{}\n", blank, indent,
+                                      caret(error, evaluated)).as_slice());
+            },
+            _ => {},
+        }
+    }
+    text
+}
+
+/// Renders `error` against the one-line `input` it came from, with a caret
+/// under the span when the error has a position:
+///
+/// ```text
+/// error[E0032]: expected a number, name or '(' but found '*' at position 4
+///   2 + * 3
+///       ^
+/// ```
+pub fn render_inline(error: &CalcError, catalog: &Catalog, input: &str) -> String {
+    let mut text = format!("error[{}]: {}\n", error.code(), catalog.message(error));
+    if error.position().is_some() {
+        text.push_str(format!("  {}\n  {}\n", input, caret(error, input)).as_slice());
+    }
+    text
+}
+
+/// How many terminal columns the characters of `text` starting from byte
+/// `from` up to byte `to` take up: two for wide ones such as emoji, none
+/// for combining marks and one for the rest.
+pub fn columns(text: &str, from: uint, to: uint) -> uint {
+    text.char_indices()
+        .filter(|&(i, _)| i >= from && i < to)
+        .fold(0, |total, (_, c)| total + width(c))
+}
+
+// the columns `c` takes up in a terminal
+fn width(c: char) -> uint {
+    match c as u32 {
+        0x0300..0x036F | 0x1AB0..0x1AFF | 0x1DC0..0x1DFF | 0x20D0..0x20FF
+            | 0xFE20..0xFE2F => 0,
+        0x1100..0x115F | 0x2E80..0x303E | 0x3041..0x33FF | 0x3400..0x4DBF
+            | 0x4E00..0x9FFF | 0xA000..0xA4CF | 0xAC00..0xD7A3 | 0xF900..0xFAFF
+            | 0xFE30..0xFE4F | 0xFF00..0xFF60 | 0xFFE0..0xFFE6
+            | 0x1F300..0x1F64F | 0x1F900..0x1F9FF | 0x20000..0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+// the spaces and carets that put `^^^` under the span of `error` in
+// `input`, which `error.position()` must be set for
+fn caret(error: &CalcError, input: &str) -> String {
+    let position = error.position().unwrap();
+    let indent = columns(input, 0, position);
+    let width = max(1, columns(input, position, position + error.span_len()));
+    format!("{}{}", String::from_char(indent, ' '), String::from_char(width, '^'))
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+    use messages::Catalog;
+    use super::{columns, render, render_inline};
+
+    // the line of carets `render_inline` draws under `input`, which must
+    // fail to parse
+    fn caret_line(input: &str) -> String {
+        let error = ExprTree::build(input).err().expect("the input should not parse");
+        let text = render_inline(&error, &Catalog::english(), input);
+        String::from_str(text.as_slice().lines().last().unwrap())
+    }
+
+    #[test]
+    fn columns_count_display_width() {
+        assert_eq!(columns("π * 3", 0, 4), 3);
+        assert_eq!(columns("µs", 0, 3), 2);
+        // `中` is three bytes and two columns wide
+        assert_eq!(columns("中1", 0, 4), 3);
+        assert_eq!(columns("中1", 3, 4), 1);
+    }
+
+    #[test]
+    fn carets_sit_under_the_error() {
+        assert_eq!(caret_line("π * 1.2.3"), String::from_str("      ^^^^^"));
+        assert_eq!(caret_line("2 + µ"), String::from_str("      ^"));
+
+        // the error is at byte 9, after the four bytes of the emoji
+        let input = "\"😀\" + 1.2.3";
+        assert_eq!(ExprTree::build(input).err().unwrap().position(), Some(9));
+        assert_eq!(columns(input, 0, 9), 7);
+        assert_eq!(caret_line(input), String::from_str("         ^^^^^"));
+    }
+
+    #[test]
+    fn locations_count_characters() {
+        let lines = vec![String::from_str("x = π * 1.2.3")];
+        let error = ExprTree::build(lines.get(0).as_slice()).err().unwrap();
+        let text = render(&error, &Catalog::english(), "rates.calc", lines.as_slice(), 0);
+        assert!(text.as_slice().contains("--> rates.calc:1:9\n"));
+        assert!(text.as_slice().ends_with(" |         ^^^^^\n"));
+    }
+
+    #[test]
+    fn scripts_show_the_lines_around_the_error() {
+        let lines = vec![String::from_str("x = 1"), String::from_str("y = 1.2.3 + x"),
+                         String::from_str("y * 2")];
+        let error = ExprTree::build(lines.get(1).as_slice()).err().unwrap();
+        let text = render(&error, &Catalog::english(), "rates.calc", lines.as_slice(), 1);
+        let shown: Vec<&str> = text.as_slice().lines().skip(1).collect();
+        assert!(text.as_slice().starts_with("error[E0012]: "));
+        assert_eq!(shown, vec![" --> rates.calc:2:5",
+                               "  |",
+                               "1 | x = 1",
+                               "2 | y = 1.2.3 + x",
+                               "  |     ^^^^^",
+                               "3 | y * 2"]);
+
+        // a leading tab is copied so the caret stays under the span
+        let lines = vec![String::from_str("\tz = 1.2.3")];
+        let error = ExprTree::build(lines.get(0).as_slice().trim()).err().unwrap();
+        let text = render(&error, &Catalog::english(), "tab.calc", lines.as_slice(), 0);
+        assert!(text.as_slice().contains("--> tab.calc:1:6\n"));
+        assert!(text.as_slice().ends_with("  | \t    ^^^^^\n"));
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_token() {
+        let error = ExprTree::build("2 + * 3").err().unwrap();
+        assert_eq!(error.code(), "E0032");
+        let text = render_inline(&error, &Catalog::english(), "2 + * 3");
+        let lines: Vec<&str> = text.as_slice().lines().collect();
+        assert!(lines.get(0).starts_with("error[E0032]: "));
+        assert_eq!(lines.slice_from(1), vec!["  2 + * 3", "      ^"].as_slice());
+
+        // a character the lexer cannot read
+        assert_eq!(caret_line("1 + $"), String::from_str("      ^"));
+    }
+}
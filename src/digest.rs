@@ -0,0 +1,163 @@
+use std::hash::Hash;
+use std::hash::sip::SipState;
+use std::mem;
+
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+
+// fixed so a formula hashes the same in every process and release; two key
+// pairs give the two halves of the 128-bit form
+static KEYS: [(u64, u64), ..2] = [
+    (0x0706050403020100, 0x0f0e0d0c0b0a0908),
+    (0x736f6d6570736575, 0x646f72616e646f6d),
+];
+
+/// A 64-bit SipHash of the tree's structure, for using a formula as a
+/// database key. Spacing, redundant parentheses and how a number is
+/// written do not count, and neither does the order of operands to
+/// commutative operators or functions, so `b * a + 1` and `1 + a*b` hash
+/// alike.
+pub fn structural_hash(node: &ExprNode) -> u64 {
+    let (k0, k1) = KEYS[0];
+    hash_node(node, k0, k1)
+}
+
+/// Two independent 64-bit hashes of the same canonical form, for when the
+/// chance of two formulas colliding must be negligible.
+pub fn structural_hash128(node: &ExprNode) -> (u64, u64) {
+    let (k0, k1) = KEYS[1];
+    (structural_hash(node), hash_node(node, k0, k1))
+}
+
+fn hash_node(node: &ExprNode, k0: u64, k1: u64) -> u64 {
+    let mut state = SipState::new_with_keys(k0, k1);
+    let hash_children = |children: &[&ExprNode]| -> Vec<u64> {
+        children.iter().map(|child| hash_node(*child, k0, k1)).collect()
+    };
+
+    match node.kind() {
+        ExprLiteral => {
+            "literal".hash(&mut state);
+            match node.value() {
+                Some(n) => {
+                    let bits = unsafe { mem::transmute::<f64, u64>(canonical(n)) };
+                    bits.hash(&mut state);
+                },
+                None => node.token().hash(&mut state),
+            }
+        },
+        ExprVariable => {
+            "variable".hash(&mut state);
+            node.token().hash(&mut state);
+        },
+        ExprText => {
+            "text".hash(&mut state);
+            node.token().hash(&mut state);
+        },
+        ExprUnit => {
+            "unit".hash(&mut state);
+            node.token().hash(&mut state);
+            hash_children(vec![node.children().get(0)].as_slice()).hash(&mut state);
+        },
+        ExprUnary => {
+            "negate".hash(&mut state);
+            hash_children(vec![node.children().get(0)].as_slice()).hash(&mut state);
+        },
+        ExprBinary => {
+            "operator".hash(&mut state);
+            node.token().hash(&mut state);
+            let operands: Vec<&ExprNode> = if is_associative(node.token()) {
+                // `a + (b + c)` and `(a + b) + c` are the same sum
+                let mut operands = vec![];
+                flatten(node, node.token(), &mut operands);
+                operands
+            } else {
+                node.children().iter().collect()
+            };
+            let mut hashes = hash_children(operands.as_slice());
+            if is_commutative(node.token()) {
+                hashes.sort();
+            }
+            hashes.hash(&mut state);
+        },
+        ExprCall => {
+            "call".hash(&mut state);
+            node.token().hash(&mut state);
+            let arguments: Vec<&ExprNode> = node.children().iter().collect();
+            let mut hashes = hash_children(arguments.as_slice());
+            if ignores_argument_order(node.token()) {
+                hashes.sort();
+            }
+            hashes.hash(&mut state);
+        },
+    }
+    state.result()
+}
+
+// 0 and -0 are equal, as are all NaNs
+fn canonical(n: f64) -> f64 {
+    if n == 0_f64 {
+        0_f64
+    } else if n.is_nan() {
+        Float::nan()
+    } else {
+        n
+    }
+}
+
+fn flatten<'a>(node: &'a ExprNode, operator: &str, operands: &mut Vec<&'a ExprNode>) {
+    if node.kind() == ExprBinary && node.token() == operator {
+        for child in node.children().iter() {
+            flatten(child, operator, operands);
+        }
+    } else {
+        operands.push(node);
+    }
+}
+
+fn is_associative(operator: &str) -> bool {
+    match operator {
+        "+"|"*"|"&"|"|"|"xor" => true,
+        _ => false,
+    }
+}
+
+fn is_commutative(operator: &str) -> bool {
+    match operator {
+        "+"|"*"|"&"|"|"|"xor"|"=="|"!=" => true,
+        _ => false,
+    }
+}
+
+fn ignores_argument_order(function: &str) -> bool {
+    match function {
+        "add"|"mul"|"max"|"min"|"sum"|"avg"|"mean"|"gcd"|"lcm" => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use exprtree::ExprTree;
+
+    fn hash(expression: &str) -> u64 {
+        ExprTree::build(expression).unwrap().structural_hash()
+    }
+
+    #[test]
+    fn only_the_structure_counts() {
+        assert_eq!(hash("b * a + 1"), hash("1 + (a*b)"));
+        assert_eq!(hash("a + (b + c)"), hash("(c + a) + b"));
+        assert_eq!(hash("max(x, 2)"), hash("max(2.0, x)"));
+        assert!(hash("a - b") != hash("b - a"));
+        assert!(hash("pow(a, b)") != hash("pow(b, a)"));
+        assert!(hash("x") != hash("y"));
+    }
+
+    #[test]
+    fn halves_are_independent() {
+        let (low, high) = ExprTree::build("a*b + 1").unwrap().structural_hash128();
+        assert_eq!(low, hash("a*b + 1"));
+        assert!(low != high);
+    }
+}
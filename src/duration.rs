@@ -0,0 +1,51 @@
+/// Reads a duration such as `90`, `250ms`, `1m30s` or `1.5h` as seconds.
+/// A bare number is seconds; otherwise each number takes one of the units
+/// `ms`, `s`, `m`, `h` or `d`.
+pub fn parse_duration(text: &str) -> Option<f64> {
+    let text = text.trim();
+    match from_str::<f64>(text) {
+        Some(seconds) => return Some(seconds),
+        None => {},
+    }
+
+    let mut total = 0_f64;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !(c.is_digit() || c == '.')).unwrap_or(rest.len());
+        let amount = match from_str::<f64>(rest.slice_to(digits)) {
+            Some(amount) => amount,
+            None => return None,
+        };
+        rest = rest.slice_from(digits);
+
+        let letters = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        let scale = match rest.slice_to(letters) {
+            "ms" => 0.001,
+            "s" => 1_f64,
+            "m" => 60_f64,
+            "h" => 3600_f64,
+            "d" => 86400_f64,
+            _ => return None,
+        };
+        rest = rest.slice_from(letters);
+
+        total += amount * scale;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_duration;
+
+    #[test]
+    fn durations_add_up_their_parts() {
+        assert_eq!(parse_duration("90"), Some(90_f64));
+        assert_eq!(parse_duration("250ms"), Some(0.25));
+        assert_eq!(parse_duration(" 1m30s "), Some(90_f64));
+        assert_eq!(parse_duration("1.5h"), Some(5400_f64));
+        assert_eq!(parse_duration("2d"), Some(172800_f64));
+        assert_eq!(parse_duration("3 weeks"), None);
+        assert_eq!(parse_duration("ms"), None);
+    }
+}
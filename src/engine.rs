@@ -0,0 +1,191 @@
+use std::rand::Rng;
+use std::string::String;
+
+use builtins::AngleMode;
+use env::{Environment, ResultHook};
+use error::CalcError;
+use exprtree::{ExprTree, Limits, ParseOptions};
+use quick;
+use registry::NativeFunction;
+use value::{Value, NumberMode};
+
+/// One calculator: the bindings, registered functions and constants,
+/// number and angle modes, random source, hooks and parse options an
+/// expression is worked out with. Engines share nothing, so one process
+/// can run any number of them side by side, each with its own function
+/// pack and limits. The unit table and the built-ins are read-only and
+/// the same for every engine. Functions registered as closures may borrow
+/// for the lifetime `'a`.
+pub struct Engine<'a> {
+    env: Environment<'a>,
+    options: ParseOptions,
+}
+
+impl<'a> Engine<'a> {
+    pub fn new() -> Engine<'a> {
+        Engine { env: Environment::new(), options: ParseOptions::new() }
+    }
+
+    /// Parses and works out `expression` with this engine's options and
+    /// bindings. Definitions and assignments are kept for later calls.
+    /// One operator between two numbers or variables, as in `a1 * 2`, is
+    /// worked out without building a tree; see `quick::eval`.
+    pub fn eval(&mut self, expression: &str) -> Result<Value, CalcError> {
+        match quick::eval(expression, &self.options, &mut self.env) {
+            Some(result) => return result,
+            None => {},
+        }
+        let tree = try!(ExprTree::build_with(expression, &self.options));
+        tree.eval_in(&mut self.env)
+    }
+
+    /// Parses `expression` without working it out.
+    pub fn parse(&self, expression: &str) -> Result<ExprTree, CalcError> {
+        ExprTree::build_with(expression, &self.options)
+    }
+
+    pub fn env<'b>(&'b self) -> &'b Environment<'a> {
+        &self.env
+    }
+
+    pub fn env_mut<'b>(&'b mut self) -> &'b mut Environment<'a> {
+        &mut self.env
+    }
+
+    pub fn options<'b>(&'b self) -> &'b ParseOptions {
+        &self.options
+    }
+
+    pub fn options_mut<'b>(&'b mut self) -> &'b mut ParseOptions {
+        &mut self.options
+    }
+
+    /// Replaces the parse limits, such as the longest input accepted.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.options.limits = limits;
+    }
+
+    /// Caps how deeply user functions may call each other.
+    pub fn set_max_call_depth(&mut self, depth: uint) {
+        self.env.max_call_depth = depth;
+    }
+
+    /// Registers a native function of one number for this engine only,
+    /// such as the closure in `register_fn("tax", |x| x * rate)`. Its name
+    /// then reads as a built-in's does, so `tax 100` calls it as
+    /// `tax(100)` does. `ReservedName` for a name the calculator already
+    /// uses.
+    pub fn register_fn(&mut self, name: &str, function: |f64|: 'a -> f64)
+                       -> Result<(), CalcError> {
+        try!(self.env.register_fn(name, function));
+        self.options.functions.insert(String::from_str(name));
+        Ok(())
+    }
+
+    /// Registers a native function of two numbers; see `register_fn`.
+    pub fn register_fn2(&mut self, name: &str, function: |f64, f64|: 'a -> f64)
+                        -> Result<(), CalcError> {
+        try!(self.env.register_fn2(name, function));
+        self.options.functions.insert(String::from_str(name));
+        Ok(())
+    }
+
+    /// Registers a native function of any arity, or one that keeps state
+    /// in a struct; see `register_fn`.
+    pub fn register_native(&mut self, name: &str,
+                           function: Box<NativeFunction>) -> Result<(), CalcError> {
+        try!(self.env.register_native(name, function));
+        self.options.functions.insert(String::from_str(name));
+        Ok(())
+    }
+
+    /// Registers a named constant for this engine only. `ReservedName`
+    /// for a name the calculator already uses.
+    pub fn register_const(&mut self, name: &str, value: f64) -> Result<(), CalcError> {
+        self.env.register_const(name, value)
+    }
+
+    /// Makes `from` parse as `to`; see `ParseOptions::alias`.
+    pub fn alias(&mut self, from: &str, to: &str) {
+        self.options.alias(from, to);
+    }
+
+    pub fn set_mode(&mut self, mode: NumberMode) {
+        self.env.mode = mode;
+    }
+
+    pub fn set_angle(&mut self, angle: AngleMode) {
+        self.env.angle = angle;
+    }
+
+    /// Parses and evaluates as older versions did, for callers migrating
+    /// from them; see `ParseOptions::legacy` and `Environment::legacy`.
+    pub fn set_legacy(&mut self, legacy: bool) {
+        self.options.legacy = legacy;
+        self.env.legacy = legacy;
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.env.set(name, value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.get(name)
+    }
+
+    /// Seeds this engine's random built-ins; see `Environment::seed`.
+    pub fn seed(&mut self, seed: uint) {
+        self.env.seed(seed);
+    }
+
+    pub fn set_rng(&mut self, rng: Box<Rng>) {
+        self.env.set_rng(rng);
+    }
+
+    /// Has `hook` told of this engine's results; see
+    /// `Environment::add_result_hook`.
+    pub fn add_result_hook(&mut self, hook: Box<ResultHook>) {
+        self.env.add_result_hook(hook);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use builtins::Degrees;
+    use value::Real;
+    use super::Engine;
+
+    #[test]
+    fn engines_share_nothing() {
+        let mut metric = Engine::new();
+        let mut imperial = Engine::new();
+        metric.register_fn("scale", |x| x * 1000_f64).unwrap();
+        imperial.register_fn("scale", |x| x * 5280_f64).unwrap();
+        metric.eval("x = 2").unwrap();
+        imperial.set_angle(Degrees);
+
+        assert_eq!(metric.eval("scale(x)"), Ok(Real(2000_f64)));
+        // registered names read as built-ins, so the parentheses may be left off
+        assert_eq!(imperial.eval("scale 1"), Ok(Real(5280_f64)));
+        assert!(imperial.eval("x").is_err());
+        assert_eq!(imperial.eval("sin(90)"), Ok(Real(1_f64)));
+        assert!(metric.eval("sin(90)") != Ok(Real(1_f64)));
+    }
+
+    #[test]
+    fn legacy_engines_stay_quiet() {
+        let mut engine = Engine::new();
+        engine.eval("x = 3").unwrap();
+        assert_eq!(engine.eval("2x"), Ok(Real(6_f64)));
+        assert!(engine.eval("acos(2)").is_err());
+
+        engine.set_legacy(true);
+        assert!(engine.options().legacy && engine.env().legacy);
+        // without implicit products the 2 and the x are not multiplied
+        assert!(engine.eval("2x") != Ok(Real(6_f64)));
+        match engine.eval("acos(2)") {
+            Ok(Real(n)) => assert!(n.is_nan()),
+            other => fail!("acos(2) gave {}", other),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, TreeMap};
+use std::mem;
+use std::rand::{Rng, SeedableRng, StdRng, weak_rng};
+use time;
+
+use builtins::{Arity, Exactly, Between, AngleMode, Radians};
+use error::{CalcError, LimitExceeded, Milliseconds};
+use exprtree::ExprNode;
+use registry::{Registry, NativeFunction};
+use value::{Value, Real, Decimal, NumberMode, FloatMode};
+
+/// A function defined by an expression such as `f(x) = x^2 + 1`.
+#[deriving(Clone)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    /// Default expression for each parameter; only trailing ones may be set.
+    pub defaults: Vec<Option<ExprNode>>,
+    pub body: Box<ExprNode>,
+    /// Declared with `memo`; results are cached by argument values.
+    pub memo: bool,
+}
+
+impl UserFunction {
+    pub fn arity(&self) -> Arity {
+        let required = self.defaults.iter().filter(|d| d.is_none()).count();
+        if required == self.params.len() {
+            Exactly(required)
+        } else {
+            Between(required, self.params.len())
+        }
+    }
+}
+
+/// Told of each top-level result, so an embedding application can act on
+/// results as they are worked out; see `Environment::add_result_hook`.
+pub trait ResultHook {
+    fn result(&mut self, value: &Value);
+}
+
+/// Variable and function bindings that persist between evaluations, so a
+/// value assigned by one expression can be read by the next. Bindings are
+/// kept sorted by name, so listings of them come out the same every run.
+/// Functions registered as closures may borrow for the lifetime `'a`.
+pub struct Environment<'a> {
+    vars: TreeMap<String, Value>,
+    functions: TreeMap<String, UserFunction>,
+    /// Number of user function calls currently being evaluated.
+    pub depth: uint,
+    pub max_call_depth: uint,
+    /// Tail calls a single call may make in place of itself.
+    pub max_iterations: uint,
+    /// How literals are read and arithmetic is done.
+    pub mode: NumberMode,
+    /// Whether trigonometric functions work in radians or degrees.
+    pub angle: AngleMode,
+    /// Built-ins give NaN outside their domain, as older versions did,
+    /// rather than an error. Compiled programs keep the error.
+    pub legacy: bool,
+    /// Results of `memo` functions, keyed by name and arguments.
+    memo: HashMap<(String, Vec<String>), Value>,
+    registry: Registry<'a>,
+    /// Where `rand` and the other random built-ins draw from.
+    rng: Box<Rng>,
+    hooks: Vec<Box<ResultHook>>,
+    hooks_muted: bool,
+    // when evaluation must stop, in `time::precise_time_ns`, and the
+    // milliseconds that allowed
+    deadline: Option<(u64, uint)>,
+}
+
+impl<'a> Environment<'a> {
+    pub fn new() -> Environment<'a> {
+        Environment {
+            vars: TreeMap::new(),
+            functions: TreeMap::new(),
+            depth: 0,
+            max_call_depth: 256,
+            max_iterations: 1_000_000,
+            mode: FloatMode,
+            angle: Radians,
+            legacy: false,
+            memo: HashMap::new(),
+            registry: Registry::new(),
+            rng: box weak_rng() as Box<Rng>,
+            hooks: vec![],
+            hooks_muted: false,
+            deadline: None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.vars.find(&String::from_str(name)).map(|v| v.clone())
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(String::from_str(name), value);
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(&String::from_str(name));
+    }
+
+    /// The variables, in name order.
+    pub fn vars<'b>(&'b self) -> &'b TreeMap<String, Value> {
+        &self.vars
+    }
+
+    pub fn function(&self, name: &str) -> Option<UserFunction> {
+        self.functions.find(&String::from_str(name)).map(|f| f.clone())
+    }
+
+    pub fn define(&mut self, name: &str, function: UserFunction) {
+        self.functions.insert(String::from_str(name), function);
+    }
+
+    /// The user-defined functions, in name order.
+    pub fn functions<'b>(&'b self) -> &'b TreeMap<String, UserFunction> {
+        &self.functions
+    }
+
+    /// Registers a native function of one number, which may be a
+    /// closure; see `Registry`. Called directly, as in `tax(100)`, it is
+    /// found whatever the parse options; use `Engine::register_fn` for
+    /// `tax 100` to read as a call too.
+    pub fn register_fn(&mut self, name: &str, function: |f64|: 'a -> f64)
+                       -> Result<(), CalcError> {
+        self.registry.register_fn(name, function)
+    }
+
+    /// Registers a native function of two numbers; see `register_fn`.
+    pub fn register_fn2(&mut self, name: &str, function: |f64, f64|: 'a -> f64)
+                        -> Result<(), CalcError> {
+        self.registry.register_fn2(name, function)
+    }
+
+    /// Registers a native function of any arity; see `register_fn`.
+    pub fn register_native(&mut self, name: &str,
+                           function: Box<NativeFunction>) -> Result<(), CalcError> {
+        self.registry.register_native(name, function)
+    }
+
+    /// Registers a named constant; see `Registry`.
+    pub fn register_const(&mut self, name: &str, value: f64) -> Result<(), CalcError> {
+        self.registry.register_const(name, value)
+    }
+
+    pub fn registry<'b>(&'b self) -> &'b Registry<'a> {
+        &self.registry
+    }
+
+    pub fn registry_mut<'b>(&'b mut self) -> &'b mut Registry<'a> {
+        &mut self.registry
+    }
+
+    /// The source the random built-ins draw from; seeded from the system
+    /// unless replaced.
+    pub fn rng<'b>(&'b mut self) -> &'b mut Rng {
+        let rng: &'b mut Rng = self.rng;
+        rng
+    }
+
+    /// Has `hook` told of the value of every top-level expression that is
+    /// not a definition, after it is worked out, in the order hooks were
+    /// added.
+    pub fn add_result_hook(&mut self, hook: Box<ResultHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Stops or resumes telling hooks of results, for analyses that work
+    /// an expression out again with changed inputs.
+    pub fn mute_hooks(&mut self, muted: bool) {
+        self.hooks_muted = muted;
+    }
+
+    pub fn notify_hooks(&mut self, value: &Value) {
+        if self.hooks_muted {
+            return;
+        }
+        for hook in self.hooks.mut_iter() {
+            hook.result(value);
+        }
+    }
+
+    /// Replaces the random source, so an embedder can supply its own.
+    pub fn set_rng(&mut self, rng: Box<Rng>) {
+        self.rng = rng;
+    }
+
+    /// Draws from a generator seeded with `seed` from now on, so the same
+    /// seed gives the same random numbers from run to run.
+    pub fn seed(&mut self, seed: uint) {
+        let seed = [seed];
+        let rng: StdRng = SeedableRng::from_seed(seed.as_slice());
+        self.rng = box rng as Box<Rng>;
+    }
+
+    pub fn memoized(&self, name: &str, args: &[Value]) -> Option<Value> {
+        self.memo.find(&memo_key(name, args)).map(|v| v.clone())
+    }
+
+    pub fn memoize(&mut self, name: &str, args: &[Value], value: Value) {
+        self.memo.insert(memo_key(name, args), value);
+    }
+
+    /// Drops every cached result. Called at the start of each top-level
+    /// evaluation, since the variables a body reads may have changed.
+    pub fn clear_memo(&mut self) {
+        self.memo.clear();
+    }
+
+    /// Makes evaluation stop with a `Milliseconds` limit error once `ms`
+    /// milliseconds from now have passed; `None` lifts the deadline.
+    pub fn set_timeout(&mut self, ms: Option<uint>) {
+        self.deadline = ms.map(|ms| (time::precise_time_ns() + ms as u64 * 1_000_000, ms));
+    }
+
+    /// The error to stop with if the deadline has passed.
+    pub fn check_deadline(&self) -> Result<(), CalcError> {
+        match self.deadline {
+            Some((deadline, ms)) if time::precise_time_ns() > deadline =>
+                Err(LimitExceeded { limit: Milliseconds, max: ms }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// f64 is not Hash, so doubles are keyed by their bit patterns and
+// everything else by how it prints
+fn memo_key(name: &str, args: &[Value]) -> (String, Vec<String>) {
+    let parts = args.iter().map(|a| match *a {
+        Real(v) => format!("r{:x}", unsafe { mem::transmute::<f64, u64>(v) }),
+        Decimal(ref d) => format!("d{}", d),
+        ref other => format!("v{}", other),
+    }).collect();
+    (String::from_str(name), parts)
+}
+
+#[cfg(test)]
+mod test {
+    use exprtree::ExprTree;
+    use value::Value;
+    use super::{Environment, ResultHook};
+
+    // hands each result on as it would be printed
+    struct Forward {
+        results: Sender<String>,
+    }
+
+    impl ResultHook for Forward {
+        fn result(&mut self, value: &Value) {
+            self.results.send(format!("{}", value));
+        }
+    }
+
+    #[test]
+    fn hooks_hear_of_top_level_results_only() {
+        let (sender, results) = channel();
+        let mut env = Environment::new();
+        env.add_result_hook(box Forward { results: sender } as Box<ResultHook>);
+
+        for expression in ["f(x) = x + 1", "y = 4", "f(2) * 2"].iter() {
+            ExprTree::build(*expression).unwrap().eval_in(&mut env).unwrap();
+        }
+        env.mute_hooks(true);
+        ExprTree::build("f(y)").unwrap().eval_in(&mut env).unwrap();
+        env.mute_hooks(false);
+
+        // an assignment has a result; a function definition does not
+        assert_eq!(results.recv(), String::from_str("4"));
+        assert_eq!(results.recv(), String::from_str("6"));
+        assert!(results.try_recv().is_err());
+    }
+
+    #[test]
+    fn bindings_are_listed_by_name() {
+        let mut env = Environment::new();
+        for expression in ["zeta = 1", "g(x) = x", "alpha = 2", "f(x) = x", "mid = 3"].iter() {
+            ExprTree::build(*expression).unwrap().eval_in(&mut env).unwrap();
+        }
+
+        let vars: Vec<String> = env.vars().iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(vars, vec![String::from_str("alpha"), String::from_str("mid"),
+                              String::from_str("zeta")]);
+        let functions: Vec<String> = env.functions().iter().map(|(name, _)| name.clone()).collect();
+        assert_eq!(functions, vec![String::from_str("f"), String::from_str("g")]);
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt;
+
+use builtins::Arity;
+
+/// Which of the parse-time `Limits` or evaluation bounds was exceeded.
+#[deriving(Show, Clone, PartialEq)]
+pub enum Limit {
+    InputLength,
+    TokenCount,
+    NestingDepth,
+    CallDepth,
+    Iterations,
+    /// The time `Environment::set_timeout` allows, in milliseconds.
+    Milliseconds,
+}
+
+/// Errors produced while building or evaluating an expression.
+#[deriving(Clone, PartialEq)]
+pub enum CalcError {
+    /// A parenthesis at this position has no partner.
+    UnbalancedParens { pub position: uint },
+    /// A name was applied as a function but no such function exists.
+    UnknownFunction(String),
+    /// An operator with no rule for the values it was given, such as `&`
+    /// between complex numbers.
+    UnknownOperator(String),
+    /// A name was read before any value was assigned to it.
+    UnknownVariable(String),
+    /// The left side of `=` is not a plain variable name.
+    InvalidAssignment,
+    /// An operator or function is missing its operand.
+    MissingOperand { pub operator: String, pub position: uint },
+    /// The input has a token where something else was needed, as in the
+    /// `*` of `2 + * 3`. An empty `found` is the end of the input.
+    UnexpectedToken {
+        pub found: String,
+        pub expected: &'static str,
+        pub position: uint,
+        pub length: uint,
+    },
+    /// A function was called with the wrong number of arguments.
+    WrongArity { pub function: String, pub expected: Arity, pub found: uint },
+    /// A `name=value` argument names no parameter of the function.
+    UnknownParameter { pub function: String, pub name: String },
+    /// A parameter was given both positionally and by name, or twice.
+    DuplicateArgument { pub function: String, pub name: String },
+    /// A parameter without a default was given no argument.
+    MissingArgument { pub function: String, pub name: String },
+    /// A comma appeared outside the argument list of a call.
+    MisplacedComma { pub position: uint },
+    /// A numeric literal such as `1.2.3` or `0xZZ` could not be read.
+    MalformedNumber { pub literal: String, pub position: uint },
+    /// A value of one kind was used where another was needed, such as a
+    /// function in arithmetic.
+    TypeMismatch { pub expected: &'static str, pub found: &'static str },
+    /// `_` appeared somewhere other than a positional call argument.
+    MisplacedPlaceholder,
+    /// A quoted string has no closing quote.
+    UnterminatedText { pub position: uint },
+    /// `rearrange` could not get the variable alone on one side.
+    CannotIsolate { pub variable: String },
+    /// Quantities of different dimensions were added, compared or
+    /// converted, such as `2 m + 3 s`; an empty side is a plain number.
+    DimensionMismatch { pub left: String, pub right: String },
+    /// A duration such as `1m30s` could not be read.
+    MalformedDuration(String),
+    /// `derive` has no rule for this operator or function.
+    CannotDifferentiate { pub function: String },
+    /// `compile` met something other than arithmetic on numbers and
+    /// variables.
+    NotCompilable { pub construct: String },
+    /// `goalseek`, or `solve` without bounds, found no value of the
+    /// variable that reaches the target.
+    NoConvergence { pub variable: String },
+    /// `solve` found no root of the equation between the bounds it was
+    /// given.
+    NoRoots { pub variable: String, pub lo: f64, pub hi: f64 },
+    /// A built-in was given an argument it is undefined for, such as
+    /// `asin(2)` or `fact(-1)`; `expected` says what it accepts.
+    OutOfDomain { pub function: String, pub argument: f64, pub expected: &'static str },
+    /// An aggregate such as `mean` or `sum` was given only empty lists.
+    EmptyList { pub function: String },
+    /// Lists or matrices of the wrong sizes met, such as a 2x3 matrix
+    /// times a 2x2 one; `left` and `right` describe the shapes.
+    ShapeMismatch { pub operation: String, pub left: String, pub right: String },
+    /// `inv` was given a matrix with no inverse.
+    SingularMatrix,
+    /// JSON given to `ExprTree::from_json` is not a tree it wrote.
+    MalformedAst(String),
+    /// A decimal, fraction or integer was divided by zero; doubles give an
+    /// infinity instead.
+    DivisionByZero,
+    /// An integer result does not fit in 64 bits, or a shift is 64 places
+    /// or more.
+    IntegerOverflow,
+    /// The input contained no operands at all.
+    EmptyExpression,
+    /// The input was rejected by one of the parse-time limits.
+    LimitExceeded { pub limit: Limit, pub max: uint },
+    /// A function or constant was registered under a name the calculator
+    /// already uses, such as `sqrt` or `pi`.
+    ReservedName(String),
+    /// A character the calculator has no use for, such as `$`, and which no
+    /// alias stands in for.
+    UnexpectedCharacter { pub character: char, pub position: uint },
+}
+
+impl CalcError {
+    /// A short code that names the kind of error and stays the same from
+    /// release to release, for looking errors up and matching on them in
+    /// scripts.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            UnbalancedParens { .. } => "E0001",
+            UnknownFunction(_) => "E0002",
+            UnknownOperator(_) => "E0003",
+            UnknownVariable(_) => "E0004",
+            InvalidAssignment => "E0005",
+            MissingOperand { .. } => "E0006",
+            WrongArity { .. } => "E0007",
+            UnknownParameter { .. } => "E0008",
+            DuplicateArgument { .. } => "E0009",
+            MissingArgument { .. } => "E0010",
+            MisplacedComma { .. } => "E0011",
+            MalformedNumber { .. } => "E0012",
+            TypeMismatch { .. } => "E0013",
+            MisplacedPlaceholder => "E0014",
+            UnterminatedText { .. } => "E0015",
+            CannotIsolate { .. } => "E0016",
+            DimensionMismatch { .. } => "E0017",
+            MalformedDuration(_) => "E0018",
+            CannotDifferentiate { .. } => "E0019",
+            NotCompilable { .. } => "E0020",
+            NoConvergence { .. } => "E0021",
+            OutOfDomain { .. } => "E0022",
+            EmptyList { .. } => "E0023",
+            MalformedAst(_) => "E0024",
+            DivisionByZero => "E0025",
+            IntegerOverflow => "E0026",
+            EmptyExpression => "E0027",
+            LimitExceeded { .. } => "E0028",
+            ReservedName(_) => "E0029",
+            ShapeMismatch { .. } => "E0030",
+            SingularMatrix => "E0031",
+            UnexpectedToken { .. } => "E0032",
+            UnexpectedCharacter { .. } => "E0033",
+            NoRoots { .. } => "E0034",
+        }
+    }
+
+    /// The byte offset in the input the error points at, when it was found
+    /// while reading the input rather than evaluating it; see
+    /// `diagnostic::columns` for where that falls on screen.
+    pub fn position(&self) -> Option<uint> {
+        match *self {
+            UnbalancedParens { position } | MisplacedComma { position } |
+            MalformedNumber { position, .. } | UnterminatedText { position } |
+            MissingOperand { position, .. } | UnexpectedToken { position, .. } |
+            UnexpectedCharacter { position, .. } => Some(position),
+            _ => None,
+        }
+    }
+
+    /// Names the message for this error in a `messages::Catalog`: the
+    /// code, except that running out of input while expecting a token is
+    /// `E0032.end`.
+    pub fn message_key(&self) -> String {
+        match *self {
+            UnexpectedToken { ref found, .. } if found.is_empty() =>
+                format!("{}.end", self.code()),
+            _ => String::from_str(self.code()),
+        }
+    }
+
+    /// The values the message is made from, in the order the English
+    /// message gives them, for a catalog's `{0}`, `{1}`, ... to stand for.
+    /// An empty side of a `DimensionMismatch` is a plain number.
+    pub fn arguments(&self) -> Vec<String> {
+        match *self {
+            UnbalancedParens { position } | MisplacedComma { position } |
+            UnterminatedText { position } => vec![format!("{}", position)],
+            UnknownFunction(ref name) | UnknownOperator(ref name) |
+            UnknownVariable(ref name) | MalformedDuration(ref name) |
+            MalformedAst(ref name) | ReservedName(ref name) => vec![name.clone()],
+            MissingOperand { ref operator, position } =>
+                vec![operator.clone(), format!("{}", position)],
+            UnexpectedToken { ref found, expected, position, .. } =>
+                vec![String::from_str(expected), found.clone(), format!("{}", position)],
+            WrongArity { ref function, expected, found } =>
+                vec![function.clone(), format!("{}", expected), format!("{}", found)],
+            UnknownParameter { ref function, ref name } |
+            DuplicateArgument { ref function, ref name } |
+            MissingArgument { ref function, ref name } => vec![function.clone(), name.clone()],
+            MalformedNumber { ref literal, position } =>
+                vec![literal.clone(), format!("{}", position)],
+            UnexpectedCharacter { character, position } =>
+                vec![format!("{}", character), format!("{}", position)],
+            TypeMismatch { expected, found } =>
+                vec![String::from_str(expected), String::from_str(found)],
+            CannotIsolate { ref variable } | NoConvergence { ref variable } =>
+                vec![variable.clone()],
+            DimensionMismatch { ref left, ref right } => vec![left.clone(), right.clone()],
+            CannotDifferentiate { ref function } | EmptyList { ref function } =>
+                vec![function.clone()],
+            NotCompilable { ref construct } => vec![construct.clone()],
+            NoRoots { ref variable, lo, hi } =>
+                vec![variable.clone(), format!("{}", lo), format!("{}", hi)],
+            OutOfDomain { ref function, argument, expected } =>
+                vec![function.clone(), format!("{}", argument), String::from_str(expected)],
+            ShapeMismatch { ref operation, ref left, ref right } =>
+                vec![operation.clone(), left.clone(), right.clone()],
+            LimitExceeded { limit, max } => vec![format!("{}", limit), format!("{}", max)],
+            InvalidAssignment | MisplacedPlaceholder | SingularMatrix | DivisionByZero |
+            IntegerOverflow | EmptyExpression => vec![],
+        }
+    }
+
+    /// How many bytes from `position` the error covers.
+    pub fn span_len(&self) -> uint {
+        match *self {
+            MalformedNumber { ref literal, .. } => literal.len(),
+            MissingOperand { ref operator, .. } => operator.len(),
+            UnexpectedCharacter { character, .. } => character.len_utf8_bytes(),
+            UnexpectedToken { length, .. } if length > 0 => length,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Show for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnbalancedParens { position } =>
+                write!(f, "unbalanced parenthesis at position {}", position),
+            UnknownFunction(ref name) =>
+                write!(f, "unknown function '{}'", name),
+            UnknownOperator(ref operator) =>
+                write!(f, "unknown operator '{}'", operator),
+            UnknownVariable(ref name) =>
+                write!(f, "unknown variable '{}'", name),
+            InvalidAssignment =>
+                write!(f, "only a variable name can be assigned to"),
+            MissingOperand { ref operator, position } =>
+                write!(f, "missing operand for '{}' at position {}", operator, position),
+            UnexpectedToken { ref found, expected, position, .. } if found.is_empty() =>
+                write!(f, "expected {} at position {} but the input ended", expected, position),
+            UnexpectedToken { ref found, expected, position, .. } =>
+                write!(f, "expected {} but found '{}' at position {}", expected, found, position),
+            WrongArity { ref function, expected, found } =>
+                write!(f, "'{}' takes {} argument(s) but was given {}",
+                       function, expected, found),
+            UnknownParameter { ref function, ref name } =>
+                write!(f, "'{}' has no parameter named '{}'", function, name),
+            DuplicateArgument { ref function, ref name } =>
+                write!(f, "'{}' was given '{}' more than once", function, name),
+            MissingArgument { ref function, ref name } =>
+                write!(f, "'{}' is missing a value for '{}'", function, name),
+            MisplacedComma { position } =>
+                write!(f, "comma outside of a function call at position {}",
+                       position),
+            MalformedNumber { ref literal, position } =>
+                write!(f, "malformed number '{}' at position {}",
+                       literal, position),
+            TypeMismatch { expected, found } =>
+                write!(f, "expected a {} but found a {}", expected, found),
+            MisplacedPlaceholder =>
+                write!(f, "'_' can only stand for a positional argument"),
+            UnterminatedText { position } =>
+                write!(f, "text starting at position {} has no closing quote",
+                       position),
+            CannotIsolate { ref variable } =>
+                write!(f, "cannot isolate '{}' in the equation", variable),
+            DimensionMismatch { ref left, ref right } => {
+                let describe = |units: &String| if units.is_empty() {
+                    String::from_str("a plain number")
+                } else {
+                    units.clone()
+                };
+                write!(f, "cannot combine {} with {}", describe(left), describe(right))
+            },
+            MalformedDuration(ref text) =>
+                write!(f, "malformed duration '{}'; use seconds or units \
+                           such as 250ms, 1m30s or 2h", text),
+            CannotDifferentiate { ref function } =>
+                write!(f, "cannot differentiate '{}'", function),
+            NotCompilable { ref construct } =>
+                write!(f, "'{}' cannot be compiled", construct),
+            NoConvergence { ref variable } =>
+                write!(f, "no value of '{}' near the guess reaches the target",
+                       variable),
+            NoRoots { ref variable, lo, hi } =>
+                write!(f, "no value of '{}' between {} and {} solves the equation",
+                       variable, lo, hi),
+            OutOfDomain { ref function, argument, expected } =>
+                write!(f, "'{}' is undefined for {}; it takes {}", function, argument, expected),
+            EmptyList { ref function } =>
+                write!(f, "'{}' needs at least one number but was given an empty list",
+                       function),
+            ShapeMismatch { ref operation, ref left, ref right } =>
+                write!(f, "cannot apply '{}' to {} and {}", operation, left, right),
+            SingularMatrix =>
+                write!(f, "the matrix is singular and has no inverse"),
+            MalformedAst(ref problem) =>
+                write!(f, "malformed JSON tree: {}", problem),
+            DivisionByZero =>
+                write!(f, "division by zero"),
+            IntegerOverflow =>
+                write!(f, "integer overflow"),
+            EmptyExpression =>
+                write!(f, "empty expression"),
+            LimitExceeded { limit, max } =>
+                write!(f, "limit exceeded: {} is capped at {}", limit, max),
+            ReservedName(ref name) =>
+                write!(f, "'{}' already has a meaning and cannot be registered", name),
+            UnexpectedCharacter { character, position } =>
+                write!(f, "unexpected character '{}' at position {}", character, position),
+        }
+    }
+}
+
+/// Non-fatal problems noticed while building a tree.
+#[deriving(Clone, PartialEq)]
+pub enum Warning {
+    /// A numeric literal has more significant digits than an f64 keeps.
+    PrecisionLoss { pub literal: String, pub position: uint },
+}
+
+impl fmt::Show for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrecisionLoss { ref literal, position } => {
+                let shown = if literal.len() > 24 {
+                    format!("{}...", literal.as_slice().slice_to(24))
+                } else {
+                    literal.clone()
+                };
+                write!(f, "literal {} at position {} has more digits than \
+                           a double can hold; it will be rounded",
+                       shown, position)
+            },
+        }
+    }
+}
@@ -1,60 +1,228 @@
-use std::str;
+use std::cmp::max;
+use std::collections::{TreeMap, TreeSet};
+use std::fmt;
+use std::num::from_str_radix;
 use std::string::String;
 
-#[deriving(Clone)]
+use num::complex;
+
+use error::{CalcError, UnbalancedParens};
+use error::{MissingOperand, EmptyExpression, LimitExceeded, UnexpectedToken};
+use error::NestingDepth;
+use error::{Warning, PrecisionLoss, UnknownVariable, InvalidAssignment};
+use error::{CallDepth, Iterations, WrongArity, MisplacedComma};
+use error::{UnknownParameter, DuplicateArgument, MissingArgument};
+use error::{TypeMismatch, MisplacedPlaceholder};
+use error::{MalformedDuration, MalformedNumber, EmptyList, OutOfDomain};
+use algebra;
+use anonymize;
+use builtins;
+use builtins::{AtLeast, Exactly};
+use calculus;
+use compile;
+use compile::Program;
+use decimal::BigDecimal;
+use digest;
+use duration;
+use env::{Environment, UserFunction};
+use format;
+use json;
+use latex;
+use lexer::Lexer;
+use mathml;
+use random;
+use registry;
+use simplify;
+use solver;
+use speech;
+use units;
+use units::Measure;
+use value::{Value, Real, Decimal, Exact, Complex, Function, List, Partial};
+use value::{Text, Expression, Quantity};
+use value::{Integer, NumberMode, FloatMode, DecimalMode, ExactMode, IntegerMode, FixedMode};
+use value::word;
+
+#[deriving(Show, Clone, PartialEq)]
+pub enum NodeKind {
+    /// A number or named constant; the node carries its value.
+    ExprLiteral,
+    /// A name looked up in the environment.
+    ExprVariable,
+    /// Negation of the single child.
+    ExprUnary,
+    /// An operator applied to two children.
+    ExprBinary,
+    /// A function applied to any number of children.
+    ExprCall,
+    /// A quoted string; the token is its contents.
+    ExprText,
+    /// The single child measured in the unit named by the token, as in
+    /// `5 km`.
+    ExprUnit,
+}
+
+#[deriving(Clone, PartialEq)]
 pub struct ExprNode {
     token: String,
+    kind: NodeKind,
     value: Option<f64>,
-    left: Option<Box<ExprNode>>,
-    right: Option<Box<ExprNode>>,
+    children: Vec<ExprNode>,
 }
 
 impl ExprNode {
-    pub fn new(token: &str,
-        left: Option<ExprNode>,
-        right: Option<ExprNode>) -> ExprNode {
-
-        let value = match from_str::<f64>(token) {
-            Some(v) => Some(v),
-            None => constant_value(token.as_slice()),
+    pub fn new(token: &str, kind: NodeKind, children: Vec<ExprNode>) -> ExprNode {
+        let value = match kind {
+            ExprLiteral => match parse_number(token) {
+                Some(v) => Some(v),
+                None => registry::builtin_constant(token.as_slice()),
+            },
+            _ => None,
         };
         
         ExprNode {
             token: String::from_str(token),
+            kind: kind,
             value: value,
-            left: match left {
-                None => None,
-                Some(l) => Some(box l),
-            },
-            right: match right {
-                None => None,
-                Some(r) => Some(box r),
-            },
+            children: children,
         }
     }
-}
 
-enum OperatorType {
-    Unary,
-    Binary,
-    NoOp,
-}
+    pub fn token<'a>(&'a self) -> &'a str {
+        self.token.as_slice()
+    }
 
-impl OperatorType {
-    fn of_operator(operator: &String) -> OperatorType {
-        let mut c = ' ';
-        for ch in operator.as_slice().chars() {
-            c = ch;
-            break;
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+
+    pub fn children<'a>(&'a self) -> &'a Vec<ExprNode> {
+        &self.children
+    }
+
+    // `name=value` inside a call's argument list
+    fn is_named_argument(&self) -> bool {
+        self.kind == ExprBinary && self.token.as_slice() == "=" &&
+            self.children.get(0).kind == ExprVariable
+    }
+
+    // `if(cond, then, else)`, which evaluates only one branch
+    fn is_conditional(&self) -> bool {
+        self.kind == ExprCall && self.token.as_slice() == "if" &&
+            !self.children.iter().any(|c| c.is_placeholder())
+    }
+
+    // `derive(x^2, x)`, `simplify(x*1)` or `solve(2*x = 4, x)`, whose
+    // arguments are taken as written rather than evaluated
+    fn is_symbolic(&self) -> bool {
+        let symbolic = match self.token.as_slice() {
+            "derive" | "simplify" | "solve" => true,
+            _ => false,
+        };
+        self.kind == ExprCall && symbolic && !self.children.iter().any(|c| c.is_placeholder())
+    }
+
+    /// Whether this is `integrate(expr, var, a, b)`, `sum(expr, var, from,
+    /// to)` or `product(expr, var, from, to)`, which work `expr` out again
+    /// for each value of `var` rather than evaluating their arguments
+    /// first. `sum` only binds when `expr` mentions `var`, so
+    /// `sum(1, 2, 3, 4)` still adds its arguments.
+    pub fn is_binding(&self) -> bool {
+        if self.kind != ExprCall || self.children.len() != 4 ||
+           self.children.iter().any(|c| c.is_placeholder()) {
+            return false;
+        }
+        let variable = self.children.get(1);
+        match self.token.as_slice() {
+            "integrate" | "product" => true,
+            "sum" => variable.kind == ExprVariable &&
+                algebra::occurrences(self.children.get(0), variable.token.as_slice()) > 0,
+            _ => false,
         }
+    }
 
-        match TokenType::of_char(c) {
-            Alphabetical => Unary,
-            _ => match operator.as_slice() {
-                "+"|"-"|"*"|"/"|"^" => Binary,
-                _ => NoOp,
-            }
-        }        
+    // `_` standing for an argument left open, as in `mul(2, _)`
+    fn is_placeholder(&self) -> bool {
+        self.kind == ExprVariable && self.token.as_slice() == "_"
+    }
+
+    /// How tightly an operator or negation binds its operands; `None`
+    /// for anything that never needs parentheses around it.
+    pub fn precedence(&self) -> Option<i32> {
+        match self.kind {
+            ExprBinary => Some(operator_precedence(&self.token)),
+            ExprUnary => Some(NEGATION_PRECEDENCE),
+            _ => None,
+        }
+    }
+
+    /// Whether this binary operator groups from the right, like `^`.
+    pub fn is_right_assoc(&self) -> bool {
+        self.kind == ExprBinary && operator_assoc(&self.token) == RightAssoc
+    }
+
+    /// Whether this node, as an operand of something binding at
+    /// `precedence`, has to be parenthesized: when it binds looser, or
+    /// equally on the side the operator doesn't group from.
+    pub fn needs_parens(&self, precedence: i32, tie_needs_parens: bool) -> bool {
+        match self.precedence() {
+            Some(own) => own < precedence || (own == precedence && tie_needs_parens),
+            None => false,
+        }
+    }
+
+    // an operand of an operator with `precedence`, in parentheses as needed
+    fn write_operand(&self, f: &mut fmt::Formatter, precedence: i32,
+                     tie_needs_parens: bool) -> fmt::Result {
+        if self.needs_parens(precedence, tie_needs_parens) {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// Writes the tree back out as infix, with only the parentheses that
+/// precedence requires.
+impl fmt::Show for ExprNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ExprLiteral | ExprVariable => write!(f, "{}", self.token),
+            ExprText => write!(f, "\"{}\"", self.token),
+            ExprUnit => {
+                try!(self.children.get(0).write_operand(f, PREFIX_PRECEDENCE, false));
+                write!(f, " {}", self.token)
+            },
+            ExprUnary => {
+                try!(write!(f, "-"));
+                self.children.get(0).write_operand(f, NEGATION_PRECEDENCE, false)
+            },
+            ExprBinary => {
+                let precedence = operator_precedence(&self.token);
+                let right_assoc = operator_assoc(&self.token) == RightAssoc;
+                try!(self.children.get(0).write_operand(f, precedence, right_assoc));
+                try!(write!(f, " {} ", self.token));
+                self.children.get(1).write_operand(f, precedence, !right_assoc)
+            },
+            ExprCall => {
+                let list = self.token.as_slice() == "list";
+                if list {
+                    try!(write!(f, "["));
+                } else {
+                    try!(write!(f, "{}(", self.token));
+                }
+                for (i, child) in self.children.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{}", child));
+                }
+                write!(f, "{}", if list { "]" } else { ")" })
+            },
+        }
     }
 }
 
@@ -64,38 +232,56 @@ enum OperatorAssoc {
     RightAssoc,
 }
 
-fn operator_precedence(operator: &String) -> i32 {
+/// How tightly the binary operator `operator` binds; higher binds tighter.
+pub fn operator_precedence(operator: &String) -> i32 {
     match operator.as_slice() {
-        "^" => 4,
-        "*"|"/" => 3,
-        "+"|"-" => 2,
+        "^" => 9,
+        "*"|"/"|"//"|"%" => 8,
+        "+"|"-" => 7,
+        // bitwise operators sit below arithmetic, as in Python, so
+        // `1 << n - 1` shifts by n - 1
+        "<<"|">>" => 6,
+        "&" => 5,
+        "xor" => 4,
+        "|" => 3,
+        "<"|">"|"<="|">="|"=="|"!=" => 2,
+        // below everything else so `x + 1 |> sin` pipes the sum, and
+        // `3 ft + 2 m to inch` converts the sum
+        "|>"|"to"|"in" => 1,
+        "=" => 0,
         _ => 1,
     }
 }
 
 fn operator_assoc(operator: &String) -> OperatorAssoc {
     match operator.as_slice() {
-        "^" => RightAssoc,
+        "^"|"=" => RightAssoc,
         _ => LeftAssoc,
     }
 }
 
-fn constant_value(constant: &str) -> Option<f64> {
-    match constant.as_slice() {
-        "pi" => Some(Float::pi()),
-        "e" => Some(Float::e()),
-        _ => None,
-    }
-}
+// precedence given to a function used without parentheses, as in `sin 3`,
+// so it applies to the operand right after it
+static PREFIX_PRECEDENCE: i32 = 10;
+
+// a leading minus binds tighter than `*` but no tighter than `^`, so
+// `-2^2` is -4 and `2 * -3` is -6
+pub static NEGATION_PRECEDENCE: i32 = 9;
 
 #[deriving(Show, Clone, PartialEq)]
-enum TokenType {
+pub enum TokenType {
     Numeric,
     Alphabetical,
     Functional,
+    PostfixFunction,
+    UnitSuffix,
+    Variable,
     Operator,
+    UnaryMinus,
     LeftParen,
     RightParen,
+    Comma,
+    Quoted,
     Invalid,
 }
 
@@ -103,39 +289,286 @@ impl TokenType {
     pub fn of_char(c: char) -> TokenType {
         if "0123456789.".contains_char(c) {
             Numeric
-        } else if "abcdefghijklmnopqrstuvwxyz".contains_char(c) {
+        } else if "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_".contains_char(c) {
             Alphabetical
-        } else if "+-*/%^".contains_char(c) {
+        } else if "+-*/%^=|&<>!".contains_char(c) {
             Operator
-        } else if c == '(' {
+        } else if c == '(' || c == '[' {
             LeftParen
-        } else if c == ')' {
+        } else if c == ')' || c == ']' {
             RightParen
+        } else if c == ',' {
+            Comma
+        } else if c == '"' {
+            Quoted
         } else {
             Invalid
         }
     }
 
-    pub fn of_alphabeticals(s: String) -> TokenType {
-        match s.as_slice() {
-            "pi" => Numeric,
-            _ => Functional,
+    /// What the name `s` stands for. Functions registered with the engine
+    /// the options come from read as built-ins do; registered constants
+    /// are looked up when evaluated, like variables.
+    pub fn of_alphabeticals(s: String, options: &ParseOptions) -> TokenType {
+        if s.as_slice() == "xor" || s.as_slice() == "to" || s.as_slice() == "in" {
+            Operator
+        } else if registry::builtin_constant(s.as_slice()).is_some() {
+            Numeric
+        } else if builtins::is_builtin(s.as_slice()) || options.functions.contains(&s) {
+            Functional
+        } else {
+            Variable
+        }
+    }
+}
+
+#[deriving(Clone)]
+pub struct Token {
+    pub ttype: TokenType,
+    pub text: String,
+    pub precedence: i32,
+    /// Byte offset of the token in the input.
+    pub position: uint,
+    /// How many bytes of the input the token covers, which differs
+    /// from the length of `text` for aliases such as `π`, and is zero for
+    /// tokens the input only implies, such as the `*` in `2x`.
+    pub length: uint,
+    /// Number of arguments; only set on function tokens in RPN order.
+    pub arity: uint,
+}
+
+impl Token {
+    pub fn new(ttype: TokenType, text: String, precedence: i32,
+               position: uint) -> Token {
+        let length = text.len();
+        Token {
+            ttype: ttype,
+            text: text,
+            precedence: precedence,
+            position: position,
+            length: length,
+            arity: 0,
+        }
+    }
+
+    pub fn with_length(mut self, length: uint) -> Token {
+        self.length = length;
+        self
+    }
+
+    // whether this token, sitting on the operator stack, has to be output
+    // before `incoming` is pushed
+    fn binds_before(&self, incoming: &Token) -> bool {
+        match self.ttype {
+            Operator|UnaryMinus => match operator_assoc(&incoming.text) {
+                LeftAssoc => incoming.precedence <= self.precedence,
+                RightAssoc => incoming.precedence < self.precedence,
+            },
+            Functional => self.precedence == PREFIX_PRECEDENCE,
+            _ => false,
+        }
+    }
+}
+
+/// Bounds checked while tokenizing and building a tree so oversized or
+/// deeply nested input is rejected before any evaluation happens.
+pub struct Limits {
+    pub max_input_len: uint,
+    pub max_tokens: uint,
+    pub max_depth: uint,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits {
+            max_input_len: 4096,
+            max_tokens: 1024,
+            max_depth: 64,
+        }
+    }
+}
+
+/// Settings that control how an expression string is turned into a tree.
+pub struct ParseOptions {
+    pub limits: Limits,
+    /// Alternate spellings rewritten before tokens are classified, keyed by
+    /// the spelling found in the input.
+    pub aliases: TreeMap<String, String>,
+    /// Lets a built-in follow its operand, as on an RPN calculator:
+    /// `30 deg sin` is `sin(deg(30))`. Off by default.
+    pub postfix: bool,
+    /// Prints the operator stack and output queue at each step of the
+    /// conversion to RPN. Off by default.
+    pub verbose: bool,
+    /// Parses as older versions did, for scripts that depend on it: no
+    /// implicit multiplication, no syntax check ahead of building the tree,
+    /// no warnings about long literals, and characters the calculator has
+    /// no use for skipped rather than reported. Off by default.
+    pub legacy: bool,
+    /// Names of the native functions registered with an `Engine`, which
+    /// read as calls the way built-in names do.
+    pub functions: TreeSet<String>,
+}
+
+impl ParseOptions {
+    pub fn new() -> ParseOptions {
+        let mut options = ParseOptions {
+            limits: Limits::new(),
+            aliases: TreeMap::new(),
+            postfix: false,
+            verbose: false,
+            legacy: false,
+            functions: TreeSet::new(),
+        };
+
+        options.alias("arcsin", "asin");
+        options.alias("arccos", "acos");
+        options.alias("arctan", "atan");
+        options.alias("cosec", "csc");
+        options.alias("lg2", "lg");
+        options.alias("nCr", "ncr");
+        options.alias("nPr", "npr");
+        options.alias("π", "pi");
+        options.alias("×", "*");
+        options.alias("÷", "/");
+        options.alias("−", "-");
+
+        options
+    }
+
+    /// Makes `from` parse exactly as `to` would. Names alias whole
+    /// identifiers; single characters outside the calculator's alphabet may
+    /// alias an operator or a name.
+    pub fn alias(&mut self, from: &str, to: &str) {
+        self.aliases.insert(String::from_str(from), String::from_str(to));
+    }
+
+    /// `name` as its alias resolves, or unchanged if it has none.
+    pub fn resolve_alias(&self, name: String) -> String {
+        match self.aliases.find(&name) {
+            Some(target) => target.clone(),
+            None => name,
+        }
+    }
+}
+
+// an f64 round-trips at most 17 significant decimal digits
+static MAX_LITERAL_DIGITS: uint = 17;
+
+// what evaluating a user function body leaves to do
+enum CallStep {
+    Done(Value),
+    TailCall(String, UserFunction, Vec<Option<Value>>),
+}
+
+// radix of a numeric literal, from its 0x, 0b or 0o prefix
+fn radix_of(text: &str) -> uint {
+    if text.len() > 2 && text.char_at(0) == '0' {
+        match text.char_at(1) {
+            'x' | 'X' => 16,
+            'b' | 'B' => 2,
+            'o' | 'O' => 8,
+            _ => 10,
         }
+    } else {
+        10
+    }
+}
+
+/// Reads a numeric literal as an i64, for integer mode. Prefixed literals
+/// up to 64 bits wrap, so `0xFFFFFFFFFFFFFFFF` is -1.
+pub fn parse_integer(text: &str) -> Option<i64> {
+    let cleaned: String = text.chars().filter(|&c| c != '_').collect();
+    let cleaned = cleaned.as_slice();
+
+    match radix_of(cleaned) {
+        10 => from_str::<i64>(cleaned),
+        radix => from_str_radix::<u64>(cleaned.slice_from(2), radix)
+            .map(|n| n as i64),
     }
 }
 
-// token type, token string, token precedence
-struct Token(TokenType, String, i32);
+/// Reads a numeric literal as written in an expression: decimal with an
+/// optional exponent, or a `0x`, `0b` or `0o` prefixed integer, with `_`
+/// allowed as a digit separator.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let cleaned: String = text.chars().filter(|&c| c != '_').collect();
+    let cleaned = cleaned.as_slice();
+
+    match radix_of(cleaned) {
+        10 => from_str::<f64>(cleaned),
+        radix => from_str_radix::<u64>(cleaned.slice_from(2), radix)
+            .map(|n| n as f64),
+    }
+}
+
+/// The value of the literal `token`, which reads as `value` in doubles, in
+/// number mode `mode`. Outside float mode literals are read again from
+/// their text, so `0.1` is exactly one tenth and `0xFFFFFFFFFFFFFFFF` keeps
+/// all its bits. Named constants are irrational, so decimal mode writes
+/// them out to as many places as it keeps in a quotient and exact mode
+/// leaves them as doubles.
+pub fn literal(token: &str, value: f64, mode: NumberMode) -> Value {
+    let constant = registry::builtin_constant(token).is_some();
+    match mode {
+        FloatMode => Real(value),
+        DecimalMode if constant => match BigDecimal::constant(token) {
+            Some(d) => Decimal(d),
+            None => Value::from_f64(value, mode),
+        },
+        FixedMode(_) => Value::from_f64(value, mode),
+        IntegerMode(bits) => match parse_integer(token) {
+            Some(n) => Integer(word(n, bits)),
+            None => Real(value),
+        },
+        ExactMode if constant => Real(value),
+        _ => match BigDecimal::parse(token) {
+            Some(d) => if mode == ExactMode { Exact(d.to_rational()) } else { Decimal(d) },
+            None => Value::from_f64(value, mode),
+        },
+    }
+}
+
+/// Ways `ExprTree::format` can write a tree out.
+#[deriving(Show, Clone, PartialEq)]
+pub enum TreeStyle {
+    /// As it would be typed, with only the parentheses precedence needs.
+    Infix,
+    /// Operands before operators, as in `1 2 x * +`.
+    Rpn,
+    /// Operators before operands, parenthesized, as in `(+ 1 (* 2 x))`.
+    Prefix,
+    /// One node per line, children indented under their parent.
+    Indented,
+    /// LaTeX math, as in `\frac{(a+b)^2}{\sqrt{2}}`.
+    Latex,
+    /// Presentation MathML, as a `<math>` element.
+    MathMl,
+    /// Words, as the expression would be read aloud; see `speech::speak`.
+    Speech,
+}
+
+/// The names an expression needs from its environment, each set in
+/// sorted order.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Dependencies {
+    /// Variables read but not assigned or bound as parameters.
+    pub variables: TreeSet<String>,
+    /// Functions called or passed as values, built-in ones included.
+    pub functions: TreeSet<String>,
+}
 
 pub struct ExprTree {
     root: Option<Box<ExprNode>>,
+    warnings: Vec<Warning>,
+    /// Set by a leading `memo` on a function definition.
+    memo: bool,
 }
 
 fn print_token_list(title: &str, tokens: &Vec<Token>) {
     print!("{}: ", title);
     for t in tokens.iter() {
-        let &Token(_, ref ts, _) = t;
-        print!("{} ", ts);
+        print!("{} ", t.text);
     }
     println!("");
 }
@@ -147,294 +580,1766 @@ impl ExprTree {
                 None => None,
                 Some(r) => Some(box r),
             },
+            warnings: vec![],
+            memo: false,
         }
     }
 
-    pub fn build(expression: &str) -> ExprTree {
-        let tokens = ExprTree::parse_tokens(expression);
-        let rpn = ExprTree::build_rpn(tokens);
-        ExprTree::from_rpn(rpn)
+    pub fn build(expression: &str) -> Result<ExprTree, CalcError> {
+        ExprTree::build_with(expression, &ParseOptions::new())
     }
 
-    fn from_rpn(rpn: Vec<Token>) -> ExprTree {
-        let mut stack: Vec<ExprNode> = vec![];
+    /// The tokens `build_with` would turn into a tree, implicit products
+    /// included unless the options are `legacy`.
+    pub fn tokenize(expression: &str, options: &ParseOptions) -> Result<Vec<Token>, CalcError> {
+        let (expression, _) = ExprTree::strip_memo(expression);
+        let tokens = try!(ExprTree::parse_tokens(expression.as_slice(), options));
+        if options.legacy {
+            return Ok(tokens);
+        }
+        Ok(ExprTree::insert_implicit_products(tokens))
+    }
 
-        for token in rpn.iter() {
-            let &Token(ttype, ref tstr, _) = token;
+    pub fn build_with(expression: &str,
+                      options: &ParseOptions) -> Result<ExprTree, CalcError> {
+        // `memo f(x) = ...` is blanked out rather than sliced off, so error
+        // positions still line up with the input
+        let (expression, memo) = ExprTree::strip_memo(expression);
 
-            match ttype {
-                Numeric => stack.push(ExprNode::new(tstr.as_slice(), None, None)),
-                Operator => {
-                    let right = stack.pop();
-                    let left = stack.pop();
-                    stack.push(ExprNode::new(tstr.as_slice(), left, right));
+        let tokens = try!(ExprTree::tokenize(expression.as_slice(), options));
+        let warnings = if options.legacy {
+            vec![]
+        } else {
+            try!(ExprTree::check_syntax(&tokens, expression.len()));
+            ExprTree::literal_warnings(&tokens)
+        };
+        let rpn = try!(ExprTree::build_rpn(tokens, options.verbose));
+        let mut tree = try!(ExprTree::from_rpn(rpn, &options.limits));
+        tree.warnings = warnings;
+        tree.memo = memo;
+
+        if memo && !tree.is_definition() {
+            return Err(InvalidAssignment);
+        }
+        Ok(tree)
+    }
+
+    fn strip_memo(expression: &str) -> (String, bool) {
+        let trimmed = expression.trim_left();
+        let rest = if trimmed.starts_with("memo") { trimmed.slice_from(4) } else { "" };
+
+        if rest.len() > 0 && rest.char_at(0).is_whitespace() {
+            let offset = expression.len() - trimmed.len();
+            let mut blanked = String::from_str(expression.slice_to(offset));
+            blanked.push_str("    ");
+            blanked.push_str(rest);
+            (blanked, true)
+        } else {
+            (String::from_str(expression), false)
+        }
+    }
+
+    // juxtaposed operands multiply, as on paper: `2pi`, `3(4+1)`, `(1+2)(3+4)`
+    fn insert_implicit_products(tokens: Vec<Token>) -> Vec<Token> {
+        let mut result: Vec<Token> = vec![];
+
+        for token in tokens.move_iter() {
+            let juxtaposed = match result.last() {
+                Some(prev) => {
+                    let ends_operand = match prev.ttype {
+                        Numeric|Variable|RightParen|PostfixFunction|UnitSuffix => true,
+                        _ => false,
+                    };
+                    let starts_operand = match token.ttype {
+                        Numeric|Variable|Functional|LeftParen => true,
+                        _ => false,
+                    };
+                    ends_operand && starts_operand
                 },
-                Functional => {
-                    let right = stack.pop();
-                    stack.push(ExprNode::new(tstr.as_slice(), None, right));
-                }
+                None => false,
+            };
+
+            if juxtaposed {
+                let times = String::from_str("*");
+                let precedence = operator_precedence(&times);
+                result.push(Token::new(Operator, times, precedence, token.position).with_length(0));
+            }
+            result.push(token);
+        }
+
+        result
+    }
+
+    // finds the first token that can't follow the one before it, such as
+    // an operator where an operand is due, so the error can point at it;
+    // `end` is the position just past the input
+    fn check_syntax(tokens: &Vec<Token>, end: uint) -> Result<(), CalcError> {
+        static OPERAND: &'static str = "a number, name or '('";
+        let unexpected = |token: &Token, expected: &'static str| UnexpectedToken {
+            found: token.text.clone(),
+            expected: expected,
+            position: token.position,
+            length: token.length,
+        };
+
+        // whether the next token has to start an operand
+        let mut operand_due = true;
+        let mut previous: Option<TokenType> = None;
+        for token in tokens.iter() {
+            match token.ttype {
+                Operator|PostfixFunction|UnitSuffix|Comma if operand_due =>
+                    return Err(unexpected(token, OPERAND)),
+                // `f()` and `[]` are empty calls
+                RightParen if operand_due && previous != Some(LeftParen) =>
+                    return Err(unexpected(token, OPERAND)),
+                Numeric|Variable|Quoted|Functional|PostfixFunction|UnitSuffix|RightParen =>
+                    operand_due = false,
+                Operator|UnaryMinus|LeftParen|Comma => operand_due = true,
                 _ => {},
             }
+            previous = Some(token.ttype);
+        }
+
+        if operand_due && !tokens.is_empty() {
+            return Err(UnexpectedToken {
+                found: String::new(),
+                expected: OPERAND,
+                position: end,
+                length: 0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Problems found while building that did not prevent evaluation.
+    pub fn warnings<'a>(&'a self) -> &'a Vec<Warning> {
+        &self.warnings
+    }
+
+    // flag literals too long to survive conversion to f64
+    fn literal_warnings(tokens: &Vec<Token>) -> Vec<Warning> {
+        let mut warnings: Vec<Warning> = vec![];
+
+        for token in tokens.iter() {
+            if token.ttype != Numeric {
+                continue;
+            }
+
+            // prefixed literals are read as exact integers, and exponent
+            // digits say nothing about precision
+            if radix_of(token.text.as_slice()) != 10 {
+                continue;
+            }
+            let digits: String = token.text.as_slice().chars()
+                .take_while(|&c| c != 'e' && c != 'E')
+                .filter(|c| c.is_digit())
+                .collect();
+            let significant = digits.as_slice()
+                .trim_left_chars('0')
+                .trim_right_chars('0')
+                .len();
+
+            if significant > MAX_LITERAL_DIGITS {
+                warnings.push(PrecisionLoss {
+                    literal: token.text.clone(),
+                    position: token.position,
+                });
+            }
         }
 
-        ExprTree::new(Some(stack.get(0).clone()))
+        warnings
+    }
+
+    fn from_rpn(rpn: Vec<Token>, limits: &Limits) -> Result<ExprTree, CalcError> {
+        let mut stack: Vec<ExprNode> = vec![];
+        // depth of each subtree on the node stack, kept in lockstep
+        let mut depths: Vec<uint> = vec![];
+
+        for token in rpn.iter() {
+            let text = token.text.as_slice();
+
+            let (kind, operands) = match token.ttype {
+                Numeric => (ExprLiteral, 0),
+                Quoted => (ExprText, 0),
+                Variable => (ExprVariable, 0),
+                Operator => (ExprBinary, 2),
+                UnaryMinus => (ExprUnary, 1),
+                Functional => (ExprCall, token.arity),
+                PostfixFunction => (ExprCall, 1),
+                UnitSuffix => (ExprUnit, 1),
+                _ => continue,
+            };
+
+            if stack.len() < operands {
+                return Err(MissingOperand {
+                    operator: token.text.clone(),
+                    position: token.position,
+                });
+            }
+
+            let mut children: Vec<ExprNode> = vec![];
+            let mut depth = 0;
+            for _ in range(0, operands) {
+                children.insert(0, stack.pop().unwrap());
+                depth = max(depth, depths.pop().unwrap());
+            }
+
+            if depth + 1 > limits.max_depth {
+                return Err(LimitExceeded {
+                    limit: NestingDepth,
+                    max: limits.max_depth,
+                });
+            }
+
+            stack.push(ExprNode::new(text, kind, children));
+            depths.push(depth + 1);
+        }
+
+        match stack.remove(0) {
+            None => Err(EmptyExpression),
+            Some(root) => Ok(ExprTree::new(Some(root))),
+        }
+    }
+
+    // move operators to the output until the innermost open parenthesis,
+    // which is left on the stack
+    fn pop_to_paren(input_stack: &mut Vec<Token>,
+                    output_queue: &mut Vec<Token>) -> bool {
+        loop {
+            match input_stack.last() {
+                None => return false,
+                Some(top) if top.ttype == LeftParen => return true,
+                _ => {},
+            }
+            output_queue.push(input_stack.pop().unwrap());
+        }
     }
 
     // put the tokens into reverse polish notation
-    fn build_rpn(tokens: Vec<Token>) -> Vec<Token> {
+    fn build_rpn(tokens: Vec<Token>, verbose: bool) -> Result<Vec<Token>, CalcError> {
         let mut output_queue: Vec<Token> = vec![];
         let mut input_stack: Vec<Token> = vec![];
+        // for each open parenthesis, whether it holds a call's arguments
+        let mut call_parens: Vec<bool> = vec![];
+        // argument count of each open call, innermost last
+        let mut arg_counts: Vec<uint> = vec![];
 
-        for token in tokens.iter() {
-            let &Token(ttype, ref tstr, tprec) = token;
+        for (index, token) in tokens.iter().enumerate() {
+            let prev_type = if index > 0 {
+                Some(tokens.get(index - 1).ttype)
+            } else {
+                None
+            };
+            let next_type = if index + 1 < tokens.len() {
+                Some(tokens.get(index + 1).ttype)
+            } else {
+                None
+            };
 
-            match ttype {
-                Numeric => {
-                    output_queue.push(Token(ttype, tstr.clone(), tprec))
+            match token.ttype {
+                Numeric|Variable|Quoted => output_queue.push(token.clone()),
+                // a function name with nothing to apply it to is a value,
+                // as in `map(sin, xs)` or `x |> sin`
+                Functional if next_type == None || next_type == Some(Comma) ||
+                              next_type == Some(RightParen) ||
+                              next_type == Some(Operator) => {
+                    let mut reference = token.clone();
+                    reference.ttype = Variable;
+                    output_queue.push(reference);
                 },
                 Functional => {
-                    input_stack.push(Token(ttype, tstr.clone(), tprec))
+                    let mut function = token.clone();
+                    if next_type != Some(LeftParen) {
+                        function.arity = 1;
+                        function.precedence = PREFIX_PRECEDENCE;
+                    }
+                    input_stack.push(function);
                 },
                 Operator => {
                     loop {
-                        match input_stack.pop() {
-                            None => break,
-                            Some(o2) => {
-                                let Token(o2type, ref o2str, o2prec) = o2;
-                                
-                                let assoc = operator_assoc(o2str);
-
-                                if o2type == Operator &&
-                                   (assoc == LeftAssoc && tprec <= o2prec ||
-                                    tprec < o2prec) {
-                                    output_queue.push(Token(o2type, o2str.clone(), o2prec));
-                                } else {
-                                    input_stack.push(Token(o2type, o2str.clone(), o2prec));
-                                    break;
-                                }
-                            },
+                        let pops = match input_stack.last() {
+                            Some(top) => top.binds_before(token),
+                            None => false,
+                        };
+                        if !pops {
+                            break;
                         }
+                        output_queue.push(input_stack.pop().unwrap());
+                    }
+                    input_stack.push(token.clone());
+                },
+                // a prefix operator has no left operand to finish, so it
+                // goes straight on the stack
+                UnaryMinus => input_stack.push(token.clone()),
+                // a postfix function's operand is already complete in the
+                // output, so it applies there and then, binding tightest
+                PostfixFunction|UnitSuffix => output_queue.push(token.clone()),
+                LeftParen => {
+                    let is_call = prev_type == Some(Functional);
+                    call_parens.push(is_call);
+                    if is_call {
+                        arg_counts.push(if next_type == Some(RightParen) { 0 } else { 1 });
+                    }
+                    input_stack.push(token.clone());
+                },
+                Comma => {
+                    if !ExprTree::pop_to_paren(&mut input_stack, &mut output_queue) ||
+                       call_parens.last() != Some(&true) {
+                        return Err(MisplacedComma { position: token.position });
                     }
-                    input_stack.push(Token(ttype, tstr.clone(), tprec));
+                    *arg_counts.mut_last().unwrap() += 1;
                 },
-                LeftParen => input_stack.push(Token(ttype, tstr.clone(), tprec)),
                 RightParen => {
-                    loop {
-                        match input_stack.pop() {
-                            None => fail!("Parenthesis mismatch!"),
-                            Some(o2) => {
-                                let Token(o2type, ref o2str, o2prec) = o2;
-                                if o2type != LeftParen {
-                                    output_queue.push(Token(o2type, o2str.clone(), o2prec));
-                                } else {
-                                    break;
-                                }
-                            },
-                        }
+                    if !ExprTree::pop_to_paren(&mut input_stack, &mut output_queue) {
+                        return Err(UnbalancedParens { position: token.position });
+                    }
+                    // a bracket closes only a bracket, and a parenthesis a
+                    // parenthesis
+                    let open = input_stack.pop().unwrap();
+                    if (open.text.as_slice() == "[") != (token.text.as_slice() == "]") {
+                        return Err(UnbalancedParens { position: token.position });
+                    }
+
+                    // a call's closing parenthesis completes the function
+                    if call_parens.pop() == Some(true) {
+                        let mut function = input_stack.pop().unwrap();
+                        function.arity = arg_counts.pop().unwrap();
+                        output_queue.push(function);
                     }
                 },
                 _ => {},
             }
 
-            print_token_list("output", &output_queue);
-            print_token_list("input", &input_stack);
+            if verbose {
+                print_token_list("output", &output_queue);
+                print_token_list("input", &input_stack);
+            }
         }
 
         loop {
             match input_stack.pop() {
                 None => break,
                 Some(o2) => {
-                    let Token(o2type, ref o2str, o2prec) = o2;
-                    match o2type {
-                        LeftParen|RightParen => fail!("Parenthesis mismatch!"),
-                        _ => {
-                            output_queue.push(Token(o2type, o2str.clone(), o2prec));
-                        },
+                    match o2.ttype {
+                        LeftParen|RightParen =>
+                            return Err(UnbalancedParens { position: o2.position }),
+                        _ => output_queue.push(o2),
                     }
                 },
             }
-            print_token_list("output", &output_queue);
-            print_token_list("input", &input_stack);
+            if verbose {
+                print_token_list("output", &output_queue);
+                print_token_list("input", &input_stack);
+            }
         }
        
-        return output_queue;
+        return Ok(output_queue);
     }
 
-    fn parse_tokens(expression: &str) -> Vec<Token> {
-        let mut result: Vec<Token> = vec![];
+    fn parse_tokens(expression: &str,
+                    options: &ParseOptions) -> Result<Vec<Token>, CalcError> {
+        let mut tokens = vec![];
+        for token in Lexer::new(expression, options) {
+            tokens.push(try!(token));
+        }
+        Ok(tokens)
+    }
 
-        let mut i = 0;
-        let mut accumulator = String::new();
-        let len = expression.len();
-        while i < len {
-            let copt = expression.chars().nth(i);
-            let c = match copt {
-                None => ' ',
-                Some(ch) => ch,
-            };
+    pub fn eval(&self) -> Result<Value, CalcError> {
+        self.eval_in(&mut Environment::new())
+    }
 
-            let token_type = TokenType::of_char(c);
-            match token_type {
-                Operator => {
-                    let op_str = str::from_char(c);
-                    let op_prec = operator_precedence(&op_str);
-                    result.push(Token(token_type, op_str, op_prec));
-                },
-                Numeric => {
-                    accumulator.push_char(c);
-                    let mut j = i + 1;
-                    while j < len {
-                        let ncopt = expression.chars().nth(j);
-                        match ncopt {
-                            Some(nc) =>
-                                match TokenType::of_char(nc) {
-                                    Numeric => accumulator.push_char(nc),
-                                    _ => break,
-                                },
-                            _ => {},
-                        }
-                        j += 1;
-                    }
+    /// True when the expression defines a function, like `f(x) = x^2`.
+    pub fn is_definition(&self) -> bool {
+        match self.root {
+            Some(ref node) if node.token.as_slice() == "=" =>
+                node.children.get(0).kind == ExprCall,
+            _ => false,
+        }
+    }
 
-                    let num_str = accumulator.clone();
-                    result.push(Token(token_type, num_str, 0));
-                    accumulator.truncate(0);
-                    i = j - 1;
-                },
-                Alphabetical => {
-                    accumulator.push_char(c);
-                    let mut j = i + 1;
-                    while j < len {
-                        let ncopt = expression.chars().nth(j);
-                        match ncopt {
-                            Some(nc) =>
-                                match TokenType::of_char(nc) {
-                                    Alphabetical => accumulator.push_char(nc),
-                                    _ => break,
-                                },
-                            _ => {},
-                        }
-                        j += 1;
-                    }
+    /// The same expression with constants folded and identity operations
+    /// removed; worth doing before evaluating one tree many times.
+    pub fn simplify(&self) -> ExprTree {
+        ExprTree {
+            root: self.root.as_ref().map(|node| box simplify::simplify(&**node)),
+            warnings: self.warnings.clone(),
+            memo: self.memo,
+        }
+    }
 
-                    let alpha_str = accumulator.clone();
-                    let atype = TokenType::of_alphabeticals(alpha_str.clone());
-                    result.push(Token(atype, alpha_str, 0));
-                    accumulator.truncate(0);
-                    i = j - 1;
-                },
-                LeftParen => {
-                    result.push(Token(LeftParen, String::from_str("("), 0));
-                },
-                RightParen => {
-                    result.push(Token(RightParen, String::from_str(")"), 0));
-                },
-                _ => {},
-            }
-            i += 1;
+    /// Flattens the tree into a `Program` for fast repeated evaluation.
+    pub fn compile(&self) -> Result<Program, CalcError> {
+        match self.root {
+            Some(ref root) => Program::compile(&**root),
+            None => Err(EmptyExpression),
         }
+    }
 
-        result
+    /// A rough measure of the work evaluating the expression takes; see
+    /// `compile::tree_cost`.
+    pub fn estimated_cost(&self) -> uint {
+        self.estimated_cost_in(&Environment::new())
     }
 
-    pub fn eval(&self) -> f64 {
+    /// The same as `estimated_cost`, with the ranges and matrices held in
+    /// the variables of `env` counted at their size.
+    pub fn estimated_cost_in(&self, env: &Environment) -> uint {
         match self.root {
-            None => 0_f64,
-            Some(ref node) => ExprTree::eval_node(node),
+            Some(ref root) => compile::tree_cost(&**root, env),
+            None => 0,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn print(&self) {
+    /// The free variables and the functions the expression uses, so a
+    /// formula can be checked against the names an application provides
+    /// before it is accepted.
+    pub fn dependencies(&self) -> Dependencies {
+        let mut deps = Dependencies { variables: TreeSet::new(), functions: TreeSet::new() };
         match self.root {
-            Some(ref node) => ExprTree::print_node(node),
+            Some(ref node) => ExprTree::collect_dependencies(&**node, &vec![], &mut deps),
             None => {},
         }
-        println!("");
+        deps
     }
 
-    #[allow(dead_code)]
-    fn print_node(node: &Box<ExprNode>) {
-        match node.value {
-            Some(v) => print!("{}", v),
-            None => {
-                print!("(");
-                match node.left {
-                    Some(ref left) => {
-                        ExprTree::print_node(left);
-                        print!(" ");
-                    },
-                    None => {},
-                }
-                print!("{} ", node.token);
-                match node.right {
-                    Some(ref right) => ExprTree::print_node(right),
-                    None => {},
+    // `bound` holds the parameters of the definition being walked
+    fn collect_dependencies(node: &ExprNode, bound: &Vec<String>, deps: &mut Dependencies) {
+        match node.kind {
+            ExprVariable if node.is_placeholder() || bound.contains(&node.token) => {},
+            ExprVariable if builtins::is_builtin(node.token.as_slice()) => {
+                deps.functions.insert(node.token.clone());
+            },
+            ExprVariable => {
+                deps.variables.insert(node.token.clone());
+            },
+            ExprBinary if node.token.as_slice() == "=" => {
+                let target = node.children.get(0);
+                if target.kind != ExprCall {
+                    // an assigned name, or a named argument, is written, not read
+                    ExprTree::collect_dependencies(node.children.get(1), bound, deps);
+                    return;
                 }
-                print!(")");
-            }
-        }
-    }
 
-    fn eval_node(node: &Box<ExprNode>) -> f64 {
-        match node.value {
-            Some(v) => v,
-            None => {
-                let ref operator = node.token;
-                let ot = OperatorType::of_operator(operator);
-                
-                match node.right {
-                    None => fail!("No available value for operator."),
-                    Some(ref right) => {
-                        match ot {
-                            Unary => {
-                                ExprTree::eval_unary(operator,
-                                    ExprTree::eval_node(right))
-                            },
-                            Binary => {
-                                match node.left {
-                                    None => ExprTree::eval_unary(operator,
-                                                ExprTree::eval_node(right)),
-                                    Some(ref left) => 
-                                        ExprTree::eval_binary(operator,
-                                        ExprTree::eval_node(left),
-                                        ExprTree::eval_node(right))
-                                }
-                            },
-                            _ => 0_f64,
-                        }
+                let mut params = bound.clone();
+                for param in target.children.iter() {
+                    let name = if param.kind == ExprBinary { param.children.get(0) } else { param };
+                    params.push(name.token.clone());
+                }
+                for param in target.children.iter().filter(|p| p.kind == ExprBinary) {
+                    ExprTree::collect_dependencies(param.children.get(1), &params, deps);
+                }
+                ExprTree::collect_dependencies(node.children.get(1), &params, deps);
+            },
+            // the right of a conversion names units
+            ExprBinary if node.token.as_slice() == "to" || node.token.as_slice() == "in" =>
+                ExprTree::collect_dependencies(node.children.get(0), bound, deps),
+            // the variable of `integrate`, `sum` or `product` is bound in
+            // the expression
+            ExprCall if node.is_binding() => {
+                deps.functions.insert(node.token.clone());
+                let mut params = bound.clone();
+                params.push(node.children.get(1).token.clone());
+                ExprTree::collect_dependencies(node.children.get(0), &params, deps);
+                for child in node.children.iter().skip(2) {
+                    ExprTree::collect_dependencies(child, bound, deps);
+                }
+            },
+            ExprCall => {
+                deps.functions.insert(node.token.clone());
+                // `derive` and `simplify` read their arguments as written
+                if !node.is_symbolic() {
+                    for child in node.children.iter() {
+                        ExprTree::collect_dependencies(child, bound, deps);
                     }
                 }
             },
+            _ => for child in node.children.iter() {
+                ExprTree::collect_dependencies(child, bound, deps);
+            },
         }
     }
 
-    fn eval_unary(operator: &String, value: f64) -> f64 {
-        match operator.as_slice() {
-            "-" => -value,
-            "ln" => value.ln(),
-            "lg" => value.log2(),
-            "log" => value.log10(),
-            "sin" => value.sin(),
-            "cos" => value.cos(),
-            "tan" => value.tan(),
-            "csc" => 1_f64 / value.sin(),
-            "sec" => 1_f64 / value.cos(),
-            "cot" => 1_f64 / value.tan(),
-            "neg" => -value,
-            "sgn" => value.signum(),
-            _ => fail!("Invalid unary operator"),
-        }
+    /// The tree as JSON, with each node's kind, token and children, for
+    /// storing or handing to other tools; `from_json` reads it back.
+    pub fn to_json(&self) -> String {
+        json::tree_to_json(self.root(), self.memo)
+    }
+
+    /// Rebuilds a tree written by `to_json`.
+    pub fn from_json(text: &str) -> Result<ExprTree, CalcError> {
+        let (root, memo) = try!(json::tree_from_json(text));
+        let mut tree = ExprTree::new(root);
+        tree.memo = memo;
+        Ok(tree)
     }
 
-    fn eval_binary(operator: &String, lhs: f64, rhs: f64) -> f64 {
-        match operator.as_slice() {
-            "+" => lhs + rhs,
-            "-" => lhs - rhs,
-            "*" => lhs * rhs,
-            "/" => lhs / rhs,
-            "^" => lhs.powf(rhs),
-            _ => 0_f64,
+    /// A stable structural hash, usable as a key for finding the same
+    /// formula stored twice; see `digest::structural_hash`. An empty
+    /// expression hashes as 0.
+    pub fn structural_hash(&self) -> u64 {
+        self.root().map_or(0, |root| digest::structural_hash(root))
+    }
+
+    /// The 128-bit form of `structural_hash`, as a pair of halves.
+    pub fn structural_hash128(&self) -> (u64, u64) {
+        self.root().map_or((0, 0), |root| digest::structural_hash128(root))
+    }
+
+    /// The same shape with variables, user functions, numbers and strings
+    /// replaced by placeholders such as `a1*a2 + f1(a1)`, for logging
+    /// formulas without the data in them; see `anonymize::anonymize`.
+    pub fn anonymize(&self) -> ExprTree {
+        ExprTree {
+            root: self.root.as_ref().map(|node| box anonymize::anonymize(&**node)),
+            warnings: vec![],
+            memo: self.memo,
         }
     }
-}
\ No newline at end of file
+
+    /// The structure read out in English, with every operation and its
+    /// operands named, as in "the quotient of the sum of a and b, and two";
+    /// see `speech::describe`. An empty tree gives an empty string.
+    pub fn to_words(&self) -> String {
+        self.root().map_or(String::new(), |root| speech::describe(root))
+    }
+
+    pub fn root<'a>(&'a self) -> Option<&'a ExprNode> {
+        self.root.as_ref().map(|node| &**node)
+    }
+
+    /// Evaluates against `env`, reading variables from it and storing any
+    /// assignment the expression makes.
+    pub fn eval_in(&self, env: &mut Environment) -> Result<Value, CalcError> {
+        if env.depth == 0 {
+            env.clear_memo();
+        }
+
+        match self.root {
+            None => Ok(Real(0_f64)),
+            Some(ref node) if self.is_definition() =>
+                ExprTree::eval_assign(&**node, self.memo, env),
+            Some(ref node) => {
+                let value = try!(ExprTree::eval_node(&**node, env));
+                if env.depth == 0 {
+                    env.notify_hooks(&value);
+                }
+                Ok(value)
+            },
+        }
+    }
+
+    /// Prints the tree in prefix form, for debugging.
+    pub fn print(&self) {
+        println!("{}", self.format(Prefix));
+    }
+
+    /// The tree written out in `style`. An empty expression gives an
+    /// empty string, and `Infix` reads back as the same tree, `memo`
+    /// included.
+    pub fn format(&self, style: TreeStyle) -> String {
+        let root = match self.root {
+            Some(ref root) => &**root,
+            None => return String::new(),
+        };
+
+        match style {
+            Infix if self.memo => format!("memo {}", root),
+            Infix => format!("{}", root),
+            Rpn => {
+                let mut words: Vec<String> = vec![];
+                ExprTree::rpn_words(root, &mut words);
+                words.as_slice().connect(" ")
+            },
+            Prefix => ExprTree::prefix_form(root),
+            Indented => {
+                let mut text = String::new();
+                ExprTree::indented_form(root, 0, &mut text);
+                text
+            },
+            Latex => latex::latex(root),
+            MathMl => mathml::mathml(root),
+            Speech => speech::speak(root),
+        }
+    }
+
+    // the leaf's own text, or the node's operator or function name
+    fn label(node: &ExprNode) -> String {
+        match node.kind {
+            ExprText => format!("\"{}\"", node.token),
+            ExprUnary => String::from_str("neg"),
+            _ => node.token.clone(),
+        }
+    }
+
+    fn rpn_words(node: &ExprNode, words: &mut Vec<String>) {
+        for child in node.children.iter() {
+            ExprTree::rpn_words(child, words);
+        }
+        // a call's argument count is only spelled out when it is not one
+        words.push(match node.kind {
+            ExprCall if node.children.len() != 1 =>
+                format!("{}/{}", node.token, node.children.len()),
+            _ => ExprTree::label(node),
+        });
+    }
+
+    fn prefix_form(node: &ExprNode) -> String {
+        if node.children.is_empty() && node.kind != ExprCall {
+            return ExprTree::label(node);
+        }
+
+        let mut text = format!("({}", ExprTree::label(node));
+        for child in node.children.iter() {
+            text.push_char(' ');
+            text.push_str(ExprTree::prefix_form(child).as_slice());
+        }
+        text.push_char(')');
+        text
+    }
+
+    fn indented_form(node: &ExprNode, depth: uint, text: &mut String) {
+        text.push_str(String::from_char(depth * 2, ' ').as_slice());
+        text.push_str(ExprTree::label(node).as_slice());
+        text.push_char('\n');
+        for child in node.children.iter() {
+            ExprTree::indented_form(child, depth + 1, text);
+        }
+    }
+
+    fn eval_node(node: &ExprNode,
+                 env: &mut Environment) -> Result<Value, CalcError> {
+        try!(env.check_deadline());
+        match node.kind {
+            ExprLiteral => ExprTree::literal_value(node, env.mode),
+            ExprText => Ok(Text(node.token.clone())),
+            ExprVariable => {
+                let name = node.token.as_slice();
+                match env.get(name) {
+                    Some(v) => Ok(v),
+                    None if node.is_placeholder() => Err(MisplacedPlaceholder),
+                    // a function named without arguments is a value
+                    None if env.function(name).is_some() || builtins::is_builtin(name) ||
+                            !env.registry().arities(name).is_empty() =>
+                        Ok(Function(Partial::of(name))),
+                    None if env.registry().constant(name).is_some() => {
+                        let constant = env.registry().constant(name).unwrap();
+                        Ok(Value::from_f64(constant, env.mode))
+                    },
+                    // the imaginary unit, unless a variable has taken the name
+                    None if name == "i" => Ok(Complex(complex::Complex::new(0_f64, 1_f64))),
+                    // physical constants such as `c` carry their units,
+                    // and win over units of the same name
+                    None if units::constant(name).is_some() =>
+                        Ok(Quantity(units::constant(name).unwrap())),
+                    // a bare unit, as in `100 / h`
+                    None if units::lookup(name).is_some() => Ok(Quantity(Measure::of(1_f64, name))),
+                    None => Err(UnknownVariable(node.token.clone())),
+                }
+            },
+            ExprUnary => {
+                let operand = try!(ExprTree::eval_node(node.children.get(0), env));
+                let mode = env.mode;
+                operand.negate().map(|value| value.in_word(mode))
+            },
+            ExprUnit => {
+                let operand = try!(ExprTree::eval_node(node.children.get(0), env));
+                // a variable of the same name wins, so `2 m` still doubles `m`
+                let name = node.token.as_slice();
+                let unit = match env.get(name) {
+                    Some(value) => value,
+                    None => Quantity(Measure::of(1_f64, name)),
+                };
+                Value::binary("*", &operand, &unit)
+            },
+            ExprBinary if node.token.as_slice() == "=" => {
+                ExprTree::eval_assign(node, false, env)
+            },
+            ExprBinary if node.token.as_slice() == "|>" => {
+                ExprTree::eval_pipe(node, env)
+            },
+            ExprBinary if node.token.as_slice() == "to" || node.token.as_slice() == "in" => {
+                let lhs = try!(ExprTree::eval_node(node.children.get(0), env));
+                let rhs = try!(ExprTree::eval_units(node.children.get(1), env));
+                Value::binary(node.token.as_slice(), &lhs, &rhs)
+            },
+            ExprBinary => {
+                let lhs = try!(ExprTree::eval_node(node.children.get(0), env));
+                let rhs = try!(ExprTree::eval_node(node.children.get(1), env));
+                let mode = env.mode;
+                Value::binary_in(node.token.as_slice(), &lhs, &rhs, mode)
+            },
+            ExprCall if node.children.iter().any(|c| c.is_placeholder()) => {
+                ExprTree::eval_partial(node, env)
+            },
+            ExprCall if node.is_conditional() => {
+                let branch = try!(ExprTree::select_branch(node, env));
+                ExprTree::eval_node(branch, env)
+            },
+            ExprCall if node.is_binding() => {
+                let variable = node.children.get(1);
+                if variable.kind != ExprVariable {
+                    return Err(TypeMismatch {
+                        expected: "variable name",
+                        found: "expression",
+                    });
+                }
+                let from = try!(try!(ExprTree::eval_node(node.children.get(2), env)).number());
+                let to = try!(try!(ExprTree::eval_node(node.children.get(3), env)).number());
+                ExprTree::eval_binding(node.token.as_slice(), node.children.get(0),
+                                       variable.token.as_slice(), from, to, env)
+            },
+            ExprCall if node.is_symbolic() => {
+                let mut args = vec![];
+                for (i, child) in node.children.iter().enumerate() {
+                    args.push(match child.kind {
+                        // the bounds given to `solve` are numbers as usual
+                        _ if i >= 2 => try!(ExprTree::eval_node(child, env)),
+                        ExprText | ExprVariable => Text(child.token.clone()),
+                        _ => Expression(box child.clone()),
+                    });
+                }
+                match node.token.as_slice() {
+                    "derive" => ExprTree::apply_derive(args),
+                    "solve" => ExprTree::apply_solve(args, env),
+                    _ => ExprTree::apply_simplify(args),
+                }
+            },
+            ExprCall => {
+                let (args, named) = try!(ExprTree::eval_arguments(node, env));
+                ExprTree::apply(&node.token, args, named, env)
+            },
+        }
+    }
+
+    // a node only the parser made is sure to hold a number; one read from
+    // JSON, or put together by hand, may not
+    fn literal_value(node: &ExprNode, mode: NumberMode) -> Result<Value, CalcError> {
+        match node.value {
+            Some(value) => Ok(literal(node.token.as_slice(), value, mode)),
+            None => Err(MalformedNumber { literal: node.token.clone(), position: 0 }),
+        }
+    }
+
+    // `x |> f` is `f(x)`; with a call on the right, `x |> f(2)` is `f(x, 2)`
+    // and `x |> f(2, _)` fills the open slot
+    fn eval_pipe(node: &ExprNode,
+                 env: &mut Environment) -> Result<Value, CalcError> {
+        let input = try!(ExprTree::eval_node(node.children.get(0), env));
+        let target = node.children.get(1);
+
+        if target.kind == ExprCall && !target.children.iter().any(|c| c.is_placeholder()) {
+            let (args, named) = try!(ExprTree::eval_arguments(target, env));
+            let mut piped = vec![input];
+            piped.push_all_move(args);
+            return ExprTree::apply(&target.token, piped, named, env);
+        }
+
+        let function = try!(ExprTree::eval_node(target, env));
+        ExprTree::apply_value(&function, vec![input], env)
+    }
+
+    // the right of a conversion, where a unit's name is the unit even when
+    // a built-in or constant shares it, so `2 h in min` is minutes
+    fn eval_units(node: &ExprNode,
+                  env: &mut Environment) -> Result<Value, CalcError> {
+        let name = node.token.as_slice();
+        match node.kind {
+            ExprVariable if env.get(name).is_none() && units::lookup(name).is_some() =>
+                Ok(Quantity(Measure::of(1_f64, name))),
+            ExprBinary if name == "*" || name == "/" => {
+                let lhs = try!(ExprTree::eval_units(node.children.get(0), env));
+                let rhs = try!(ExprTree::eval_units(node.children.get(1), env));
+                Value::binary(name, &lhs, &rhs)
+            },
+            ExprBinary if name == "^" => {
+                let lhs = try!(ExprTree::eval_units(node.children.get(0), env));
+                let rhs = try!(ExprTree::eval_node(node.children.get(1), env));
+                Value::binary(name, &lhs, &rhs)
+            },
+            _ => ExprTree::eval_node(node, env),
+        }
+    }
+
+    // the branch of `if(cond, then, else)` that the condition picks; the
+    // other branch is never evaluated, so it may divide by zero or recurse
+    fn select_branch<'a>(node: &'a ExprNode,
+                         env: &mut Environment) -> Result<&'a ExprNode, CalcError> {
+        try!(builtins::check_arity("if", node.children.len()));
+        let condition = try!(ExprTree::eval_node(node.children.get(0), env));
+        let taken = if try!(condition.is_truthy()) { 1 } else { 2 };
+        Ok(node.children.get(taken))
+    }
+
+    // `f(2, _)` evaluates the arguments it has and leaves the rest open
+    fn eval_partial(node: &ExprNode,
+                    env: &mut Environment) -> Result<Value, CalcError> {
+        let mut args: Vec<Option<Value>> = vec![];
+
+        for child in node.children.iter() {
+            if child.is_placeholder() {
+                args.push(None);
+            } else if child.is_named_argument() {
+                return Err(MisplacedPlaceholder);
+            } else {
+                args.push(Some(try!(ExprTree::eval_node(child, env))));
+            }
+        }
+
+        Ok(Function(Partial { name: node.token.clone(), args: args }))
+    }
+
+    // call `name`, which may be a user function, a variable holding a
+    // function value, or a built-in
+    fn apply(name: &String, args: Vec<Value>, named: Vec<(String, Value)>,
+             env: &mut Environment) -> Result<Value, CalcError> {
+        match env.function(name.as_slice()) {
+            Some(function) => {
+                let slots = try!(ExprTree::bind_arguments(name, &function, args, named));
+                return ExprTree::eval_call(name, &function, slots, env);
+            },
+            None => {},
+        }
+
+        match env.get(name.as_slice()) {
+            Some(Function(partial)) => return ExprTree::apply_partial(&partial, args, named, env),
+            Some(other) => return Err(TypeMismatch {
+                expected: "function",
+                found: other.kind_name(),
+            }),
+            None => {},
+        }
+
+        if !named.is_empty() {
+            let &(ref param, _) = named.get(0);
+            return Err(UnknownParameter {
+                function: name.clone(),
+                name: param.clone(),
+            });
+        }
+
+        let arities = env.registry().arities(name.as_slice());
+        if !arities.is_empty() {
+            // with no overload for this many arguments, the first one
+            // registered says what was expected
+            if !arities.iter().any(|arity| arity.accepts(args.len())) {
+                return Err(WrongArity {
+                    function: name.clone(),
+                    expected: arities.get(0).clone(),
+                    found: args.len(),
+                });
+            }
+            let mut numbers: Vec<f64> = vec![];
+            for arg in args.iter() {
+                numbers.push(try!(arg.number()));
+            }
+            return Ok(Real(env.registry_mut().call(name.as_slice(), numbers.as_slice()).unwrap()));
+        }
+
+        match name.as_slice() {
+            "map"|"reduce" => ExprTree::apply_higher_order(name, args, env),
+            "rearrange" => ExprTree::apply_rearrange(args),
+            "goalseek" => ExprTree::apply_goalseek(args, env),
+            "solve" => ExprTree::apply_solve(args, env),
+            "integrate"|"product" => ExprTree::apply_binding(name.as_slice(), args, env),
+            "derive" => ExprTree::apply_derive(args),
+            "simplify" => ExprTree::apply_simplify(args),
+            "rate" => ExprTree::apply_rate(args),
+            // `[1, 2, 3]` is read as `list(1, 2, 3)`
+            "list" => Ok(List(args)),
+            "rand"|"randint"|"normal" => {
+                try!(builtins::check_arity(name.as_slice(), args.len()));
+                let mut numbers: Vec<f64> = vec![];
+                for arg in args.iter() {
+                    numbers.push(try!(arg.number()));
+                }
+                let result = try!(random::sample(name.as_slice(), numbers.as_slice(), env.rng()));
+                Ok(match env.mode {
+                    FixedMode(_) => Value::from_f64(result, env.mode),
+                    _ => Real(result),
+                })
+            },
+            // `to_hex(255)` gives the text `0xff`
+            "to_hex"|"to_oct"|"to_bin" => {
+                try!(builtins::check_arity(name.as_slice(), args.len()));
+                let base = match name.as_slice() {
+                    "to_hex" => 16,
+                    "to_oct" => 8,
+                    _ => 2,
+                };
+                let whole = match *args.get(0) {
+                    Integer(n) => Some(n),
+                    ref other => format::whole(try!(other.number())),
+                };
+                match whole {
+                    Some(n) => Ok(Text(format::radix(n, base))),
+                    None => Err(OutOfDomain {
+                        function: name.clone(),
+                        argument: try!(args.get(0).number()),
+                        expected: "whole numbers",
+                    }),
+                }
+            },
+            "dot"|"cross"|"det"|"inv"|"transpose" => {
+                try!(builtins::check_arity(name.as_slice(), args.len()));
+                builtins::call_matrix(name.as_slice(), args.as_slice())
+            },
+            // reached only through a function value, as in
+            // `map(if(_, 1, -1), xs)`, with both branches already evaluated
+            "if" => {
+                try!(builtins::check_arity("if", args.len()));
+                let taken = if try!(args.get(0).is_truthy()) { 1 } else { 2 };
+                Ok(args.get(taken).clone())
+            },
+            // the one built-in that turns real arguments complex
+            "sqrt" if args.len() == 1 && args.get(0).kind_name() != "list" =>
+                args.get(0).sqrt(),
+            _ => {
+                // built-ins work on doubles, with lists spread out
+                let mut numbers: Vec<f64> = vec![];
+                for arg in args.iter() {
+                    match *arg {
+                        List(ref items) => for item in items.iter() {
+                            numbers.push(try!(item.number()));
+                        },
+                        ref other => numbers.push(try!(other.number())),
+                    }
+                }
+                if numbers.is_empty() && !args.is_empty() {
+                    return Err(EmptyList { function: name.clone() });
+                }
+                let result = match builtins::call(name.as_slice(), numbers.as_slice(), env.angle) {
+                    // before domain checks, `ln(-1)` and the like gave NaN
+                    Err(OutOfDomain { .. }) if env.legacy => Float::nan(),
+                    result => try!(result),
+                };
+                // in fixed point, a function's result is stored to a word
+                Ok(match env.mode {
+                    FixedMode(_) => Value::from_f64(result, env.mode),
+                    _ => Real(result),
+                })
+            },
+        }
+    }
+
+    // `rearrange("F = m*a", "a")` gives the expression `a = F / m`
+    fn apply_rearrange(args: Vec<Value>) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("rearrange", args.len()));
+
+        let (equation, variable) = match (args.get(0), args.get(1)) {
+            (&Text(ref equation), &Text(ref variable)) => (equation, variable),
+            (&Text(_), other) | (other, _) => return Err(TypeMismatch {
+                expected: "text",
+                found: other.kind_name(),
+            }),
+        };
+
+        let tree = try!(ExprTree::build(equation.as_slice()));
+        let root = match tree.root() {
+            Some(root) => root,
+            None => return Err(EmptyExpression),
+        };
+        let solved = try!(algebra::rearrange(root, variable.as_slice()));
+        Ok(Expression(box solved))
+    }
+
+    // `derive(x^2 + sin(x), x)` gives the expression `2 * x + cos(x)`;
+    // through a function value the expression comes as text or an
+    // expression value, and the variable as text
+    fn apply_derive(args: Vec<Value>) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("derive", args.len()));
+
+        let variable = match *args.get(1) {
+            Text(ref variable) => variable.clone(),
+            ref other => return Err(TypeMismatch {
+                expected: "text",
+                found: other.kind_name(),
+            }),
+        };
+
+        let expression = try!(ExprTree::expression_argument(args.get(0)));
+        let derivative = try!(calculus::derive(&expression, variable.as_slice()));
+        Ok(Expression(box derivative))
+    }
+
+    // `simplify(2*3 + x*1)` gives the expression `6 + x`
+    fn apply_simplify(args: Vec<Value>) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("simplify", args.len()));
+
+        let expression = try!(ExprTree::expression_argument(args.get(0)));
+        Ok(Expression(box simplify::simplify(&expression)))
+    }
+
+    // an argument standing for an expression, given as an expression value
+    // or as text to parse
+    fn expression_argument(arg: &Value) -> Result<ExprNode, CalcError> {
+        match *arg {
+            Expression(ref node) => Ok((**node).clone()),
+            Text(ref text) => match try!(ExprTree::build(text.as_slice())).root {
+                Some(root) => Ok(*root),
+                None => Err(EmptyExpression),
+            },
+            ref other => Err(TypeMismatch {
+                expected: "expression",
+                found: other.kind_name(),
+            }),
+        }
+    }
+
+    // `rate(1.5e9, "1m30s")` gives the quantity `16.666667 mb/s`, or
+    // without units a number of bytes a second; the duration is seconds, or
+    // text with units
+    fn apply_rate(args: Vec<Value>) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("rate", args.len()));
+
+        let bytes = try!(args.get(0).number());
+        let seconds = match *args.get(1) {
+            Text(ref text) => match duration::parse_duration(text.as_slice()) {
+                Some(seconds) => seconds,
+                None => return Err(MalformedDuration(text.clone())),
+            },
+            ref other => try!(other.number()),
+        };
+        if !(seconds > 0_f64) {
+            return Err(OutOfDomain {
+                function: String::from_str("rate"),
+                argument: seconds,
+                expected: "durations above zero",
+            });
+        }
+
+        let rate = bytes / seconds;
+        Ok(match units::data_rate(rate) {
+            Some(measure) => Quantity(measure),
+            None => Real(rate),
+        })
+    }
+
+    // `goalseek("x^2 + 1", "x", 10, 2)` gives the x near 2 where x^2 + 1
+    // is 10
+    fn apply_goalseek(args: Vec<Value>, env: &mut Environment) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("goalseek", args.len()));
+
+        let (expression, variable) = match (args.get(0), args.get(1)) {
+            (&Text(ref expression), &Text(ref variable)) => (expression, variable),
+            (&Text(_), other) | (other, _) => return Err(TypeMismatch {
+                expected: "text",
+                found: other.kind_name(),
+            }),
+        };
+        let target = try!(args.get(2).number());
+        let guess = try!(args.get(3).number());
+
+        let tree = try!(ExprTree::build(expression.as_slice()));
+        let root = match tree.root() {
+            Some(root) => root,
+            None => return Err(EmptyExpression),
+        };
+        let x = try!(solver::goalseek(root, variable.as_slice(), target, guess, env));
+        Ok(Value::from_f64(x, env.mode))
+    }
+
+    // `integrate` or `product` through a function value, with the
+    // expression as text or an expression value and the variable as text
+    fn apply_binding(name: &str, args: Vec<Value>, env: &mut Environment) -> Result<Value, CalcError> {
+        try!(builtins::check_arity(name, args.len()));
+
+        let variable = match *args.get(1) {
+            Text(ref variable) => variable.clone(),
+            ref other => return Err(TypeMismatch {
+                expected: "text",
+                found: other.kind_name(),
+            }),
+        };
+        let body = try!(ExprTree::expression_argument(args.get(0)));
+        let from = try!(args.get(2).number());
+        let to = try!(args.get(3).number());
+        ExprTree::eval_binding(name, &body, variable.as_slice(), from, to, env)
+    }
+
+    // works `body` out with `variable` bound to each value in turn, then
+    // puts back whatever the variable held before
+    fn eval_binding(name: &str, body: &ExprNode, variable: &str, from: f64, to: f64,
+                    env: &mut Environment) -> Result<Value, CalcError> {
+        let original = env.get(variable);
+        let result = if name == "integrate" {
+            ExprTree::eval_integral(body, variable, from, to, env)
+        } else {
+            ExprTree::eval_series(name, body, variable, from, to, env)
+        };
+        match original {
+            Some(value) => env.set(variable, value),
+            None => env.unset(variable),
+        }
+        result
+    }
+
+    // `integrate(x^2, x, 0, 3)` gives 9, by adaptive quadrature in doubles
+    // whatever the number mode
+    fn eval_integral(body: &ExprNode, variable: &str, from: f64, to: f64,
+                     env: &mut Environment) -> Result<Value, CalcError> {
+        for &bound in [from, to].iter() {
+            if !bound.is_finite() {
+                return Err(OutOfDomain {
+                    function: String::from_str("integrate"),
+                    argument: bound,
+                    expected: "finite bounds",
+                });
+            }
+        }
+
+        let area = try!(calculus::integrate(|x| {
+            env.set(variable, Real(x));
+            ExprTree::eval_node(body, env).and_then(|v| v.number())
+        }, from, to));
+        Ok(Real(area))
+    }
+
+    // `sum(k^2, k, 1, 3)` gives 14 and `product(k, k, 1, 5)` gives 120,
+    // in the current number mode; an empty range gives 0 or 1
+    fn eval_series(name: &str, body: &ExprNode, variable: &str, from: f64, to: f64,
+                   env: &mut Environment) -> Result<Value, CalcError> {
+        for &bound in [from, to].iter() {
+            if !bound.is_finite() || bound.fract() != 0_f64 {
+                return Err(OutOfDomain {
+                    function: String::from_str(name),
+                    argument: bound,
+                    expected: "whole-number bounds",
+                });
+            }
+        }
+
+        let (operator, identity) = if name == "sum" { ("+", 0_f64) } else { ("*", 1_f64) };
+        let mode = env.mode;
+        let mut total = Value::from_f64(identity, mode);
+        let mut k = from;
+        let mut steps = 0u;
+        while k <= to {
+            steps += 1;
+            if steps > env.max_iterations {
+                return Err(LimitExceeded {
+                    limit: Iterations,
+                    max: env.max_iterations,
+                });
+            }
+            env.set(variable, Value::from_f64(k, mode));
+            let term = try!(ExprTree::eval_node(body, env));
+            total = try!(Value::binary(operator, &total, &term));
+            k += 1_f64;
+        }
+        Ok(total)
+    }
+
+    // `solve(2*x + 3 = 11, x)` gives the expression `x = 4`, and
+    // `solve(x^2 = 2, x, -10, 10)` a list of every root between -10 and 10;
+    // through a function value the equation comes as text or an expression
+    // value, and the variable as text
+    fn apply_solve(args: Vec<Value>, env: &mut Environment) -> Result<Value, CalcError> {
+        try!(builtins::check_arity("solve", args.len()));
+        // bounds come as a pair
+        if args.len() == 3 {
+            return Err(WrongArity {
+                function: String::from_str("solve"),
+                expected: Exactly(4),
+                found: 3,
+            });
+        }
+
+        let variable = match *args.get(1) {
+            Text(ref variable) => variable.clone(),
+            ref other => return Err(TypeMismatch {
+                expected: "text",
+                found: other.kind_name(),
+            }),
+        };
+        let bounds = if args.len() == 4 {
+            let (lo, hi) = (try!(args.get(2).number()), try!(args.get(3).number()));
+            Some((lo.min(hi), lo.max(hi)))
+        } else {
+            None
+        };
+
+        let equation = try!(ExprTree::expression_argument(args.get(0)));
+        let mut solutions = try!(solver::solve(&equation, variable.as_slice(), bounds, env));
+        if solutions.len() == 1 {
+            Ok(Expression(box solutions.pop().unwrap()))
+        } else {
+            Ok(List(solutions.move_iter().map(|s| Expression(box s)).collect()))
+        }
+    }
+
+    // fill the open arguments of a function value; with some still open
+    // the result is another function value
+    fn apply_partial(partial: &Partial, args: Vec<Value>, named: Vec<(String, Value)>,
+                     env: &mut Environment) -> Result<Value, CalcError> {
+        let filled = partial.fill(args);
+        if filled.iter().any(|a| a.is_none()) {
+            return Ok(Function(Partial { name: partial.name.clone(), args: filled }));
+        }
+
+        // `f = f` would otherwise resolve forever
+        if env.depth >= env.max_call_depth {
+            return Err(LimitExceeded {
+                limit: CallDepth,
+                max: env.max_call_depth,
+            });
+        }
+
+        env.depth += 1;
+        let args = filled.move_iter().map(|a| a.unwrap()).collect();
+        let result = ExprTree::apply(&partial.name, args, named, env);
+        env.depth -= 1;
+        result
+    }
+
+    fn apply_value(function: &Value, args: Vec<Value>,
+                   env: &mut Environment) -> Result<Value, CalcError> {
+        match *function {
+            Function(ref partial) => ExprTree::apply_partial(partial, args, vec![], env),
+            ref other => Err(TypeMismatch {
+                expected: "function",
+                found: other.kind_name(),
+            }),
+        }
+    }
+
+    // `map(f, ...)` applies f to each value, `reduce(f, ...)` folds them
+    // from the left; lists among the values are spread out
+    fn apply_higher_order(name: &String, args: Vec<Value>,
+                          env: &mut Environment) -> Result<Value, CalcError> {
+        try!(builtins::check_arity(name.as_slice(), args.len()));
+
+        let mut args = args.move_iter();
+        let function = args.next().unwrap();
+        let mut items: Vec<Value> = vec![];
+        for arg in args {
+            match arg {
+                List(list) => items.push_all_move(list),
+                other => items.push(other),
+            }
+        }
+
+        if name.as_slice() == "map" {
+            let mut results: Vec<Value> = vec![];
+            for item in items.move_iter() {
+                results.push(try!(ExprTree::apply_value(&function, vec![item], env)));
+            }
+            return Ok(List(results));
+        }
+
+        let mut items = items.move_iter();
+        let mut total = match items.next() {
+            Some(first) => first,
+            None => return Err(WrongArity {
+                function: name.clone(),
+                expected: AtLeast(2),
+                found: 1,
+            }),
+        };
+        for item in items {
+            total = try!(ExprTree::apply_value(&function, vec![total, item], env));
+        }
+        Ok(total)
+    }
+
+    fn eval_assign(node: &ExprNode, memo: bool,
+                   env: &mut Environment) -> Result<Value, CalcError> {
+        let target = node.children.get(0);
+        let value = node.children.get(1);
+
+        match target.kind {
+            ExprVariable => {
+                let result = try!(ExprTree::eval_node(value, env));
+                env.set(target.token.as_slice(), result.clone());
+                Ok(result)
+            },
+            ExprCall => {
+                let mut params: Vec<String> = vec![];
+                let mut defaults: Vec<Option<ExprNode>> = vec![];
+
+                // parameters are plain names, optionally followed by
+                // `= default`; once one has a default the rest need one too
+                for param in target.children.iter() {
+                    match param.kind {
+                        ExprVariable if defaults.iter().all(|d| d.is_none()) => {
+                            params.push(param.token.clone());
+                            defaults.push(None);
+                        },
+                        ExprBinary if param.token.as_slice() == "=" &&
+                                      param.children.get(0).kind == ExprVariable => {
+                            params.push(param.children.get(0).token.clone());
+                            defaults.push(Some(param.children.get(1).clone()));
+                        },
+                        _ => return Err(InvalidAssignment),
+                    }
+                }
+
+                env.define(target.token.as_slice(), UserFunction {
+                    params: params,
+                    defaults: defaults,
+                    body: box value.clone(),
+                    memo: memo,
+                });
+                Ok(Real(0_f64))
+            },
+            _ => Err(InvalidAssignment),
+        }
+    }
+
+    // match positional and `name=value` arguments to parameter slots;
+    // slots left empty fall back to the parameter's default
+    fn bind_arguments(name: &String, function: &UserFunction, args: Vec<Value>,
+                      named: Vec<(String, Value)>) -> Result<Vec<Option<Value>>, CalcError> {
+        if args.len() > function.params.len() {
+            return Err(WrongArity {
+                function: name.clone(),
+                expected: function.arity(),
+                found: args.len() + named.len(),
+            });
+        }
+
+        let mut slots: Vec<Option<Value>> = Vec::from_elem(function.params.len(), None);
+        for (i, arg) in args.move_iter().enumerate() {
+            *slots.get_mut(i) = Some(arg);
+        }
+
+        for (param, value) in named.move_iter() {
+            let index = match function.params.iter().position(|p| *p == param) {
+                Some(index) => index,
+                None => return Err(UnknownParameter {
+                    function: name.clone(),
+                    name: param.clone(),
+                }),
+            };
+            if slots.get(index).is_some() {
+                return Err(DuplicateArgument {
+                    function: name.clone(),
+                    name: param.clone(),
+                });
+            }
+            *slots.get_mut(index) = Some(value);
+        }
+
+        for (i, slot) in slots.iter().enumerate() {
+            if slot.is_none() && function.defaults.get(i).is_none() {
+                return Err(MissingArgument {
+                    function: name.clone(),
+                    name: function.params.get(i).clone(),
+                });
+            }
+        }
+
+        Ok(slots)
+    }
+
+    // evaluate a call's arguments, split into positional and `name=value`
+    fn eval_arguments(node: &ExprNode, env: &mut Environment)
+                      -> Result<(Vec<Value>, Vec<(String, Value)>), CalcError> {
+        let mut args: Vec<Value> = vec![];
+        let mut named: Vec<(String, Value)> = vec![];
+
+        for child in node.children.iter() {
+            if child.is_named_argument() {
+                let value = try!(ExprTree::eval_node(child.children.get(1), env));
+                named.push((child.children.get(0).token.clone(), value));
+            } else {
+                args.push(try!(ExprTree::eval_node(child, env)));
+            }
+        }
+
+        Ok((args, named))
+    }
+
+    // bind the arguments over the parameters for the duration of the body,
+    // then put back whatever the caller had under those names. A user
+    // function call in tail position replaces the current call instead of
+    // nesting inside it, so tail recursion runs at constant depth.
+    fn eval_call(name: &String, function: &UserFunction, slots: Vec<Option<Value>>,
+                 env: &mut Environment) -> Result<Value, CalcError> {
+
+        if env.depth >= env.max_call_depth {
+            return Err(LimitExceeded {
+                limit: CallDepth,
+                max: env.max_call_depth,
+            });
+        }
+
+        let mut name = name.clone();
+        let mut function = function.clone();
+        let mut slots = slots;
+        let mut iterations = 0u;
+        let result;
+        env.depth += 1;
+
+        loop {
+            let (saved, bound) = ExprTree::bind_params(&function, &slots, env);
+            let step = bound.and_then(|bound| {
+                ExprTree::eval_body(&name, &function, bound, env)
+            });
+            ExprTree::restore_params(&function, saved, env);
+
+            match step {
+                Ok(TailCall(next_name, next_function, next_slots)) => {
+                    iterations += 1;
+                    if iterations >= env.max_iterations {
+                        result = Err(LimitExceeded {
+                            limit: Iterations,
+                            max: env.max_iterations,
+                        });
+                        break;
+                    }
+                    name = next_name;
+                    function = next_function;
+                    slots = next_slots;
+                },
+                Ok(Done(value)) => {
+                    result = Ok(value);
+                    break;
+                },
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                },
+            }
+        }
+
+        env.depth -= 1;
+        result
+    }
+
+    // omitted arguments take their defaults, evaluated with the earlier
+    // parameters already bound; returns the caller's bindings for
+    // `restore_params` along with the bound values
+    fn bind_params(function: &UserFunction, slots: &Vec<Option<Value>>,
+                   env: &mut Environment)
+                   -> (Vec<Option<Value>>, Result<Vec<Value>, CalcError>) {
+        let mut saved: Vec<Option<Value>> = vec![];
+        let mut bound: Vec<Value> = vec![];
+
+        for (i, param) in function.params.iter().enumerate() {
+            let value = match *slots.get(i) {
+                Some(ref v) => Ok(v.clone()),
+                None => ExprTree::eval_node(function.defaults.get(i).get_ref(), env),
+            };
+
+            saved.push(env.get(param.as_slice()));
+            match value {
+                Ok(v) => {
+                    env.set(param.as_slice(), v.clone());
+                    bound.push(v);
+                },
+                Err(e) => return (saved, Err(e)),
+            }
+        }
+
+        (saved, Ok(bound))
+    }
+
+    fn restore_params(function: &UserFunction, mut saved: Vec<Option<Value>>,
+                      env: &mut Environment) {
+        for i in range(0, saved.len()).rev() {
+            let param = function.params.get(i).as_slice();
+            match saved.pop().unwrap() {
+                Some(value) => env.set(param, value),
+                None => env.unset(param),
+            }
+        }
+    }
+
+    // evaluate a function body with its parameters bound, stopping short of
+    // a tail call so the caller can make it without nesting
+    fn eval_body(name: &String, function: &UserFunction, bound: Vec<Value>,
+                 env: &mut Environment) -> Result<CallStep, CalcError> {
+        // a memoized result is only known once the call returns, so those
+        // bodies are always evaluated in full
+        if function.memo {
+            match env.memoized(name.as_slice(), bound.as_slice()) {
+                Some(value) => return Ok(Done(value)),
+                None => {},
+            }
+            let value = try!(ExprTree::eval_node(&*function.body, env));
+            env.memoize(name.as_slice(), bound.as_slice(), value.clone());
+            return Ok(Done(value));
+        }
+
+        // the tail position runs through whichever `if` branches are taken
+        let mut body = &*function.body;
+        while body.is_conditional() {
+            body = try!(ExprTree::select_branch(body, env));
+        }
+
+        let callee = match body.kind {
+            ExprCall if !body.children.iter().any(|c| c.is_placeholder()) =>
+                env.function(body.token.as_slice()),
+            _ => None,
+        };
+
+        match callee {
+            Some(callee) => {
+                let (args, named) = try!(ExprTree::eval_arguments(body, env));
+                let slots = try!(ExprTree::bind_arguments(&body.token, &callee,
+                                                          args, named));
+                Ok(TailCall(body.token.clone(), callee, slots))
+            },
+            None => Ok(Done(try!(ExprTree::eval_node(body, env)))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use env::Environment;
+    use value::{DecimalMode, ExactMode, IntegerMode};
+    use super::{ExprTree, ParseOptions, Infix, Rpn, Prefix, Indented};
+
+    // `expression` evaluated in `env` and printed as the calculator prints
+    // results, or the error it gave
+    fn show(expression: &str, env: &mut Environment) -> String {
+        match ExprTree::build(expression).and_then(|tree| tree.eval_in(env)) {
+            Ok(value) => format!("{}", value),
+            Err(error) => format!("error: {}", error),
+        }
+    }
+
+    #[test]
+    fn placeholders_leave_arguments_open() {
+        let mut env = Environment::new();
+        show("f(x, y) = x - y", &mut env);
+        assert_eq!(show("g = f(10, _)", &mut env), String::from_str("f(10, _)"));
+        assert_eq!(show("g(3)", &mut env), String::from_str("7"));
+        assert_eq!(show("map(f(_, 1), 1, 2, 3)", &mut env),
+                   String::from_str("[0, 1, 2]"));
+        assert_eq!(show("double = mul(2, _)", &mut env), String::from_str("mul(2, _)"));
+        assert_eq!(show("double(21)", &mut env), String::from_str("42"));
+    }
+
+    #[test]
+    fn exact_mode_keeps_fractions() {
+        let mut env = Environment::new();
+        env.mode = ExactMode;
+        assert_eq!(show("1/3 + 1/6", &mut env), String::from_str("1/2"));
+        assert_eq!(show("0.1 + 0.2", &mut env), String::from_str("3/10"));
+        assert_eq!(show("(2/3)^2", &mut env), String::from_str("4/9"));
+        // irrational results fall back to doubles
+        assert_eq!(show("sqrt(2)", &mut env), format!("{}", 2_f64.sqrt()));
+    }
+
+    #[test]
+    fn constants_keep_their_places_in_decimal_mode() {
+        let mut env = Environment::new();
+        env.mode = DecimalMode;
+        assert_eq!(show("pi", &mut env),
+                   String::from_str("3.1415926535897932384626433832795"));
+        assert_eq!(show("e", &mut env),
+                   String::from_str("2.71828182845904523536028747135266"));
+    }
+
+    #[test]
+    fn i_is_the_imaginary_unit() {
+        let mut env = Environment::new();
+        assert_eq!(show("(2+3i)*(1-i)", &mut env), String::from_str("5 + i"));
+        assert_eq!(show("i*i", &mut env), String::from_str("-1"));
+        assert_eq!(show("sqrt(-4)", &mut env), String::from_str("2i"));
+        assert_eq!(show("(1+i) - (1+i)", &mut env), String::from_str("0"));
+    }
+
+    #[test]
+    fn names_may_continue_with_capitals() {
+        let mut env = Environment::new();
+        assert_eq!(show("nCr(5, 2)", &mut env), String::from_str("10"));
+        assert_eq!(show("nPr(5, 2)", &mut env), String::from_str("20"));
+    }
+
+    #[test]
+    fn pipes_pass_the_left_value_first() {
+        let mut env = Environment::new();
+        assert_eq!(show("-2.6 |> abs |> round", &mut env), String::from_str("3"));
+        assert_eq!(show("2 |> pow(3)", &mut env), String::from_str("8"));
+        assert_eq!(show("3 |> pow(2, _)", &mut env), String::from_str("8"));
+        // everything else binds tighter than a pipe
+        assert_eq!(show("1 + 15 |> sqrt", &mut env), String::from_str("4"));
+    }
+
+    #[test]
+    fn integer_mode_has_bitwise_operators() {
+        let mut env = Environment::new();
+        env.mode = IntegerMode(64);
+        assert_eq!(show("6 | 9", &mut env), String::from_str("15"));
+        assert_eq!(show("7 & 3", &mut env), String::from_str("3"));
+        assert_eq!(show("5 xor 3", &mut env), String::from_str("6"));
+        assert_eq!(show("1 << 4", &mut env), String::from_str("16"));
+        assert_eq!(show("256 >> 4", &mut env), String::from_str("16"));
+        assert_eq!(show("7 / 2", &mut env), String::from_str("3"));
+        // `//` and `%` round toward minus infinity
+        assert_eq!(show("-7 // 2", &mut env), String::from_str("-4"));
+        assert_eq!(show("-7 % 3", &mut env), String::from_str("2"));
+        assert_eq!(show("7 / 0", &mut env), String::from_str("error: division by zero"));
+    }
+
+    #[test]
+    fn narrow_words_shift_bits_out() {
+        let mut env = Environment::new();
+        env.mode = IntegerMode(8);
+        assert_eq!(show("1 << 7", &mut env), String::from_str("-128"));
+        assert_eq!(show("3 << 7", &mut env), String::from_str("-128"));
+        assert_eq!(show("0x7f << 60", &mut env), String::from_str("0"));
+        assert_eq!(show("1 << 8", &mut env), String::from_str("0"));
+        assert_eq!(show("1 << 70", &mut env), String::from_str("0"));
+        // a full word still reports bits lost off the top
+        env.mode = IntegerMode(64);
+        assert_eq!(show("1 << 70", &mut env), String::from_str("error: integer overflow"));
+    }
+
+    #[test]
+    fn built_ins_may_follow_their_operand() {
+        let mut options = ParseOptions::new();
+        options.postfix = true;
+        let value = |expression: &str| {
+            let tree = ExprTree::build_with(expression, &options).unwrap();
+            format!("{}", tree.eval().unwrap())
+        };
+        assert_eq!(value("30 deg sin"), format!("{}", 30_f64.to_radians().sin()));
+        assert_eq!(value("9 sqrt + 1"), String::from_str("4"));
+        assert_eq!(value("2 (3 + 6) sqrt"), String::from_str("6"));
+
+        // and only with the option
+        assert!(ExprTree::build("30 deg sin").and_then(|tree| tree.eval()).is_err());
+    }
+
+    #[test]
+    fn comparisons_give_one_or_zero() {
+        let mut env = Environment::new();
+        assert_eq!(show("3^2 > 2^3", &mut env), String::from_str("1"));
+        assert_eq!(show("2 <= 1", &mut env), String::from_str("0"));
+        assert_eq!(show("2 >= 2", &mut env), String::from_str("1"));
+        assert_eq!(show("2 != 1", &mut env), String::from_str("1"));
+        // arithmetic binds tighter than comparison
+        assert_eq!(show("1 + 1 == 2", &mut env), String::from_str("1"));
+        assert_eq!(show("(1 < 2) + (3 > 4)", &mut env), String::from_str("1"));
+    }
+
+    #[test]
+    fn only_the_branch_taken_is_evaluated() {
+        let mut env = Environment::new();
+        assert_eq!(show("if(1 > 2, 1/0, 5)", &mut env), String::from_str("5"));
+        assert_eq!(show("if(1, 2, undefined)", &mut env), String::from_str("2"));
+        show("f(n) = if(n <= 1, 1, n * f(n - 1))", &mut env);
+        assert_eq!(show("f(5)", &mut env), String::from_str("120"));
+    }
+
+    #[test]
+    fn goal_seek_finds_the_root_near_the_guess() {
+        let mut env = Environment::new();
+        assert_eq!(show("goalseek(\"x^2 + 1\", \"x\", 10, 2)", &mut env), String::from_str("3"));
+        assert_eq!(show("goalseek(\"x^2 + 1\", \"x\", 10, -2)", &mut env), String::from_str("-3"));
+        assert_eq!(show("goalseek(\"x^2 + 1\", \"x\", 0, 2)", &mut env),
+                   String::from_str("error: no value of 'x' near the guess reaches the target"));
+    }
+
+    #[test]
+    #[cfg(feature = "units")]
+    fn quantities_convert_between_units() {
+        let mut env = Environment::new();
+        assert_eq!(show("5 km to m", &mut env), String::from_str("5000 m"));
+        assert_eq!(show("3 m + 50 cm", &mut env), String::from_str("3.5 m"));
+        // units that cancel leave a plain number
+        assert_eq!(show("2 km / 500 m", &mut env), String::from_str("4"));
+        assert!(show("5 km to s", &mut env).as_slice().starts_with("error: "));
+    }
+
+    #[test]
+    #[cfg(feature = "units")]
+    fn rates_are_quantities() {
+        let mut env = Environment::new();
+        assert_eq!(show("rate(1.5e9, \"1m30s\")", &mut env), String::from_str("16.666667 mb/s"));
+        assert_eq!(show("rate(1e9, \"1m\") to MB/s", &mut env),
+                   String::from_str("16.666667 MB/s"));
+        assert_eq!(show("rate(12500, 1) to kb/s", &mut env), String::from_str("12.5 kb/s"));
+    }
+
+    #[test]
+    fn rates_need_time_to_pass() {
+        let mut env = Environment::new();
+        for expression in ["rate(10, 0)", "rate(10, -1)", "rate(10, \"0s\")"].iter() {
+            assert!(show(*expression, &mut env).as_slice().starts_with("error: "));
+        }
+    }
+
+    #[test]
+    fn dependencies_leave_out_bound_names() {
+        let deps = ExprTree::build("f(x) = x + a * sin(y) + sum(k, k, 1, n)").unwrap().dependencies();
+        let variables: Vec<&str> = deps.variables.iter().map(|name| name.as_slice()).collect();
+        let functions: Vec<&str> = deps.functions.iter().map(|name| name.as_slice()).collect();
+        assert_eq!(variables, vec!["a", "n", "y"]);
+        assert_eq!(functions, vec!["sin", "sum"]);
+    }
+
+    #[test]
+    fn trees_format_in_each_style() {
+        let tree = ExprTree::build("1 + 2*x").unwrap();
+        assert_eq!(tree.format(Infix), String::from_str("1 + 2 * x"));
+        assert_eq!(tree.format(Rpn), String::from_str("1 2 x * +"));
+        assert_eq!(tree.format(Prefix), String::from_str("(+ 1 (* 2 x))"));
+
+        // a call's argument count is spelled out unless it is one
+        let tree = ExprTree::build("-max(a, 3) + sqrt(b)").unwrap();
+        assert_eq!(tree.format(Rpn), String::from_str("a 3 max/2 neg b sqrt +"));
+        assert_eq!(tree.format(Indented).as_slice(), "+\n  neg\n    max\n      a\n      3\n  \
+                                                      sqrt\n    b\n");
+    }
+
+    #[test]
+    fn aggregates_spread_their_lists() {
+        let mut env = Environment::new();
+        assert_eq!(show("xs = [4, 1, 3]", &mut env), String::from_str("[4, 1, 3]"));
+        assert_eq!(show("prod(xs, 2)", &mut env), String::from_str("24"));
+        assert_eq!(show("max(xs) - min(xs)", &mut env), String::from_str("3"));
+        assert_eq!(show("mean(xs, [2, 0])", &mut env), String::from_str("2"));
+        assert!(show("sum([])", &mut env).as_slice().starts_with("error: "));
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn median_and_stddev_read_lists() {
+        let mut env = Environment::new();
+        assert_eq!(show("median([5, 1, 4, 2])", &mut env), String::from_str("3"));
+        assert_eq!(show("stddev([2, 4, 6])", &mut env), String::from_str("2"));
+    }
+
+    #[test]
+    fn radix_built_ins_give_text() {
+        let mut env = Environment::new();
+        assert_eq!(show("to_hex(255)", &mut env), String::from_str("\"0xff\""));
+        assert_eq!(show("to_bin(-5)", &mut env), String::from_str("\"-0b101\""));
+        assert_eq!(show("to_oct(0x40)", &mut env), String::from_str("\"0o100\""));
+        assert!(show("to_hex(1.5)", &mut env).as_slice().starts_with("error: "));
+    }
+
+    #[test]
+    fn bound_variables_are_put_back() {
+        let mut env = Environment::new();
+        show("x = 5", &mut env);
+        assert_eq!(show("integrate(x^2, x, 0, 3)", &mut env), String::from_str("9"));
+        assert_eq!(show("x", &mut env), String::from_str("5"));
+        assert_eq!(show("sum(k^2, k, 1, 3)", &mut env), String::from_str("14"));
+        assert_eq!(show("product(k, k, 1, 5)", &mut env), String::from_str("120"));
+        assert!(env.get("k").is_none());
+        // an empty range gives the identity
+        assert_eq!(show("sum(k, k, 1, 0)", &mut env), String::from_str("0"));
+        assert!(show("sum(k, k, 1, 2.5)", &mut env).as_slice().starts_with("error: "));
+        env.mode = ExactMode;
+        assert_eq!(show("sum(1/k, k, 1, 3)", &mut env), String::from_str("11/6"));
+    }
+
+    #[test]
+    fn words_follow_the_structure() {
+        let words = |expression: &str| ExprTree::build(expression).unwrap().to_words();
+        assert_eq!(words("(a + b) / 2"),
+                   String::from_str("the quotient of the sum of a and b, and two"));
+        assert_eq!(words("a + b / 2"),
+                   String::from_str("the sum of a and the quotient of b and two"));
+        assert_eq!(words("sqrt(x) < 2"),
+                   String::from_str("whether the square root of x is less than two"));
+        assert_eq!(ExprTree::new(None).to_words(), String::new());
+    }
+}
@@ -0,0 +1,137 @@
+use std::f64;
+use std::string::String;
+
+/// The fixed payment that pays off `principal` in `periods` payments with
+/// interest of `rate` per period, as in a spreadsheet's `PMT`.
+pub fn pmt(rate: f64, periods: f64, principal: f64) -> f64 {
+    if rate == 0_f64 {
+        principal / periods
+    } else {
+        principal * rate / (1_f64 - (1_f64 + rate).powf(-periods))
+    }
+}
+
+/// One payment of an amortization schedule.
+pub struct Period {
+    pub number: uint,
+    pub payment: f64,
+    pub interest: f64,
+    /// The part of the payment that goes to the principal.
+    pub principal: f64,
+    /// What is still owed after the payment.
+    pub balance: f64,
+}
+
+/// The schedule for a fixed-rate loan of `principal` at `annual_rate`
+/// (0.045 for 4.5%) paid `per_year` times a year for `years` years. The
+/// last payment is trimmed so the balance ends at exactly zero.
+pub fn amortize(principal: f64, annual_rate: f64, years: uint, per_year: uint) -> Vec<Period> {
+    let periods = years * per_year;
+    let rate = annual_rate / per_year as f64;
+    let payment = pmt(rate, periods as f64, principal);
+
+    let mut balance = principal;
+    let mut schedule = Vec::with_capacity(periods);
+    for number in range(1, periods + 1) {
+        let interest = balance * rate;
+        let mut paid = payment - interest;
+        if number == periods {
+            paid = balance;
+        }
+        balance -= paid;
+
+        schedule.push(Period {
+            number: number,
+            payment: paid + interest,
+            interest: interest,
+            principal: paid,
+            balance: balance,
+        });
+    }
+    schedule
+}
+
+/// Lays the schedule out as a table with a header line, to the cent.
+pub fn schedule_table(schedule: &[Period]) -> String {
+    let mut table = format!("{:>6} {:>14} {:>14} {:>14} {:>16}\n",
+                            "period", "payment", "interest", "principal", "balance");
+    for period in schedule.iter() {
+        table.push_str(format!("{:>6} {:>14.2} {:>14.2} {:>14.2} {:>16.2}\n",
+                               period.number, period.payment, period.interest,
+                               period.principal, period.balance).as_slice());
+    }
+    table
+}
+
+/// The schedule as comma-separated values with a header row.
+pub fn schedule_csv(schedule: &[Period]) -> String {
+    let mut csv = String::from_str("period,payment,interest,principal,balance\n");
+    for period in schedule.iter() {
+        csv.push_str(format!("{},{:.2},{:.2},{:.2},{:.2}\n",
+                             period.number, period.payment, period.interest,
+                             period.principal, period.balance).as_slice());
+    }
+    csv
+}
+
+/// The amount due on `amount` under tiered rates, as in a progressive tax
+/// or volume pricing. `tiers` holds pairs of an upper limit and the rate
+/// for the part of `amount` between the previous limit and that one; a
+/// lone rate at the end applies to everything above the last limit, which
+/// is otherwise not charged. Limits that do not increase add nothing.
+pub fn tiered(amount: f64, tiers: &[f64]) -> f64 {
+    let mut total = 0_f64;
+    let mut lower = 0_f64;
+
+    for tier in tiers.chunks(2) {
+        let (limit, rate) = if tier.len() == 2 {
+            (tier[0], tier[1])
+        } else {
+            (f64::INFINITY, tier[0])
+        };
+
+        if amount > lower && limit > lower {
+            total += (amount.min(limit) - lower) * rate;
+        }
+        lower = lower.max(limit);
+    }
+    total
+}
+
+#[cfg(test)]
+mod test {
+    use super::{amortize, pmt, schedule_csv, tiered};
+
+    #[test]
+    fn schedules_pay_the_loan_off() {
+        let schedule = amortize(1000_f64, 0.12, 1, 12);
+        assert_eq!(schedule.len(), 12);
+        assert!((schedule.get(0).payment - pmt(0.01, 12_f64, 1000_f64)).abs() < 1e-9);
+        assert!((schedule.get(0).interest - 10_f64).abs() < 1e-9);
+        assert_eq!(schedule.last().unwrap().balance, 0_f64);
+
+        let repaid = schedule.iter().fold(0_f64, |total, p| total + p.principal);
+        assert!((repaid - 1000_f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_line_per_period() {
+        let csv = schedule_csv(amortize(1200_f64, 0_f64, 1, 12).as_slice());
+        let lines: Vec<&str> = csv.as_slice().lines().collect();
+        assert_eq!(lines.len(), 13);
+        assert_eq!(*lines.get(0), "period,payment,interest,principal,balance");
+        assert_eq!(*lines.get(1), "1,100.00,0.00,100.00,1100.00");
+    }
+
+    #[test]
+    fn each_tier_charges_its_own_part() {
+        let tiers = [10000_f64, 0.1, 40000_f64, 0.2];
+        assert!((tiered(5000_f64, tiers.as_slice()) - 500_f64).abs() < 1e-9);
+        assert!((tiered(25000_f64, tiers.as_slice()) - 4000_f64).abs() < 1e-9);
+        // above the last limit nothing is charged without a lone rate
+        assert!((tiered(50000_f64, tiers.as_slice()) - 7000_f64).abs() < 1e-9);
+
+        let tiers = [10000_f64, 0.1, 40000_f64, 0.2, 0.3];
+        assert!((tiered(50000_f64, tiers.as_slice()) - 10000_f64).abs() < 1e-9);
+    }
+}
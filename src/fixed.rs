@@ -0,0 +1,202 @@
+use std::fmt;
+
+use error::{CalcError, DivisionByZero, UnknownOperator};
+
+/// What happens to a result too large for the format.
+#[deriving(Show, Clone, PartialEq)]
+pub enum Overflow {
+    /// Clamps to the largest or smallest value, as saturating DSP
+    /// instructions do.
+    Saturate,
+    /// Keeps the low bits, as plain integer arithmetic on an MCU does.
+    Wrap,
+}
+
+/// A signed Q format: a sign bit, `integer_bits` above the binary point and
+/// `fraction_bits` below it, so Q15.16 is a 32-bit word. Formats are capped
+/// at 32 bits so a product fits in 64 before it is shifted back down.
+#[deriving(Show, Clone, PartialEq)]
+pub struct QFormat {
+    pub integer_bits: uint,
+    pub fraction_bits: uint,
+    pub overflow: Overflow,
+}
+
+impl QFormat {
+    pub fn new(integer_bits: uint, fraction_bits: uint, overflow: Overflow) -> Option<QFormat> {
+        if 1 + integer_bits + fraction_bits > 32 {
+            return None;
+        }
+        Some(QFormat {
+            integer_bits: integer_bits,
+            fraction_bits: fraction_bits,
+            overflow: overflow,
+        })
+    }
+
+    /// Reads a format written `M.N` or `qM.N`, such as `q15.16` or `1.14`.
+    pub fn parse(text: &str, overflow: Overflow) -> Option<QFormat> {
+        let text = text.trim_left_chars(|c: char| c == 'q' || c == 'Q');
+        let parts: Vec<&str> = text.splitn('.', 1).collect();
+        if parts.len() != 2 {
+            return None;
+        }
+        match (from_str::<uint>(*parts.get(0)), from_str::<uint>(*parts.get(1))) {
+            (Some(m), Some(n)) => QFormat::new(m, n, overflow),
+            _ => None,
+        }
+    }
+
+    fn bits(&self) -> uint {
+        1 + self.integer_bits + self.fraction_bits
+    }
+
+    fn max_raw(&self) -> i64 {
+        (1i64 << (self.bits() - 1)) - 1
+    }
+
+    fn min_raw(&self) -> i64 {
+        -(1i64 << (self.bits() - 1))
+    }
+
+    fn scale(&self) -> f64 {
+        (1i64 << self.fraction_bits) as f64
+    }
+
+    // bring a raw result back into range
+    fn fit(&self, raw: i64) -> i64 {
+        if raw >= self.min_raw() && raw <= self.max_raw() {
+            return raw;
+        }
+        match self.overflow {
+            Saturate => if raw < 0 { self.min_raw() } else { self.max_raw() },
+            Wrap => {
+                let unused = 64 - self.bits();
+                (raw << unused) >> unused
+            },
+        }
+    }
+}
+
+/// A number as a Q-format word holds it: `raw / 2^fraction_bits`.
+#[deriving(Clone, PartialEq)]
+pub struct FixedPoint {
+    pub raw: i64,
+    pub format: QFormat,
+}
+
+impl FixedPoint {
+    /// The nearest value the format can hold, as a firmware constant
+    /// would be quantized.
+    pub fn from_f64(value: f64, format: QFormat) -> FixedPoint {
+        let scaled = (value * format.scale()).round();
+        let raw = if scaled.is_nan() {
+            0
+        } else if scaled > format.max_raw() as f64 || scaled < format.min_raw() as f64 {
+            match format.overflow {
+                Saturate => if scaled < 0_f64 { format.min_raw() } else { format.max_raw() },
+                // only what a 64-bit word can hold has low bits to keep
+                Wrap if scaled.abs() < 9.2e18 => format.fit(scaled as i64),
+                Wrap => 0,
+            }
+        } else {
+            scaled as i64
+        };
+        FixedPoint { raw: raw, format: format }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.raw as f64 / self.format.scale()
+    }
+
+    pub fn neg(&self) -> FixedPoint {
+        self.with_raw(-self.raw)
+    }
+
+    /// Applies `operator` the way integer firmware would: products and
+    /// quotients are rescaled with an arithmetic shift, which rounds toward
+    /// negative infinity, and every result is saturated or wrapped to the
+    /// format. Bitwise operators and shifts act on the raw words.
+    pub fn binary(&self, operator: &str, other: &FixedPoint) -> Result<FixedPoint, CalcError> {
+        let (a, b) = (self.raw, other.raw);
+        let n = self.format.fraction_bits;
+        Ok(match operator {
+            "+" => self.with_raw(a + b),
+            "-" => self.with_raw(a - b),
+            "*" => self.with_raw((a * b) >> n),
+            "/" if b == 0 => return Err(DivisionByZero),
+            "/" => self.with_raw((a << n) / b),
+            "&" => self.with_raw(a & b),
+            "|" => self.with_raw(a | b),
+            "xor" => self.with_raw(a ^ b),
+            "<<"|">>" => {
+                let places = other.to_f64();
+                if places < 0_f64 || places >= 64_f64 || places.fract() != 0_f64 {
+                    return Err(UnknownOperator(String::from_str(operator)));
+                }
+                let places = places as uint;
+                if operator == "<<" {
+                    // past the word, every bit is gone
+                    self.with_raw(if places >= 32 { 0 } else { a << places })
+                } else {
+                    self.with_raw(a >> places)
+                }
+            },
+            // whole powers are repeated products; anything else goes
+            // through doubles
+            "^" if other.to_f64() >= 0_f64 && other.to_f64().fract() == 0_f64 &&
+                   other.to_f64() <= 64_f64 => {
+                let mut result = FixedPoint::from_f64(1_f64, self.format);
+                for _ in range(0, other.to_f64() as uint) {
+                    result = try!(result.binary("*", self));
+                }
+                result
+            },
+            _ => return Err(UnknownOperator(String::from_str(operator))),
+        })
+    }
+
+    fn with_raw(&self, raw: i64) -> FixedPoint {
+        FixedPoint { raw: self.format.fit(raw), format: self.format }
+    }
+}
+
+impl fmt::Show for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_f64())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{QFormat, FixedPoint, Overflow, Saturate, Wrap};
+
+    // `a operator b` in signed Q1.6, an 8-bit word
+    fn q16(a: f64, operator: &str, b: f64, overflow: Overflow) -> f64 {
+        let format = QFormat::new(1, 6, overflow).unwrap();
+        let (a, b) = (FixedPoint::from_f64(a, format), FixedPoint::from_f64(b, format));
+        a.binary(operator, &b).unwrap().to_f64()
+    }
+
+    #[test]
+    fn formats_fit_in_32_bits() {
+        assert_eq!(QFormat::parse("q15.16", Saturate), QFormat::new(15, 16, Saturate));
+        assert_eq!(QFormat::parse("1.14", Wrap), QFormat::new(1, 14, Wrap));
+        assert!(QFormat::parse("q16.16", Saturate).is_none());
+        assert!(QFormat::parse("q15", Saturate).is_none());
+    }
+
+    #[test]
+    fn results_saturate_or_wrap() {
+        assert_eq!(q16(0.5, "*", 0.5, Saturate), 0.25);
+        assert_eq!(q16(1.5, "+", 1.5, Saturate), 127_f64 / 64_f64);
+        assert_eq!(q16(1.5, "+", 1.5, Wrap), -1_f64);
+        assert_eq!(q16(-1_f64, "-", 1_f64, Saturate), -2_f64);
+        // constants are quantized to the nearest step
+        assert_eq!(FixedPoint::from_f64(0.1, QFormat::new(1, 6, Saturate).unwrap()).raw, 6);
+
+        let one = FixedPoint::from_f64(1_f64, QFormat::new(1, 6, Saturate).unwrap());
+        let zero = FixedPoint::from_f64(0_f64, one.format);
+        assert!(one.binary("/", &zero).is_err());
+    }
+}
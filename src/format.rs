@@ -0,0 +1,403 @@
+use std::cmp::min;
+use std::num::{Zero, Signed};
+
+use num::Integer;
+use num::bigint::{BigInt, ToBigInt};
+use num::rational::BigRational;
+
+// SI prefixes from yocto to yotta, indexed by (exponent / 3) + 8; micro
+// is `u`, since results should read back in and the lexer takes no `µ`
+pub static SI_PREFIXES: [&'static str, ..17] = [
+    "y", "z", "a", "f", "p", "n", "u", "m", "",
+    "k", "M", "G", "T", "P", "E", "Z", "Y",
+];
+
+/// Formats `value` in engineering notation using an SI prefix, so
+/// `0.0000047` prints as `4.7 u` and `12500` as `12.5 k`.
+pub fn engineering(value: f64) -> String {
+    if value == 0_f64 || value.is_nan() || value.is_infinite() {
+        return format!("{}", value);
+    }
+
+    let (mantissa, prefix) = scaled(value, 12);
+    if prefix.is_empty() {
+        format!("{}", mantissa)
+    } else {
+        format!("{} {}", mantissa, prefix)
+    }
+}
+
+/// How `Formatter` writes the exponent of a number, if at all.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Notation {
+    /// Digits only, as in `12500`.
+    PlainNotation,
+    /// One digit before the point and a power of ten, as in `1.25e4`.
+    ScientificNotation,
+    /// A power of a thousand as an SI prefix, as in `12.5 k`.
+    EngineeringNotation,
+}
+
+/// How results are printed, kept apart from how they are worked out.
+/// Significant digits and fixed places are alternatives, so setting one
+/// clears the other; with neither a number is printed as short as it
+/// reads back exactly.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Formatter {
+    pub notation: Notation,
+    /// Significant digits, at least 1.
+    pub digits: Option<uint>,
+    /// Places after the decimal point.
+    pub places: Option<uint>,
+    /// Whether the whole part of plain numbers is grouped by thousands,
+    /// as in `1,234,567.5`.
+    pub separators: bool,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter { notation: PlainNotation, digits: None, places: None, separators: false }
+    }
+
+    pub fn set_digits(&mut self, digits: uint) {
+        self.digits = Some(if digits == 0 { 1 } else { digits });
+        self.places = None;
+    }
+
+    pub fn set_places(&mut self, places: uint) {
+        self.places = Some(places);
+        self.digits = None;
+    }
+
+    /// Changes one setting as written after `set format` in the REPL:
+    /// `digits N`, `fixed N`, `plain`, `sci`, `eng`, `separators on|off` or
+    /// `reset`. The error says what was expected.
+    pub fn configure(&mut self, setting: &str) -> Result<(), String> {
+        let words: Vec<&str> = setting.words().collect();
+        let count = words.as_slice().get(1).and_then(|n| from_str::<uint>(*n));
+        match (words.as_slice().get(0).map(|w| *w), words.len()) {
+            (Some("digits"), 2) if count.is_some() => self.set_digits(count.unwrap()),
+            (Some("fixed"), 2) if count.is_some() => self.set_places(count.unwrap()),
+            (Some("plain"), 1) => self.notation = PlainNotation,
+            (Some("sci"), 1) => self.notation = ScientificNotation,
+            (Some("eng"), 1) => self.notation = EngineeringNotation,
+            (Some("separators"), 2) if *words.get(1) == "on" => self.separators = true,
+            (Some("separators"), 2) if *words.get(1) == "off" => self.separators = false,
+            (Some("reset"), 1) => *self = Formatter::new(),
+            _ => return Err(format!("'{}' is not a format setting; try digits N, fixed N, \
+                                     plain, sci, eng, separators on|off or reset", setting)),
+        }
+        Ok(())
+    }
+
+    /// `value` printed with these settings.
+    pub fn number(&self, value: f64) -> String {
+        if value.is_nan() || value.is_infinite() {
+            return format!("{}", value);
+        }
+
+        match self.notation {
+            EngineeringNotation if value != 0_f64 => {
+                let (mantissa, prefix) = scaled(value, self.digits.unwrap_or(12) as i32);
+                let mantissa = self.fraction(mantissa);
+                if prefix.is_empty() { mantissa } else { format!("{} {}", mantissa, prefix) }
+            },
+            ScientificNotation => {
+                let mut exponent = if value == 0_f64 { 0 } else { value.abs().log10().floor() as i32 };
+                let digits = self.digits.unwrap_or(15) as i32;
+                let mut mantissa = round_significant(value / 10_f64.powi(exponent), digits);
+                // rounding 9.99 up to two digits carries into the exponent
+                if mantissa.abs() >= 10_f64 {
+                    mantissa /= 10_f64;
+                    exponent += 1;
+                }
+                format!("{}e{}", self.fraction(mantissa), exponent)
+            },
+            _ => {
+                let text = match self.digits {
+                    Some(digits) => format!("{}", round_significant(value, digits as i32)),
+                    None => self.fraction(value),
+                };
+                if self.separators { group_thousands(text.as_slice()) } else { text }
+            },
+        }
+    }
+
+    // a number already rounded to its significant digits, to the fixed
+    // places if there are any
+    fn fraction(&self, value: f64) -> String {
+        match self.places {
+            Some(places) => format!("{:.1$}", value, places),
+            None => format!("{}", value),
+        }
+    }
+}
+
+// commas between each group of three digits in the whole part of `text`
+fn group_thousands(text: &str) -> String {
+    let (sign, rest) = if text.starts_with("-") { ("-", text.slice_from(1)) } else { ("", text) };
+    let (whole, fraction) = match rest.find('.') {
+        Some(i) => (rest.slice_to(i), rest.slice_from(i)),
+        None => (rest, ""),
+    };
+
+    let mut grouped = String::from_str(sign);
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push_char(',');
+        }
+        grouped.push_char(c);
+    }
+    grouped.push_str(fraction);
+    grouped
+}
+
+/// Writes `n` in base `base`, 2 to 36, with the prefix literals are read
+/// back with for bases 2, 8 and 16, so `radix(255, 16)` gives `0xff` and
+/// `radix(-5, 2)` gives `-0b101`.
+pub fn radix(n: i64, base: uint) -> String {
+    // the magnitude is worked unsigned so the most negative i64 survives
+    let magnitude = if n < 0 { (-(n + 1)) as u64 + 1 } else { n as u64 };
+    format!("{}{}{}", if n < 0 { "-" } else { "" }, radix_prefix(base), digits(magnitude, base))
+}
+
+/// Writes the low `bits` bits of `n` in base `base` as an unsigned word,
+/// which is two's complement for negative numbers, padded with zeros to
+/// the whole word in bases 2, 8 and 16: `word_radix(-1, 8, 16)` gives
+/// `0xff` and `word_radix(5, 8, 2)` gives `0b00000101`.
+pub fn word_radix(n: i64, bits: uint, base: uint) -> String {
+    let pattern = if bits >= 64 { n as u64 } else { n as u64 & ((1u64 << bits) - 1) };
+    let width = match base {
+        2 => bits,
+        8 => (bits + 2) / 3,
+        16 => (bits + 3) / 4,
+        _ => 0,
+    };
+    let digits = digits(pattern, base);
+    let zeros = String::from_char(width - min(width, digits.len()), '0');
+    format!("{}{}{}", radix_prefix(base), zeros, digits)
+}
+
+fn radix_prefix(base: uint) -> &'static str {
+    match base {
+        2 => "0b",
+        8 => "0o",
+        16 => "0x",
+        _ => "",
+    }
+}
+
+// the digits of `n` in base `base`, 2 to 36
+fn digits(mut n: u64, base: uint) -> String {
+    static DIGITS: &'static str = "0123456789abcdefghijklmnopqrstuvwxyz";
+
+    let mut digits: Vec<char> = vec![];
+    loop {
+        digits.push(DIGITS.char_at((n % base as u64) as uint));
+        n /= base as u64;
+        if n == 0 {
+            break;
+        }
+    }
+    digits.iter().rev().map(|&c| c).collect()
+}
+
+/// Digits worked out for the repeating part before `repeating` gives up
+/// and ends with `...`.
+pub static MAX_PERIOD: uint = 1000;
+
+/// Writes `r` as a decimal with the digits that repeat in parentheses, so
+/// 1/3 is `0.(3)`, 1/6 is `0.1(6)` and 1/4 is `0.25`.
+pub fn repeating(r: &BigRational) -> String {
+    let ten: BigInt = 10u.to_bigint().unwrap();
+    let denom = r.denom().abs();
+    let (whole, mut remainder) = r.numer().abs().div_rem(&denom);
+    let sign = if r.numer().is_negative() { "-" } else { "" };
+
+    // a remainder met before starts the same digits over again
+    let mut seen: Vec<BigInt> = vec![];
+    let mut fraction = String::new();
+    while !remainder.is_zero() {
+        match seen.iter().position(|earlier| *earlier == remainder) {
+            Some(start) => return format!("{}{}.{}({})", sign, whole,
+                                          fraction.as_slice().slice_to(start),
+                                          fraction.as_slice().slice_from(start)),
+            None if seen.len() == MAX_PERIOD => {
+                return format!("{}{}.{}...", sign, whole, fraction);
+            },
+            None => {},
+        }
+        seen.push(remainder.clone());
+        let (digit, rest) = (remainder * ten).div_rem(&denom);
+        fraction.push_str(format!("{}", digit).as_slice());
+        remainder = rest;
+    }
+
+    if fraction.is_empty() {
+        format!("{}{}", sign, whole)
+    } else {
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// `n` in decimal, hex, octal and binary, one base to a line with the
+/// digits lined up on the right, as programmer calculators show integers:
+///
+/// ```text
+/// dec         255
+/// hex        0xff
+/// oct       0o377
+/// bin  0b11111111
+/// ```
+///
+/// With a word width in `bits`, hex, octal and binary show the word's two's
+/// complement, as `word_radix` does.
+pub fn bases(n: i64, bits: Option<uint>) -> String {
+    let other = |base: uint| match bits {
+        Some(bits) => word_radix(n, bits, base),
+        None => radix(n, base),
+    };
+    let rows = [("dec", radix(n, 10)), ("hex", other(16)), ("oct", other(8)), ("bin", other(2))];
+    let width = rows.iter().map(|&(_, ref digits)| digits.len()).max().unwrap();
+    let lines: Vec<String> = rows.iter()
+        .map(|&(name, ref digits)| format!("{}  {:>2$}", name, digits, width))
+        .collect();
+    lines.connect("\n")
+}
+
+/// `value` as a whole number, when it is one that fits in an i64.
+pub fn whole(value: f64) -> Option<i64> {
+    if value.is_finite() && value == value.trunc() &&
+       value >= -9223372036854775808_f64 && value < 9223372036854775808_f64 {
+        Some(value as i64)
+    } else {
+        None
+    }
+}
+
+// split `value` into a mantissa rounded to `digits` significant digits and
+// the SI prefix for its power of a thousand
+fn scaled(value: f64, digits: i32) -> (f64, &'static str) {
+    let exponent = (value.abs().log10() / 3_f64).floor() as int * 3;
+    let exponent = if exponent < -24 {
+        -24
+    } else if exponent > 24 {
+        24
+    } else {
+        exponent
+    };
+
+    let mantissa = round_significant(value / 10_f64.powi(exponent as i32), digits);
+    (mantissa, SI_PREFIXES[(exponent / 3 + 8) as uint])
+}
+
+// drop the representation noise left over from scaling by a power of ten
+fn round_significant(value: f64, digits: i32) -> f64 {
+    if value == 0_f64 {
+        return value;
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let scale = 10_f64.powi(digits - 1 - magnitude);
+    (value * scale).round() / scale
+}
+
+#[cfg(test)]
+mod test {
+    use std::i64;
+
+    use num::bigint::ToBigInt;
+    use num::rational::Ratio;
+
+    use super::{Formatter, ScientificNotation, EngineeringNotation};
+    use super::{bases, engineering, radix, repeating, word_radix};
+
+    // `repeating` of n/d
+    fn decimal(n: int, d: int) -> String {
+        repeating(&Ratio::new(n.to_bigint().unwrap(), d.to_bigint().unwrap()))
+    }
+
+    #[test]
+    fn bases_line_up_on_the_right() {
+        assert_eq!(bases(255, None).as_slice(), "dec         255\n\
+                                           hex        0xff\n\
+                                           oct       0o377\n\
+                                           bin  0b11111111");
+        assert_eq!(bases(-5, None).as_slice(), "dec      -5\n\
+                                          hex    -0x5\n\
+                                          oct    -0o5\n\
+                                          bin  -0b101");
+    }
+
+    #[test]
+    fn radixes_carry_their_prefix() {
+        assert_eq!(radix(255, 16).as_slice(), "0xff");
+        assert_eq!(radix(-5, 2).as_slice(), "-0b101");
+        assert_eq!(radix(8, 8).as_slice(), "0o10");
+        assert_eq!(radix(0, 16).as_slice(), "0x0");
+        assert_eq!(radix(35, 36).as_slice(), "z");
+        assert_eq!(radix(i64::MIN, 16).as_slice(), "-0x8000000000000000");
+    }
+
+    #[test]
+    fn prefixes_are_plain_letters() {
+        assert_eq!(engineering(0.0000047).as_slice(), "4.7 u");
+        assert_eq!(engineering(12500_f64).as_slice(), "12.5 k");
+    }
+
+    #[test]
+    fn formatters_round_and_group() {
+        let mut formatter = Formatter::new();
+        formatter.separators = true;
+        assert_eq!(formatter.number(1234567.5).as_slice(), "1,234,567.5");
+        formatter.set_places(2);
+        assert_eq!(formatter.number(-1234.5).as_slice(), "-1,234.50");
+        formatter.set_digits(3);
+        assert_eq!(formatter.places, None);
+        assert_eq!(formatter.number(3.14159).as_slice(), "3.14");
+
+        formatter.notation = ScientificNotation;
+        assert_eq!(formatter.number(12500_f64).as_slice(), "1.25e4");
+        formatter.set_digits(2);
+        assert_eq!(formatter.number(9.99).as_slice(), "1e1");
+        formatter.notation = EngineeringNotation;
+        assert_eq!(formatter.number(12500_f64).as_slice(), "13 k");
+    }
+
+    #[test]
+    fn format_settings_are_read_from_words() {
+        let mut formatter = Formatter::new();
+        assert!(formatter.configure("fixed 3").is_ok());
+        assert!(formatter.configure("separators on").is_ok());
+        assert!(formatter.configure("eng").is_ok());
+        assert_eq!(formatter.places, Some(3));
+        assert!(formatter.separators);
+        assert_eq!(formatter.notation, EngineeringNotation);
+
+        assert!(formatter.configure("digits many").is_err());
+        assert!(formatter.configure("separators").is_err());
+        assert!(formatter.configure("reset").is_ok());
+        assert_eq!(formatter, Formatter::new());
+    }
+
+    #[test]
+    fn words_show_twos_complement() {
+        assert_eq!(word_radix(-1, 8, 16).as_slice(), "0xff");
+        assert_eq!(word_radix(5, 8, 2).as_slice(), "0b00000101");
+        assert_eq!(word_radix(-128, 8, 8).as_slice(), "0o200");
+        assert_eq!(word_radix(-1, 64, 16).as_slice(), "0xffffffffffffffff");
+        assert_eq!(bases(-1, Some(16)).as_slice(), "dec                  -1\n\
+                                                    hex              0xffff\n\
+                                                    oct            0o177777\n\
+                                                    bin  0b1111111111111111");
+    }
+
+    #[test]
+    fn repeating_digits_are_bracketed() {
+        assert_eq!(decimal(1, 3).as_slice(), "0.(3)");
+        assert_eq!(decimal(1, 6).as_slice(), "0.1(6)");
+        assert_eq!(decimal(-22, 7).as_slice(), "-3.(142857)");
+        assert_eq!(decimal(1, 4).as_slice(), "0.25");
+        assert_eq!(decimal(-1, 3).as_slice(), "-0.(3)");
+        assert_eq!(decimal(6, 3).as_slice(), "2");
+    }
+}
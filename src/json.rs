@@ -0,0 +1,170 @@
+use std::collections::TreeMap;
+use std::string::String;
+
+use serialize::json;
+use serialize::json::{Json, ToJson};
+
+use error::{CalcError, MalformedAst};
+use exprtree::{ExprNode, NodeKind, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall};
+use exprtree::{ExprText, ExprUnit};
+
+/// Writes a tree as `{"memo": false, "root": NODE}`, where each node is
+/// `{"kind": ..., "token": ..., "children": [...]}` and an empty tree's
+/// root is `null`.
+pub fn tree_to_json(root: Option<&ExprNode>, memo: bool) -> String {
+    let mut object = TreeMap::new();
+    object.insert(String::from_str("memo"), memo.to_json());
+    object.insert(String::from_str("root"), match root {
+        Some(node) => node.to_json(),
+        None => json::Null,
+    });
+    format!("{}", object.to_json())
+}
+
+/// Reads what `tree_to_json` writes, checking each node has a known kind
+/// and the number of children that kind takes.
+pub fn tree_from_json(text: &str) -> Result<(Option<ExprNode>, bool), CalcError> {
+    let document = match json::from_str(text) {
+        Ok(document) => document,
+        Err(e) => return Err(MalformedAst(format!("{}", e))),
+    };
+
+    let memo = match document.find(&String::from_str("memo")) {
+        Some(memo) => match memo.as_boolean() {
+            Some(memo) => memo,
+            None => return Err(malformed("'memo' is not true or false")),
+        },
+        None => false,
+    };
+    let root = match document.find(&String::from_str("root")) {
+        Some(root) if root.is_null() => None,
+        Some(root) => Some(try!(node_from_json(root))),
+        None => return Err(malformed("there is no 'root'")),
+    };
+    Ok((root, memo))
+}
+
+impl ToJson for ExprNode {
+    fn to_json(&self) -> Json {
+        let mut object = TreeMap::new();
+        object.insert(String::from_str("kind"), String::from_str(kind_name(self.kind())).to_json());
+        object.insert(String::from_str("token"), String::from_str(self.token()).to_json());
+        object.insert(String::from_str("children"), self.children().to_json());
+        object.to_json()
+    }
+}
+
+fn node_from_json(node: &Json) -> Result<ExprNode, CalcError> {
+    let field = |name: &str| node.find(&String::from_str(name));
+
+    let kind = match field("kind").and_then(|k| k.as_string()) {
+        Some(name) => match kind_of(name) {
+            Some(kind) => kind,
+            None => return Err(malformed(format!("unknown kind '{}'", name))),
+        },
+        None => return Err(malformed("a node has no 'kind'")),
+    };
+    let token = match field("token").and_then(|t| t.as_string()) {
+        Some(token) => token,
+        None => return Err(malformed("a node has no 'token'")),
+    };
+
+    let mut children: Vec<ExprNode> = vec![];
+    match field("children") {
+        Some(list) => match list.as_list() {
+            Some(items) => for item in items.iter() {
+                children.push(try!(node_from_json(item)));
+            },
+            None => return Err(malformed("'children' is not a list")),
+        },
+        None => {},
+    }
+
+    let expected = match kind {
+        ExprLiteral | ExprVariable | ExprText => Some(0),
+        ExprUnary | ExprUnit => Some(1),
+        ExprBinary => Some(2),
+        ExprCall => None,
+    };
+    match expected {
+        Some(count) if count != children.len() =>
+            return Err(malformed(format!("a {} node has {} children, not {}",
+                                         kind_name(kind), children.len(), count))),
+        _ => {},
+    }
+
+    let node = ExprNode::new(token, kind, children);
+    if kind == ExprLiteral && node.value().is_none() {
+        return Err(malformed(format!("the literal '{}' is not a number", token)));
+    }
+    Ok(node)
+}
+
+fn kind_name(kind: NodeKind) -> &'static str {
+    match kind {
+        ExprLiteral => "literal",
+        ExprVariable => "variable",
+        ExprUnary => "unary",
+        ExprBinary => "binary",
+        ExprCall => "call",
+        ExprText => "text",
+        ExprUnit => "unit",
+    }
+}
+
+fn kind_of(name: &str) -> Option<NodeKind> {
+    match name {
+        "literal" => Some(ExprLiteral),
+        "variable" => Some(ExprVariable),
+        "unary" => Some(ExprUnary),
+        "binary" => Some(ExprBinary),
+        "call" => Some(ExprCall),
+        "text" => Some(ExprText),
+        "unit" => Some(ExprUnit),
+        _ => None,
+    }
+}
+
+fn malformed<S: Str>(problem: S) -> CalcError {
+    MalformedAst(String::from_str(problem.as_slice()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use error::MalformedAst;
+    use exprtree::{ExprTree, Prefix};
+
+    #[test]
+    fn trees_survive_a_round_trip() {
+        assert_eq!(ExprTree::build("x").unwrap().to_json(),
+                   String::from_str("{\"memo\":false,\"root\":\
+                                     {\"children\":[],\"kind\":\"variable\",\"token\":\"x\"}}"));
+
+        for expression in ["1 + 2*x", "-max(a, 3) / sqrt(b)", "f(x) = x^2", "\"text\""].iter() {
+            let tree = ExprTree::build(*expression).unwrap();
+            let read = ExprTree::from_json(tree.to_json().as_slice()).unwrap();
+            assert_eq!(read.format(Prefix), tree.format(Prefix));
+        }
+    }
+
+    #[test]
+    fn nodes_need_their_children() {
+        let text = "{\"root\": {\"kind\": \"binary\", \"token\": \"+\", \"children\": []}}";
+        match ExprTree::from_json(text) {
+            Err(MalformedAst(problem)) =>
+                assert_eq!(problem, String::from_str("a binary node has 0 children, not 2")),
+            _ => fail!("a childless operator was read"),
+        }
+        assert!(ExprTree::from_json("{\"memo\": false}").is_err());
+
+        let text = "{\"root\": {\"kind\": \"literal\", \"token\": \"abc\"}}";
+        match ExprTree::from_json(text) {
+            Err(MalformedAst(problem)) =>
+                assert_eq!(problem, String::from_str("the literal 'abc' is not a number")),
+            _ => fail!("a literal that is not a number was read"),
+        }
+        assert!(ExprTree::from_json("1 +").is_err());
+    }
+}
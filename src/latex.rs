@@ -0,0 +1,202 @@
+use std::string::String;
+
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+
+/// Renders `node` as LaTeX math with only the parentheses precedence
+/// needs. Division becomes a fraction, `^` a superscript and `sqrt` a
+/// root, so `(a+b)^2 / sqrt(2)` gives `\frac{(a+b)^2}{\sqrt{2}}`.
+pub fn latex(node: &ExprNode) -> String {
+    let children = node.children();
+    match node.kind() {
+        ExprLiteral => literal(node.token()),
+        ExprVariable => name(node.token()),
+        ExprText => format!("\\text{{{}}}", escape(node.token())),
+        ExprUnit => format!("{}\\,\\mathrm{{{}}}", base(children.get(0)), node.token()),
+        ExprUnary => format!("-{}", operand(children.get(0), node.precedence().unwrap(), false)),
+        ExprBinary => {
+            let (lhs, rhs) = (children.get(0), children.get(1));
+            match node.token() {
+                "/" => format!("\\frac{{{}}}{{{}}}", latex(lhs), latex(rhs)),
+                "//" => format!("\\left\\lfloor\\frac{{{}}}{{{}}}\\right\\rfloor",
+                                latex(lhs), latex(rhs)),
+                "^" => format!("{}^{}", base(lhs), braced(latex(rhs))),
+                operator => {
+                    let precedence = node.precedence().unwrap();
+                    let right_assoc = node.is_right_assoc();
+                    format!("{}{}{}", operand(lhs, precedence, right_assoc),
+                            operator_symbol(operator),
+                            operand(rhs, precedence, !right_assoc))
+                },
+            }
+        },
+        ExprCall => {
+            let args: Vec<String> = children.iter().map(|child| latex(child)).collect();
+            let joined = args.as_slice().connect(", ");
+            match (node.token(), args.len()) {
+                ("list", _) => format!("\\left[{}\\right]", joined),
+                ("sqrt", 1) => format!("\\sqrt{{{}}}", joined),
+                ("cbrt", 1) => format!("\\sqrt[3]{{{}}}", joined),
+                ("abs", 1) => format!("\\left|{}\\right|", joined),
+                ("floor", 1) => format!("\\left\\lfloor {}\\right\\rfloor", joined),
+                ("ceil", 1) => format!("\\left\\lceil {}\\right\\rceil", joined),
+                ("exp", 1) => format!("e^{}", braced(joined)),
+                ("fact", 1) => format!("{}!", base(children.get(0))),
+                ("lg", 1) => format!("\\log_2({})", joined),
+                ("log", 1) => format!("\\log_{{10}}({})", joined),
+                ("log", 2) => format!("\\log_{}({})", braced(args.get(1).clone()),
+                                      args.get(0)),
+                (function, _) => format!("{}({})", function_name(function), joined),
+            }
+        },
+    }
+}
+
+// an operand, in parentheses as precedence needs; fractions and floors are
+// already set apart
+fn operand(child: &ExprNode, precedence: i32, tie_needs_parens: bool) -> String {
+    let delimited = child.kind() == ExprBinary &&
+        (child.token() == "/" || child.token() == "//");
+    if !delimited && child.needs_parens(precedence, tie_needs_parens) {
+        format!("({})", latex(child))
+    } else {
+        latex(child)
+    }
+}
+
+// the base of a power, factorial or unit, which any operator must be kept
+// apart from
+fn base(child: &ExprNode) -> String {
+    if child.precedence().is_some() {
+        format!("({})", latex(child))
+    } else {
+        latex(child)
+    }
+}
+
+// a superscript of more than one character goes in braces
+fn braced(text: String) -> String {
+    if text.as_slice().char_len() == 1 {
+        text
+    } else {
+        format!("{{{}}}", text)
+    }
+}
+
+fn literal(token: &str) -> String {
+    String::from_str(match token {
+        "pi" => "\\pi",
+        "tau" => "\\tau",
+        "phi" => "\\phi",
+        "euler_gamma" => "\\gamma",
+        "sqrt2" => "\\sqrt{2}",
+        "ln2" => "\\ln 2",
+        "ln10" => "\\ln 10",
+        other => other,
+    })
+}
+
+static GREEK: [&'static str, ..20] = [
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "kappa", "lambda",
+    "mu", "nu", "xi", "rho", "sigma", "omega", "upsilon", "chi", "psi", "omicron",
+];
+
+// single letters are italic as usual, a trailing number is a subscript and
+// longer names are set upright
+fn name(token: &str) -> String {
+    if GREEK.iter().any(|&letter| letter == token) {
+        return format!("\\{}", token);
+    }
+
+    let letters = token.find(|c: char| c.is_digit()).unwrap_or(token.len());
+    let (stem, digits) = (token.slice_to(letters), token.slice_from(letters));
+    let stem = if stem.char_len() == 1 {
+        String::from_str(stem)
+    } else {
+        format!("\\mathrm{{{}}}", escape(stem))
+    };
+    if !digits.is_empty() && digits.chars().all(|c| c.is_digit()) {
+        format!("{}_{}", stem, braced(String::from_str(digits)))
+    } else {
+        format!("{}{}", stem, escape(digits))
+    }
+}
+
+fn function_name(function: &str) -> String {
+    match function {
+        "sin"|"cos"|"tan"|"csc"|"sec"|"cot"|"sinh"|"cosh"|"tanh" |
+        "ln"|"max"|"min"|"gcd" => format!("\\{}", function),
+        "asin" => String::from_str("\\arcsin"),
+        "acos" => String::from_str("\\arccos"),
+        "atan" => String::from_str("\\arctan"),
+        _ if function.char_len() == 1 => String::from_str(function),
+        _ => format!("\\operatorname{{{}}}", escape(function)),
+    }
+}
+
+fn operator_symbol(operator: &str) -> String {
+    String::from_str(match operator {
+        "+" => "+",
+        "-" => "-",
+        "*" => " \\cdot ",
+        "%" => " \\bmod ",
+        "<" => "<",
+        ">" => ">",
+        "<=" => " \\le ",
+        ">=" => " \\ge ",
+        "=="|"=" => "=",
+        "!=" => " \\ne ",
+        "&" => " \\mathbin{\\&} ",
+        "|" => " \\mathbin{|} ",
+        "xor" => " \\oplus ",
+        "<<" => " \\ll ",
+        ">>" => " \\gg ",
+        "|>" => " \\triangleright ",
+        "to"|"in" => " \\to ",
+        other => return format!(" \\mathbin{{{}}} ", escape(other)),
+    })
+}
+
+// characters LaTeX treats specially
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{'|'}'|'_'|'&'|'%'|'$'|'#' => {
+                escaped.push_char('\\');
+                escaped.push_char(c);
+            },
+            '^' => escaped.push_str("\\^{}"),
+            '~' => escaped.push_str("\\~{}"),
+            _ => escaped.push_char(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::{ExprTree, Latex};
+
+    fn latex(expression: &str) -> String {
+        ExprTree::build(expression).unwrap().format(Latex)
+    }
+
+    #[test]
+    fn only_needed_parentheses_are_kept() {
+        assert_eq!(latex("(a+b)^2 / sqrt(2)").as_slice(), "\\frac{(a+b)^2}{\\sqrt{2}}");
+        assert_eq!(latex("a - (b - c)").as_slice(), "a-(b-c)");
+        assert_eq!(latex("(a - b) - c").as_slice(), "a-b-c");
+        assert_eq!(latex("2 * (x + y)").as_slice(), "2 \\cdot (x+y)");
+    }
+
+    #[test]
+    fn names_are_typeset() {
+        assert_eq!(latex("x12 * alpha").as_slice(), "x_{12} \\cdot \\alpha");
+        assert_eq!(latex("sin(theta) + speed").as_slice(), "\\sin(\\theta)+\\mathrm{speed}");
+        assert_eq!(latex("log(8, 2)").as_slice(), "\\log_2(8)");
+    }
+}
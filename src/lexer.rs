@@ -0,0 +1,459 @@
+use std::str;
+use std::str::Chars;
+use std::string::String;
+
+use error::{CalcError, LimitExceeded, InputLength, TokenCount, NestingDepth};
+use error::{MalformedNumber, UnterminatedText, UnexpectedCharacter};
+use exprtree::{Token, TokenType, ParseOptions, NEGATION_PRECEDENCE};
+use exprtree::{operator_precedence, parse_number};
+use exprtree::{Numeric, Alphabetical, Functional, PostfixFunction, Variable, Operator};
+use exprtree::{UnitSuffix, UnaryMinus, LeftParen, RightParen, Comma, Quoted, Invalid};
+use units;
+
+// operators spelled with two characters; the first character alone is
+// also an operator
+static COMPOUND_OPERATORS: [&'static str, ..8] = [
+    "|>", "<<", ">>", "//", "<=", ">=", "==", "!=",
+];
+
+// identifiers start with a letter or underscore and may continue with
+// digits, e.g. `sqrt2`, `euler_gamma`, `nCr`, `F` or the placeholder `_`
+fn continues_identifier(c: char) -> bool {
+    c.is_digit() || TokenType::of_char(c) == Alphabetical
+}
+
+/// Splits an expression into tokens in a single pass, yielding each one as
+/// it is read. Positions and lengths are byte offsets into the input, so
+/// `input.slice(position, position + length)` is always the text a token
+/// came from, multi-byte characters such as `π` or `µ` included. Stops
+/// after the first error, which includes going over any of the `Limits` in
+/// the options.
+pub struct Lexer<'a> {
+    chars: Chars<'a>,
+    options: &'a ParseOptions,
+    /// Byte offset of the next character.
+    position: uint,
+    /// Type of the last token yielded, which decides how a `-`, a unit
+    /// name or a postfix built-in is read.
+    previous: Option<TokenType>,
+    /// Yielded before anything else is read: the `(` after the `list` a
+    /// `[` stands for, or an input that is too long.
+    queued: Option<Result<Token, CalcError>>,
+    depth: uint,
+    count: uint,
+    finished: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(expression: &'a str, options: &'a ParseOptions) -> Lexer<'a> {
+        let max = options.limits.max_input_len;
+        let queued = if expression.len() > max {
+            Some(Err(LimitExceeded { limit: InputLength, max: max }))
+        } else {
+            None
+        };
+
+        Lexer {
+            chars: expression.chars(),
+            options: options,
+            position: 0,
+            previous: None,
+            queued: queued,
+            depth: 0,
+            count: 0,
+            finished: false,
+        }
+    }
+
+    // looking ahead copies the iterator, which only holds a slice
+    fn peek_nth(&self, n: uint) -> Option<char> {
+        self.chars.clone().nth(n)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_nth(0)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        match c {
+            Some(c) => self.position += c.len_utf8_bytes(),
+            None => {},
+        }
+        c
+    }
+
+    fn take_while(&mut self, text: &mut String, accept: |char| -> bool) {
+        loop {
+            match self.peek() {
+                Some(c) if accept(c) => {
+                    text.push_char(c);
+                    self.bump();
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn after_operand(&self) -> bool {
+        match self.previous {
+            Some(Numeric) | Some(Variable) | Some(RightParen)
+                | Some(PostfixFunction) | Some(UnitSuffix) => true,
+            _ => false,
+        }
+    }
+
+    // the next token, or `None` at the end of the input
+    fn scan(&mut self) -> Option<Result<Token, CalcError>> {
+        loop {
+            let start = self.position;
+            let mut c = match self.bump() {
+                Some(c) => c,
+                None => return None,
+            };
+
+            // characters outside the alphabet may stand in for an operator
+            // or a whole name, e.g. `×` or `π`
+            if TokenType::of_char(c) == Invalid {
+                let symbol = self.options.resolve_alias(str::from_char(c));
+                if symbol.as_slice().char_len() == 1 {
+                    c = symbol.as_slice().char_at(0);
+                } else if symbol.len() > 1 {
+                    let stype = TokenType::of_alphabeticals(symbol.clone(), self.options);
+                    return Some(Ok(Token::new(stype, symbol, 0, start)
+                                       .with_length(self.position - start)));
+                }
+            }
+
+            let token = match TokenType::of_char(c) {
+                Operator => self.operator(c, start),
+                Numeric => self.number(c, start),
+                Alphabetical => self.identifier(c, start),
+                LeftParen => self.open(c, start),
+                RightParen => {
+                    if self.depth > 0 {
+                        self.depth -= 1;
+                    }
+                    Ok(Token::new(RightParen, str::from_char(c), 0, start))
+                },
+                Comma => Ok(Token::new(Comma, String::from_str(","), 0, start)),
+                Quoted => self.text(start),
+                _ if c.is_whitespace() || self.options.legacy => continue,
+                _ => Err(UnexpectedCharacter { character: c, position: start }),
+            };
+            // a token covers what it was read from, which for an alias or a
+            // `[` is not its own text
+            return Some(token.map(|token| token.with_length(self.position - start)));
+        }
+    }
+
+    fn operator(&mut self, c: char, start: uint) -> Result<Token, CalcError> {
+        // a minus negates when nothing it could subtract from comes before
+        // it: `-5`, `2 * -3`, `(-x)`, `f(1, -2)`
+        let prefix = c == '-' && match self.previous {
+            None => true,
+            Some(Operator) | Some(UnaryMinus) | Some(Functional)
+                | Some(LeftParen) | Some(Comma) => true,
+            _ => false,
+        };
+
+        let mut text = str::from_char(c);
+        match self.peek() {
+            Some(next) => {
+                let mut pair = text.clone();
+                pair.push_char(next);
+                if COMPOUND_OPERATORS.iter().any(|op| *op == pair.as_slice()) {
+                    text = pair;
+                    self.bump();
+                }
+            },
+            None => {},
+        }
+
+        // a lone `!` is the factorial of what comes before it
+        if text.as_slice() == "!" {
+            let fact = String::from_str("fact");
+            Ok(Token::new(PostfixFunction, fact, 0, start))
+        } else if prefix {
+            Ok(Token::new(UnaryMinus, text, NEGATION_PRECEDENCE, start))
+        } else {
+            let precedence = operator_precedence(&text);
+            Ok(Token::new(Operator, text, precedence, start))
+        }
+    }
+
+    fn number(&mut self, first: char, start: uint) -> Result<Token, CalcError> {
+        let mut text = str::from_char(first);
+
+        let prefixed = first == '0' && match self.peek() {
+            Some('x') | Some('X') | Some('b') | Some('B')
+                | Some('o') | Some('O') => true,
+            _ => false,
+        };
+
+        if prefixed {
+            text.push_char(self.bump().unwrap());
+            self.take_while(&mut text, |c| c.is_alphanumeric() || c == '_');
+        } else {
+            self.take_while(&mut text, |c| c.is_digit() || c == '_' || c == '.');
+
+            // an e only starts an exponent when digits follow, so `2e` is
+            // still two times the constant
+            match self.peek() {
+                Some('e') | Some('E') => {
+                    let sign = match self.peek_nth(1) {
+                        Some('+') | Some('-') => 1,
+                        _ => 0,
+                    };
+                    if self.peek_nth(1 + sign).map_or(false, |c| c.is_digit()) {
+                        for _ in range(0, 1 + sign) {
+                            text.push_char(self.bump().unwrap());
+                        }
+                        self.take_while(&mut text, |c| c.is_digit() || c == '_');
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        if text.as_slice().ends_with("_") || parse_number(text.as_slice()).is_none() {
+            return Err(MalformedNumber { literal: text, position: start });
+        }
+        Ok(Token::new(Numeric, text, 0, start))
+    }
+
+    fn identifier(&mut self, first: char, start: uint) -> Result<Token, CalcError> {
+        let mut name = str::from_char(first);
+        self.take_while(&mut name, |c| continues_identifier(c));
+
+        let name = self.options.resolve_alias(name);
+        let mut ttype = TokenType::of_alphabeticals(name.clone(), self.options);
+
+        // any name directly followed by `(` is a call, which is how
+        // user-defined functions are recognized
+        let called = self.chars.clone().skip_while(|&c| c == ' ').next() == Some('(');
+        if ttype == Variable && called {
+            ttype = Functional;
+        }
+
+        // with postfix enabled, a built-in right after an operand applies
+        // to it
+        if self.options.postfix && ttype == Functional && self.after_operand() && !called {
+            ttype = PostfixFunction;
+        }
+
+        // a unit name right after a number or a parenthesis measures it,
+        // as in `5 km` or `(1 + 2) h`
+        let after_value = self.previous == Some(Numeric) || self.previous == Some(RightParen);
+        if (ttype == Variable || ttype == Functional) && after_value && !called &&
+           units::lookup(name.as_slice()).is_some() {
+            ttype = UnitSuffix;
+        }
+
+        let precedence = if ttype == Operator { operator_precedence(&name) } else { 0 };
+        Ok(Token::new(ttype, name, precedence, start))
+    }
+
+    fn open(&mut self, c: char, start: uint) -> Result<Token, CalcError> {
+        self.depth += 1;
+        let max = self.options.limits.max_depth;
+        if self.depth > max {
+            return Err(LimitExceeded { limit: NestingDepth, max: max });
+        }
+
+        // a list literal is a call to `list`
+        let paren = Token::new(LeftParen, str::from_char(c), 0, start);
+        if c == '[' {
+            self.queued = Some(Ok(paren));
+            Ok(Token::new(Functional, String::from_str("list"), 0, start))
+        } else {
+            Ok(paren)
+        }
+    }
+
+    // text runs to the next quote, with no escapes
+    fn text(&mut self, start: uint) -> Result<Token, CalcError> {
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some(c) => text.push_char(c),
+                None => return Err(UnterminatedText { position: start }),
+            }
+        }
+        Ok(Token::new(Quoted, text, 0, start))
+    }
+}
+
+impl<'a> Iterator<Result<Token, CalcError>> for Lexer<'a> {
+    fn next(&mut self) -> Option<Result<Token, CalcError>> {
+        if self.finished {
+            return None;
+        }
+
+        let item = match self.queued.take() {
+            Some(item) => Some(item),
+            None => self.scan(),
+        };
+
+        match item {
+            Some(Ok(token)) => {
+                self.count += 1;
+                let max = self.options.limits.max_tokens;
+                if self.count > max {
+                    self.finished = true;
+                    return Some(Err(LimitExceeded { limit: TokenCount, max: max }));
+                }
+                self.previous = Some(token.ttype);
+                Some(Ok(token))
+            },
+            Some(Err(error)) => {
+                self.finished = true;
+                Some(Err(error))
+            },
+            None => {
+                self.finished = true;
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use error::{MalformedNumber, UnexpectedCharacter};
+    use exprtree::{ParseOptions, TokenType};
+    use exprtree::{Numeric, Functional, Variable, Operator, UnaryMinus, LeftParen, RightParen};
+    use super::Lexer;
+
+    // the position and length of each token of `expression`, which must lex
+    fn spans(expression: &str) -> Vec<(uint, uint)> {
+        let options = ParseOptions::new();
+        Lexer::new(expression, &options)
+            .map(|token| token.unwrap())
+            .map(|token| (token.position, token.length))
+            .collect()
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        assert_eq!(spans("12 + x"), vec![(0, 2), (3, 1), (5, 1)]);
+        // `π` is two bytes, and an alias of `pi`
+        assert_eq!(spans("2 * π + 1"), vec![(0, 1), (2, 1), (4, 2), (7, 1), (9, 1)]);
+        // the emoji is four bytes of the quoted text
+        assert_eq!(spans("\"😀\" + 1"), vec![(0, 6), (7, 1), (9, 1)]);
+    }
+
+    #[test]
+    fn spans_slice_the_input() {
+        let input = "\"µ😀\" + π * 3";
+        let pieces: Vec<&str> = spans(input).iter()
+            .map(|&(position, length)| input.slice(position, position + length))
+            .collect();
+        assert_eq!(pieces, vec!["\"µ😀\"", "+", "π", "*", "3"]);
+    }
+
+    #[test]
+    fn errors_point_at_byte_offsets() {
+        let options = ParseOptions::new();
+        match Lexer::new("π + µ", &options).last() {
+            Some(Err(error)) =>
+                assert_eq!(error, UnexpectedCharacter { character: 'µ', position: 5 }),
+            _ => fail!("µ should not lex"),
+        }
+        match Lexer::new("\"😀\" + 1.2.3", &options).last() {
+            Some(Err(error)) => assert_eq!(error, MalformedNumber {
+                literal: String::from_str("1.2.3"),
+                position: 9,
+            }),
+            _ => fail!("1.2.3 should not lex"),
+        }
+    }
+
+    #[test]
+    fn minus_depends_on_what_came_before() {
+        let options = ParseOptions::new();
+        let tokens: Vec<(String, TokenType)> = Lexer::new("-sqrt(x) >= 2 - y", &options)
+            .map(|token| token.unwrap())
+            .map(|token| (token.text, token.ttype))
+            .collect();
+        let types: Vec<TokenType> = tokens.iter().map(|&(_, ttype)| ttype).collect();
+        assert_eq!(types, vec![UnaryMinus, Functional, LeftParen, Variable, RightParen,
+                               Operator, Numeric, Operator, Variable]);
+        let (ref text, _) = *tokens.get(5);
+        assert_eq!(text.as_slice(), ">=");
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use std::string::String;
+    use std::uint;
+    use test::Bencher;
+
+    use exprtree::{Limits, ParseOptions};
+    use super::Lexer;
+
+    // `terms` terms of numbers, names, calls and parentheses, each piece
+    // followed by `extra`
+    fn long_expression(terms: uint, extra: &str) -> String {
+        let mut text = String::from_str("1");
+        for i in range(0, terms) {
+            text.push_str(match i % 4 {
+                0 => " + 2.5 * x",
+                1 => " - sqrt(16)",
+                2 => " / (y + 3)",
+                _ => " ^ 2",
+            });
+            text.push_str(extra);
+        }
+        text
+    }
+
+    fn unlimited() -> ParseOptions {
+        let mut options = ParseOptions::new();
+        options.limits = Limits {
+            max_input_len: uint::MAX,
+            max_tokens: uint::MAX,
+            max_depth: 64,
+        };
+        options
+    }
+
+    fn lex(b: &mut Bencher, expression: &str) {
+        let options = unlimited();
+        assert!(Lexer::new(expression, &options).all(|token| token.is_ok()));
+        b.bytes = expression.len() as u64;
+        b.iter(|| Lexer::new(expression, &options).count());
+    }
+
+    #[bench]
+    fn lex_1k_terms(b: &mut Bencher) {
+        lex(b, long_expression(1_000, "").as_slice());
+    }
+
+    #[bench]
+    fn lex_10k_terms(b: &mut Bencher) {
+        lex(b, long_expression(10_000, "").as_slice());
+    }
+
+    #[bench]
+    fn lex_10k_terms_multibyte(b: &mut Bencher) {
+        lex(b, long_expression(10_000, " × π").as_slice());
+    }
+
+    // the old tokenizer found each character with `chars().nth(i)`, which
+    // starts over from the front every time, so its cost grows with the
+    // square of the length; compare with `lex_1k_terms`
+    #[bench]
+    fn nth_char_scan_1k_terms(b: &mut Bencher) {
+        let expression = long_expression(1_000, "");
+        let text = expression.as_slice();
+        b.bytes = text.len() as u64;
+        b.iter(|| {
+            range(0, text.char_len()).filter(|&i| text.chars().nth(i) == Some('x')).count()
+        });
+    }
+}
@@ -0,0 +1,85 @@
+#![crate_id = "calc"]
+#![crate_type = "lib"]
+#![feature(struct_variant)]
+
+extern crate num;
+extern crate serialize;
+extern crate time;
+#[cfg(test)]
+extern crate test;
+
+pub use builtins::{Arity, Exactly, AtLeast, Between};
+pub use builtins::{AngleMode, Radians, Degrees};
+pub use compile::Program;
+pub use decimal::BigDecimal;
+pub use engine::Engine;
+pub use env::{Environment, UserFunction, ResultHook};
+pub use registry::{Registry, NativeFunction};
+pub use error::{CalcError, Limit, Warning};
+pub use exprtree::{ExprTree, ExprNode, NodeKind, Limits, ParseOptions, Dependencies};
+pub use exprtree::{Token, TokenType};
+pub use lexer::Lexer;
+pub use exprtree::{Numeric, Alphabetical, Functional, PostfixFunction, Variable, Operator};
+pub use exprtree::{UnitSuffix, UnaryMinus, LeftParen, RightParen, Comma, Quoted, Invalid};
+pub use exprtree::{ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+pub use exprtree::ExprUnit;
+pub use exprtree::{TreeStyle, Infix, Rpn, Prefix, Indented, Latex, MathMl, Speech};
+pub use value::{Value, Real, Decimal, Exact, Complex, Integer, Function, List, Partial};
+pub use value::{Text, Expression, Quantity};
+pub use units::Measure;
+pub use value::{NumberMode, FloatMode, DecimalMode, ExactMode, IntegerMode, FixedMode};
+pub use value::Fixed;
+pub use fixed::{QFormat, FixedPoint, Overflow, Saturate, Wrap};
+pub use format::{Formatter, Notation, PlainNotation, ScientificNotation, EngineeringNotation};
+
+pub mod algebra;
+pub mod analysis;
+pub mod anonymize;
+pub mod builtins;
+pub mod calculus;
+#[cfg(feature = "repl")]
+pub mod clipboard;
+pub mod compile;
+pub mod decimal;
+pub mod diagnostic;
+pub mod digest;
+pub mod duration;
+pub mod engine;
+pub mod env;
+pub mod error;
+pub mod exprtree;
+#[cfg(feature = "finance")]
+pub mod finance;
+pub mod fixed;
+pub mod format;
+pub mod json;
+pub mod latex;
+pub mod lexer;
+pub mod lint;
+pub mod mathml;
+#[cfg(feature = "matrix")]
+pub mod matrix;
+pub mod messages;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod quick;
+pub mod random;
+pub mod registry;
+#[cfg(feature = "repl")]
+pub mod session;
+pub mod simplify;
+pub mod solver;
+pub mod speech;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "repl")]
+pub mod transcript;
+pub mod units;
+pub mod usage;
+pub mod value;
+
+/// Parses and evaluates `expression` in one step, with doubles.
+pub fn evaluate(expression: &str) -> Result<f64, CalcError> {
+    let tree = try!(ExprTree::build(expression));
+    tree.eval().and_then(|value| value.number())
+}
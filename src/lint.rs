@@ -0,0 +1,276 @@
+use std::collections::TreeMap;
+use std::string::String;
+
+use error::CalcError;
+use exprtree::{ExprTree, ExprNode, ParseOptions, Rpn};
+use exprtree::{ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall};
+use exprtree::{LeftParen, RightParen, Functional};
+
+/// Reads a formula file a line at a time, as `-f` would evaluate it, and
+/// points out what is likely a mistake or could be written more simply:
+/// variables assigned again or hidden by a function's parameter,
+/// arithmetic on numbers alone that could be worked out beforehand,
+/// parentheses that change nothing, `log` with no base, which is base 10
+/// rather than the natural logarithm, and `==` or `!=` between values
+/// that rounding may have made differ.
+pub struct Linter<'a> {
+    options: &'a ParseOptions,
+    // the line each variable was last assigned on
+    assigned: TreeMap<String, uint>,
+    line: uint,
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(options: &'a ParseOptions) -> Linter<'a> {
+        Linter { options: options, assigned: TreeMap::new(), line: 0 }
+    }
+
+    /// What there is to say about the next line of the file, or the error
+    /// reading it. Blank lines are counted but have nothing to find.
+    pub fn lint(&mut self, line: &str) -> Result<Vec<String>, CalcError> {
+        self.line += 1;
+        let text = line.trim();
+        if text.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let tree = try!(ExprTree::build_with(text, self.options));
+        let root = match tree.root() {
+            Some(root) => root,
+            None => return Ok(vec![]),
+        };
+
+        let mut found: Vec<String> = vec![];
+        self.shadowing(root, &mut found);
+        suspicious(root, &mut found);
+        // a line that reads no variables is a calculation, and working it
+        // out is what it is for
+        let body = if root.kind() == ExprBinary && root.token() == "=" {
+            root.children().get(1)
+        } else {
+            root
+        };
+        if reads_variables(body) {
+            foldable(body, &mut found);
+        }
+        redundant_parens(text, &tree, self.options, &mut found);
+        Ok(found)
+    }
+
+    fn shadowing(&mut self, root: &ExprNode, found: &mut Vec<String>) {
+        if !(root.kind() == ExprBinary && root.token() == "=") {
+            return;
+        }
+
+        let target = root.children().get(0);
+        if target.kind() == ExprVariable {
+            let name = String::from_str(target.token());
+            match self.assigned.find(&name) {
+                Some(line) => found.push(format!("{} is assigned again, hiding its value from \
+                                                  line {}", name, line)),
+                None => {},
+            }
+            self.assigned.insert(name, self.line);
+            return;
+        }
+
+        for param in target.children().iter() {
+            // a parameter with a default is written `name=value`
+            let param = if param.kind() == ExprBinary && param.token() == "=" {
+                param.children().get(0)
+            } else {
+                param
+            };
+            match self.assigned.find(&String::from_str(param.token())) {
+                Some(line) => found.push(format!("the parameter {} of {} hides the variable \
+                                                  assigned on line {}",
+                                                 param.token(), target.token(), line)),
+                None => {},
+            }
+        }
+    }
+}
+
+// `log` with no base and exact comparisons of inexact values, anywhere in
+// the line
+fn suspicious(node: &ExprNode, found: &mut Vec<String>) {
+    match node.kind() {
+        ExprCall if node.token() == "log" && node.children().len() == 1 => {
+            let arg = node.children().get(0);
+            found.push(format!("log({}) is the base-10 logarithm; write ln({}) for the natural \
+                                one, or log({}, 10) to make the base plain", arg, arg, arg));
+        },
+        ExprBinary if node.token() == "==" || node.token() == "!=" => {
+            if !node.children().iter().all(|child| is_exact(child)) {
+                found.push(format!("{} compares with {} exactly, but rounding can make equal \
+                                    values differ; compare their difference with a tolerance",
+                                   node, node.token()));
+            }
+        },
+        _ => {},
+    }
+
+    for child in node.children().iter() {
+        suspicious(child, found);
+    }
+}
+
+// values that come out of a double exactly as written: variables and
+// whole numbers
+fn is_exact(node: &ExprNode) -> bool {
+    match node.kind() {
+        ExprVariable => true,
+        ExprLiteral => from_str::<i64>(node.token()).is_some(),
+        ExprUnary => is_exact(node.children().get(0)),
+        _ => false,
+    }
+}
+
+// the largest parts of `node` worked out from numbers alone, other than a
+// negated number
+fn foldable(node: &ExprNode, found: &mut Vec<String>) {
+    for child in node.children().iter() {
+        if !is_arithmetic(child) {
+            foldable(child, found);
+            continue;
+        }
+        if child.kind() == ExprLiteral ||
+           (child.kind() == ExprUnary && child.children().get(0).kind() == ExprLiteral) {
+            continue;
+        }
+        match ExprTree::build(format!("{}", child).as_slice()).and_then(|tree| tree.eval()) {
+            Ok(value) => found.push(format!("{} is constant and could be written as {}",
+                                            child, value)),
+            // such as a division by zero, which evaluating will report
+            Err(_) => {},
+        }
+    }
+}
+
+fn reads_variables(node: &ExprNode) -> bool {
+    node.kind() == ExprVariable || node.children().iter().any(|child| reads_variables(child))
+}
+
+// whether `node` is arithmetic on numbers written out in digits; named
+// constants such as `pi` say more than their values do
+fn is_arithmetic(node: &ExprNode) -> bool {
+    match node.kind() {
+        ExprLiteral => node.token().chars().next().map_or(false, |c| c.is_digit() || c == '.'),
+        ExprUnary => is_arithmetic(node.children().get(0)),
+        ExprBinary => match node.token() {
+            "+"|"-"|"*"|"/"|"//"|"%"|"^" =>
+                node.children().iter().all(|child| is_arithmetic(child)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// parentheses that can be taken out of `text` without changing its tree;
+// those of calls and lists are needed
+fn redundant_parens(text: &str, tree: &ExprTree, options: &ParseOptions,
+                    found: &mut Vec<String>) {
+    let tokens = match ExprTree::tokenize(text, options) {
+        Ok(tokens) => tokens,
+        Err(_) => return,
+    };
+    let shape = tree.format(Rpn);
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.ttype != LeftParen || (i > 0 && tokens.get(i - 1).ttype == Functional) {
+            continue;
+        }
+
+        let mut depth = 0u;
+        let mut partner = None;
+        for other in tokens.iter().skip(i) {
+            match other.ttype {
+                LeftParen => depth += 1,
+                RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        partner = Some(other.position);
+                        break;
+                    }
+                },
+                _ => {},
+            }
+        }
+        let (open, close) = match partner {
+            Some(close) => (token.position, close),
+            None => continue,
+        };
+
+        let without = format!("{}{}{}", text.slice_to(open), text.slice(open + 1, close),
+                              text.slice_from(close + 1));
+        match ExprTree::build_with(without.as_slice(), options) {
+            Ok(ref simpler) if simpler.format(Rpn) == shape =>
+                found.push(format!("the parentheses around {} are not needed",
+                                   text.slice(open + 1, close).trim())),
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ParseOptions;
+    use super::Linter;
+
+    // the findings for each line of `lines`, in order
+    fn lint(lines: &[&str]) -> Vec<Vec<String>> {
+        let options = ParseOptions::new();
+        let mut linter = Linter::new(&options);
+        lines.iter().map(|line| linter.lint(*line).unwrap()).collect()
+    }
+
+    #[test]
+    fn shadowed_variables_are_found() {
+        let found = lint(&["rate = 5", "", "f(rate, n=2) = rate * n", "rate = 6"]);
+        assert!(found.get(0).is_empty() && found.get(1).is_empty());
+        assert_eq!(found.get(2), &vec![String::from_str("the parameter rate of f hides the \
+                                                         variable assigned on line 1")]);
+        assert_eq!(found.get(3), &vec![String::from_str("rate is assigned again, hiding its \
+                                                         value from line 1")]);
+    }
+
+    #[test]
+    fn constant_parts_could_be_folded() {
+        assert_eq!(lint(&["x * (60 * 60)"]), vec![vec![
+            String::from_str("60 * 60 is constant and could be written as 3600"),
+        ]]);
+        // a line of numbers alone is a calculation, and constants and
+        // negative numbers are left as written
+        assert!(lint(&["y = 2 * 3 + 1", "2 * pi * r + -1 * r", "sin(2 * 3) > 0"]).iter()
+                .all(|f| f.is_empty()));
+    }
+
+    #[test]
+    fn only_parentheses_that_change_nothing_are_redundant() {
+        assert_eq!(lint(&["y = (a + b)"]), vec![vec![
+            String::from_str("the parentheses around a + b are not needed"),
+        ]]);
+        assert!(lint(&["(a + b) * c", "a - (b - c)", "sin(a) + [a, b]"]).iter()
+                .all(|f| f.is_empty()));
+    }
+
+    #[test]
+    fn log_and_exact_comparisons_are_suspicious() {
+        let found = lint(&["log(x) + log(x, 2)", "a / 3 == b", "a == -1"]);
+        assert_eq!(found.get(0), &vec![String::from_str("log(x) is the base-10 logarithm; \
+                                                         write ln(x) for the natural one, or \
+                                                         log(x, 10) to make the base plain")]);
+        assert_eq!(found.get(1).len(), 1);
+        assert!(found.get(1).get(0).as_slice().starts_with("a / 3 == b compares with =="));
+        assert!(found.get(2).is_empty());
+    }
+
+    #[test]
+    fn unreadable_lines_are_errors() {
+        let options = ParseOptions::new();
+        let mut linter = Linter::new(&options);
+        assert!(linter.lint("2 + * 3").is_err());
+    }
+}
@@ -1,26 +1,1987 @@
-use exprtree::ExprTree;
-use std::os;
+extern crate calc;
 
-mod exprtree;
+use calc::{CalcError, Environment, ExprTree, ParseOptions, Value, Expression, List, Integer};
+use calc::{Exact, Quantity};
+use calc::Program;
+use calc::ResultHook;
+use calc::{Infix, Rpn, Indented, Latex, MathMl, Speech};
+use calc::Numeric;
+use calc::{DecimalMode, ExactMode, IntegerMode, FixedMode, Degrees};
+#[cfg(feature = "repl")]
+use calc::Radians;
+use calc::{QFormat, Saturate, Wrap};
+use calc::{Formatter, EngineeringNotation, ScientificNotation};
+use calc::{analysis, calculus, diagnostic, format, quick, solver, speech, units};
+use calc::lint::Linter;
+#[cfg(feature = "repl")]
+use calc::clipboard;
+#[cfg(feature = "finance")]
+use calc::finance;
+#[cfg(feature = "plot")]
+use calc::plot;
+#[cfg(feature = "stats")]
+use calc::stats;
+use calc::usage::Usage;
+use calc::messages::{Catalog, env_language};
+#[cfg(feature = "repl")]
+use calc::session::{Session, DEFAULT_REGISTER};
+#[cfg(feature = "repl")]
+use calc::transcript::{Transcript, Evaluated, Failed, Plotted};
+use calc::error::{EmptyExpression, LimitExceeded, Milliseconds};
+use std::collections::{Deque, RingBuf};
+#[cfg(feature = "finance")]
+use std::from_str::FromStr;
+use std::io;
+use std::io::File;
+use std::io::process::Command;
+use std::os;
+#[cfg(feature = "finance")]
+use std::slice;
 
+#[deriving(Clone, PartialEq)]
 enum Options {
     Verbose,
+    Engineering,
+    BigPrecision,
+    ExactFractions,
+    Repeating,
+    Integers,
+    WordWidth(uint),
+    FixedFormat(String),
+    Wrapping,
+    Postfix,
+    Sensitivity(f64),
+    Stability(uint),
+    ShowCondition,
+    Plot(String),
+    Range(f64, f64),
+    Stats,
+    OperationStats,
+    Fold(String),
+    Init(f64),
+    Table(String),
+    Step(f64),
+    Csv,
+    Script(String),
+    KeepGoing,
+    Timeout(uint),
+    Window(uint),
+    DegreeAngles,
+    Legacy,
+    Quiet,
+    Digits(uint),
+    SignificantDigits(uint),
+    ScientificFormat,
+    Separators,
+    Format(Formatter),
+    OutputBase(uint),
+    Bases,
+    OutUnit(String),
+    CopyResult,
+    Seed(uint),
+    Exec(String),
+    Language(String),
+    ForceRepl,
+    Help,
+    ShowAst,
+    ShowRpn,
+    ShowTokens,
+    EmitAstJson,
+    EmitLatex,
+    EmitMathMl,
+    EmitSpeech,
+    SpeakFormat,
+    FromAstJson,
+}
+
+static USAGE: &'static str = "\
+usage: calc [OPTIONS] [EXPRESSION | -]
+       calc [OPTIONS] derive EXPRESSION VARIABLE
+       calc [OPTIONS] fmt FILE
+       calc [OPTIONS] lint FILE
+       calc [OPTIONS] simplify EXPRESSION
+       calc [OPTIONS] solve EQUATION VARIABLE [LOW HIGH]
+       calc [OPTIONS] amortize --principal AMOUNT --rate ANNUAL_RATE --years YEARS
+                               [--per-year PAYMENTS]
+
+With no expression, calc reads expressions interactively; with -, it
+reads one per line from stdin. Units, matrices, --stats, amortize,
+plotting and the interactive session are optional parts of calc, each
+there only when it was built with that feature; see the Makefile.
+
+options:
+  -h, --help             show this help
+  -v, --verbose          show the RPN steps and tree for each expression
+  --ast                  show each expression's tree, one node per line
+  --show-rpn             show each expression in RPN
+  --show-tokens          show each expression's tokens
+  --emit ast-json        print each expression's tree as JSON instead of its value
+  --emit latex|mathml    print each expression as LaTeX or MathML instead of its value
+  --emit speech          print each expression as it would be read aloud
+  --from ast-json        read trees written by --emit ast-json instead of expressions
+  -q, --quiet            print results and errors only, without warnings
+  --repl                 read expressions interactively; :plot EXPR charts EXPR,
+                         :copy copies the last result, :vars lists variables
+                         and functions by name and :export FILE saves
+                         the session as Markdown; history lists results,
+                         #N recalls one, and M+, M-, MR, MC, store NAME and
+                         recall NAME work memory registers kept in
+                         ~/.calc_memory; set format digits N, fixed N,
+                         plain, sci, eng, separators on|off or reset
+                         changes how results are printed
+  -f FILE                evaluate each line of FILE
+  --keep-going           carry on after a line fails in -f or - mode, then
+                         report how many failed and exit with status 1
+  --timeout-ms N         in -f or - mode, stop any line still being worked out
+                         after N milliseconds with an error, and go on to
+                         the next
+  --precision N          print results to N decimal places
+  --precision big|double work in big decimals or doubles
+  --exec CMD             run the shell command CMD after each result, with {}
+                         in it replaced by the result
+  --seed N               seed rand, randint, normal and --stability so runs repeat
+  --lang LANG            write error messages in LANG, such as de or pt_BR, from
+                         $CALC_MESSAGES/LANG.txt or ~/.calc_messages/LANG.txt;
+                         by default the language comes from LC_ALL,
+                         LC_MESSAGES or LANG, and English is used when there
+                         is no catalog for it
+  --copy                 put the result on the clipboard as well
+  --output-base N        print whole-number results in base N, 2 to 36
+  --bases                with --int, print each result in decimal, hex, octal
+                         and binary, in aligned columns
+  --out-unit UNIT        print quantities of UNIT's dimension in UNIT, such as
+                         kw or mi; others, and every quantity without this,
+                         come out in the unit that keeps them readable
+  --format plain|eng|sci print results plainly or in engineering or scientific
+                         notation
+  --digits N             print results to N significant digits
+  --fixed N              print results to N decimal places, same as --precision N
+  --separators           group the digits of results by thousands, as in 1,234,567
+  --speak-format         print results as words, as in `three point one four`,
+                         for screen readers and voice assistants
+  --eng                  same as --format eng
+  --sci                  same as --format sci
+  --exact                work in exact fractions
+  --repeating            work in exact fractions and print them as decimals
+                         with the repeating digits in parentheses, as in 0.(3)
+  --int                  work in 64-bit integers
+  --word-width BITS      work in integers of 8, 16, 32 or 64 bits, wrapping
+                         results to the word, and print them in two's
+                         complement with --output-base and --bases
+  --qformat M.N          work in signed Q M.N fixed point, saturating on overflow
+  --wrap                 wrap instead of saturating in --qformat
+  --degrees              work angles in degrees
+  --legacy               behave as older versions did: no implicit
+                         multiplication, syntax check or precision warnings,
+                         unknown characters skipped, and NaN instead of
+                         domain errors from built-ins
+  --postfix              let a built-in follow its operand, as in `30 deg sin`
+  --sensitivity PCT      show how the result responds to each input
+  --condition            show each result's condition number
+  --stability RUNS       work each expression RUNS times with randomized rounding
+                         and show how far the result spreads
+  --plot EXPR            chart EXPR in x over --range
+  --range FROM:TO        range for --plot and --table, default -10:10
+  --table EXPR           tabulate EXPR in x over --range in steps of --step
+  --step STEP            step for --table
+  --csv                  write tables as CSV
+  --stats                summarize the numbers on stdin
+  --op-stats             count the operators and built-ins the formulas on stdin
+                         use, most used first
+  --fold EXPR            reduce the numbers on stdin with EXPR in acc and x
+  --init VALUE           starting acc for --fold
+  --window N             evaluate EXPR over the last N numbers on stdin";
+
+// words that take their own flags, which are left for them to read
+static SUBCOMMANDS: [&'static str, ..4] = ["amortize", "derive", "simplify", "solve"];
+
+// split the command line into recognized flags and everything else; an
+// unrecognized `--flag` is an error unless a subcommand will read it, and
+// everything after `--` is positional
+fn parse_options(args: &[String]) -> Result<(Vec<Options>, Vec<String>), String> {
+    let mut options: Vec<Options> = vec![];
+    let mut positional: Vec<String> = vec![];
+
+    let mut iter = args.iter();
+    loop {
+        let arg = match iter.next() {
+            Some(arg) => arg,
+            None => break,
+        };
+
+        match arg.as_slice() {
+            "-h"|"--help" => options.push(Help),
+            "-v"|"--verbose" => options.push(Verbose),
+            "-q"|"--quiet" => options.push(Quiet),
+            "--ast" => options.push(ShowAst),
+            "--show-rpn" => options.push(ShowRpn),
+            "--show-tokens" => options.push(ShowTokens),
+            "--emit" => match iter.next().map(|e| e.as_slice()) {
+                Some("ast-json") => options.push(EmitAstJson),
+                Some("latex") => options.push(EmitLatex),
+                Some("mathml") => options.push(EmitMathMl),
+                Some("speech") => options.push(EmitSpeech),
+                other => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --emit takes 'ast-json', 'latex', \
+                                              'mathml' or 'speech', not '{}'; \
+                                              ignoring it", other.unwrap_or(""));
+                },
+            },
+            "--from" => match iter.next().map(|e| e.as_slice()) {
+                Some("ast-json") => options.push(FromAstJson),
+                other => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --from takes 'ast-json', not '{}'; \
+                                              reading expressions", other.unwrap_or(""));
+                },
+            },
+            "--repl" => options.push(ForceRepl),
+            "--format" => match iter.next().map(|f| f.as_slice()) {
+                Some("eng") => options.push(Engineering),
+                Some("sci") => options.push(ScientificFormat),
+                // the last notation asked for wins, so this undoes an
+                // earlier `--eng` or `--sci`
+                Some("plain") => options.retain(|option| {
+                    *option != Engineering && *option != ScientificFormat
+                }),
+                other => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --format takes 'plain', 'eng' or 'sci', \
+                                              not '{}'; using plain",
+                                     other.unwrap_or(""));
+                },
+            },
+            "--eng" => options.push(Engineering),
+            "--sci" => options.push(ScientificFormat),
+            "--separators" => options.push(Separators),
+            "--digits" => match iter.next().and_then(|d| from_str::<uint>(d.as_slice())) {
+                Some(digits) => options.push(SignificantDigits(digits)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --digits takes a number of significant \
+                                              digits; ignoring it");
+                },
+            },
+            "--speak-format" => options.push(SpeakFormat),
+            "--exact" => options.push(ExactFractions),
+            "--repeating" => options.push(Repeating),
+            "--int" => options.push(Integers),
+            "--word-width" => match iter.next().and_then(|w| from_str::<uint>(w.as_slice())) {
+                Some(bits) if bits == 8 || bits == 16 || bits == 32 || bits == 64 =>
+                    options.push(WordWidth(bits)),
+                _ => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --word-width takes 8, 16, 32 or 64; \
+                                              using 64");
+                },
+            },
+            "--wrap" => options.push(Wrapping),
+            "--fixed" => match iter.next().and_then(|p| from_str::<uint>(p.as_slice())) {
+                Some(places) => options.push(Digits(places)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --fixed takes a number of decimal places; \
+                                              for fixed-point arithmetic use --qformat M.N; \
+                                              ignoring it");
+                },
+            },
+            "--qformat" => match iter.next() {
+                Some(format) => options.push(FixedFormat(format.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --qformat takes a Q format such as \
+                                              q15.16; ignoring it");
+                },
+            },
+            "--postfix" => options.push(Postfix),
+            "--stats" => options.push(Stats),
+            "--op-stats" => options.push(OperationStats),
+            "--csv" => options.push(Csv),
+            "--keep-going" => options.push(KeepGoing),
+            "--degrees" => options.push(DegreeAngles),
+            "--legacy" => options.push(Legacy),
+            "--window" => match iter.next().and_then(|n| from_str::<uint>(n.as_slice())) {
+                Some(size) if size > 0 => options.push(Window(size)),
+                _ => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --window takes a positive count; ignoring it");
+                },
+            },
+            "-f" => match iter.next() {
+                Some(path) => options.push(Script(path.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: -f takes a file name; ignoring it");
+                },
+            },
+            "--timeout-ms" => match iter.next().and_then(|n| from_str::<uint>(n.as_slice())) {
+                Some(ms) => options.push(Timeout(ms)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --timeout-ms takes a number of \
+                                              milliseconds; ignoring it");
+                },
+            },
+            "--sensitivity" => match iter.next().and_then(|p| from_str::<f64>(p.as_slice())) {
+                Some(pct) => options.push(Sensitivity(pct)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --sensitivity takes a percentage; ignoring it");
+                },
+            },
+            "--condition" => options.push(ShowCondition),
+            "--stability" => match iter.next().and_then(|r| from_str::<uint>(r.as_slice())) {
+                Some(runs) if runs > 0 => options.push(Stability(runs)),
+                _ => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --stability takes a number of runs; ignoring it");
+                },
+            },
+            "--plot" => match iter.next() {
+                Some(expression) => options.push(Plot(expression.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --plot takes an expression in x; ignoring it");
+                },
+            },
+            "--fold" => match iter.next() {
+                Some(expression) => options.push(Fold(expression.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --fold takes an expression in acc and x; \
+                                              ignoring it");
+                },
+            },
+            "--init" => match iter.next().and_then(|i| from_str::<f64>(i.as_slice())) {
+                Some(init) => options.push(Init(init)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --init takes a number; using 0");
+                },
+            },
+            "--table" => match iter.next() {
+                Some(expression) => options.push(Table(expression.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --table takes an expression in x; ignoring it");
+                },
+            },
+            "--step" => match iter.next().and_then(|s| from_str::<f64>(s.as_slice())) {
+                Some(step) if step > 0_f64 => options.push(Step(step)),
+                _ => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --step takes a positive number; \
+                                              using a tenth of the range");
+                },
+            },
+            "--range" => match iter.next().and_then(|r| parse_range(r.as_slice())) {
+                Some((from, to)) => options.push(Range(from, to)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --range takes FROM:TO, as in -10:10; \
+                                              using -10:10");
+                },
+            },
+            "--precision" => match iter.next().map(|p| p.as_slice()) {
+                Some("big") => options.push(BigPrecision),
+                Some("double") => {},
+                Some(digits) if from_str::<uint>(digits).is_some() =>
+                    options.push(Digits(from_str::<uint>(digits).unwrap())),
+                other => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --precision takes a number of places, \
+                                              'big' or 'double', not '{}'; using double",
+                                     other.unwrap_or(""));
+                },
+            },
+            "--copy" => options.push(CopyResult),
+            "--exec" => match iter.next() {
+                Some(command) => options.push(Exec(command.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --exec takes a command; ignoring it");
+                },
+            },
+            "--lang" => match iter.next() {
+                Some(language) => options.push(Language(language.clone())),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --lang takes a language such as de; \
+                                              ignoring it");
+                },
+            },
+            "--seed" => match iter.next().and_then(|s| from_str::<uint>(s.as_slice())) {
+                Some(seed) => options.push(Seed(seed)),
+                None => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --seed takes a whole number; ignoring it");
+                },
+            },
+            "--output-base" => match iter.next().and_then(|b| from_str::<uint>(b.as_slice())) {
+                Some(base) if base >= 2 && base <= 36 => options.push(OutputBase(base)),
+                _ => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --output-base takes a base from 2 to 36; \
+                                              using 10");
+                },
+            },
+            "--bases" => options.push(Bases),
+            "--out-unit" => match iter.next().map(|u| u.as_slice()) {
+                Some(unit) if units::lookup(unit).is_some() =>
+                    options.push(OutUnit(String::from_str(unit))),
+                other => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "warning: --out-unit takes the name of a unit, \
+                                              not '{}'; ignoring it",
+                                     other.unwrap_or(""));
+                },
+            },
+            "--" => {
+                for rest in iter.by_ref() {
+                    positional.push(rest.clone());
+                }
+            },
+            flag if flag.starts_with("--") && !in_subcommand(&positional) =>
+                return Err(format!("unknown option '{}'", flag)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    Ok((options, positional))
+}
+
+fn in_subcommand(positional: &Vec<String>) -> bool {
+    !positional.is_empty() &&
+        SUBCOMMANDS.iter().any(|&name| name == positional.get(0).as_slice())
+}
+
+// `-10:10` as a pair of bounds, lower first
+fn parse_range(text: &str) -> Option<(f64, f64)> {
+    let bounds: Vec<&str> = text.splitn(':', 1).collect();
+    if bounds.len() != 2 {
+        return None;
+    }
+    match (from_str::<f64>(*bounds.get(0)), from_str::<f64>(*bounds.get(1))) {
+        (Some(from), Some(to)) if from < to => Some((from, to)),
+        _ => None,
+    }
+}
+
+fn environment(options: &Vec<Options>) -> Environment {
+    let mut env = Environment::new();
+    let fixed = options.iter().filter_map(|option| match *option {
+        FixedFormat(ref format) => Some(format.clone()),
+        _ => None,
+    }).last();
+    let overflow = if options.contains(&Wrapping) { Wrap } else { Saturate };
+
+    if fixed.is_some() {
+        let text = fixed.unwrap();
+        match QFormat::parse(text.as_slice(), overflow) {
+            Some(format) => env.mode = FixedMode(format),
+            None => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "warning: '{}' is not a Q format of at most 32 bits, \
+                                          such as q15.16; using doubles", text);
+            },
+        }
+    } else if options.contains(&Integers) || word_width(options).is_some() {
+        env.mode = IntegerMode(word_width(options).unwrap_or(64));
+    } else if options.contains(&ExactFractions) || options.contains(&Repeating) {
+        env.mode = ExactMode;
+    } else if options.contains(&BigPrecision) {
+        env.mode = DecimalMode;
+    }
+    if options.contains(&DegreeAngles) {
+        env.angle = Degrees;
+    }
+    env.legacy = options.contains(&Legacy);
+    match options.iter().filter_map(|option| match *option {
+        Seed(seed) => Some(seed),
+        _ => None,
+    }).last() {
+        Some(seed) => env.seed(seed),
+        None => {},
+    }
+    for option in options.iter() {
+        match *option {
+            Exec(ref command) => env.add_result_hook(box ExecHook {
+                command: command.clone(),
+                options: options.clone(),
+            } as Box<ResultHook>),
+            _ => {},
+        }
+    }
+    env
+}
+
+// runs a shell command after each result, with `{}` replaced by the result
+// as it is printed, quoted for the shell
+struct ExecHook {
+    command: String,
+    options: Vec<Options>,
+}
+
+impl ResultHook for ExecHook {
+    fn result(&mut self, value: &Value) {
+        let shown = show_value(value, &self.options);
+        let quoted = format!("'{}'", shown.as_slice().replace("'", "'\\''"));
+        let command = self.command.as_slice().replace("{}", quoted.as_slice());
+
+        let mut stderr = io::stderr();
+        match Command::new("sh").arg("-c").arg(command.as_slice()).status() {
+            Ok(status) if status.success() => {},
+            Ok(status) => {
+                let _ = writeln!(stderr, "warning: --exec command {}: {}", status, command);
+            },
+            Err(e) => {
+                let _ = writeln!(stderr, "warning: cannot run --exec command: {}", e);
+            },
+        }
+    }
+}
+
+fn parse_settings(options: &Vec<Options>) -> ParseOptions {
+    let mut settings = ParseOptions::new();
+    settings.postfix = options.contains(&Postfix);
+    settings.verbose = options.contains(&Verbose);
+    settings.legacy = options.contains(&Legacy);
+    settings
+}
+
+fn sensitivity_pct(options: &Vec<Options>) -> Option<f64> {
+    options.iter().filter_map(|option| match *option {
+        Sensitivity(pct) => Some(pct),
+        _ => None,
+    }).last()
+}
+
+fn stability_runs(options: &Vec<Options>) -> Option<uint> {
+    options.iter().filter_map(|option| match *option {
+        Stability(runs) => Some(runs),
+        _ => None,
+    }).last()
+}
+
+// print the condition number if asked, and warn of an ill-conditioned
+// result either way unless quiet; expressions it can't be worked out for
+// are passed over without comment
+fn report_condition(tree: &ExprTree, env: &Environment, options: &Vec<Options>) {
+    let shown = options.contains(&ShowCondition);
+    if !shown && options.contains(&Quiet) {
+        return;
+    }
+
+    let condition = match analysis::condition(tree, env) {
+        Ok(Some(condition)) => condition,
+        _ => return,
+    };
+    if shown {
+        let inputs: Vec<String> = condition.inputs.iter()
+            .map(|&(ref name, term)| format!("{} {:e}", name, term))
+            .collect();
+        println!("condition: {:e} ({})", condition.number, inputs.as_slice().connect(", "));
+    }
+    match condition_warning(&condition) {
+        Some(ref warning) if !options.contains(&Quiet) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "warning: {}", warning);
+        },
+        _ => {},
+    }
+}
+
+fn condition_warning(condition: &analysis::Condition) -> Option<String> {
+    if condition.is_ill_conditioned() {
+        Some(format!("the result is ill-conditioned (condition number {:e}); small errors \
+                      in its inputs are greatly magnified", condition.number))
+    } else {
+        None
+    }
+}
+
+// whether `line` may skip the parser: nothing about its tree was asked
+// for beyond the condition warning, which the quick path finds itself
+fn quick_path(options: &Vec<Options>) -> bool {
+    let inspected = [ShowTokens, Verbose, ShowRpn, ShowAst, EmitAstJson, EmitLatex, EmitMathMl,
+                     EmitSpeech, ShowCondition, FromAstJson].iter()
+        .any(|option| options.contains(option)) ||
+        sensitivity_pct(options).is_some() || stability_runs(options).is_some();
+    !inspected
+}
+
+// evaluates one line; definitions produce no value to print
+fn evaluate_line(line: &str, settings: &ParseOptions, options: &Vec<Options>,
+                 env: &mut Environment) -> Result<Option<Value>, CalcError> {
+    let from_json = options.contains(&FromAstJson);
+
+    // a single operator between numbers or variables, such as `2+2`, may
+    // skip the parser
+    if quick_path(options) {
+        match quick::eval(line, settings, env) {
+            Some(result) => {
+                if result.is_ok() && !options.contains(&Quiet) {
+                    let condition = quick::condition(line, settings, env);
+                    match condition.as_ref().and_then(condition_warning) {
+                        Some(warning) => {
+                            let mut stderr = io::stderr();
+                            let _ = writeln!(stderr, "warning: {}", warning);
+                        },
+                        None => {},
+                    }
+                }
+                return result.map(|value| Some(value));
+            },
+            None => {},
+        }
+    }
+    if options.contains(&ShowTokens) && !from_json {
+        let tokens = try!(ExprTree::tokenize(line, settings));
+        let shown: Vec<String> = tokens.iter()
+            .map(|token| format!("{}:{}", token.text, token.ttype))
+            .collect();
+        println!("tokens: {}", shown.as_slice().connect(" "));
+    }
+
+    let built = if from_json {
+        ExprTree::from_json(line)
+    } else {
+        ExprTree::build_with(line, settings)
+    };
+    built.and_then(|tree| {
+        if !options.contains(&Quiet) {
+            let mut stderr = io::stderr();
+            for warning in tree.warnings().iter() {
+                let _ = writeln!(stderr, "warning: {}", warning);
+            }
+        }
+
+        if options.contains(&Verbose) {
+            tree.print();
+        }
+        if options.contains(&ShowRpn) {
+            println!("rpn: {}", tree.format(Rpn));
+        }
+        if options.contains(&ShowAst) {
+            print!("{}", tree.format(Indented));
+        }
+        if options.contains(&EmitAstJson) {
+            println!("{}", tree.to_json());
+            return Ok(None);
+        }
+        if options.contains(&EmitLatex) {
+            println!("{}", tree.format(Latex));
+            return Ok(None);
+        }
+        if options.contains(&EmitMathMl) {
+            println!("{}", tree.format(MathMl));
+            return Ok(None);
+        }
+        if options.contains(&EmitSpeech) {
+            println!("{}", tree.format(Speech));
+            return Ok(None);
+        }
+
+        let value = try!(tree.eval_in(env));
+        if tree.is_definition() {
+            return Ok(None);
+        }
+
+        match sensitivity_pct(options) {
+            Some(pct) => {
+                let rows = try!(analysis::sensitivity(&tree, env, None, pct));
+                if !rows.is_empty() {
+                    print!("{}", analysis::sensitivity_table(rows.as_slice(), pct));
+                }
+            },
+            None => {},
+        }
+        report_condition(&tree, env, options);
+        match stability_runs(options) {
+            Some(runs) => match try!(analysis::stability(&tree, env, runs)) {
+                Some(stability) => print!("{}", analysis::stability_report(&stability)),
+                None => {},
+            },
+            None => {},
+        }
+        Ok(Some(value))
+    })
+}
+
+fn word_width(options: &Vec<Options>) -> Option<uint> {
+    options.iter().filter_map(|option| match *option {
+        WordWidth(bits) => Some(bits),
+        _ => None,
+    }).last()
+}
+
+fn out_unit(options: &Vec<Options>) -> Option<String> {
+    options.iter().filter_map(|option| match *option {
+        OutUnit(ref unit) => Some(unit.clone()),
+        _ => None,
+    }).last()
+}
+
+fn output_base(options: &Vec<Options>) -> uint {
+    options.iter().filter_map(|option| match *option {
+        OutputBase(base) => Some(base),
+        _ => None,
+    }).last().unwrap_or(10)
+}
+
+// the messages to describe errors with, in the language --lang or the
+// locale asks for
+fn catalog(options: &Vec<Options>) -> Catalog {
+    let language = options.iter().filter_map(|option| match *option {
+        Language(ref language) => Some(language.clone()),
+        _ => None,
+    }).last().or_else(|| env_language());
+    let language = match language {
+        Some(language) => language,
+        None => return Catalog::english(),
+    };
+
+    let mut dirs = vec![];
+    match os::getenv("CALC_MESSAGES") {
+        Some(dir) => dirs.push(Path::new(dir)),
+        None => {},
+    }
+    match os::homedir() {
+        Some(home) => dirs.push(home.join(".calc_messages")),
+        None => {},
+    }
+    Catalog::find(language.as_slice(), dirs.as_slice())
+}
+
+// the print settings the flags ask for, or those last set with `set
+// format` in the REPL
+fn formatter(options: &Vec<Options>) -> Formatter {
+    let mut formatter = Formatter::new();
+    for option in options.iter() {
+        match *option {
+            Engineering => formatter.notation = EngineeringNotation,
+            ScientificFormat => formatter.notation = ScientificNotation,
+            Digits(places) => formatter.set_places(places),
+            SignificantDigits(digits) => formatter.set_digits(digits),
+            Separators => formatter.separators = true,
+            Format(ref settings) => formatter = settings.clone(),
+            _ => {},
+        }
+    }
+    formatter
+}
+
+fn show_number(n: f64, options: &Vec<Options>) -> String {
+    // fractions are left in decimal, since few readers want 0.1 in binary
+    let base = output_base(options);
+    match format::whole(n) {
+        Some(whole) if base != 10 => return format::radix(whole, base),
+        _ => {},
+    }
+
+    formatter(options).number(n)
+}
+
+fn show_value(value: &Value, options: &Vec<Options>) -> String {
+    if options.contains(&SpeakFormat) {
+        speak_value(value, options)
+    } else {
+        write_value(value, options)
+    }
+}
+
+// a result read out as words, for --speak-format
+fn speak_value(value: &Value, options: &Vec<Options>) -> String {
+    match *value {
+        Expression(ref node) => speech::speak(&**node),
+        List(ref items) => {
+            let words: Vec<String> = items.iter().map(|item| speak_value(item, options)).collect();
+            format!("the list {}", speech::join(words.as_slice()))
+        },
+        _ => speech::text(write_value(value, options).as_slice()),
+    }
+}
+
+fn write_value(value: &Value, options: &Vec<Options>) -> String {
+    // integers are written out directly, since those past 2^53 would lose
+    // digits on the way through a double; with a word width, bases other
+    // than ten show its two's complement
+    let base = output_base(options);
+    match (value, word_width(options)) {
+        (&Integer(n), bits) if options.contains(&Bases) => return format::bases(n, bits),
+        (&Integer(n), Some(bits)) if base != 10 => return format::word_radix(n, bits, base),
+        (&Integer(n), None) if base != 10 => return format::radix(n, base),
+        (&Exact(ref r), _) if options.contains(&Repeating) => return format::repeating(r),
+        _ => {},
+    }
+    // a quantity that --out-unit cannot express is printed as it is
+    match (value, out_unit(options)) {
+        (&Quantity(ref m), Some(ref unit)) => match m.convert(unit.as_slice()) {
+            Ok(converted) => return format!("{}", converted),
+            Err(_) => {},
+        },
+        _ => {},
+    }
+    match value.number() {
+        Ok(n) => show_number(n, options),
+        _ => format!("{}", value),
+    }
+}
+
+fn report(result: Result<Option<Value>, CalcError>, options: &Vec<Options>) {
+    match result {
+        Ok(None) => {},
+        Ok(Some(value)) => println!("{}", show_value(&value, options)),
+        Err(e) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "error: {}", catalog(options).message(&e));
+            os::set_exit_status(1);
+        },
+    }
+}
+
+// `report`, with errors that point into `input` shown under it with a caret
+fn report_input(result: Result<Option<Value>, CalcError>, input: &str, options: &Vec<Options>) {
+    match result {
+        Err(ref e) if e.position().is_some() => {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "{}", diagnostic::render_inline(e, &catalog(options), input));
+            os::set_exit_status(1);
+        },
+        result => report(result, options),
+    }
+}
+
+// `calc derive EXPR VAR` prints the derivative of EXPR with respect to VAR
+fn derive_command(args: &[String]) -> Result<Option<Value>, CalcError> {
+    if args.len() != 2 {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "usage: calc derive EXPRESSION VARIABLE");
+        os::set_exit_status(2);
+        return Ok(None);
+    }
+
+    let tree = try!(ExprTree::build(args[0].as_slice()));
+    match tree.root() {
+        Some(root) => calculus::derive(root, args[1].as_slice())
+                          .map(|node| Some(Expression(box node))),
+        None => Err(EmptyExpression),
+    }
+}
+
+// the argument after a flag, parsed
+#[cfg(feature = "finance")]
+fn flag_value<T: FromStr>(iter: &mut slice::Items<String>) -> Option<T> {
+    iter.next().and_then(|v| from_str(v.as_slice()))
+}
+
+// `calc amortize --principal P --rate R --years Y [--per-year N] [--csv]`
+// prints the payment schedule of a fixed-rate loan
+#[cfg(feature = "finance")]
+fn amortize_command(args: &[String], options: &Vec<Options>) {
+    let mut principal: Option<f64> = None;
+    let mut rate: Option<f64> = None;
+    let mut years: Option<uint> = None;
+    let mut per_year = Some(12u);
+
+    let mut iter = args.iter();
+    loop {
+        let arg = match iter.next() {
+            Some(arg) => arg,
+            None => break,
+        };
+        match arg.as_slice() {
+            "--principal" => principal = flag_value(&mut iter),
+            "--rate" => rate = flag_value(&mut iter),
+            "--years" => years = flag_value(&mut iter),
+            "--per-year" => per_year = flag_value(&mut iter),
+            _ => {
+                principal = None;
+                break;
+            },
+        }
+    }
+
+    match (principal, rate, years, per_year) {
+        (Some(principal), Some(rate), Some(years), Some(per_year)) if per_year > 0 => {
+            let schedule = finance::amortize(principal, rate, years, per_year);
+            if options.contains(&Csv) {
+                print!("{}", finance::schedule_csv(schedule.as_slice()));
+            } else {
+                print!("{}", finance::schedule_table(schedule.as_slice()));
+            }
+        },
+        _ => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "usage: calc amortize --principal AMOUNT --rate ANNUAL_RATE \
+                                      --years YEARS [--per-year PAYMENTS] [--csv]");
+            os::set_exit_status(2);
+        },
+    }
+}
+
+#[cfg(not(feature = "finance"))]
+fn amortize_command(_: &[String], _: &Vec<Options>) {
+    missing_feature("finance");
+}
+
+// `calc simplify EXPR` prints EXPR with constants folded
+fn simplify_command(args: &[String]) -> Result<Option<Value>, CalcError> {
+    if args.len() != 1 {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "usage: calc simplify EXPRESSION");
+        os::set_exit_status(2);
+        return Ok(None);
+    }
+
+    let tree = try!(ExprTree::build(args[0].as_slice())).simplify();
+    match tree.root() {
+        Some(root) => Ok(Some(Expression(box root.clone()))),
+        None => Err(EmptyExpression),
+    }
+}
+
+// `calc fmt FILE` prints FILE with each line in the canonical form of
+// `format_line`; lines that can't be read are printed as they are
+fn fmt_command(args: &[String], options: &Vec<Options>) {
+    if args.len() != 1 {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "usage: calc fmt FILE");
+        os::set_exit_status(2);
+        return;
+    }
+
+    let path = args[0].as_slice();
+    let text = match File::open(&Path::new(path)).and_then(|mut file| file.read_to_str()) {
+        Ok(text) => text,
+        Err(e) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "error: cannot read {}: {}", path, e);
+            os::set_exit_status(1);
+            return;
+        },
+    };
+
+    let settings = parse_settings(options);
+    let messages = catalog(options);
+    for (number, line) in text.as_slice().lines_any().enumerate() {
+        match format_line(line, &settings) {
+            Ok(formatted) => println!("{}", formatted),
+            Err(e) => {
+                println!("{}", line);
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "error: {}:{}: {}", path, number + 1,
+                                 messages.message(&e));
+                os::set_exit_status(1);
+            },
+        }
+    }
+}
+
+// `line` as `calc fmt` writes it: numbers in their shortest spelling,
+// then the tree written back out, with single spaces around operators and
+// only the parentheses it needs
+fn format_line(line: &str, settings: &ParseOptions) -> Result<String, CalcError> {
+    let mut text = String::from_str(line.trim());
+    if text.is_empty() {
+        return Ok(text);
+    }
+
+    // from the end, so the positions of the numbers before are unchanged
+    let tokens = try!(ExprTree::tokenize(text.as_slice(), settings));
+    for token in tokens.iter().rev().filter(|token| token.ttype == Numeric) {
+        let (start, end) = (token.position, token.position + token.length);
+        text = format!("{}{}{}", text.as_slice().slice_to(start),
+                       number_spelling(text.as_slice().slice(start, end)),
+                       text.as_slice().slice_from(end));
+    }
+    Ok(try!(ExprTree::build_with(text.as_slice(), settings)).format(Infix))
+}
+
+// a decimal number without leading or trailing zeros, a trailing point or
+// a `+` or capital in its exponent, so `00.50E+03` is `0.5e3`; other
+// numbers, such as `0xff` or `1_000`, are kept as they are written
+fn number_spelling(number: &str) -> String {
+    let (mantissa, exponent) = split_at(number, number.find('e').or(number.find('E')));
+    let (whole, fraction) = split_at(mantissa, mantissa.find('.'));
+    let negative = exponent.starts_with("-");
+    let power = if negative || exponent.starts_with("+") {
+        exponent.slice_from(1)
+    } else {
+        exponent
+    };
+    if !(is_digits(whole) && is_digits(fraction) && is_digits(power)) ||
+       (whole.is_empty() && fraction.is_empty()) ||
+       (power.is_empty() && !exponent.is_empty()) {
+        return String::from_str(number);
+    }
+
+    let mut spelled = String::from_str(whole.trim_left_chars('0'));
+    if spelled.is_empty() {
+        spelled.push_char('0');
+    }
+    let fraction = fraction.trim_right_chars('0');
+    if !fraction.is_empty() {
+        spelled.push_char('.');
+        spelled.push_str(fraction);
+    }
+    let power = power.trim_left_chars('0');
+    if !power.is_empty() {
+        spelled.push_str(format!("e{}{}", if negative { "-" } else { "" }, power).as_slice());
+    }
+    spelled
+}
+
+fn is_digits(text: &str) -> bool {
+    text.chars().all(|c| c.is_digit())
+}
+
+// the text either side of the character at `at`, or all of it and nothing
+fn split_at<'a>(text: &'a str, at: Option<uint>) -> (&'a str, &'a str) {
+    match at {
+        Some(i) => (text.slice_to(i), text.slice_from(i + 1)),
+        None => (text, ""),
+    }
+}
+
+// `calc lint FILE` points out the lines of FILE that are likely mistakes
+// or could be written more simply, and those that can't be read
+fn lint_command(args: &[String], options: &Vec<Options>) {
+    if args.len() != 1 {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "usage: calc lint FILE");
+        os::set_exit_status(2);
+        return;
+    }
+
+    let path = args[0].as_slice();
+    let text = match File::open(&Path::new(path)).and_then(|mut file| file.read_to_str()) {
+        Ok(text) => text,
+        Err(e) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "error: cannot read {}: {}", path, e);
+            os::set_exit_status(1);
+            return;
+        },
+    };
+
+    let settings = parse_settings(options);
+    let messages = catalog(options);
+    let mut linter = Linter::new(&settings);
+    for (number, line) in text.as_slice().lines_any().enumerate() {
+        match linter.lint(line) {
+            Ok(findings) => for finding in findings.iter() {
+                println!("{}:{}: warning: {}", path, number + 1, finding);
+                os::set_exit_status(1);
+            },
+            Err(e) => {
+                println!("{}:{}: error: {}", path, number + 1, messages.message(&e));
+                os::set_exit_status(1);
+            },
+        }
+    }
+}
+
+// `calc solve EQUATION VAR [LOW HIGH]` prints the solutions of EQUATION
+// for VAR; with bounds, every root between them
+fn solve_command(args: &[String], options: &Vec<Options>) -> Result<Option<Value>, CalcError> {
+    let bounds = match args.len() {
+        2 => Some(None),
+        4 => match (from_str::<f64>(args[2].as_slice()), from_str::<f64>(args[3].as_slice())) {
+            (Some(lo), Some(hi)) => Some(Some((lo.min(hi), lo.max(hi)))),
+            _ => None,
+        },
+        _ => None,
+    };
+    let bounds = match bounds {
+        Some(bounds) => bounds,
+        None => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "usage: calc solve EQUATION VARIABLE [LOW HIGH]");
+            os::set_exit_status(2);
+            return Ok(None);
+        },
+    };
+
+    let tree = try!(ExprTree::build_with(args[0].as_slice(), &parse_settings(options)));
+    let root = match tree.root() {
+        Some(root) => root,
+        None => return Err(EmptyExpression),
+    };
+    let mut env = environment(options);
+    let mut solutions = try!(solver::solve(root, args[1].as_slice(), bounds, &mut env));
+    if solutions.len() == 1 {
+        Ok(Some(Expression(box solutions.pop().unwrap())))
+    } else {
+        Ok(Some(List(solutions.move_iter().map(|s| Expression(box s)).collect())))
+    }
+}
+
+// `calc --plot EXPR [--range FROM:TO]` charts EXPR as a function of x
+fn plot_command(expression: &str, options: &Vec<Options>) -> Result<Option<Value>, CalcError> {
+    let tree = try!(ExprTree::build_with(expression, &parse_settings(options)));
+    let mut env = environment(options);
+    match chart(&tree, options, &mut env) {
+        Some(chart) => print!("{}", try!(chart)),
+        None => missing_feature("plot"),
+    }
+    Ok(None)
+}
+
+// the chart of `tree` in x over --range, or `None` when calc was built
+// without the plot feature
+#[cfg(feature = "plot")]
+fn chart(tree: &ExprTree, options: &Vec<Options>,
+         env: &mut Environment) -> Option<Result<String, CalcError>> {
+    let (from, to) = range_option(options);
+    Some(plot::plot(tree, "x", from, to, plot::DEFAULT_WIDTH, plot::DEFAULT_HEIGHT, env))
+}
+
+#[cfg(not(feature = "plot"))]
+fn chart(_: &ExprTree, _: &Vec<Options>,
+         _: &mut Environment) -> Option<Result<String, CalcError>> {
+    None
+}
+
+// reports that calc was built without `feature`, which was asked for
+fn missing_feature(feature: &str) {
+    let mut stderr = io::stderr();
+    let _ = writeln!(stderr, "error: calc was built without the {} feature; \
+                              see FEATURES in the Makefile", feature);
+    os::set_exit_status(2);
+}
+
+// `calc --stats` summarizes the numbers on stdin, one per line
+#[cfg(feature = "stats")]
+fn stats_command() {
+    let mut summary = stats::Summary::new();
+    let mut stdin = io::stdin();
+    let mut stderr = io::stderr();
+
+    for (number, line) in stdin.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let text = line.as_slice().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        match from_str::<f64>(text) {
+            Some(x) => summary.add(x),
+            None => {
+                let _ = writeln!(stderr, "warning: line {} is not a number: {}", number + 1, text);
+            },
+        }
+    }
+
+    print!("{}", summary.report());
+}
+
+#[cfg(not(feature = "stats"))]
+fn stats_command() {
+    missing_feature("stats");
+}
+
+// `calc --op-stats` counts the operators and built-ins used by the
+// formulas on stdin, one per line, and reports them most used first
+fn operation_stats_command(options: &Vec<Options>) {
+    let settings = parse_settings(options);
+    let mut usage = Usage::new();
+    let mut stdin = io::stdin();
+
+    for line in stdin.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let text = line.as_slice().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        match ExprTree::build_with(text, &settings) {
+            Ok(tree) => usage.add(&tree),
+            Err(_) => usage.skip(),
+        }
+    }
+
+    print!("{}", usage.report());
+}
+
+// `calc --fold EXPR [--init N]` folds the numbers on stdin into one value,
+// with each number bound to x and the running value to acc
+fn fold_command(expression: &str, options: &Vec<Options>) -> Result<Option<Value>, CalcError> {
+    let init = options.iter().filter_map(|option| match *option {
+        Init(init) => Some(init),
+        _ => None,
+    }).last().unwrap_or(0_f64);
+
+    let tree = try!(ExprTree::build_with(expression, &parse_settings(options)));
+    let mut program = tree.compile().ok();
+    let mut env = environment(options);
+    let mut acc = init;
+
+    // acc and x change from line to line, so any other inputs are read once
+    match program {
+        Some(ref mut program) => {
+            let mode = env.mode;
+            env.set("acc", Value::from_f64(acc, mode));
+            env.set("x", Value::from_f64(0_f64, mode));
+            try!(program.load(&env));
+        },
+        None => {},
+    }
+
+    let mut stdin = io::stdin();
+    for (number, line) in stdin.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let text = line.as_slice().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let x = match from_str::<f64>(text) {
+            Some(x) => x,
+            None => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "warning: line {} is not a number: {}", number + 1, text);
+                continue;
+            },
+        };
+
+        let mode = env.mode;
+        env.set("acc", Value::from_f64(acc, mode));
+        env.set("x", Value::from_f64(x, mode));
+        // trees that can't be compiled are walked instead
+        acc = try!(match program {
+            Some(ref mut program) => {
+                set_input(program, "acc", acc);
+                set_input(program, "x", x);
+                program.eval_loaded(env.angle)
+            },
+            None => tree.eval_in(&mut env).and_then(|v| v.number()),
+        });
+    }
+
+    Ok(Some(Value::from_f64(acc, env.mode)))
+}
+
+// puts `value` in the slot `program` reads `name` from, if it reads it
+fn set_input(program: &mut Program, name: &str, value: f64) {
+    match program.slot(name) {
+        Some(slot) => program.set_slot(slot, value),
+        None => {},
+    }
+}
+
+fn range_option(options: &Vec<Options>) -> (f64, f64) {
+    options.iter().filter_map(|option| match *option {
+        Range(from, to) => Some((from, to)),
+        _ => None,
+    }).last().unwrap_or((-10_f64, 10_f64))
+}
+
+// `calc --table EXPR [--range FROM:TO] [--step S] [--csv]` lists x and the
+// value of EXPR at each step across the range
+fn table_command(expression: &str, options: &Vec<Options>) -> Result<Option<Value>, CalcError> {
+    let (from, to) = range_option(options);
+    let step = options.iter().filter_map(|option| match *option {
+        Step(step) => Some(step),
+        _ => None,
+    }).last().unwrap_or((to - from) / 10_f64);
+    let csv = options.contains(&Csv);
+
+    let tree = try!(ExprTree::build_with(expression, &parse_settings(options)));
+    let mut program = tree.compile().ok();
+    let mut env = environment(options);
+
+    // only x changes from row to row, so any other inputs are read once
+    match program {
+        Some(ref mut program) => {
+            let mode = env.mode;
+            env.set("x", Value::from_f64(from, mode));
+            try!(program.load(&env));
+        },
+        None => {},
+    }
+
+    if csv {
+        println!("x,{}", expression);
+    } else {
+        println!("{:>14}  {}", "x", expression);
+    }
+
+    // stepping by multiplication keeps rounding from piling up
+    let steps = ((to - from) / step + 1e-9).floor() as uint;
+    for i in range(0, steps + 1) {
+        let x = from + step * i as f64;
+        let mode = env.mode;
+        env.set("x", Value::from_f64(x, mode));
+        let y = try!(match program {
+            Some(ref mut program) => {
+                set_input(program, "x", x);
+                program.eval_loaded(env.angle)
+            },
+            None => tree.eval_in(&mut env).and_then(|v| v.number()),
+        });
+
+        if csv {
+            println!("{},{}", x, y);
+        } else {
+            println!("{:>14}  {}", show_number(x, options), show_number(y, options));
+        }
+    }
+    Ok(None)
+}
+
+// evaluate each line of `input` in order, sharing one environment; an
+// error stops the run unless --keep-going was given
+fn batch<B: Buffer>(input: &mut B, options: &Vec<Options>) {
+    let lines = input.lines().take_while(|line| line.is_ok()).map(|line| line.unwrap());
+    run_lines(lines, None, options);
+}
+
+// the body of `batch`; a script's path and whole text let errors be shown
+// in context
+fn run_lines<I: Iterator<String>>(lines: I, script: Option<(&str, &[String])>,
+                                  options: &Vec<Options>) {
+    let mut env = environment(options);
+    let settings = parse_settings(options);
+    let messages = catalog(options);
+    let (mut evaluated, mut failed) = (0u, 0u);
+    let timeout = options.iter().filter_map(|option| match *option {
+        Timeout(ms) => Some(ms),
+        _ => None,
+    }).last();
+
+    for (number, line) in lines.enumerate() {
+        let expression = line.as_slice().trim();
+        if expression.is_empty() {
+            continue;
+        }
+
+        evaluated += 1;
+        // each line gets the whole of --timeout-ms
+        env.set_timeout(timeout);
+        match evaluate_line(expression, &settings, options, &mut env) {
+            Err(e) => {
+                failed += 1;
+                let mut stderr = io::stderr();
+                let _ = match script {
+                    Some((path, source)) =>
+                        write!(stderr, "{}", diagnostic::render(&e, &messages, path, source,
+                                                                number)),
+                    None => writeln!(stderr, "error: line {}: {}", number + 1,
+                                     messages.message(&e)),
+                };
+                os::set_exit_status(1);
+                // a line that ran out of time says nothing of the next
+                let timed_out = match e {
+                    LimitExceeded { limit: Milliseconds, .. } => true,
+                    _ => false,
+                };
+                if !options.contains(&KeepGoing) && !timed_out {
+                    break;
+                }
+            },
+            result => report(result, options),
+        }
+    }
+
+    // with --keep-going a failure early on may have scrolled away, so the
+    // count is given again at the end
+    if failed > 0 && options.contains(&KeepGoing) {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "error: {} of {} lines failed", failed, evaluated);
+        os::set_exit_status(1);
+    }
+}
+
+// `calc --window N EXPR` evaluates EXPR for each number on stdin, with the
+// last N numbers bound to the list `xs` and the newest to `x`
+fn window_command(size: uint, expression: &str, options: &Vec<Options>) {
+    let tree = match ExprTree::build_with(expression, &parse_settings(options)) {
+        Ok(tree) => tree,
+        Err(e) => return report(Err(e), options),
+    };
+    let mut env = environment(options);
+    let mut window: RingBuf<f64> = RingBuf::with_capacity(size);
+
+    let mut stdin = io::stdin();
+    for (number, line) in stdin.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let text = line.as_slice().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let x = match from_str::<f64>(text) {
+            Some(x) => x,
+            None => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "warning: line {} is not a number: {}", number + 1, text);
+                continue;
+            },
+        };
+        if window.len() == size {
+            window.pop_front();
+        }
+        window.push_back(x);
+
+        let mode = env.mode;
+        env.set("x", Value::from_f64(x, mode));
+        env.set("xs", List(window.iter().map(|&v| Value::from_f64(v, mode)).collect()));
+        match tree.eval_in(&mut env) {
+            Err(e) => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "error: line {}: {}", number + 1,
+                                 catalog(options).message(&e));
+                os::set_exit_status(1);
+                if !options.contains(&KeepGoing) {
+                    break;
+                }
+            },
+            result => report(result.map(|v| Some(v)), options),
+        }
+    }
 }
 
-// fn parse_options(args: Vec<String>) -> Vec<Options> {
-//     let mut result: Vec<Options> = vec![];
+fn script_command(path: &str, options: &Vec<Options>) {
+    match File::open(&Path::new(path)).and_then(|mut file| file.read_to_str()) {
+        Ok(text) => {
+            let source: Vec<String> = text.as_slice().lines_any()
+                .map(|line| String::from_str(line))
+                .collect();
+            run_lines(source.iter().map(|line| line.clone()),
+                      Some((path, source.as_slice())), options);
+        },
+        Err(e) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "error: cannot read {}: {}", path, e);
+            os::set_exit_status(1);
+        },
+    }
+}
+
+// read expressions line by line, keeping variables between them; the
+// session is recorded for `:export FILE`
+#[cfg(feature = "repl")]
+fn repl(options: &Vec<Options>) {
+    let mut env = environment(options);
+    let settings = parse_settings(options);
+    let mut stdin = io::stdin();
+    let mut transcript = Transcript::new();
+    let messages = catalog(options);
+    // `set format ...` changes how results are printed from then on
+    let mut options = options.clone();
+    // the last result as printed, for `:copy`
+    let mut last: Option<String> = None;
+    // memory registers are kept between sessions in the home directory
+    let mut session = match os::homedir() {
+        Some(home) => Session::load(home.join(".calc_memory")),
+        None => Session::new(),
+    };
+
+    loop {
+        print!("> ");
+        io::stdio::flush();
+
+        let line = match stdin.read_line() {
+            Ok(line) => line,
+            Err(_) => break,
+        };
 
-//     for arg in args.iter() {
+        let expression = line.as_slice().trim();
+        if expression.is_empty() {
+            continue;
+        }
 
-//     }
+        // `mode deg` or `mode rad` switches the angle unit for what follows
+        match expression {
+            "mode deg" => {
+                env.angle = Degrees;
+                continue;
+            },
+            "mode rad" => {
+                env.angle = Radians;
+                continue;
+            },
+            _ => {},
+        }
+        if expression.starts_with("set format ") {
+            let mut format = formatter(&options);
+            match format.configure(expression.slice_from(11)) {
+                Ok(()) => {
+                    options.retain(|option| match *option {
+                        Format(_) => false,
+                        _ => true,
+                    });
+                    options.push(Format(format));
+                },
+                Err(message) => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "error: {}", message);
+                },
+            }
+            continue;
+        }
 
-// }
+        // `:copy` puts the last result on the clipboard, `:export FILE`
+        // writes the session so far as Markdown, and `:plot EXPR` charts
+        // EXPR in x over --range
+        if expression == ":copy" {
+            match last {
+                Some(ref text) => copy_text(text.as_slice()),
+                None => println!("no result to copy yet"),
+            }
+            continue;
+        }
+        if expression.starts_with(":export ") {
+            let path = expression.slice_from(8).trim();
+            match transcript.export(&Path::new(path)) {
+                Ok(()) => println!("wrote the session to {}", path),
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    let _ = writeln!(stderr, "error: cannot write {}: {}", path, e);
+                },
+            }
+            continue;
+        }
+        if expression.starts_with(":plot ") {
+            let plotted = match ExprTree::build_with(expression.slice_from(6), &settings) {
+                Ok(tree) => match chart(&tree, &options, &mut env) {
+                    Some(chart) => chart,
+                    None => {
+                        println!("calc was built without plotting");
+                        continue;
+                    },
+                },
+                Err(e) => Err(e),
+            };
+            match plotted {
+                Ok(chart) => {
+                    print!("{}", chart);
+                    transcript.record(Plotted {
+                        input: String::from_str(expression),
+                        chart: chart,
+                    });
+                },
+                Err(e) => {
+                    transcript.record(Failed {
+                        input: String::from_str(expression),
+                        message: messages.message(&e),
+                    });
+                    report(Err(e), &options);
+                },
+            }
+            continue;
+        }
+
+        // `:vars` lists the variables and then the functions defined so
+        // far, each in name order
+        if expression == ":vars" {
+            for (name, value) in env.vars().iter() {
+                println!("{} = {}", name, show_value(value, &options));
+            }
+            for (name, function) in env.functions().iter() {
+                let params: Vec<String> = function.params.iter().zip(function.defaults.iter())
+                    .map(|(param, default)| match *default {
+                        Some(ref default) => format!("{} = {}", param, default),
+                        None => param.clone(),
+                    })
+                    .collect();
+                println!("{}({}) = {}", name, params.as_slice().connect(", "), function.body);
+            }
+            continue;
+        }
+
+        // `history` lists the results so far, and `#N` in an expression
+        // stands for result N
+        if expression == "history" {
+            for (i, &(ref input, ref value)) in session.history().iter().enumerate() {
+                println!("#{:<4} {} = {}", i + 1, input, show_value(value, &options));
+            }
+            continue;
+        }
+        if memory_command(expression, &mut session, &mut env, &options) {
+            continue;
+        }
+        let expanded = match session.expand(expression) {
+            Ok(expanded) => expanded,
+            Err(message) => {
+                let mut stderr = io::stderr();
+                let _ = writeln!(stderr, "error: {}", message);
+                continue;
+            },
+        };
+
+        let result = evaluate_line(expanded.as_slice(), &settings, &options, &mut env);
+        match result {
+            Ok(Some(ref value)) => { session.record(expression, value.clone(), &mut env); },
+            _ => {},
+        }
+        transcript.record(match result {
+            Ok(ref value) => {
+                let output = value.as_ref().map(|value| show_value(value, &options));
+                if output.is_some() {
+                    last = output.clone();
+                }
+                Evaluated { input: String::from_str(expression), output: output }
+            },
+            Err(ref e) => Failed {
+                input: String::from_str(expression),
+                message: messages.message(e),
+            },
+        });
+        report_input(result, expanded.as_slice(), &options);
+    }
+}
+
+// without the repl feature, expressions are read from stdin one per line
+// as with `-`, with no commands or memory registers
+#[cfg(not(feature = "repl"))]
+fn repl(options: &Vec<Options>) {
+    batch(&mut io::stdin(), options);
+}
+
+// carry out a memory register command, if `line` is one: `M+` and `M-`
+// add the last result to the default register or take it away, `MR`
+// recalls it and `MC` empties it; `store NAME` and `recall NAME` do the
+// same for a named register, and `memory` lists them all
+#[cfg(feature = "repl")]
+fn memory_command(line: &str, session: &mut Session, env: &mut Environment,
+                  options: &Vec<Options>) -> bool {
+    let words: Vec<&str> = line.words().collect();
+    let (command, name) = match words.len() {
+        1 => (*words.get(0), DEFAULT_REGISTER),
+        2 if *words.get(0) == "store" || *words.get(0) == "recall" =>
+            (*words.get(0), *words.get(1)),
+        _ => return false,
+    };
+    let mut stderr = io::stderr();
+
+    let last = session.last().map(|value| value.number());
+    let saved = match command {
+        "memory" => {
+            for (name, value) in session.registers().iter() {
+                println!("{:<12} {}", name, show_number(*value, options));
+            }
+            Ok(())
+        },
+        "M+"|"M-"|"store" => {
+            let value = match last {
+                Some(Ok(value)) => value,
+                Some(Err(e)) => {
+                    let _ = writeln!(stderr, "error: {}", catalog(options).message(&e));
+                    return true;
+                },
+                None => {
+                    let _ = writeln!(stderr, "error: there is no result to store yet");
+                    return true;
+                },
+            };
+            match command {
+                "M+" => session.accumulate(name, value).map(|_| ()),
+                "M-" => session.accumulate(name, -value).map(|_| ()),
+                _ => session.store(name, value),
+            }
+        },
+        "MR"|"recall" => {
+            match session.recall(name) {
+                Some(value) => {
+                    let value = Value::from_f64(value, env.mode);
+                    println!("{}", show_value(&value, options));
+                    session.record(line, value, env);
+                },
+                None => {
+                    let _ = writeln!(stderr, "error: register '{}' is empty", name);
+                },
+            }
+            Ok(())
+        },
+        "MC" => session.clear(name),
+        _ => return false,
+    };
+
+    match saved {
+        Ok(()) => {},
+        Err(e) => {
+            let _ = writeln!(stderr, "warning: cannot save the memory registers: {}", e);
+        },
+    }
+    true
+}
 
 fn main() {
     let args = os::args();
-    let expression = args.get(1);
+    let (options, positional) = match parse_options(args.slice_from(1)) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "error: {}; see --help", message);
+            os::set_exit_status(2);
+            return;
+        },
+    };
+    if options.contains(&Help) {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let plotted = options.iter().filter_map(|option| match *option {
+        Plot(ref expression) => Some(expression.clone()),
+        _ => None,
+    }).last();
+
+    let folded = options.iter().filter_map(|option| match *option {
+        Fold(ref expression) => Some(expression.clone()),
+        _ => None,
+    }).last();
+
+    let tabulated = options.iter().filter_map(|option| match *option {
+        Table(ref expression) => Some(expression.clone()),
+        _ => None,
+    }).last();
+
+    let script = options.iter().filter_map(|option| match *option {
+        Script(ref path) => Some(path.clone()),
+        _ => None,
+    }).last();
+
+    let window = options.iter().filter_map(|option| match *option {
+        Window(size) => Some(size),
+        _ => None,
+    }).last();
+
+    match (plotted, folded, tabulated) {
+        (Some(expression), _, _) =>
+            report(plot_command(expression.as_slice(), &options), &options),
+        (None, Some(expression), _) =>
+            report(fold_command(expression.as_slice(), &options), &options),
+        (None, None, Some(expression)) =>
+            report(table_command(expression.as_slice(), &options), &options),
+        _ if options.contains(&Stats) => stats_command(),
+        _ if options.contains(&OperationStats) => operation_stats_command(&options),
+        _ if script.is_some() => script_command(script.unwrap().as_slice(), &options),
+        _ if positional.is_empty() || options.contains(&ForceRepl) => repl(&options),
+        _ if window.is_some() =>
+            window_command(window.unwrap(), positional.get(0).as_slice(), &options),
+        _ => match positional.get(0).as_slice() {
+            "-" => batch(&mut io::stdin(), &options),
+            "amortize" => amortize_command(positional.slice_from(1), &options),
+            "derive" => report(derive_command(positional.slice_from(1)), &options),
+            "fmt" => fmt_command(positional.slice_from(1), &options),
+            "lint" => lint_command(positional.slice_from(1), &options),
+            "simplify" => report(simplify_command(positional.slice_from(1)), &options),
+            "solve" => report(solve_command(positional.slice_from(1), &options), &options),
+            expression => {
+                let mut env = environment(&options);
+                let settings = parse_settings(&options);
+                let result = evaluate_line(expression, &settings, &options, &mut env);
+                if options.contains(&CopyResult) {
+                    match result {
+                        Ok(Some(ref value)) => copy_text(show_value(value, &options).as_slice()),
+                        _ => {},
+                    }
+                }
+                report_input(result, expression, &options);
+            },
+        },
+    }
+}
+
+// put `text` on the clipboard, warning when that can't be done
+#[cfg(feature = "repl")]
+fn copy_text(text: &str) {
+    match clipboard::copy(text) {
+        Ok(()) => {},
+        Err(message) => {
+            let mut stderr = io::stderr();
+            let _ = writeln!(stderr, "warning: cannot copy the result: {}", message);
+        },
+    }
+}
+
+#[cfg(not(feature = "repl"))]
+fn copy_text(_: &str) {
+    let mut stderr = io::stderr();
+    let _ = writeln!(stderr, "warning: cannot copy the result: calc was built \
+                              without the repl feature");
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use calc::{ExprTree, ParseOptions, Real, List};
+    use calc::{analysis, quick};
+    use calc::error::{LimitExceeded, Milliseconds};
+    use super::{Quiet, ShowCondition, Fold, Init, Range, Step, Timeout, Window};
+    use super::{Help, ForceRepl, Engineering, Digits, BigPrecision, CopyResult, Separators};
+    use super::FixedFormat;
+    use super::{condition_warning, environment, parse_options, parse_range, quick_path};
+    use super::{evaluate_line, format_line, parse_settings, range_option, show_value};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split(' ').map(|arg| String::from_str(arg)).collect()
+    }
+
+    #[test]
+    fn near_equal_differences_warn() {
+        let mut env = environment(&vec![]);
+        env.set("a", Real(1.000000001));
+        env.set("b", Real(1_f64));
+        let tree = ExprTree::build("a - b").unwrap();
+        let condition = analysis::condition(&tree, &env).unwrap().unwrap();
+        assert!(condition_warning(&condition).is_some());
+
+        // `a - b` is one operator between variables, which the quick path
+        // finds the same condition without the parser
+        let settings = ParseOptions::new();
+        let quick = quick::condition("a - b", &settings, &env).unwrap();
+        assert_eq!(quick.number, condition.number);
+        assert!(quick::condition("2 - 1.999", &settings, &env).is_none());
+    }
+
+    #[test]
+    fn plain_invocations_take_the_quick_path() {
+        let (options, _) = parse_options(args("price*1.08").as_slice()).unwrap();
+        assert!(quick_path(&options) && quick_path(&vec![Quiet]));
+        assert!(!quick_path(&vec![ShowCondition]));
+
+        let settings = parse_settings(&options);
+        let mut env = environment(&options);
+        env.set("price", Real(20_f64));
+        let quick = quick::eval("price*1.08", &settings, &mut env).unwrap().unwrap();
+        let value = evaluate_line("price*1.08", &settings, &options, &mut env).unwrap();
+        assert!(value == Some(quick));
+    }
+
+    #[test]
+    fn fold_reads_its_expression_and_start() {
+        let (options, positional) = parse_options(args("--fold acc+x --init 5").as_slice()).unwrap();
+        assert!(options == vec![Fold(String::from_str("acc+x")), Init(5_f64)]);
+        assert!(positional.is_empty());
+
+        // a start that isn't a number is left out, so acc begins at zero
+        let (options, _) = parse_options(args("--fold acc*x --init one").as_slice()).unwrap();
+        assert!(options == vec![Fold(String::from_str("acc*x"))]);
+    }
+
+    #[test]
+    fn tables_default_to_minus_ten_to_ten() {
+        assert_eq!(parse_range("-2.5:4"), Some((-2.5, 4_f64)));
+        assert_eq!(parse_range("4:-2.5"), None);
+        assert_eq!(parse_range("4"), None);
+        assert_eq!(range_option(&vec![]), (-10_f64, 10_f64));
+
+        let (options, _) = parse_options(args("--range 0:1 --step 0.25 --step -1").as_slice()).unwrap();
+        assert!(options == vec![Range(0_f64, 1_f64), Step(0.25)]);
+        assert_eq!(range_option(&options), (0_f64, 1_f64));
+    }
+
+    #[test]
+    fn formatting_twice_changes_nothing() {
+        let settings = parse_settings(&vec![]);
+        let lines = ["x=1.50*(a+b)", "memo f(n, k=2)=(n*k)", "  00.50E+03 -2.", "y = ((a)) - -b",
+                     "\"km\" + 0xFF + 1_000", ""];
+        let formatted: Vec<String> = lines.iter()
+            .map(|line| format_line(*line, &settings).unwrap())
+            .collect();
+        let expected = ["x = 1.5 * (a + b)", "memo f(n, k = 2) = n * k", "0.5e3 - 2",
+                        "y = a - -b", "\"km\" + 0xFF + 1_000", ""];
+        for (line, expected) in formatted.iter().zip(expected.iter()) {
+            assert_eq!(line.as_slice(), *expected);
+            assert_eq!(&format_line(line.as_slice(), &settings).unwrap(), line);
+        }
+    }
+
+    #[test]
+    fn batch_lines_share_an_environment() {
+        let options = vec![];
+        let settings = parse_settings(&options);
+        let mut env = environment(&options);
+
+        // function definitions have no value to print, and later lines see
+        // what earlier ones defined
+        let line = |line: &str| evaluate_line(line, &settings, &options, &mut env);
+        assert!(line("f(x) = x + 1").ok() == Some(None));
+        assert!(line("a = 2").ok() == Some(Some(Real(2_f64))));
+        assert!(line("f(a) * 3").ok() == Some(Some(Real(9_f64))));
+        assert!(line("width * 3").is_err());
+    }
+
+    #[test]
+    fn slow_lines_time_out_alone() {
+        let (options, _) = parse_options(args("--timeout-ms 1 --timeout-ms soon").as_slice())
+            .unwrap();
+        assert!(options == vec![Timeout(1)]);
+
+        let settings = parse_settings(&options);
+        let mut env = environment(&options);
+        env.set_timeout(Some(1));
+        assert!(evaluate_line("sum(k, k, 1, 1e6)", &settings, &options, &mut env).err() ==
+                Some(LimitExceeded { limit: Milliseconds, max: 1 }));
+        // the next line has its own time
+        env.set_timeout(Some(1));
+        assert!(evaluate_line("2 * (1 + 2)", &settings, &options, &mut env).ok() ==
+                Some(Some(Real(6_f64))));
+    }
+
+    #[test]
+    fn windows_are_lists_of_recent_numbers() {
+        let (options, _) = parse_options(args("--window 0 --window 3").as_slice()).unwrap();
+        assert!(options == vec![Window(3)]);
+
+        let mut env = environment(&options);
+        env.set("x", Real(6_f64));
+        env.set("xs", List(vec![Real(2_f64), Real(4_f64), Real(6_f64)]));
+        let tree = ExprTree::build("x - mean(xs)").unwrap();
+        assert!(tree.eval_in(&mut env).ok() == Some(Real(2_f64)));
+    }
+
+    #[test]
+    fn unknown_flags_are_refused_outside_subcommands() {
+        let (options, positional) =
+            parse_options(args("-h -q --repl --format eng --precision 3 --precision big").as_slice())
+                .unwrap();
+        assert!(options == vec![Help, Quiet, ForceRepl, Engineering, Digits(3), BigPrecision]);
+        assert!(positional.is_empty());
+
+        let (options, _) = parse_options(args("--eng --sci --format plain -q").as_slice()).unwrap();
+        assert!(options == vec![Quiet]);
+        let (options, _) = parse_options(args("--format plain --eng").as_slice()).unwrap();
+        assert!(options == vec![Engineering]);
+
+        assert!(parse_options(args("--frobnicate 2+2").as_slice()).is_err());
+        let (_, positional) = parse_options(args("amortize --years 5").as_slice()).unwrap();
+        assert_eq!(positional, args("amortize --years 5"));
+        let (_, positional) = parse_options(args("-- --frobnicate").as_slice()).unwrap();
+        assert_eq!(positional, args("--frobnicate"));
+    }
+
+    #[test]
+    fn copies_are_written_as_printed() {
+        let (options, _) = parse_options(args("--copy --precision 2 --separators").as_slice()).unwrap();
+        assert!(options == vec![CopyResult, Digits(2), Separators]);
+        assert_eq!(show_value(&Real(1234.5678), &options), String::from_str("1,234.57"));
+    }
 
-    let tree = ExprTree::build(expression.as_slice());
-    tree.print();
-    println!("{}", tree.eval());
+    #[test]
+    fn places_and_q_formats_have_their_own_flags() {
+        let (options, _) = parse_options(args("--fixed 2 --qformat q15.16").as_slice()).unwrap();
+        assert!(options == vec![Digits(2), FixedFormat(String::from_str("q15.16"))]);
+        // a Q format is not a number of places
+        let (options, positional) = parse_options(args("--fixed 15.16").as_slice()).unwrap();
+        assert!(options.is_empty() && positional.is_empty());
+    }
 }
@@ -0,0 +1,167 @@
+use std::string::String;
+
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+
+/// Renders `node` as a presentation MathML `<math>` element, with the
+/// same parenthesization, fractions and roots as the LaTeX renderer.
+pub fn mathml(node: &ExprNode) -> String {
+    format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>", element(node))
+}
+
+fn element(node: &ExprNode) -> String {
+    let children = node.children();
+    match node.kind() {
+        ExprLiteral => literal(node.token()),
+        ExprVariable => format!("<mi>{}</mi>", escape(name(node.token()))),
+        ExprText => format!("<mtext>{}</mtext>", escape(node.token())),
+        ExprUnit => format!("<mrow>{}<mspace width=\"0.2em\"/>\
+                             <mi mathvariant=\"normal\">{}</mi></mrow>",
+                            base(children.get(0)), escape(node.token())),
+        ExprUnary => format!("<mrow><mo>-</mo>{}</mrow>",
+                             operand(children.get(0), node.precedence().unwrap(), false)),
+        ExprBinary => {
+            let (lhs, rhs) = (children.get(0), children.get(1));
+            match node.token() {
+                "/" => format!("<mfrac>{}{}</mfrac>", element(lhs), element(rhs)),
+                "//" => format!("<mrow><mo>⌊</mo><mfrac>{}{}</mfrac><mo>⌋</mo></mrow>",
+                                element(lhs), element(rhs)),
+                "^" => format!("<msup>{}{}</msup>", base(lhs), element(rhs)),
+                operator => {
+                    let precedence = node.precedence().unwrap();
+                    let right_assoc = node.is_right_assoc();
+                    format!("<mrow>{}<mo>{}</mo>{}</mrow>",
+                            operand(lhs, precedence, right_assoc),
+                            escape(operator_symbol(operator)),
+                            operand(rhs, precedence, !right_assoc))
+                },
+            }
+        },
+        ExprCall => {
+            let args: Vec<String> = children.iter().map(|child| element(child)).collect();
+            match (node.token(), args.len()) {
+                ("list", _) => fenced("[", args.as_slice().connect("<mo>,</mo>").as_slice(), "]"),
+                ("sqrt", 1) => format!("<msqrt>{}</msqrt>", args.get(0)),
+                ("cbrt", 1) => format!("<mroot>{}<mn>3</mn></mroot>", args.get(0)),
+                ("abs", 1) => fenced("|", args.get(0).as_slice(), "|"),
+                ("floor", 1) => fenced("⌊", args.get(0).as_slice(), "⌋"),
+                ("ceil", 1) => fenced("⌈", args.get(0).as_slice(), "⌉"),
+                ("exp", 1) => format!("<msup><mi>e</mi>{}</msup>", args.get(0)),
+                ("fact", 1) => format!("<mrow>{}<mo>!</mo></mrow>", base(children.get(0))),
+                ("log", 2) => format!("<mrow><msub><mi>log</mi>{}</msub><mo>&#x2061;</mo>{}</mrow>",
+                                      args.get(1), fenced("(", args.get(0).as_slice(), ")")),
+                (function, _) => {
+                    let head = match function {
+                        "lg" => String::from_str("<msub><mi>log</mi><mn>2</mn></msub>"),
+                        "log" => String::from_str("<msub><mi>log</mi><mn>10</mn></msub>"),
+                        "asin" => String::from_str("<mi>arcsin</mi>"),
+                        "acos" => String::from_str("<mi>arccos</mi>"),
+                        "atan" => String::from_str("<mi>arctan</mi>"),
+                        _ => format!("<mi>{}</mi>", escape(function)),
+                    };
+                    let listed = args.as_slice().connect("<mo>,</mo>");
+                    format!("<mrow>{}<mo>&#x2061;</mo>{}</mrow>",
+                            head, fenced("(", listed.as_slice(), ")"))
+                },
+            }
+        },
+    }
+}
+
+// an operand, in parentheses as precedence needs; fractions and floors are
+// already set apart
+fn operand(child: &ExprNode, precedence: i32, tie_needs_parens: bool) -> String {
+    let delimited = child.kind() == ExprBinary &&
+        (child.token() == "/" || child.token() == "//");
+    if !delimited && child.needs_parens(precedence, tie_needs_parens) {
+        fenced("(", element(child).as_slice(), ")")
+    } else {
+        element(child)
+    }
+}
+
+// the base of a power, factorial or unit, which any operator must be kept
+// apart from
+fn base(child: &ExprNode) -> String {
+    if child.precedence().is_some() {
+        fenced("(", element(child).as_slice(), ")")
+    } else {
+        element(child)
+    }
+}
+
+fn fenced(open: &str, inner: &str, close: &str) -> String {
+    format!("<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>", open, inner, close)
+}
+
+fn literal(token: &str) -> String {
+    match token {
+        "pi" => String::from_str("<mi>π</mi>"),
+        "tau" => String::from_str("<mi>τ</mi>"),
+        "phi" => String::from_str("<mi>φ</mi>"),
+        "euler_gamma" => String::from_str("<mi>γ</mi>"),
+        "e" => String::from_str("<mi>e</mi>"),
+        "sqrt2" => String::from_str("<msqrt><mn>2</mn></msqrt>"),
+        "ln2" => String::from_str("<mrow><mi>ln</mi><mn>2</mn></mrow>"),
+        "ln10" => String::from_str("<mrow><mi>ln</mi><mn>10</mn></mrow>"),
+        number => format!("<mn>{}</mn>", escape(number)),
+    }
+}
+
+fn name<'a>(token: &'a str) -> &'a str {
+    match token {
+        "alpha" => "α", "beta" => "β", "gamma" => "γ", "delta" => "δ",
+        "epsilon" => "ε", "zeta" => "ζ", "eta" => "η", "theta" => "θ",
+        "kappa" => "κ", "lambda" => "λ", "mu" => "μ", "nu" => "ν",
+        "xi" => "ξ", "rho" => "ρ", "sigma" => "σ", "omega" => "ω",
+        "upsilon" => "υ", "chi" => "χ", "psi" => "ψ", "omicron" => "ο",
+        other => other,
+    }
+}
+
+fn operator_symbol<'a>(operator: &'a str) -> &'a str {
+    match operator {
+        "*" => "⋅",
+        "%" => "mod",
+        "<=" => "≤",
+        ">=" => "≥",
+        "==" => "=",
+        "!=" => "≠",
+        "xor" => "⊕",
+        "<<" => "≪",
+        ">>" => "≫",
+        "|>" => "▷",
+        "to"|"in" => "→",
+        other => other,
+    }
+}
+
+// characters XML treats specially
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push_char(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use exprtree::{ExprTree, MathMl};
+
+    #[test]
+    fn operators_are_escaped() {
+        let math = ExprTree::build("a < b").unwrap().format(MathMl);
+        assert_eq!(math.as_slice(), "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">\
+                                     <mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow></math>");
+
+        let math = ExprTree::build("sqrt(x) / 2").unwrap().format(MathMl);
+        assert!(math.as_slice().contains("<mfrac><msqrt><mi>x</mi></msqrt><mn>2</mn></mfrac>"));
+    }
+}
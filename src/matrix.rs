@@ -0,0 +1,304 @@
+use std::string::String;
+
+use error::{CalcError, ShapeMismatch, SingularMatrix, TypeMismatch, UnknownFunction};
+use value::{Value, Real, List};
+
+/// Multiplies where `operator` is `*` and a matrix, written as a list of
+/// equally long rows, is on either side: a matrix times a matrix or a list
+/// is a matrix product, with a plain list standing for a column on the
+/// right and a row on the left. `None` for anything else, which lists do
+/// element by element.
+pub fn binary(operator: &str, lhs: &Value, rhs: &Value) -> Option<Result<Value, CalcError>> {
+    if operator != "*" {
+        return None;
+    }
+
+    let (a, b) = match (rows(lhs), rows(rhs)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => return Some(Err(e)),
+    };
+    match (a, b, lhs, rhs) {
+        (Some(a), Some(b), _, _) => Some(product(a, b)),
+        (Some(a), None, _, rhs) => match *rhs {
+            List(ref v) => {
+                let column = v.iter().map(|x| vec![x.clone()]).collect();
+                Some(product(a, column).map(flatten))
+            },
+            _ => None,
+        },
+        (None, Some(b), lhs, _) => match *lhs {
+            List(ref v) => Some(product(vec![v.clone()], b).map(flatten)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Applies the matrix built-in `name`: `dot`, `cross`, `det`, `inv` or
+/// `transpose`. Arity is checked by the caller.
+pub fn call(name: &str, args: &[Value]) -> Result<Value, CalcError> {
+    match name {
+        "dot" => {
+            let (a, b) = (try!(vector(&args[0])), try!(vector(&args[1])));
+            if a.len() != b.len() || a.is_empty() {
+                return Err(mismatch(name, &args[0], &args[1]));
+            }
+            let mut total = try!(Value::binary("*", a.get(0), b.get(0)));
+            for (x, y) in a.iter().zip(b.iter()).skip(1) {
+                total = try!(Value::binary("+", &total, &try!(Value::binary("*", x, y))));
+            }
+            Ok(total)
+        },
+        "cross" => {
+            let (a, b) = (try!(vector(&args[0])), try!(vector(&args[1])));
+            if a.len() != 3 || b.len() != 3 {
+                return Err(mismatch(name, &args[0], &args[1]));
+            }
+            let term = |i: uint, j: uint| -> Result<Value, CalcError> {
+                let left = try!(Value::binary("*", a.get(i), b.get(j)));
+                let right = try!(Value::binary("*", a.get(j), b.get(i)));
+                Value::binary("-", &left, &right)
+            };
+            Ok(List(vec![try!(term(1, 2)), try!(term(2, 0)), try!(term(0, 1))]))
+        },
+        "transpose" => {
+            let rows = match try!(rows(&args[0])) {
+                Some(rows) => rows,
+                // a plain list becomes a column
+                None => return Ok(List(try!(vector(&args[0])).iter()
+                                       .map(|x| List(vec![x.clone()]))
+                                       .collect())),
+            };
+            let columns = range(0, rows.get(0).len())
+                .map(|j| List(rows.iter().map(|row| row.get(j).clone()).collect()))
+                .collect();
+            Ok(List(columns))
+        },
+        "det" => {
+            let mut m = try!(square(name, &args[0]));
+            Ok(Real(eliminate(&mut m, None)))
+        },
+        "inv" => {
+            let mut m = try!(square(name, &args[0]));
+            let n = m.len();
+            let mut inverse: Vec<Vec<f64>> = range(0, n).map(|i| {
+                range(0, n).map(|j| if i == j { 1_f64 } else { 0_f64 }).collect()
+            }).collect();
+            if eliminate(&mut m, Some(&mut inverse)) == 0_f64 {
+                return Err(SingularMatrix);
+            }
+            Ok(List(inverse.move_iter()
+                    .map(|row| List(row.move_iter().map(|x| Real(x)).collect()))
+                    .collect()))
+        },
+        _ => Err(UnknownFunction(String::from_str(name))),
+    }
+}
+
+/// The shape of a value as error messages give it: `2x3` for a matrix,
+/// the length for a list, and `a number` for anything else.
+pub fn shape(value: &Value) -> String {
+    match *value {
+        List(ref items) => match rows(value) {
+            Ok(Some(rows)) => format!("{}x{}", rows.len(), rows.get(0).len()),
+            _ => format!("a list of {}", items.len()),
+        },
+        _ => String::from_str("a number"),
+    }
+}
+
+// the rows of a list of equally long, nonempty lists; `None` for anything
+// that isn't a list of lists
+fn rows(value: &Value) -> Result<Option<Vec<Vec<Value>>>, CalcError> {
+    let items = match *value {
+        List(ref items) if !items.is_empty() => items,
+        _ => return Ok(None),
+    };
+
+    let mut rows: Vec<Vec<Value>> = vec![];
+    for item in items.iter() {
+        match *item {
+            List(ref row) if !row.is_empty() => rows.push(row.clone()),
+            _ => return Ok(None),
+        }
+    }
+    if rows.iter().any(|row| row.len() != rows.get(0).len()) {
+        return Err(TypeMismatch { expected: "matrix with rows of one length",
+                                  found: "ragged list" });
+    }
+    Ok(Some(rows))
+}
+
+// the elements of a flat list
+fn vector(value: &Value) -> Result<Vec<Value>, CalcError> {
+    match *value {
+        List(ref items) if items.iter().all(|item| item.kind_name() != "list") =>
+            Ok(items.clone()),
+        ref other => Err(TypeMismatch { expected: "list of numbers", found: other.kind_name() }),
+    }
+}
+
+// a square matrix in doubles
+fn square(name: &str, value: &Value) -> Result<Vec<Vec<f64>>, CalcError> {
+    let rows = match try!(rows(value)) {
+        Some(rows) => rows,
+        None => vec![],
+    };
+    if rows.is_empty() || rows.len() != rows.get(0).len() {
+        return Err(ShapeMismatch {
+            operation: String::from_str(name),
+            left: shape(value),
+            right: String::from_str("a square matrix"),
+        });
+    }
+
+    let mut m: Vec<Vec<f64>> = vec![];
+    for row in rows.iter() {
+        let mut numbers: Vec<f64> = vec![];
+        for x in row.iter() {
+            numbers.push(try!(x.number()));
+        }
+        m.push(numbers);
+    }
+    Ok(m)
+}
+
+// reduces `m` to the identity by Gauss-Jordan elimination with partial
+// pivoting, applying the same steps to `inverse` if given, and returns the
+// determinant; a zero determinant stops it early
+fn eliminate(m: &mut Vec<Vec<f64>>, mut inverse: Option<&mut Vec<Vec<f64>>>) -> f64 {
+    let n = m.len();
+    let mut det = 1_f64;
+
+    for col in range(0, n) {
+        let pivot = range(col, n).fold(col, |best, row| {
+            if m.get(row).get(col).abs() > m.get(best).get(col).abs() { row } else { best }
+        });
+        if *m.get(pivot).get(col) == 0_f64 {
+            return 0_f64;
+        }
+        if pivot != col {
+            m.as_mut_slice().swap(pivot, col);
+            match inverse {
+                Some(ref mut inv) => inv.as_mut_slice().swap(pivot, col),
+                None => {},
+            }
+            det = -det;
+        }
+
+        let scale = *m.get(col).get(col);
+        det *= scale;
+        for j in range(0, n) {
+            *m.get_mut(col).get_mut(j) /= scale;
+            match inverse {
+                Some(ref mut inv) => *inv.get_mut(col).get_mut(j) /= scale,
+                None => {},
+            }
+        }
+
+        for row in range(0, n) {
+            let factor = *m.get(row).get(col);
+            if row == col || factor == 0_f64 {
+                continue;
+            }
+            for j in range(0, n) {
+                let delta = factor * *m.get(col).get(j);
+                *m.get_mut(row).get_mut(j) -= delta;
+                match inverse {
+                    Some(ref mut inv) => {
+                        let delta = factor * *inv.get(col).get(j);
+                        *inv.get_mut(row).get_mut(j) -= delta;
+                    },
+                    None => {},
+                }
+            }
+        }
+    }
+    det
+}
+
+// the matrix product of an m x n and an n x p matrix
+fn product(a: Vec<Vec<Value>>, b: Vec<Vec<Value>>) -> Result<Value, CalcError> {
+    if a.get(0).len() != b.len() {
+        return Err(ShapeMismatch {
+            operation: String::from_str("*"),
+            left: format!("{}x{}", a.len(), a.get(0).len()),
+            right: format!("{}x{}", b.len(), b.get(0).len()),
+        });
+    }
+
+    let mut result: Vec<Value> = vec![];
+    for row in a.iter() {
+        let mut cells: Vec<Value> = vec![];
+        for j in range(0, b.get(0).len()) {
+            let mut total = try!(Value::binary("*", row.get(0), b.get(0).get(j)));
+            for k in range(1, b.len()) {
+                let term = try!(Value::binary("*", row.get(k), b.get(k).get(j)));
+                total = try!(Value::binary("+", &total, &term));
+            }
+            cells.push(total);
+        }
+        result.push(List(cells));
+    }
+    Ok(List(result))
+}
+
+// a single row or column as a plain list
+fn flatten(matrix: Value) -> Value {
+    match matrix {
+        List(rows) => {
+            let mut items: Vec<Value> = vec![];
+            for row in rows.move_iter() {
+                match row {
+                    List(cells) => items.push_all_move(cells),
+                    other => items.push(other),
+                }
+            }
+            List(items)
+        },
+        other => other,
+    }
+}
+
+fn mismatch(operation: &str, lhs: &Value, rhs: &Value) -> CalcError {
+    ShapeMismatch {
+        operation: String::from_str(operation),
+        left: shape(lhs),
+        right: shape(rhs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+
+    // `expression` as the calculator prints it, or the error it gave
+    fn show(expression: &str) -> String {
+        match ExprTree::build(expression).and_then(|tree| tree.eval()) {
+            Ok(value) => format!("{}", value),
+            Err(error) => format!("error: {}", error),
+        }
+    }
+
+    #[test]
+    fn products_follow_the_shapes() {
+        assert_eq!(show("[[1, 2], [3, 4]] * [[5, 6], [7, 8]]"),
+                   String::from_str("[[19, 22], [43, 50]]"));
+        // a plain list is a column on the right and a row on the left
+        assert_eq!(show("[[1, 2], [3, 4]] * [1, 1]"), String::from_str("[3, 7]"));
+        assert_eq!(show("[1, 1] * [[1, 2], [3, 4]]"), String::from_str("[4, 6]"));
+        assert_eq!(show("[1, 2] + [10, 20]"), String::from_str("[11, 22]"));
+        assert!(show("[[1, 2, 3]] * [[1, 2]]").as_slice().starts_with("error: "));
+    }
+
+    #[test]
+    fn matrix_built_ins() {
+        assert_eq!(show("det([[2, 1], [4, 3]])"), String::from_str("2"));
+        assert_eq!(show("dot([1, 2, 3], [4, 5, 6])"), String::from_str("32"));
+        assert_eq!(show("cross([1, 0, 0], [0, 1, 0])"), String::from_str("[0, 0, 1]"));
+        assert_eq!(show("transpose([[1, 2], [3, 4]])"), String::from_str("[[1, 3], [2, 4]]"));
+        assert!(show("inv([[1, 2], [2, 4]])").as_slice().starts_with("error: "));
+    }
+}
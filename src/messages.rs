@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::io::{File, IoResult};
+use std::os;
+use std::string::String;
+
+use error::CalcError;
+
+/// Error messages in one language, keyed by `CalcError::message_key`.
+/// Errors whose message the catalog lacks are described in English, as
+/// `CalcError`'s `Show` does, and codes are never translated, so scripts
+/// can match on them whatever the language.
+///
+/// A catalog file has one message per line, the key, `=` and the text,
+/// where `{0}`, `{1}`, ... stand for the error's `arguments`. Blank lines
+/// and lines starting with `#` are skipped:
+///
+/// ```text
+/// # German
+/// E0004 = unbekannte Variable '{0}'
+/// E0032.end = {0} erwartet an Position {2}, aber die Eingabe endet
+/// ```
+pub struct Catalog {
+    language: String,
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// The built-in English messages.
+    pub fn english() -> Catalog {
+        Catalog { language: String::from_str("en"), messages: HashMap::new() }
+    }
+
+    /// Reads the messages for `language` from `text`, in the catalog file
+    /// format.
+    pub fn parse(language: &str, text: &str) -> Catalog {
+        let mut catalog = Catalog { language: String::from_str(language), messages: HashMap::new() };
+        for line in text.lines_any() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+            match line.find('=') {
+                Some(i) => catalog.define(line.slice_to(i).trim(), line.slice_from(i + 1).trim()),
+                None => {},
+            }
+        }
+        catalog
+    }
+
+    /// Reads the catalog file at `path`.
+    pub fn load(language: &str, path: &Path) -> IoResult<Catalog> {
+        let text = try!(try!(File::open(path)).read_to_str());
+        Ok(Catalog::parse(language, text.as_slice()))
+    }
+
+    /// The catalog for `language`, such as `de_AT`, read from the first
+    /// of `dirs` that has `de_AT.txt` or, failing that, `de.txt`. English
+    /// when none does.
+    pub fn find(language: &str, dirs: &[Path]) -> Catalog {
+        let general = language.split('_').next().unwrap_or(language);
+        for name in [language, general].iter() {
+            for dir in dirs.iter() {
+                match Catalog::load(*name, &dir.join(format!("{}.txt", name).as_slice())) {
+                    Ok(catalog) => return catalog,
+                    Err(_) => {},
+                }
+            }
+        }
+        Catalog::english()
+    }
+
+    pub fn language<'a>(&'a self) -> &'a str {
+        self.language.as_slice()
+    }
+
+    /// Sets the message for `key`, replacing any already there.
+    pub fn define(&mut self, key: &str, template: &str) {
+        self.messages.insert(String::from_str(key), String::from_str(template));
+    }
+
+    /// `error` described in this catalog's language.
+    pub fn message(&self, error: &CalcError) -> String {
+        match self.messages.find(&error.message_key()) {
+            Some(template) => fill(template.as_slice(), error.arguments().as_slice()),
+            None => format!("{}", error),
+        }
+    }
+}
+
+// `template` with each `{N}` replaced by argument N; anything else in
+// braces, or a number past the last argument, is left as written
+fn fill(template: &str, arguments: &[String]) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    loop {
+        let open = match rest.find('{') {
+            Some(open) => open,
+            None => break,
+        };
+        let close = match rest.slice_from(open).find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+
+        text.push_str(rest.slice_to(open));
+        match from_str::<uint>(rest.slice(open + 1, close)) {
+            Some(n) if n < arguments.len() => text.push_str(arguments[n].as_slice()),
+            _ => text.push_str(rest.slice(open, close + 1)),
+        }
+        rest = rest.slice_from(close + 1);
+    }
+    text.push_str(rest);
+    text
+}
+
+/// The language the environment asks messages to be in, from `LC_ALL`,
+/// `LC_MESSAGES` or `LANG`, the first one set, without its encoding: `de_DE`
+/// for `de_DE.UTF-8`. `None` for the `C` and `POSIX` locales.
+pub fn env_language() -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|name| os::getenv(*name))
+        .find(|value| !value.is_empty());
+
+    locale.and_then(|locale| {
+        let language = locale.as_slice().split(|c: char| c == '.' || c == '@')
+            .next().unwrap_or("");
+        match language {
+            "" | "C" | "POSIX" => None,
+            language => Some(String::from_str(language)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{File, TempDir};
+    use std::string::String;
+
+    use error::{UnknownVariable, UnexpectedToken, DivisionByZero};
+    use super::Catalog;
+
+    static GERMAN: &'static str = "# German\n\
+        E0004 = unbekannte Variable '{0}'\n\
+        \n\
+        E0032.end = {0} erwartet an Position {2}, aber die Eingabe endet\n";
+
+    #[test]
+    fn templates_are_filled_from_the_arguments() {
+        let catalog = Catalog::parse("de", GERMAN);
+        assert_eq!(catalog.message(&UnknownVariable(String::from_str("zins"))),
+                   String::from_str("unbekannte Variable 'zins'"));
+        let end = UnexpectedToken {
+            found: String::new(),
+            expected: "a number",
+            position: 4,
+            length: 0,
+        };
+        assert_eq!(catalog.message(&end),
+                   String::from_str("a number erwartet an Position 4, aber die Eingabe endet"));
+        // what the catalog lacks is left in English
+        assert_eq!(catalog.message(&DivisionByZero), format!("{}", DivisionByZero));
+    }
+
+    #[test]
+    fn regional_catalogs_fall_back_to_the_language() {
+        let dir = TempDir::new("calc-messages").unwrap();
+        File::create(&dir.path().join("de.txt")).and_then(|mut file| file.write_str(GERMAN)).unwrap();
+
+        let dirs = [dir.path().clone()];
+        assert_eq!(Catalog::find("de_AT", dirs.as_slice()).language(), "de");
+        assert_eq!(Catalog::find("fr", dirs.as_slice()).language(), "en");
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::max;
+use std::str;
+use std::string::String;
+
+use env::Environment;
+use error::CalcError;
+use exprtree::ExprTree;
+use value::Value;
+
+/// Columns and rows of the chart area, not counting the labels.
+pub static DEFAULT_WIDTH: uint = 72;
+pub static DEFAULT_HEIGHT: uint = 20;
+
+/// Draws `tree` as a function of `variable` over `from..to` as a text
+/// chart `width` columns wide and `height` rows tall, with axes where zero
+/// is in view. Other variables are read from `env`. The tree is compiled
+/// when it can be; points where the expression is undefined are left
+/// blank.
+pub fn plot(tree: &ExprTree, variable: &str, from: f64, to: f64, width: uint, height: uint,
+            env: &mut Environment) -> Result<String, CalcError> {
+    let (width, height) = (max(width, 2), max(height, 2));
+
+    let mut ys: Vec<f64> = Vec::with_capacity(width);
+    let mode = env.mode;
+    let original = env.get(variable);
+    let sampled = match tree.compile() {
+        // the other variables are read once, and each sample only sets
+        // the slot of `variable`
+        Ok(mut program) => {
+            env.set(variable, Value::from_f64(from, mode));
+            let slot = program.slot(variable);
+            let angle = env.angle;
+            match program.load(env) {
+                Ok(()) => sample(&mut ys, from, to, width, |x| {
+                    match slot {
+                        Some(slot) => program.set_slot(slot, x),
+                        None => {},
+                    }
+                    program.eval_loaded(angle)
+                }),
+                Err(e) => Err(e),
+            }
+        },
+        // samples are not results, so result hooks aren't told of them
+        Err(_) => {
+            env.mute_hooks(true);
+            let sampled = sample(&mut ys, from, to, width, |x| {
+                env.set(variable, Value::from_f64(x, mode));
+                tree.eval_in(env).and_then(|v| v.number())
+            });
+            env.mute_hooks(false);
+            sampled
+        },
+    };
+    match original {
+        Some(value) => env.set(variable, value),
+        None => env.unset(variable),
+    }
+    try!(sampled);
+
+    let finite: Vec<f64> = ys.iter().map(|&y| y).filter(|y| y.is_finite()).collect();
+    if finite.is_empty() {
+        return Ok(String::from_str("(nothing to plot: the expression is undefined over the range)\n"));
+    }
+    let low = finite.iter().fold(*finite.get(0), |a, &b| a.min(b));
+    let mut high = finite.iter().fold(*finite.get(0), |a, &b| a.max(b));
+    if high == low {
+        high = low + 1_f64;
+    }
+    let row_of = |y: f64| ((high - y) / (high - low) * (height - 1) as f64).round() as uint;
+
+    let mut grid = Vec::from_elem(height, Vec::from_elem(width, ' '));
+    if low <= 0_f64 && high >= 0_f64 {
+        let axis = row_of(0_f64);
+        for column in range(0, width) {
+            *grid.get_mut(axis).get_mut(column) = '-';
+        }
+    }
+    if from <= 0_f64 && to >= 0_f64 {
+        let axis = (-from / (to - from) * (width - 1) as f64).round() as uint;
+        for row in range(0, height) {
+            *grid.get_mut(row).get_mut(axis) = '|';
+        }
+    }
+    for (column, &y) in ys.iter().enumerate() {
+        if y.is_finite() {
+            *grid.get_mut(row_of(y)).get_mut(column) = '*';
+        }
+    }
+
+    let top = format!("{}", high);
+    let bottom = format!("{}", low);
+    let margin = max(top.len(), bottom.len());
+
+    let mut chart = String::new();
+    for (row, line) in grid.iter().enumerate() {
+        let label = if row == 0 {
+            top.as_slice()
+        } else if row == height - 1 {
+            bottom.as_slice()
+        } else {
+            ""
+        };
+        chart.push_str(format!("{:>1$} |", label, margin).as_slice());
+        chart.push_str(str::from_chars(line.as_slice()).as_slice());
+        chart.push_char('\n');
+    }
+
+    let left = format!("{}", from);
+    let right = format!("{}", to);
+    let gap = max(width, left.len() + right.len() + 1) - left.len() - right.len();
+    chart.push_str(String::from_char(margin + 2, ' ').as_slice());
+    chart.push_str(left.as_slice());
+    chart.push_str(String::from_char(gap, ' ').as_slice());
+    chart.push_str(right.as_slice());
+    chart.push_char('\n');
+    Ok(chart)
+}
+
+// evaluate at evenly spaced points from `from` to `to`, one per column
+fn sample(ys: &mut Vec<f64>, from: f64, to: f64, width: uint,
+          f: |f64| -> Result<f64, CalcError>) -> Result<(), CalcError> {
+    for column in range(0, width) {
+        ys.push(try!(f(from + (to - from) * column as f64 / (width - 1) as f64)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use env::Environment;
+    use exprtree::ExprTree;
+    use super::plot;
+
+    #[test]
+    fn a_line_crosses_the_axes() {
+        let tree = ExprTree::build("x").unwrap();
+        let mut env = Environment::new();
+        let chart = plot(&tree, "x", -1_f64, 1_f64, 5, 5, &mut env).unwrap();
+
+        // the chart area is the end of each row, after the labels
+        let rows: Vec<&str> = chart.as_slice().lines().map(|row| row.slice_from(row.len() - 5)).collect();
+        assert_eq!(rows.len(), 6);
+        assert_eq!(*rows.get(0), "  | *");
+        assert_eq!(*rows.get(1), "  |* ");
+        assert_eq!(*rows.get(2), "--*--");
+        assert_eq!(*rows.get(3), " *|  ");
+        assert_eq!(*rows.get(4), "* |  ");
+
+        // the variable is left as it was found
+        assert!(env.get("x").is_none());
+    }
+}
@@ -0,0 +1,303 @@
+use std::cmp::{Less, Equal, Greater};
+use std::string::String;
+
+use analysis::Condition;
+use calculus;
+use calculus::Dual;
+use env::Environment;
+use error::CalcError;
+use exprtree::{ParseOptions, TokenType, Variable, literal, parse_number};
+use value::Value;
+
+// the operators the fast path takes; anything else, or any of these
+// doubled up as in `//` or `2*-3`, goes through the parser
+static OPERATORS: &'static str = "+-*/%^";
+
+// longer literals can lose digits in doubles, which the parser warns of
+static MAX_QUICK_DIGITS: uint = 15;
+
+/// Works out `expression` straight from its text when it is a single
+/// operator between two operands, each a plain decimal number or a variable
+/// that is set, as in `2+2` or `price * 1.08`, without tokenizing or
+/// building a tree. `None` for anything else, which is left to `ExprTree`,
+/// as is everything when the options ask for legacy parsing or verbose
+/// output or their limits would turn the input away; the result is always
+/// the one the tree would give, hooks included.
+pub fn eval(expression: &str, options: &ParseOptions,
+            env: &mut Environment) -> Option<Result<Value, CalcError>> {
+    let (lhs, operator, rhs) = match split(expression, options) {
+        Some(parts) => parts,
+        None => return None,
+    };
+    let lhs = match operand(lhs, options, env) {
+        Some(lhs) => lhs,
+        None => return None,
+    };
+    let rhs = match operand(rhs, options, env) {
+        Some(rhs) => rhs,
+        None => return None,
+    };
+
+    if env.depth == 0 {
+        env.clear_memo();
+    }
+    let mode = env.mode;
+    let result = Value::binary(operator, &lhs, &rhs).map(|value| value.in_word(mode));
+    match result {
+        Ok(ref value) if env.depth == 0 => env.notify_hooks(value),
+        _ => {},
+    }
+    Some(result)
+}
+
+/// The condition of a line `eval` works out, as `analysis::condition`
+/// finds it from the tree, worked out from the two operands alone: each
+/// variable's term is its share of the operator's derivative. Two literals
+/// have nothing to condition the result on and give `None`, as do lines
+/// `eval` leaves to the tree and operators with no derivative.
+pub fn condition(expression: &str, options: &ParseOptions,
+                 env: &Environment) -> Option<Condition> {
+    let (lhs, operator, rhs) = match split(expression, options) {
+        Some(parts) => parts,
+        None => return None,
+    };
+    let lhs = match input(lhs, options, env) {
+        Some(lhs) => lhs,
+        None => return None,
+    };
+    let rhs = match input(rhs, options, env) {
+        Some(rhs) => rhs,
+        None => return None,
+    };
+
+    let mut value = 0_f64;
+    let mut inputs: Vec<(String, f64)> = vec![];
+    for &(ref name, x) in [&lhs, &rhs].iter().map(|&input| input) {
+        let name = match *name {
+            Some(ref name) if !inputs.iter().any(|&(ref seen, _)| seen == name) => name,
+            _ => continue,
+        };
+        let dual = match calculus::binary(operator, seed(&lhs, name), seed(&rhs, name)) {
+            Some(dual) => dual,
+            None => return None,
+        };
+        value = dual.value;
+        let term = if x == 0_f64 || dual.derivative == 0_f64 {
+            0_f64
+        } else {
+            (x * dual.derivative / dual.value).abs()
+        };
+        inputs.push((name.clone(), term));
+    }
+
+    if inputs.is_empty() {
+        return None;
+    }
+    inputs.sort_by(|&(_, a), &(_, b)| if a > b { Less } else if a < b { Greater } else { Equal });
+    Some(Condition {
+        value: value,
+        number: inputs.iter().fold(0_f64, |sum, &(_, term)| sum + term),
+        inputs: inputs,
+    })
+}
+
+// the left operand, the operator and the right operand of a line the fast
+// path may take
+fn split<'a>(expression: &'a str,
+             options: &ParseOptions) -> Option<(&'a str, &'a str, &'a str)> {
+    // an operator between two operands is three tokens and a tree two deep
+    let limits = &options.limits;
+    if options.legacy || options.verbose || expression.len() > limits.max_input_len ||
+       limits.max_tokens < 3 || limits.max_depth < 2 {
+        return None;
+    }
+
+    let text = expression.trim();
+    let split = match text.find(|c: char| OPERATORS.contains_char(c)) {
+        Some(split) if split > 0 => split,
+        _ => return None,
+    };
+    let rest = text.slice_from(split + 1);
+    if rest.chars().next().map_or(false, |c| OPERATORS.contains_char(c)) {
+        return None;
+    }
+    Some((text.slice_to(split).trim(), text.slice(split, split + 1), rest.trim()))
+}
+
+// an operand as the condition sees it: the variable it names, if any, and
+// its value as a double
+fn input(text: &str, options: &ParseOptions,
+         env: &Environment) -> Option<(Option<String>, f64)> {
+    let value = match operand(text, options, env) {
+        Some(value) => value,
+        None => return None,
+    };
+    if text.char_at(0).is_digit() {
+        parse_number(text).map(|x| (None, x))
+    } else {
+        value.number().ok().map(|x| (Some(String::from_str(text)), x))
+    }
+}
+
+// the operand as a dual, varying with `variable` when it is that variable
+fn seed(input: &(Option<String>, f64), variable: &String) -> Dual {
+    let varies = match input.ref0() {
+        &Some(ref name) => name == variable,
+        &None => false,
+    };
+    Dual { value: *input.ref1(), derivative: if varies { 1_f64 } else { 0_f64 } }
+}
+
+// a short decimal literal, or a name the parser would read as a variable,
+// which is set and which no alias rewrites
+fn operand(text: &str, options: &ParseOptions, env: &Environment) -> Option<Value> {
+    if text.is_empty() {
+        return None;
+    }
+
+    let first = text.char_at(0);
+    if first.is_digit() {
+        let plain = text.chars().all(|c| c.is_digit() || c == '.') &&
+                    text.chars().filter(|&c| c == '.').count() <= 1 &&
+                    !text.ends_with(".") && text.len() <= MAX_QUICK_DIGITS;
+        if !plain {
+            return None;
+        }
+        parse_number(text).map(|value| literal(text, value, env.mode))
+    } else if is_name_char(first) {
+        let name = String::from_str(text);
+        let variable = text.chars().all(|c| is_name_char(c)) && text != "_" &&
+                       options.aliases.find(&name).is_none() &&
+                       TokenType::of_alphabeticals(name.clone(), options) == Variable;
+        if !variable {
+            return None;
+        }
+        env.get(text)
+    } else {
+        None
+    }
+}
+
+// names outside ASCII are left to the parser, which knows its own alphabet
+fn is_name_char(c: char) -> bool {
+    c == '_' || (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || (c >= '0' && c <= '9')
+}
+
+#[cfg(test)]
+mod test {
+    use analysis;
+    use env::Environment;
+    use error::CalcError;
+    use exprtree::{ExprTree, ParseOptions};
+    use value::{Value, Real, Integer, IntegerMode};
+    use super::{eval, condition};
+
+    // what building and evaluating a tree gives for `expression`
+    fn tree(expression: &str, options: &ParseOptions,
+            env: &mut Environment) -> Result<Value, CalcError> {
+        ExprTree::build_with(expression, options).and_then(|tree| tree.eval_in(env))
+    }
+
+    fn environment() -> Environment {
+        let mut env = Environment::new();
+        env.set("price", Real(20_f64));
+        env.set("a1", Real(3_f64));
+        env
+    }
+
+    #[test]
+    fn agrees_with_the_tree() {
+        let options = ParseOptions::new();
+        let mut env = environment();
+        let expressions = ["2+2", "price * 1.08", " 7 % 3 ", "2^10", "a1 - price", "1/0"];
+        for &expression in expressions.iter() {
+            match eval(expression, &options, &mut env) {
+                Some(quick) => assert_eq!(quick, tree(expression, &options, &mut env)),
+                None => fail!("{} should take the fast path", expression),
+            }
+        }
+    }
+
+    #[test]
+    fn conditions_agree_with_the_tree() {
+        let options = ParseOptions::new();
+        let env = environment();
+        let expressions = ["a1 - price", "price * 1.08", "a1 / price", "a1 ^ 2", "2 ^ a1",
+                           "a1 - a1"];
+        for &expression in expressions.iter() {
+            let tree = ExprTree::build(expression).unwrap();
+            let expected = analysis::condition(&tree, &env).unwrap().unwrap();
+            let quick = condition(expression, &options, &env).unwrap();
+            assert_eq!(quick.number, expected.number);
+            assert_eq!(quick.inputs, expected.inputs);
+        }
+        // nothing to condition on, and an operator with no derivative
+        assert!(condition("2 - 1.5", &options, &env).is_none());
+        assert!(condition("a1 % 2", &options, &env).is_none());
+    }
+
+    #[test]
+    fn wraps_to_the_word_as_the_tree_does() {
+        let options = ParseOptions::new();
+        let mut env = Environment::new();
+        env.mode = IntegerMode(8);
+        assert_eq!(eval("127+1", &options, &mut env), Some(Ok(Integer(-128))));
+        assert_eq!(tree("127+1", &options, &mut env), Ok(Integer(-128)));
+        assert_eq!(tree("1 << 7", &options, &mut env), Ok(Integer(-128)));
+        assert_eq!(tree("0xff", &options, &mut env), Ok(Integer(-1)));
+    }
+
+    #[test]
+    fn leaves_the_rest_to_the_tree() {
+        let options = ParseOptions::new();
+        let mut env = environment();
+        for &expression in ["A+1", "Price*2", "2*-3", "unset+1", "pi*2"].iter() {
+            assert!(eval(expression, &options, &mut env).is_none());
+        }
+        assert!(tree("A+1", &options, &mut env).is_err());
+    }
+
+    #[test]
+    fn names_may_have_capitals() {
+        let options = ParseOptions::new();
+        let mut env = environment();
+        env.set("xY", Real(1_f64));
+        env.set("F", Real(3_f64));
+        for &expression in ["xY+1", "F*2"].iter() {
+            assert_eq!(eval(expression, &options, &mut env),
+                       Some(tree(expression, &options, &mut env)));
+        }
+    }
+
+    #[test]
+    fn steps_aside_for_legacy_and_verbose() {
+        let mut env = environment();
+        let mut options = ParseOptions::new();
+        options.legacy = true;
+        assert!(eval("2+2", &options, &mut env).is_none());
+
+        let mut options = ParseOptions::new();
+        options.verbose = true;
+        assert!(eval("2+2", &options, &mut env).is_none());
+    }
+
+    #[test]
+    fn steps_aside_for_limits() {
+        let mut env = environment();
+
+        let mut options = ParseOptions::new();
+        options.limits.max_input_len = 4;
+        assert!(eval("10+20", &options, &mut env).is_none());
+        assert!(tree("10+20", &options, &mut env).is_err());
+
+        let mut options = ParseOptions::new();
+        options.limits.max_tokens = 2;
+        assert!(eval("1+2", &options, &mut env).is_none());
+        assert!(tree("1+2", &options, &mut env).is_err());
+
+        let mut options = ParseOptions::new();
+        options.limits.max_depth = 1;
+        assert!(eval("1+2", &options, &mut env).is_none());
+        assert!(tree("1+2", &options, &mut env).is_err());
+    }
+}
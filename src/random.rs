@@ -0,0 +1,92 @@
+use std::f64::consts::PI;
+use std::rand::Rng;
+use std::string::String;
+
+use error::{CalcError, OutOfDomain, UnknownFunction};
+
+/// A double drawn evenly from [0, 1), built from the top 53 bits of one
+/// draw so every representable step is equally likely.
+pub fn uniform(rng: &mut Rng) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Draws from the random built-in `name` using `rng`, with arity already
+/// checked: `rand()` is uniform on [0, 1), `rand(a, b)` uniform on [a, b),
+/// `randint(a, b)` a whole number from a to b inclusive, and
+/// `normal(mu, sigma)` normally distributed.
+pub fn sample(name: &str, args: &[f64], rng: &mut Rng) -> Result<f64, CalcError> {
+    let out_of_domain = |argument: f64, expected: &'static str| OutOfDomain {
+        function: String::from_str(name),
+        argument: argument,
+        expected: expected,
+    };
+
+    match (name, args.len()) {
+        ("rand", 0) => Ok(uniform(rng)),
+        ("rand", 1) => Ok(args[0] * uniform(rng)),
+        ("rand", _) => Ok(args[0] + (args[1] - args[0]) * uniform(rng)),
+        ("randint", _) => {
+            let (low, high) = (args[0], args[1]);
+            match args.iter().find(|&&arg| !arg.is_finite() || arg != arg.trunc()) {
+                Some(&arg) => return Err(out_of_domain(arg, "whole numbers")),
+                None => {},
+            }
+            if high < low {
+                return Err(out_of_domain(high, "a bound no smaller than the first"));
+            }
+            Ok(low + (uniform(rng) * (high - low + 1_f64)).floor())
+        },
+        ("normal", _) => {
+            let (mu, sigma) = (args[0], args[1]);
+            if sigma < 0_f64 {
+                return Err(out_of_domain(sigma, "a standard deviation of at least 0"));
+            }
+            // Box-Muller; the first draw is moved to (0, 1] to keep ln finite
+            let (u, v) = (1_f64 - uniform(rng), uniform(rng));
+            Ok(mu + sigma * (-2_f64 * u.ln()).sqrt() * (2_f64 * PI * v).cos())
+        },
+        _ => Err(UnknownFunction(String::from_str(name))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rand::{StdRng, SeedableRng};
+
+    use env::Environment;
+    use exprtree::ExprTree;
+    use super::sample;
+
+    fn seeded(seed: uint) -> StdRng {
+        let seed = [seed];
+        SeedableRng::from_seed(seed.as_slice())
+    }
+
+    #[test]
+    fn draws_stay_in_their_bounds() {
+        let mut rng = seeded(42);
+        for _ in range(0u, 1000) {
+            let die = sample("randint", &[1_f64, 6_f64], &mut rng).unwrap();
+            assert!(die >= 1_f64 && die <= 6_f64 && die == die.trunc());
+            let x = sample("rand", &[-2_f64, 3_f64], &mut rng).unwrap();
+            assert!(x >= -2_f64 && x < 3_f64);
+        }
+        assert_eq!(sample("normal", &[5_f64, 0_f64], &mut rng), Ok(5_f64));
+
+        assert!(sample("randint", &[1.5, 6_f64], &mut rng).is_err());
+        assert!(sample("randint", &[6_f64, 1_f64], &mut rng).is_err());
+        assert!(sample("normal", &[0_f64, -1_f64], &mut rng).is_err());
+    }
+
+    #[test]
+    fn the_same_seed_repeats_the_draws() {
+        let draws = |seed: uint| {
+            let mut env = Environment::new();
+            env.seed(seed);
+            let tree = ExprTree::build("[rand(), randint(1, 100), normal(0, 1)]").unwrap();
+            format!("{}", tree.eval_in(&mut env).unwrap())
+        };
+        assert_eq!(draws(7), draws(7));
+        assert!(draws(7) != draws(8));
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::TreeMap;
+use std::f64::consts;
+use std::string::String;
+
+use builtins;
+use builtins::{Arity, Exactly, Builtin};
+use error::{CalcError, ReservedName};
+use exprtree::{TokenType, ParseOptions, Variable};
+
+/// A function supplied by the embedding application that takes any number
+/// of arguments. Plain functions of one or two numbers already are one, as
+/// in `fn tax(x: f64) -> f64 { x * 1.0825 }`; one that takes more, or that
+/// carries state of its own, is a struct implementing this, the way result
+/// hooks are. A closure of one or two numbers needs neither; see
+/// `Registry::register_fn`.
+pub trait NativeFunction {
+    /// The number of arguments the function takes.
+    fn arity(&self) -> Arity;
+
+    /// Works the function out for `args`, which always fit the arity.
+    fn call(&self, args: &[f64]) -> f64;
+}
+
+impl NativeFunction for fn(f64) -> f64 {
+    fn arity(&self) -> Arity {
+        Exactly(1)
+    }
+
+    fn call(&self, args: &[f64]) -> f64 {
+        (*self)(args[0])
+    }
+}
+
+impl NativeFunction for fn(f64, f64) -> f64 {
+    fn arity(&self) -> Arity {
+        Exactly(2)
+    }
+
+    fn call(&self, args: &[f64]) -> f64 {
+        (*self)(args[0], args[1])
+    }
+}
+
+// a registered function: an object, or a closure of one or two numbers
+// that may borrow from the application for as long as the registry lives
+enum Native<'a> {
+    Object(Box<NativeFunction>),
+    Unary(|f64|: 'a -> f64),
+    Binary(|f64, f64|: 'a -> f64),
+}
+
+impl<'a> Native<'a> {
+    fn arity(&self) -> Arity {
+        match *self {
+            Object(ref function) => function.arity(),
+            Unary(_) => Exactly(1),
+            Binary(_) => Exactly(2),
+        }
+    }
+
+    // closures may change what they capture, so calls take `&mut`
+    fn call(&mut self, args: &[f64]) -> f64 {
+        match *self {
+            Object(ref function) => function.call(args),
+            Unary(ref mut function) => (*function)(args[0]),
+            Binary(ref mut function) => (*function)(args[0], args[1]),
+        }
+    }
+}
+
+/// The built-in function `name`, if there is one. Built-ins live in the
+/// static tables of `builtins` rather than in each `Registry`, since names
+/// are read as functions when parsing, before there is an environment.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    builtins::CORE.iter()
+        .chain(builtins::FINANCE.iter())
+        .chain(builtins::STATS.iter())
+        .chain(builtins::MATRIX.iter())
+        .find(|builtin| builtin.name == name)
+}
+
+// `decimal` has each of these to more places
+static CONSTANTS: [(&'static str, f64), ..8] = [
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("tau", 6.28318530717958647692528676655900576_f64),
+    ("phi", 1.618033988749894848204586834365638118_f64),
+    ("sqrt2", consts::SQRT2),
+    ("ln2", consts::LN_2),
+    ("ln10", consts::LN_10),
+    ("euler_gamma", 0.577215664901532860606512090082402431_f64),
+];
+
+/// The value of the built-in constant `name`, if there is one.
+pub fn builtin_constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter().find(|constant| constant.val0() == name).map(|constant| constant.val1())
+}
+
+/// Functions and constants registered at runtime by an application using
+/// the calculator as a library. They are looked up by name after
+/// variables and user-defined functions, so an expression can still
+/// shadow them. Names the calculator already gives a meaning, such as
+/// `sqrt`, `list`, `pi` or `xor`, cannot be registered, so a registration
+/// never changes what an existing expression means; see `builtin` and
+/// `builtin_constant` for those. Both are kept in name order.
+///
+/// A function is keyed by its name and arity, so one name may have an
+/// overload for each number of arguments, and a call goes to the one that
+/// takes as many as it passes.
+pub struct Registry<'a> {
+    functions: TreeMap<String, Vec<Native<'a>>>,
+    constants: TreeMap<String, f64>,
+}
+
+impl<'a> Registry<'a> {
+    pub fn new() -> Registry<'a> {
+        Registry {
+            functions: TreeMap::new(),
+            constants: TreeMap::new(),
+        }
+    }
+
+    /// Makes `name(x)` call `function`, which may be a closure, as in
+    /// `register_fn("tax", |x| x * rate)`. Replaces any earlier
+    /// registration of `name` that takes one argument; others stay as
+    /// overloads. `ReservedName` if `name` is a built-in, constant or
+    /// operator.
+    pub fn register_fn(&mut self, name: &str, function: |f64|: 'a -> f64)
+                       -> Result<(), CalcError> {
+        self.register(name, Unary(function))
+    }
+
+    /// Makes `name(x, y)` call `function`; see `register_fn`.
+    pub fn register_fn2(&mut self, name: &str, function: |f64, f64|: 'a -> f64)
+                        -> Result<(), CalcError> {
+        self.register(name, Binary(function))
+    }
+
+    /// Makes `name` call `function` with as many arguments as its arity
+    /// takes; see `register_fn`.
+    pub fn register_native(&mut self, name: &str,
+                           function: Box<NativeFunction>) -> Result<(), CalcError> {
+        self.register(name, Object(function))
+    }
+
+    // adds `function` as an overload of `name`, in place of any that takes
+    // some of the same numbers of arguments
+    fn register(&mut self, name: &str, function: Native<'a>) -> Result<(), CalcError> {
+        try!(check_name(name));
+        let name = String::from_str(name);
+        if self.functions.find(&name).is_none() {
+            self.functions.insert(name.clone(), vec![]);
+        }
+        let overloads = self.functions.find_mut(&name).unwrap();
+        overloads.retain(|existing| !existing.arity().overlaps(&function.arity()));
+        overloads.push(function);
+        Ok(())
+    }
+
+    /// Makes `name` read as `value`, replacing any earlier registration.
+    /// `ReservedName` if `name` is a built-in, constant or operator.
+    pub fn register_const(&mut self, name: &str, value: f64) -> Result<(), CalcError> {
+        try!(check_name(name));
+        self.constants.insert(String::from_str(name), value);
+        Ok(())
+    }
+
+    /// Calls the overload of `name` that takes as many arguments as
+    /// `args` holds; `None` if there is none.
+    pub fn call(&mut self, name: &str, args: &[f64]) -> Option<f64> {
+        let overloads = match self.functions.find_mut(&String::from_str(name)) {
+            Some(overloads) => overloads,
+            None => return None,
+        };
+        match overloads.mut_iter().find(|function| function.arity().accepts(args.len())) {
+            Some(function) => Some(function.call(args)),
+            None => None,
+        }
+    }
+
+    /// The arities of the overloads of `name`, none if it is not registered.
+    pub fn arities(&self, name: &str) -> Vec<Arity> {
+        match self.functions.find(&String::from_str(name)) {
+            Some(overloads) => overloads.iter().map(|function| function.arity()).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn constant(&self, name: &str) -> Option<f64> {
+        self.constants.find(&String::from_str(name)).map(|&c| c)
+    }
+
+    /// The names of the registered functions, in order.
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// The registered constants, in name order.
+    pub fn constants(&self) -> Vec<(String, f64)> {
+        self.constants.iter().map(|(name, &value)| (name.clone(), value)).collect()
+    }
+}
+
+// a name that reads as anything but a variable by default would never
+// reach the registry, or would shadow a built-in in trees but not in
+// compiled programs
+fn check_name(name: &str) -> Result<(), CalcError> {
+    if TokenType::of_alphabeticals(String::from_str(name), &ParseOptions::new()) != Variable {
+        return Err(ReservedName(String::from_str(name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use analysis::{impurities, Nondeterministic};
+    use builtins::{Exactly, AtLeast, Between};
+    use env::Environment;
+    use error::{CalcError, WrongArity, ReservedName};
+    use exprtree::ExprTree;
+    use value::{Value, Real};
+    use super::{NativeFunction, builtin, builtin_constant};
+
+    fn eval(expression: &str, env: &mut Environment) -> Result<Value, CalcError> {
+        ExprTree::build(expression).and_then(|tree| tree.eval_in(env))
+    }
+
+    fn half(x: f64) -> f64 {
+        x / 2_f64
+    }
+
+    fn mean(x: f64, y: f64) -> f64 {
+        (x + y) / 2_f64
+    }
+
+    fn third(x: f64) -> f64 {
+        x / 3_f64
+    }
+
+    #[test]
+    fn calls_go_to_the_overload_of_their_arity() {
+        let mut env = Environment::new();
+        env.register_native("mid", box half as Box<NativeFunction>).unwrap();
+        env.register_native("mid", box mean as Box<NativeFunction>).unwrap();
+        assert_eq!(eval("mid(8)", &mut env), Ok(Real(4_f64)));
+        assert_eq!(eval("mid(2, 8)", &mut env), Ok(Real(5_f64)));
+        assert_eq!(eval("mid(1, 2, 3)", &mut env), Err(WrongArity {
+            function: String::from_str("mid"),
+            expected: Exactly(1),
+            found: 3,
+        }));
+
+        // the same arity again replaces the first
+        env.register_native("mid", box third as Box<NativeFunction>).unwrap();
+        assert_eq!(eval("mid(9)", &mut env), Ok(Real(3_f64)));
+        assert_eq!(env.registry().arities("mid").len(), 2);
+    }
+
+    #[test]
+    fn closures_keep_what_they_capture() {
+        let rate = 1.0825;
+        let mut calls = 0u;
+        {
+            let mut env = Environment::new();
+            env.register_fn("tax", |x| x * rate).unwrap();
+            env.register_fn2("tally", |x, y| {
+                calls += 1;
+                x + y
+            }).unwrap();
+            assert_eq!(eval("tax(200)", &mut env), Ok(Real(216.5)));
+            assert_eq!(eval("tally(1, 2) + tally(3, 4)", &mut env), Ok(Real(10_f64)));
+
+            // registered functions are opaque, so they are not pure
+            let tree = ExprTree::build("tax(1)").unwrap();
+            assert_eq!(impurities(&tree, &env), vec![Nondeterministic(String::from_str("tax"))]);
+        }
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn built_ins_take_optional_arguments() {
+        let mut env = Environment::new();
+        assert_eq!(eval("log(100)", &mut env), Ok(Real(2_f64)));
+        assert_eq!(eval("log(8, 2)", &mut env), Ok(Real(3_f64)));
+        assert_eq!(eval("round(2.567)", &mut env), Ok(Real(3_f64)));
+        assert_eq!(eval("round(2.567, 2)", &mut env), Ok(Real(2.57)));
+        assert_eq!(eval("round(1250, -2)", &mut env), Ok(Real(1300_f64)));
+        assert!(Between(1, 2).overlaps(&Exactly(2)));
+        assert!(!Exactly(1).overlaps(&AtLeast(2)));
+    }
+
+    #[test]
+    fn built_ins_are_looked_up_in_their_tables() {
+        assert!(builtin("sqrt").unwrap().arity == Exactly(1));
+        assert!(builtin("log").unwrap().arity == Between(1, 2));
+        // those the evaluator applies have no way to be worked from numbers
+        assert!(builtin("map").unwrap().apply.is_none());
+        assert!(builtin("tax").is_none());
+        assert_eq!(builtin_constant("tau"), Some(Float::two_pi()));
+        assert_eq!(builtin_constant("sqrt"), None);
+    }
+
+    #[test]
+    fn constants_read_as_variables_would() {
+        let mut env = Environment::new();
+        env.register_const("vat", 0.2).unwrap();
+        env.register_const("fee", 5_f64).unwrap();
+        assert_eq!(eval("100 * (1 + vat) + fee", &mut env), Ok(Real(125_f64)));
+        // a variable of the same name shadows the constant
+        eval("fee = 0", &mut env).unwrap();
+        assert_eq!(eval("100 * (1 + vat) + fee", &mut env), Ok(Real(120_f64)));
+        assert_eq!(env.registry().constants(),
+                   vec![(String::from_str("fee"), 5_f64), (String::from_str("vat"), 0.2)]);
+
+        for name in ["pi", "sqrt", "xor"].iter() {
+            assert_eq!(env.register_const(*name, 1_f64), Err(ReservedName(String::from_str(*name))));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::TreeMap;
+use std::io::{File, IoResult};
+use std::string::String;
+
+use env::Environment;
+use value::Value;
+
+/// The register `M+`, `M-` and `MR` work on.
+pub static DEFAULT_REGISTER: &'static str = "m";
+
+/// What a REPL session remembers: every input with its result, for
+/// `history` and recall as `#3`, and named memory registers, which are
+/// kept in a file so they outlast the session.
+pub struct Session {
+    history: Vec<(String, Value)>,
+    registers: TreeMap<String, f64>,
+    path: Option<Path>,
+}
+
+impl Session {
+    /// A session whose registers live only in memory.
+    pub fn new() -> Session {
+        Session { history: vec![], registers: TreeMap::new(), path: None }
+    }
+
+    /// A session whose registers are read from `path` and written back to
+    /// it whenever they change. A missing file starts with none; lines
+    /// that aren't a name and a number are skipped.
+    pub fn load(path: Path) -> Session {
+        let mut registers = TreeMap::new();
+        match File::open(&path).and_then(|mut file| file.read_to_str()) {
+            Ok(text) => for line in text.as_slice().lines_any() {
+                let words: Vec<&str> = line.words().collect();
+                if words.len() != 2 {
+                    continue;
+                }
+                match from_str::<f64>(*words.get(1)) {
+                    Some(number) => { registers.insert(String::from_str(*words.get(0)), number); },
+                    None => {},
+                }
+            },
+            Err(_) => {},
+        }
+        Session { history: vec![], registers: registers, path: Some(path) }
+    }
+
+    /// Adds a result to the history and binds it to `ans` in `env`.
+    /// Returns its number for recall, counting from 1.
+    pub fn record(&mut self, input: &str, value: Value, env: &mut Environment) -> uint {
+        env.set("ans", value.clone());
+        self.history.push((String::from_str(input), value));
+        self.history.len()
+    }
+
+    pub fn history<'a>(&'a self) -> &'a [(String, Value)] {
+        self.history.as_slice()
+    }
+
+    pub fn last<'a>(&'a self) -> Option<&'a Value> {
+        match self.history.last() {
+            Some(&(_, ref value)) => Some(value),
+            None => None,
+        }
+    }
+
+    /// Replaces each `#N` in `line` with result N in parentheses, so
+    /// `#1 * 2` doubles the first result. Names a missing result in the
+    /// error.
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let mut expanded = String::new();
+        let mut chars = line.chars().peekable();
+        loop {
+            match chars.next() {
+                Some('#') => {
+                    let mut digits = String::new();
+                    loop {
+                        match chars.peek() {
+                            Some(&c) if c.is_digit() => digits.push_char(c),
+                            _ => break,
+                        }
+                        chars.next();
+                    }
+                    let index = from_str::<uint>(digits.as_slice()).unwrap_or(0);
+                    if index == 0 || index > self.history.len() {
+                        return Err(format!("there is no result #{}", digits));
+                    }
+                    let &(_, ref value) = self.history.get(index - 1);
+                    expanded.push_str(format!("({})", value).as_slice());
+                },
+                Some(c) => expanded.push_char(c),
+                None => break,
+            }
+        }
+        Ok(expanded)
+    }
+
+    pub fn registers<'a>(&'a self) -> &'a TreeMap<String, f64> {
+        &self.registers
+    }
+
+    pub fn recall(&self, name: &str) -> Option<f64> {
+        self.registers.find(&String::from_str(name)).map(|&n| n)
+    }
+
+    /// Sets register `name` to `value`, saving the registers.
+    pub fn store(&mut self, name: &str, value: f64) -> IoResult<()> {
+        self.registers.insert(String::from_str(name), value);
+        self.save()
+    }
+
+    /// Adds `delta` to register `name`, which starts at zero, saving the
+    /// registers. Returns the new contents.
+    pub fn accumulate(&mut self, name: &str, delta: f64) -> IoResult<f64> {
+        let total = self.recall(name).unwrap_or(0_f64) + delta;
+        try!(self.store(name, total));
+        Ok(total)
+    }
+
+    /// Empties register `name`, saving the registers.
+    pub fn clear(&mut self, name: &str) -> IoResult<()> {
+        self.registers.remove(&String::from_str(name));
+        self.save()
+    }
+
+    fn save(&self) -> IoResult<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut file = try!(File::create(path));
+        for (name, value) in self.registers.iter() {
+            try!(writeln!(file, "{} {}", name, value));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::TempDir;
+    use std::string::String;
+
+    use env::Environment;
+    use value::Real;
+    use super::{Session, DEFAULT_REGISTER};
+
+    #[test]
+    fn results_are_recalled_by_number() {
+        let mut session = Session::new();
+        let mut env = Environment::new();
+        assert_eq!(session.record("1 + 1", Real(2_f64), &mut env), 1);
+        assert_eq!(session.record("ans * 5", Real(10_f64), &mut env), 2);
+        assert_eq!(env.get("ans"), Some(Real(10_f64)));
+        assert_eq!(session.last(), Some(&Real(10_f64)));
+
+        assert_eq!(session.expand("#1 * #2 + 1"), Ok(String::from_str("(2) * (10) + 1")));
+        assert_eq!(session.expand("#3"), Err(String::from_str("there is no result #3")));
+        assert!(session.expand("# 1").is_err());
+    }
+
+    #[test]
+    fn registers_outlast_the_session() {
+        let dir = TempDir::new("calc-session").unwrap();
+        let path = dir.path().join("memory");
+        {
+            let mut session = Session::load(path.clone());
+            assert_eq!(session.accumulate(DEFAULT_REGISTER, 5_f64), Ok(5_f64));
+            assert_eq!(session.accumulate(DEFAULT_REGISTER, -2_f64), Ok(3_f64));
+            session.store("rate", 0.25).unwrap();
+            session.store("gone", 1_f64).unwrap();
+            session.clear("gone").unwrap();
+        }
+
+        let session = Session::load(path);
+        assert_eq!(session.recall(DEFAULT_REGISTER), Some(3_f64));
+        assert_eq!(session.recall("rate"), Some(0.25));
+        assert_eq!(session.recall("gone"), None);
+    }
+}
@@ -0,0 +1,152 @@
+use exprtree::{ExprNode, ExprLiteral, ExprUnary, ExprBinary};
+use exprtree::parse_number;
+
+// doubles hold every whole number up to 2^53 exactly
+static EXACT_LIMIT: f64 = 9007199254740992_f64;
+
+/// `node` with constant subtrees folded (`2*3+x` becomes `6 + x`),
+/// identity operations such as `x*1` and `x+0` removed and double
+/// negations collapsed.
+///
+/// Constants are folded only when the operands and the result are whole
+/// numbers, so the folded tree evaluates the same in every number mode;
+/// `1/3` and `0.1 + 0.2` are left as written.
+pub fn simplify(node: &ExprNode) -> ExprNode {
+    let mut children: Vec<ExprNode> = node.children().iter()
+        .map(|child| simplify(child)).collect();
+
+    match node.kind() {
+        ExprUnary => negate(children.pop().unwrap()),
+        ExprBinary => {
+            let rhs = children.pop().unwrap();
+            let lhs = children.pop().unwrap();
+            match node.token() {
+                "+" => add(lhs, rhs),
+                "-" => sub(lhs, rhs),
+                "*" => mul(lhs, rhs),
+                "/" => div(lhs, rhs),
+                "^" => pow(lhs, rhs),
+                operator => ExprNode::new(operator, ExprBinary, vec![lhs, rhs]),
+            }
+        },
+        kind => ExprNode::new(node.token(), kind, children),
+    }
+}
+
+/// The value of a plain numeric literal, or of a negated one. Named
+/// constants such as `pi` stay symbolic and give `None`.
+pub fn numeric(node: &ExprNode) -> Option<f64> {
+    match node.kind() {
+        ExprLiteral => parse_number(node.token()),
+        ExprUnary => numeric(node.children().get(0)).map(|v| -v),
+        _ => None,
+    }
+}
+
+/// A literal for `value`. Literals carry no sign, so a negative number is a
+/// negated literal.
+pub fn number(value: f64) -> ExprNode {
+    let literal = ExprNode::new(format!("{}", value.abs()).as_slice(), ExprLiteral, vec![]);
+    if value < 0_f64 { ExprNode::new("-", ExprUnary, vec![literal]) } else { literal }
+}
+
+/// `lhs + rhs`, simplified.
+pub fn add(lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    match (numeric(&lhs), numeric(&rhs)) {
+        (Some(a), Some(b)) if foldable(a, b, a + b) => number(a + b),
+        (Some(0_f64), _) => rhs,
+        (_, Some(0_f64)) => lhs,
+        _ => binary("+", lhs, rhs),
+    }
+}
+
+/// `lhs - rhs`, simplified.
+pub fn sub(lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    match (numeric(&lhs), numeric(&rhs)) {
+        (Some(a), Some(b)) if foldable(a, b, a - b) => number(a - b),
+        (Some(0_f64), _) => negate(rhs),
+        (_, Some(0_f64)) => lhs,
+        _ => binary("-", lhs, rhs),
+    }
+}
+
+/// `lhs * rhs`, simplified, with a constant factor written first.
+pub fn mul(lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    match (numeric(&lhs), numeric(&rhs)) {
+        (Some(a), Some(b)) if foldable(a, b, a * b) => number(a * b),
+        (Some(0_f64), _) | (_, Some(0_f64)) => number(0_f64),
+        (Some(1_f64), _) => rhs,
+        (_, Some(1_f64)) => lhs,
+        (Some(-1_f64), _) => negate(rhs),
+        (_, Some(-1_f64)) => negate(lhs),
+        (None, Some(_)) => binary("*", rhs, lhs),
+        _ => binary("*", lhs, rhs),
+    }
+}
+
+/// `lhs / rhs`, simplified.
+pub fn div(lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    match (numeric(&lhs), numeric(&rhs)) {
+        (Some(a), Some(b)) if b != 0_f64 && foldable(a, b, a / b) => number(a / b),
+        (Some(0_f64), None) => number(0_f64),
+        (_, Some(1_f64)) => lhs,
+        _ => binary("/", lhs, rhs),
+    }
+}
+
+/// `base ^ exponent`, simplified.
+pub fn pow(base: ExprNode, exponent: ExprNode) -> ExprNode {
+    match (numeric(&base), numeric(&exponent)) {
+        (Some(a), Some(b)) if b >= 0_f64 && foldable(a, b, a.powf(b)) => number(a.powf(b)),
+        (_, Some(0_f64)) => number(1_f64),
+        (_, Some(1_f64)) => base,
+        _ => binary("^", base, exponent),
+    }
+}
+
+/// `-operand`, simplified, so `--x` is `x`.
+pub fn negate(operand: ExprNode) -> ExprNode {
+    match numeric(&operand) {
+        Some(v) => number(-v),
+        None if operand.kind() == ExprUnary => operand.children().get(0).clone(),
+        None => ExprNode::new("-", ExprUnary, vec![operand]),
+    }
+}
+
+fn binary(operator: &str, lhs: ExprNode, rhs: ExprNode) -> ExprNode {
+    ExprNode::new(operator, ExprBinary, vec![lhs, rhs])
+}
+
+fn foldable(a: f64, b: f64, result: f64) -> bool {
+    whole(a) && whole(b) && whole(result)
+}
+
+fn whole(v: f64) -> bool {
+    v == v.trunc() && v.abs() <= EXACT_LIMIT
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+
+    fn simplified(expression: &str) -> String {
+        format!("{}", ExprTree::build(expression).unwrap().simplify().root().unwrap())
+    }
+
+    #[test]
+    fn constants_fold() {
+        assert_eq!(simplified("2*3+x"), String::from_str("6 + x"));
+        assert_eq!(simplified("2^3*y"), String::from_str("8 * y"));
+    }
+
+    #[test]
+    fn identities_drop_out() {
+        assert_eq!(simplified("x*1"), String::from_str("x"));
+        assert_eq!(simplified("x+0"), String::from_str("x"));
+        assert_eq!(simplified("x^1"), String::from_str("x"));
+        assert_eq!(simplified("0*x"), String::from_str("0"));
+        assert_eq!(simplified("--x"), String::from_str("x"));
+    }
+}
@@ -0,0 +1,261 @@
+use std::string::String;
+
+use algebra;
+use env::Environment;
+use error::{CalcError, NoConvergence, NoRoots};
+use exprtree::{ExprTree, ExprNode, ExprLiteral, ExprVariable, ExprBinary};
+use simplify;
+use value::Value;
+
+/// Secant steps `find_root` takes before giving up, and halvings
+/// `find_roots` makes of each interval.
+pub static MAX_STEPS: uint = 100;
+
+/// Pieces `find_roots` cuts its interval into when looking for sign
+/// changes.
+pub static SCAN_STEPS: uint = 1000;
+
+/// Steps smaller than this, relative to the estimate, count as converged.
+pub static TOLERANCE: f64 = 1e-12;
+
+/// Finds an x near `guess` where `f(x)` is zero by the secant method, or
+/// `None` if the iteration stalls, runs away or takes too long.
+pub fn find_root(f: |f64| -> Result<f64, CalcError>, guess: f64) -> Result<Option<f64>, CalcError> {
+    let mut x0 = guess;
+    let mut x1 = if guess == 0_f64 { 1e-4 } else { guess * (1_f64 + 1e-4) };
+    let mut f0 = try!(f(x0));
+
+    for _ in range(0, MAX_STEPS) {
+        let f1 = try!(f(x1));
+        if f1 == 0_f64 {
+            return Ok(Some(x1));
+        }
+        if f1 == f0 {
+            return Ok(None);
+        }
+
+        let x2 = x1 - f1 * (x1 - x0) / (f1 - f0);
+        if !x2.is_finite() {
+            return Ok(None);
+        }
+        if (x2 - x1).abs() <= TOLERANCE * x2.abs().max(1_f64) {
+            return Ok(Some(x2));
+        }
+
+        x0 = x1;
+        f0 = f1;
+        x1 = x2;
+    }
+    Ok(None)
+}
+
+/// Finds the value of `variable`, starting from `guess`, that makes `expr`
+/// come out as `target`, by finding a root of `expr - target`. Other
+/// variables are read from `env`, and `variable` is left as it was.
+pub fn goalseek(expr: &ExprNode, variable: &str, target: f64, guess: f64,
+                env: &mut Environment) -> Result<f64, CalcError> {
+    // literals have no sign, so a negative target is added instead
+    let offset = ExprNode::new(format!("{}", target.abs()).as_slice(), ExprLiteral, vec![]);
+    let operator = if target < 0_f64 { "+" } else { "-" };
+    let shifted = ExprTree::new(Some(ExprNode::new(operator, ExprBinary,
+                                                   vec![expr.clone(), offset]))).simplify();
+
+    let mode = env.mode;
+    let original = env.get(variable);
+    let root = find_root(|x| {
+        env.set(variable, Value::from_f64(x, mode));
+        shifted.eval_in(env).and_then(|v| v.number())
+    }, guess);
+
+    match original {
+        Some(value) => env.set(variable, value),
+        None => env.unset(variable),
+    }
+
+    match try!(root) {
+        Some(x) => Ok(x),
+        None => Err(NoConvergence { variable: String::from_str(variable) }),
+    }
+}
+
+/// Every x between `lo` and `hi` where `f(x)` is zero, in increasing
+/// order. The interval is cut into `SCAN_STEPS` pieces and each piece
+/// where `f` changes sign is bisected, so roots closer together than a
+/// piece, and roots where `f` touches zero without crossing it, can be
+/// missed.
+pub fn find_roots(f: |f64| -> Result<f64, CalcError>, lo: f64,
+                  hi: f64) -> Result<Vec<f64>, CalcError> {
+    let mut roots: Vec<f64> = vec![];
+    let width = (hi - lo) / SCAN_STEPS as f64;
+    let mut a = lo;
+    let mut fa = try!(f(a));
+
+    for step in range(1, SCAN_STEPS + 1) {
+        let b = if step == SCAN_STEPS { hi } else { lo + width * step as f64 };
+        let fb = try!(f(b));
+
+        if fa == 0_f64 {
+            roots.push(a);
+        } else if fb != 0_f64 && fa.is_finite() && fb.is_finite() &&
+                  fa.signum() != fb.signum() {
+            let (mut x0, mut x1, mut f0) = (a, b, fa);
+            for _ in range(0, MAX_STEPS) {
+                let mid = x0 + (x1 - x0) / 2_f64;
+                let fm = try!(f(mid));
+                if fm == 0_f64 {
+                    x0 = mid;
+                    x1 = mid;
+                    break;
+                }
+                if fm.signum() == f0.signum() {
+                    x0 = mid;
+                    f0 = fm;
+                } else {
+                    x1 = mid;
+                }
+                if x1 - x0 <= TOLERANCE * x0.abs().max(1_f64) {
+                    break;
+                }
+            }
+
+            // f also changes sign across a pole, as tan does, but grows
+            // there instead of shrinking
+            let root = x0 + (x1 - x0) / 2_f64;
+            if try!(f(root)).abs() <= fa.abs().max(fb.abs()) {
+                roots.push(root);
+            }
+        }
+
+        a = b;
+        fa = fb;
+    }
+    if fa == 0_f64 {
+        roots.push(a);
+    }
+    Ok(roots)
+}
+
+/// Solves `equation` for `variable`. The equation is `lhs = rhs` or
+/// `lhs == rhs`; anything else is taken as equal to zero. Each solution
+/// comes back as a `variable = value` tree.
+///
+/// Without bounds the variable is first isolated with
+/// `algebra::rearrange`. When everything else it depends on is known the
+/// solution is worked out, as in `x = 4`, and otherwise it is left as an
+/// expression, as in `x = b / a`. Equations it cannot rearrange, such as
+/// `cos(x) = x`, fall back to the secant method starting from the
+/// variable's value in `env`, or from 0. With bounds `(lo, hi)` every
+/// root between them is found numerically instead; see `find_roots`.
+///
+/// Other variables are read from `env`, and `variable` is left as it was.
+pub fn solve(equation: &ExprNode, variable: &str, bounds: Option<(f64, f64)>,
+             env: &mut Environment) -> Result<Vec<ExprNode>, CalcError> {
+    let is_equality = equation.kind() == ExprBinary &&
+        (equation.token() == "=" || equation.token() == "==");
+    let (lhs, rhs) = if is_equality {
+        (equation.children().get(0).clone(), equation.children().get(1).clone())
+    } else {
+        (equation.clone(), simplify::number(0_f64))
+    };
+    if bounds.is_none() {
+        let equation = ExprNode::new("=", ExprBinary, vec![lhs.clone(), rhs.clone()]);
+        match algebra::rearrange(&equation, variable) {
+            Ok(solved) => {
+                let value = simplify::simplify(solved.children().get(1));
+                env.mute_hooks(true);
+                let number = ExprTree::new(Some(value.clone())).eval_in(env)
+                    .and_then(|v| v.number());
+                env.mute_hooks(false);
+                return Ok(vec![assignment(variable, match number {
+                    Ok(number) => simplify::number(number),
+                    Err(_) => value,
+                })]);
+            },
+            Err(_) => {},
+        }
+    }
+
+    let residual = ExprTree::new(Some(ExprNode::new("-", ExprBinary, vec![lhs, rhs]))).simplify();
+    let mode = env.mode;
+    let original = env.get(variable);
+    let guess = original.as_ref().and_then(|v| v.number().ok()).unwrap_or(0_f64);
+
+    // the values tried along the way aren't results, so result hooks are
+    // told of none of them
+    env.mute_hooks(true);
+    let roots = match bounds {
+        Some((lo, hi)) => find_roots(|x| {
+            env.set(variable, Value::from_f64(x, mode));
+            residual.eval_in(env).and_then(|v| v.number())
+        }, lo, hi),
+        None => find_root(|x| {
+            env.set(variable, Value::from_f64(x, mode));
+            residual.eval_in(env).and_then(|v| v.number())
+        }, guess).map(|root| match root {
+            Some(root) => vec![root],
+            None => vec![],
+        }),
+    };
+    env.mute_hooks(false);
+
+    match original {
+        Some(value) => env.set(variable, value),
+        None => env.unset(variable),
+    }
+
+    let roots = try!(roots);
+    if roots.is_empty() {
+        return Err(match bounds {
+            Some((lo, hi)) => NoRoots { variable: String::from_str(variable), lo: lo, hi: hi },
+            None => NoConvergence { variable: String::from_str(variable) },
+        });
+    }
+    Ok(roots.move_iter().map(|root| assignment(variable, simplify::number(root))).collect())
+}
+
+// `variable = value`
+fn assignment(variable: &str, value: ExprNode) -> ExprNode {
+    let target = ExprNode::new(variable, ExprVariable, vec![]);
+    ExprNode::new("=", ExprBinary, vec![target, value])
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use env::Environment;
+    use exprtree::ExprTree;
+    use value::Real;
+    use super::{find_roots, solve};
+
+    // the solutions of `equation` for x, as the calculator prints them
+    fn solutions(equation: &str, bounds: Option<(f64, f64)>, env: &mut Environment) -> Vec<String> {
+        let tree = ExprTree::build(equation).unwrap();
+        solve(tree.root().unwrap(), "x", bounds, env).unwrap().iter()
+            .map(|solution| format!("{}", solution))
+            .collect()
+    }
+
+    #[test]
+    fn sign_changes_are_bisected() {
+        let roots = find_roots(|x| Ok(x * x - 4_f64), -5_f64, 5_f64).unwrap();
+        assert_eq!(roots.len(), 2);
+        assert!((*roots.get(0) + 2_f64).abs() < 1e-9);
+        assert!((*roots.get(1) - 2_f64).abs() < 1e-9);
+        // a pole is not a root
+        assert!(find_roots(|x| Ok(1_f64 / x), -1_f64, 1.5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn equations_are_rearranged_when_they_can_be() {
+        let mut env = Environment::new();
+        assert_eq!(solutions("2*x + 3 = 11", None, &mut env), vec![String::from_str("x = 4")]);
+        assert_eq!(solutions("a*x = b", None, &mut env), vec![String::from_str("x = b / a")]);
+
+        env.set("x", Real(7_f64));
+        assert_eq!(solutions("x^2 = 4", Some((-4_f64, 4_f64)), &mut env),
+                   vec![String::from_str("x = -2"), String::from_str("x = 2")]);
+        // the variable is left as it was
+        assert_eq!(env.get("x"), Some(Real(7_f64)));
+    }
+}
@@ -0,0 +1,472 @@
+use std::string::String;
+
+use builtins;
+use exprtree::{ExprNode, ExprLiteral, ExprVariable, ExprUnary, ExprBinary, ExprCall, ExprText};
+use exprtree::ExprUnit;
+
+static ONES: [&'static str, ..20] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen",
+    "nineteen",
+];
+
+static TENS: [&'static str, ..10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+static SCALES: [&'static str, ..7] = [
+    "", "thousand", "million", "billion", "trillion", "quadrillion", "quintillion",
+];
+
+// an i64 has at most 19 digits; longer runs are read digit by digit
+static MAX_READ_DIGITS: uint = 18;
+
+/// Reads `node` aloud the way a person would say it, for screen readers
+/// and voice assistants: `x^2 + 2x` is "x squared plus two x" and
+/// `sqrt(2) / 2` is "the square root of two divided by two". Where a
+/// reading would be ambiguous the grouping is said, as in "open paren a
+/// plus b close paren squared".
+pub fn speak(node: &ExprNode) -> String {
+    let children = node.children();
+    match node.kind() {
+        ExprLiteral => literal(node.token()),
+        ExprVariable => name(node.token()),
+        ExprText => format!("the text {}", node.token()),
+        ExprUnit => format!("{} {}", base(children.get(0)), unit(node.token())),
+        ExprUnary =>
+            format!("negative {}", operand(children.get(0), node.precedence().unwrap(), false)),
+        ExprBinary => {
+            let (lhs, rhs) = (children.get(0), children.get(1));
+            match (node.token(), rhs.token()) {
+                ("^", "2") => format!("{} squared", base(lhs)),
+                ("^", "3") => format!("{} cubed", base(lhs)),
+                ("^", _) => format!("{} to the power of {}", base(lhs), base(rhs)),
+                ("*", _) if lhs.kind() == ExprLiteral && is_term(rhs) =>
+                    format!("{} {}", speak(lhs), speak(rhs)),
+                (operator, _) => {
+                    let precedence = node.precedence().unwrap();
+                    let right_assoc = node.is_right_assoc();
+                    format!("{} {} {}", operand(lhs, precedence, right_assoc),
+                            operator_words(operator), operand(rhs, precedence, !right_assoc))
+                },
+            }
+        },
+        ExprCall => {
+            let args: Vec<String> = children.iter().map(|child| speak(child)).collect();
+            if node.token() == "list" {
+                return format!("the list {}", join(args.as_slice()));
+            }
+            if node.token() == "log" && children.len() == 2 {
+                return format!("log base {} of {}", args.get(1), args.get(0));
+            }
+            if children.len() != 1 {
+                return format!("{} of {}", function_name(node.token()), join(args.as_slice()));
+            }
+
+            let arg = base(children.get(0));
+            match node.token() {
+                "sqrt" => format!("the square root of {}", arg),
+                "cbrt" => format!("the cube root of {}", arg),
+                "abs" => format!("the absolute value of {}", arg),
+                "floor" => format!("the floor of {}", arg),
+                "ceil" => format!("the ceiling of {}", arg),
+                "exp" => format!("e to the power of {}", arg),
+                "fact" => format!("{} factorial", arg),
+                function => format!("{} of {}", function_name(function), arg),
+            }
+        },
+    }
+}
+
+/// Reads out how `node` is built rather than how it would be said: every
+/// operation is named with what it applies to, so `(a + b) / 2` is "the
+/// quotient of the sum of a and b, and two". Nothing is left to precedence,
+/// which lets a student check that an expression was read the way they
+/// meant.
+pub fn describe(node: &ExprNode) -> String {
+    let children = node.children();
+    match node.kind() {
+        ExprLiteral | ExprVariable | ExprText => speak(node),
+        ExprUnit if children.get(0).kind() == ExprLiteral =>
+            format!("{} {}", speak(children.get(0)), unit(node.token())),
+        ExprUnit => format!("{}, measured in {}", describe(children.get(0)), unit(node.token())),
+        ExprUnary => format!("the negation of {}", describe(children.get(0))),
+        ExprBinary => {
+            let (lhs, rhs) = (describe(children.get(0)), describe(children.get(1)));
+            match node.token() {
+                "=" => format!("the equation {}", pair(lhs, "equals", rhs)),
+                "to" | "in" => format!("{}, converted to {}", lhs, rhs),
+                "|>" => format!("{}, piped into {}", lhs, rhs),
+                "<" | ">" | "<=" | ">=" | "==" | "!=" =>
+                    format!("whether {}", pair(lhs, operator_words(node.token()), rhs)),
+                operator => format!("the {} of {}", operation(operator), pair(lhs, "and", rhs)),
+            }
+        },
+        ExprCall => {
+            let args: Vec<String> = children.iter().map(|child| describe(child)).collect();
+            if node.token() == "list" {
+                return format!("the list of {}", join(args.as_slice()));
+            }
+            let function = match node.token() {
+                "sqrt" => String::from_str("the square root"),
+                "cbrt" => String::from_str("the cube root"),
+                "abs" => String::from_str("the absolute value"),
+                "ceil" => String::from_str("the ceiling"),
+                "fact" => String::from_str("the factorial"),
+                token if builtins::is_builtin(token) && !function_name(token).as_slice().starts_with("the ") =>
+                    format!("the {}", function_name(token)),
+                token => function_name(token),
+            };
+            format!("{} of {}", function, join(args.as_slice()))
+        },
+    }
+}
+
+/// A number as printed, such as `-3.14` or `6.02e23`, read aloud: "negative
+/// three point one four" or "six point zero two times ten to the power of
+/// twenty-three". `None` when `text` is not a number.
+pub fn number(text: &str) -> Option<String> {
+    match text {
+        "inf" => return Some(String::from_str("infinity")),
+        "-inf" => return Some(String::from_str("negative infinity")),
+        "NaN" => return Some(String::from_str("not a number")),
+        _ => {},
+    }
+
+    let (sign, text) = if text.starts_with("-") {
+        ("negative ", text.slice_from(1))
+    } else {
+        ("", text)
+    };
+    let (mantissa, exponent) = match text.find(|c: char| c == 'e' || c == 'E') {
+        Some(i) => (text.slice_to(i), Some(text.slice_from(i + 1))),
+        None => (text, None),
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(i) => (mantissa.slice_to(i), Some(mantissa.slice_from(i + 1))),
+        None => (mantissa, None),
+    };
+
+    if !all_digits(whole) || !fraction.map_or(true, |f| all_digits(f)) {
+        return None;
+    }
+
+    let mut words = format!("{}{}", sign, integer(whole));
+    match fraction {
+        Some(fraction) => {
+            words.push_str(" point ");
+            words.push_str(digit_by_digit(fraction).as_slice());
+        },
+        None => {},
+    }
+    match exponent {
+        Some(exponent) => match number(exponent.trim_left_chars('+')) {
+            Some(power) => {
+                words.push_str(" times ten to the power of ");
+                words.push_str(power.as_slice());
+            },
+            None => return None,
+        },
+        None => {},
+    }
+    Some(words)
+}
+
+/// A printed result read aloud: numbers are read with `number`, fractions
+/// such as `1/3` as "one over three" and SI prefixes by name, and any
+/// other word is left as it is.
+pub fn text(shown: &str) -> String {
+    let words: Vec<String> = shown.words().map(|word| {
+        match number(word) {
+            Some(words) => return words,
+            None => {},
+        }
+        let parts: Vec<&str> = word.split('/').collect();
+        if parts.len() == 2 {
+            match (number(*parts.get(0)), number(*parts.get(1))) {
+                (Some(numerator), Some(denominator)) =>
+                    return format!("{} over {}", numerator, denominator),
+                _ => {},
+            }
+        }
+        String::from_str(match word {
+            "k" => "kilo",
+            "M" => "mega",
+            "G" => "giga",
+            "T" => "tera",
+            "m" => "milli",
+            "u" | "µ" => "micro",
+            "n" => "nano",
+            "p" => "pico",
+            other => other,
+        })
+    }).collect();
+    words.as_slice().connect(" ")
+}
+
+/// `items` as a spoken list: "a, b and c".
+pub fn join(items: &[String]) -> String {
+    match items.len() {
+        0 => String::from_str("nothing"),
+        1 => items[0].clone(),
+        n => format!("{} and {}", items.slice_to(n - 1).connect(", "), items[n - 1]),
+    }
+}
+
+// a whole number written in decimal digits, which may be long
+fn integer(digits: &str) -> String {
+    let digits = digits.trim_left_chars('0');
+    if digits.is_empty() {
+        return String::from_str("zero");
+    }
+    if digits.len() > MAX_READ_DIGITS {
+        return digit_by_digit(digits);
+    }
+
+    let mut n: u64 = from_str(digits).unwrap();
+    let mut groups: Vec<String> = vec![];
+    let mut scale = 0u;
+    while n > 0 {
+        let group = (n % 1000) as uint;
+        if group > 0 {
+            let words = hundreds(group);
+            groups.push(if scale == 0 { words } else { format!("{} {}", words, SCALES[scale]) });
+        }
+        n /= 1000;
+        scale += 1;
+    }
+    groups.reverse();
+    groups.as_slice().connect(" ")
+}
+
+// 1 to 999
+fn hundreds(n: uint) -> String {
+    let rest = n % 100;
+    let tens = if rest < 20 {
+        String::from_str(ONES[rest])
+    } else if rest % 10 == 0 {
+        String::from_str(TENS[rest / 10])
+    } else {
+        format!("{}-{}", TENS[rest / 10], ONES[rest % 10])
+    };
+
+    match (n / 100, rest) {
+        (0, _) => tens,
+        (h, 0) => format!("{} hundred", ONES[h]),
+        (h, _) => format!("{} hundred {}", ONES[h], tens),
+    }
+}
+
+fn all_digits(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_digit())
+}
+
+fn digit_by_digit(digits: &str) -> String {
+    let words: Vec<&str> = digits.chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|d| ONES[d])
+        .collect();
+    words.as_slice().connect(" ")
+}
+
+// what comes straight after a number it multiplies, as in "two x"
+fn is_term(node: &ExprNode) -> bool {
+    match node.kind() {
+        ExprVariable | ExprCall => true,
+        ExprBinary => node.token() == "^" && node.children().get(0).kind() == ExprVariable,
+        _ => false,
+    }
+}
+
+// an operand, with its grouping said when precedence needs it
+fn operand(child: &ExprNode, precedence: i32, tie_needs_parens: bool) -> String {
+    if child.needs_parens(precedence, tie_needs_parens) {
+        format!("open paren {} close paren", speak(child))
+    } else {
+        speak(child)
+    }
+}
+
+// the base or exponent of a power, or the argument of a function, which
+// any operation must be set apart from
+fn base(child: &ExprNode) -> String {
+    if child.precedence().is_some() {
+        format!("open paren {} close paren", speak(child))
+    } else {
+        speak(child)
+    }
+}
+
+// two operands around `word`, with a comma after the first when it
+// already has an "and" of its own, so it is clear where it ends
+fn pair(lhs: String, word: &str, rhs: String) -> String {
+    if lhs.as_slice().contains(" and ") {
+        format!("{}, {} {}", lhs, word, rhs)
+    } else {
+        format!("{} {} {}", lhs, word, rhs)
+    }
+}
+
+// what the result of a binary operator is called
+fn operation<'a>(operator: &'a str) -> &'a str {
+    match operator {
+        "+" => "sum",
+        "-" => "difference",
+        "*" => "product",
+        "/" => "quotient",
+        "//" => "floor quotient",
+        "%" => "remainder",
+        "^" => "power",
+        "<<" => "left shift",
+        ">>" => "right shift",
+        "&" => "bitwise and",
+        "|" => "bitwise or",
+        "xor" => "exclusive or",
+        other => other,
+    }
+}
+
+fn operator_words<'a>(operator: &'a str) -> &'a str {
+    match operator {
+        "+" => "plus",
+        "-" => "minus",
+        "*" => "times",
+        "/" => "divided by",
+        "//" => "floor divided by",
+        "%" => "modulo",
+        "<<" => "shifted left by",
+        ">>" => "shifted right by",
+        "&" => "bitwise and",
+        "|" => "bitwise or",
+        "xor" => "exclusive or",
+        "<" => "is less than",
+        ">" => "is greater than",
+        "<=" => "is less than or equal to",
+        ">=" => "is greater than or equal to",
+        "==" => "is equal to",
+        "!=" => "is not equal to",
+        "=" => "equals",
+        "|>" => "piped into",
+        "to" | "in" => "in",
+        other => other,
+    }
+}
+
+fn function_name(function: &str) -> String {
+    String::from_str(match function {
+        "sin" => "sine",
+        "cos" => "cosine",
+        "tan" => "tangent",
+        "csc" => "cosecant",
+        "sec" => "secant",
+        "cot" => "cotangent",
+        "asin" => "arc sine",
+        "acos" => "arc cosine",
+        "atan" | "atan2" => "arc tangent",
+        "sinh" => "hyperbolic sine",
+        "cosh" => "hyperbolic cosine",
+        "tanh" => "hyperbolic tangent",
+        "ln" => "the natural log",
+        "log" => "log base ten",
+        "lg" => "log base two",
+        "sgn" => "the sign",
+        other => return name(other),
+    })
+}
+
+fn literal(token: &str) -> String {
+    match token {
+        "sqrt2" => String::from_str("the square root of two"),
+        "ln2" => String::from_str("the natural log of two"),
+        "ln10" => String::from_str("the natural log of ten"),
+        "euler_gamma" => String::from_str("Euler's gamma"),
+        "pi" | "e" | "tau" | "phi" => String::from_str(token),
+        _ => {
+            let plain: String = token.chars().filter(|&c| c != '_').collect();
+            number(plain.as_slice()).unwrap_or(String::from_str(token))
+        },
+    }
+}
+
+// underscores separate words in names
+fn name(token: &str) -> String {
+    token.replace("_", " ")
+}
+
+fn unit<'a>(name: &'a str) -> &'a str {
+    match name {
+        "m" => "meters",
+        "km" => "kilometers",
+        "cm" => "centimeters",
+        "mm" => "millimeters",
+        "mi" => "miles",
+        "yd" => "yards",
+        "ft" => "feet",
+        "inch" => "inches",
+        "kg" => "kilograms",
+        "g" => "grams",
+        "lb" => "pounds",
+        "oz" => "ounces",
+        "s" => "seconds",
+        "ms" => "milliseconds",
+        "min" => "minutes",
+        "h" => "hours",
+        "day" => "days",
+        "wk" => "weeks",
+        "k" => "kelvin",
+        "c" => "degrees Celsius",
+        "f" => "degrees Fahrenheit",
+        "b" => "bytes",
+        "kb" => "kilobytes",
+        "mb" => "megabytes",
+        "gb" => "gigabytes",
+        "tb" => "terabytes",
+        "bit" => "bits",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+    use super::{speak, describe, number, text, join};
+
+    fn spoken(expression: &str) -> String {
+        let tree = ExprTree::build(expression).unwrap();
+        speak(tree.root().unwrap())
+    }
+
+    fn described(expression: &str) -> String {
+        let tree = ExprTree::build(expression).unwrap();
+        describe(tree.root().unwrap())
+    }
+
+    #[test]
+    fn numbers_are_read_as_words() {
+        assert_eq!(number("-3.14"), Some(String::from_str("negative three point one four")));
+        assert_eq!(number("1200045"),
+                   Some(String::from_str("one million two hundred thousand forty-five")));
+        assert_eq!(number("6.02e23").unwrap().as_slice(),
+                   "six point zero two times ten to the power of twenty-three");
+        assert_eq!(number("x1"), None);
+        assert_eq!(text("1/3").as_slice(), "one over three");
+    }
+
+    #[test]
+    fn grouping_is_said_only_when_needed() {
+        assert_eq!(spoken("x^2 + 2x").as_slice(), "x squared plus two x");
+        assert_eq!(spoken("sqrt(2) / 2").as_slice(), "the square root of two divided by two");
+        assert_eq!(spoken("(a + b)^2").as_slice(), "open paren a plus b close paren squared");
+        assert_eq!(described("(a + b) / 2").as_slice(),
+                   "the quotient of the sum of a and b, and two");
+    }
+
+    #[test]
+    fn lists_are_joined_with_and() {
+        let items = vec![String::from_str("a"), String::from_str("b"), String::from_str("c")];
+        assert_eq!(join(items.as_slice()).as_slice(), "a, b and c");
+        assert_eq!(join(&[]).as_slice(), "nothing");
+    }
+}
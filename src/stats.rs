@@ -0,0 +1,138 @@
+use std::cmp::{Less, Equal, Greater};
+use std::f64;
+use std::string::String;
+
+/// Running statistics over a stream of numbers. Count, extremes, mean and
+/// variance are updated in one pass with Welford's method; the values are
+/// also kept so percentiles can be read at the end.
+pub struct Summary {
+    count: uint,
+    min: f64,
+    max: f64,
+    mean: f64,
+    // sum of squared differences from the running mean
+    m2: f64,
+    values: Vec<f64>,
+    sorted: bool,
+}
+
+impl Summary {
+    pub fn new() -> Summary {
+        Summary {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0_f64,
+            m2: 0_f64,
+            values: vec![],
+            sorted: true,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+
+        self.values.push(x);
+        self.sorted = false;
+    }
+
+    pub fn count(&self) -> uint {
+        self.count
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The sample standard deviation; zero for fewer than two values.
+    pub fn stddev(&self) -> f64 {
+        if self.count < 2 {
+            0_f64
+        } else {
+            (self.m2 / (self.count - 1) as f64).sqrt()
+        }
+    }
+
+    /// The `p`th percentile, 0 to 100, interpolating between the two
+    /// nearest values. NaN when nothing has been added.
+    pub fn percentile(&mut self, p: f64) -> f64 {
+        if self.values.is_empty() {
+            return f64::NAN;
+        }
+        if !self.sorted {
+            self.values.sort_by(|a, b| if a < b { Less } else if a > b { Greater } else { Equal });
+            self.sorted = true;
+        }
+
+        let rank = p.max(0_f64).min(100_f64) / 100_f64 * (self.values.len() - 1) as f64;
+        let (below, above) = (rank.floor() as uint, rank.ceil() as uint);
+        let weight = rank - below as f64;
+        *self.values.get(below) * (1_f64 - weight) + *self.values.get(above) * weight
+    }
+
+    /// The summary as aligned `name value` lines.
+    pub fn report(&mut self) -> String {
+        let mut lines = format!("{:<7} {}\n", "count", self.count);
+        if self.count == 0 {
+            return lines;
+        }
+
+        let rows = [
+            ("min", self.min),
+            ("max", self.max),
+            ("mean", self.mean),
+            ("stddev", self.stddev()),
+            ("p50", self.percentile(50_f64)),
+            ("p90", self.percentile(90_f64)),
+            ("p95", self.percentile(95_f64)),
+            ("p99", self.percentile(99_f64)),
+        ];
+        for &(name, value) in rows.iter() {
+            lines.push_str(format!("{:<7} {}\n", name, value).as_slice());
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use super::Summary;
+
+    #[test]
+    fn summaries_match_the_whole_set() {
+        let mut summary = Summary::new();
+        for &x in [4_f64, 2.0, 8.0, 6.0].iter() {
+            summary.add(x);
+        }
+        assert_eq!(summary.count(), 4);
+        assert_eq!(summary.min(), 2_f64);
+        assert_eq!(summary.max(), 8_f64);
+        assert!((summary.mean() - 5_f64).abs() < 1e-9);
+        assert!((summary.stddev() - (20_f64 / 3_f64).sqrt()).abs() < 1e-9);
+        assert!((summary.percentile(50_f64) - 5_f64).abs() < 1e-9);
+        assert_eq!(summary.percentile(100_f64), 8_f64);
+    }
+
+    #[test]
+    fn empty_summaries_only_count() {
+        let mut summary = Summary::new();
+        assert!(summary.percentile(50_f64).is_nan());
+        assert_eq!(summary.report(), String::from_str("count   0\n"));
+    }
+}
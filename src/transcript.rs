@@ -0,0 +1,95 @@
+use std::io::{File, IoResult};
+use std::string::String;
+
+/// One exchange in an interactive session.
+#[deriving(Show, Clone, PartialEq)]
+pub enum Entry {
+    /// An input and what it printed; definitions print nothing.
+    Evaluated { pub input: String, pub output: Option<String> },
+    /// An input that failed, with the error's message.
+    Failed { pub input: String, pub message: String },
+    /// An input that drew a chart.
+    Plotted { pub input: String, pub chart: String },
+}
+
+/// A record of a REPL session, kept so it can be written out as Markdown
+/// and pasted into documentation or a ticket.
+pub struct Transcript {
+    entries: Vec<Entry>,
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript { entries: vec![] }
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries<'a>(&'a self) -> &'a [Entry] {
+        self.entries.as_slice()
+    }
+
+    /// The session as Markdown: each exchange is a fenced block holding the
+    /// prompt and input followed by the output, error or chart, the way it
+    /// looked at the terminal.
+    pub fn to_markdown(&self) -> String {
+        let mut text = String::from_str("# calc session\n");
+        for entry in self.entries.iter() {
+            let (input, shown) = match *entry {
+                Evaluated { ref input, ref output } =>
+                    (input, output.clone().unwrap_or(String::new())),
+                Failed { ref input, ref message } => (input, format!("error: {}", message)),
+                Plotted { ref input, ref chart } => (input, chart.clone()),
+            };
+
+            text.push_str(format!("\n```\n> {}\n", input).as_slice());
+            if !shown.is_empty() {
+                text.push_str(shown.as_slice().trim_right_chars('\n'));
+                text.push_char('\n');
+            }
+            text.push_str("```\n");
+        }
+        text
+    }
+
+    /// Writes `to_markdown` to the file at `path`, replacing it.
+    pub fn export(&self, path: &Path) -> IoResult<()> {
+        let mut file = try!(File::create(path));
+        file.write_str(self.to_markdown().as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use super::{Transcript, Evaluated, Failed, Plotted};
+
+    #[test]
+    fn each_exchange_is_a_fenced_block() {
+        let mut transcript = Transcript::new();
+        transcript.record(Evaluated { input: String::from_str("x = 2"), output: None });
+        transcript.record(Evaluated {
+            input: String::from_str("x * 3"),
+            output: Some(String::from_str("6")),
+        });
+        transcript.record(Failed {
+            input: String::from_str("y"),
+            message: String::from_str("unknown variable 'y'"),
+        });
+        transcript.record(Plotted {
+            input: String::from_str(":plot x"),
+            chart: String::from_str("  *\n *\n*\n"),
+        });
+
+        assert_eq!(transcript.entries().len(), 4);
+        let expected = "# calc session\n\
+                        \n```\n> x = 2\n```\n\
+                        \n```\n> x * 3\n6\n```\n\
+                        \n```\n> y\nerror: unknown variable 'y'\n```\n\
+                        \n```\n> :plot x\n  *\n *\n*\n```\n";
+        assert_eq!(transcript.to_markdown().as_slice(), expected);
+    }
+}
@@ -0,0 +1,375 @@
+#[cfg(feature = "units")]
+use std::ascii::StrAsciiExt;
+use std::fmt;
+use std::num::Zero;
+use std::string::String;
+
+use error::{CalcError, DimensionMismatch};
+
+/// Powers of the base dimensions, in the order length, mass, time,
+/// temperature and data.
+pub type Dimensions = [i32, ..5];
+
+/// A unit the calculator knows. An amount `x` of it is `x * factor +
+/// offset` of the base unit for its dimension: metres, kilograms,
+/// seconds, kelvin or bytes. Only temperatures have an offset.
+pub struct Unit {
+    pub name: &'static str,
+    pub dimensions: Dimensions,
+    pub factor: f64,
+    pub offset: f64,
+}
+
+// names are lower case, though `lookup` takes `kW` or `MB` as well; `in`
+// is taken by the conversion operator, so inches are `inch`
+#[cfg(feature = "units")]
+static UNITS: [Unit, ..34] = [
+    Unit { name: "m", dimensions: [1, 0, 0, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "km", dimensions: [1, 0, 0, 0, 0], factor: 1000.0, offset: 0.0 },
+    Unit { name: "cm", dimensions: [1, 0, 0, 0, 0], factor: 0.01, offset: 0.0 },
+    Unit { name: "mm", dimensions: [1, 0, 0, 0, 0], factor: 0.001, offset: 0.0 },
+    Unit { name: "mi", dimensions: [1, 0, 0, 0, 0], factor: 1609.344, offset: 0.0 },
+    Unit { name: "yd", dimensions: [1, 0, 0, 0, 0], factor: 0.9144, offset: 0.0 },
+    Unit { name: "ft", dimensions: [1, 0, 0, 0, 0], factor: 0.3048, offset: 0.0 },
+    Unit { name: "inch", dimensions: [1, 0, 0, 0, 0], factor: 0.0254, offset: 0.0 },
+    Unit { name: "kg", dimensions: [0, 1, 0, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "g", dimensions: [0, 1, 0, 0, 0], factor: 0.001, offset: 0.0 },
+    Unit { name: "lb", dimensions: [0, 1, 0, 0, 0], factor: 0.45359237, offset: 0.0 },
+    Unit { name: "oz", dimensions: [0, 1, 0, 0, 0], factor: 0.028349523125, offset: 0.0 },
+    Unit { name: "s", dimensions: [0, 0, 1, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "ms", dimensions: [0, 0, 1, 0, 0], factor: 0.001, offset: 0.0 },
+    Unit { name: "min", dimensions: [0, 0, 1, 0, 0], factor: 60.0, offset: 0.0 },
+    Unit { name: "h", dimensions: [0, 0, 1, 0, 0], factor: 3600.0, offset: 0.0 },
+    Unit { name: "day", dimensions: [0, 0, 1, 0, 0], factor: 86400.0, offset: 0.0 },
+    Unit { name: "wk", dimensions: [0, 0, 1, 0, 0], factor: 604800.0, offset: 0.0 },
+    Unit { name: "k", dimensions: [0, 0, 0, 1, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "c", dimensions: [0, 0, 0, 1, 0], factor: 1.0, offset: 273.15 },
+    Unit { name: "f", dimensions: [0, 0, 0, 1, 0], factor: 0.5555555555555556,
+           offset: 255.3722222222222 },
+    Unit { name: "b", dimensions: [0, 0, 0, 0, 1], factor: 1.0, offset: 0.0 },
+    Unit { name: "kb", dimensions: [0, 0, 0, 0, 1], factor: 1e3, offset: 0.0 },
+    Unit { name: "mb", dimensions: [0, 0, 0, 0, 1], factor: 1e6, offset: 0.0 },
+    Unit { name: "gb", dimensions: [0, 0, 0, 0, 1], factor: 1e9, offset: 0.0 },
+    Unit { name: "tb", dimensions: [0, 0, 0, 0, 1], factor: 1e12, offset: 0.0 },
+    Unit { name: "bit", dimensions: [0, 0, 0, 0, 1], factor: 0.125, offset: 0.0 },
+    Unit { name: "n", dimensions: [1, 1, -2, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "kn", dimensions: [1, 1, -2, 0, 0], factor: 1000.0, offset: 0.0 },
+    Unit { name: "j", dimensions: [2, 1, -2, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "kj", dimensions: [2, 1, -2, 0, 0], factor: 1000.0, offset: 0.0 },
+    Unit { name: "kwh", dimensions: [2, 1, -2, 0, 0], factor: 3.6e6, offset: 0.0 },
+    Unit { name: "w", dimensions: [2, 1, -3, 0, 0], factor: 1.0, offset: 0.0 },
+    Unit { name: "kw", dimensions: [2, 1, -3, 0, 0], factor: 1000.0, offset: 0.0 },
+];
+
+// the units a result may be moved between to keep its magnitude readable,
+// smallest first, one list per dimension
+#[cfg(feature = "units")]
+static LADDERS: [&'static [&'static str], ..7] = [
+    &["mm", "cm", "m", "km"],
+    &["g", "kg"],
+    &["ms", "s", "min", "h", "day"],
+    &["b", "kb", "mb", "gb", "tb"],
+    &["n", "kn"],
+    &["j", "kj"],
+    &["w", "kw"],
+];
+
+/// The registered unit called `name`, or failing that the one whose name
+/// differs only in case, so `kW` is `kw`.
+#[cfg(feature = "units")]
+pub fn lookup(name: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.name == name).or_else(|| {
+        UNITS.iter().find(|unit| unit.name.eq_ignore_ascii_case(name))
+    })
+}
+
+// the units of the same dimensions as `measure` that it may be moved
+// between, if there are any
+#[cfg(feature = "units")]
+fn ladder(measure: &Measure) -> Option<&'static [&'static str]> {
+    let dimensions = measure.dimensions();
+    LADDERS.iter().map(|&ladder| ladder)
+        .find(|ladder| lookup(ladder[0]).unwrap().dimensions == dimensions)
+}
+
+// a physical constant, which is a quantity: `magnitude` of the product of
+// `units`, each to its power
+#[cfg(feature = "units")]
+struct Constant {
+    name: &'static str,
+    magnitude: f64,
+    units: &'static [(&'static str, i32)],
+}
+
+#[cfg(feature = "units")]
+static CONSTANTS: [Constant, ..2] = [
+    Constant { name: "c", magnitude: 299792458.0, units: &[("m", 1), ("s", -1)] },
+    Constant { name: "gn", magnitude: 9.80665, units: &[("m", 1), ("s", -2)] },
+];
+
+/// The physical constant called `name` with its units, such as `c`, the
+/// speed of light in m/s, so `m*c^2` of a mass comes out in joules.
+#[cfg(feature = "units")]
+pub fn constant(name: &str) -> Option<Measure> {
+    CONSTANTS.iter().find(|constant| constant.name == name).map(|constant| Measure {
+        magnitude: constant.magnitude,
+        units: constant.units.iter()
+            .map(|&(unit, power)| (String::from_str(unit), power))
+            .collect(),
+    })
+}
+
+#[cfg(not(feature = "units"))]
+pub fn constant(_: &str) -> Option<Measure> {
+    None
+}
+
+#[cfg(feature = "units")]
+static DATA_UNITS: [&'static str, ..5] = ["b", "kb", "mb", "gb", "tb"];
+
+/// `bytes` a second as a quantity, in whichever of b/s, kb/s, mb/s, gb/s
+/// and tb/s puts the magnitude under 1000, or as near as they allow.
+#[cfg(feature = "units")]
+pub fn data_rate(bytes: f64) -> Option<Measure> {
+    let name = DATA_UNITS.iter().rev().map(|name| *name)
+        .find(|name| (bytes / lookup(*name).unwrap().factor).abs() >= 1_f64)
+        .unwrap_or("b");
+    Some(Measure {
+        magnitude: bytes / lookup(name).unwrap().factor,
+        units: vec![(String::from_str(name), 1), (String::from_str("s"), -1)],
+    })
+}
+
+#[cfg(not(feature = "units"))]
+pub fn data_rate(_: f64) -> Option<Measure> {
+    None
+}
+
+/// Without the `units` feature no name is a unit, so no quantity can be
+/// written; `Measure` stays, as values can hold one.
+#[cfg(not(feature = "units"))]
+pub fn lookup(_: &str) -> Option<&'static Unit> {
+    None
+}
+
+#[cfg(not(feature = "units"))]
+fn ladder(_: &Measure) -> Option<&'static [&'static str]> {
+    None
+}
+
+/// A number with units, such as `2.5 km/h`. Units are kept as written,
+/// each with its power, so results come out in the units that went in
+/// unless `nicest` finds a more readable one.
+#[deriving(Clone, PartialEq)]
+pub struct Measure {
+    pub magnitude: f64,
+    pub units: Vec<(String, i32)>,
+}
+
+impl Measure {
+    /// `magnitude` of the unit `name`, which must be registered.
+    pub fn of(magnitude: f64, name: &str) -> Measure {
+        Measure { magnitude: magnitude, units: vec![(String::from_str(name), 1)] }
+    }
+
+    /// A plain number, with no units.
+    pub fn number(magnitude: f64) -> Measure {
+        Measure { magnitude: magnitude, units: vec![] }
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        let mut dimensions = [0i32, ..5];
+        for &(ref name, power) in self.units.iter() {
+            let unit = lookup(name.as_slice()).unwrap();
+            for (total, &d) in dimensions.mut_iter().zip(unit.dimensions.iter()) {
+                *total += d * power;
+            }
+        }
+        dimensions
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimensions().iter().all(|d| d.is_zero())
+    }
+
+    /// The size of one of these units in base units, ignoring offsets.
+    pub fn scale(&self) -> f64 {
+        self.units.iter().fold(1_f64, |scale, &(ref name, power)| {
+            scale * lookup(name.as_slice()).unwrap().factor.powi(power)
+        })
+    }
+
+    /// The magnitude expressed in the units of `other`, whose own magnitude
+    /// is ignored. A lone temperature converts with its offset, so `100 f`
+    /// in `c` is about 37.8.
+    pub fn in_units_of(&self, other: &Measure) -> Result<f64, CalcError> {
+        if self.dimensions() != other.dimensions() {
+            return Err(DimensionMismatch {
+                left: self.unit_text(),
+                right: other.unit_text(),
+            });
+        }
+
+        match (self.lone_unit(), other.lone_unit()) {
+            (Some(from), Some(to)) =>
+                Ok((self.magnitude * from.factor + from.offset - to.offset) / to.factor),
+            _ => Ok(self.magnitude * self.scale() / other.scale()),
+        }
+    }
+
+    /// `self` in the unit called `name`, which must be registered.
+    pub fn convert(&self, name: &str) -> Result<Measure, CalcError> {
+        let magnitude = try!(self.in_units_of(&Measure::of(1_f64, name)));
+        Ok(Measure::of(magnitude, name))
+    }
+
+    /// `self` in whichever unit of its dimension puts the magnitude
+    /// between 1 and 1000, or as near as the units allow, so `1500 m` is
+    /// `1.5 km` and a product of `kg*m^2/s^2` comes out in joules. A lone
+    /// unit with no larger or smaller kin, such as `ft`, is kept, as is one
+    /// whose magnitude is already in range.
+    pub fn nicest(&self) -> Measure {
+        let ladder = match ladder(self) {
+            Some(ladder) => ladder,
+            None => return self.clone(),
+        };
+        if self.units.len() == 1 {
+            let (ref name, power) = *self.units.get(0);
+            let readable = self.magnitude.abs() >= 1_f64 && self.magnitude.abs() < 1000_f64;
+            if power == 1 && (readable || !ladder.iter().any(|n| *n == name.as_slice())) {
+                return self.clone();
+            }
+        }
+        if self.magnitude == 0_f64 || !self.magnitude.is_finite() {
+            return self.clone();
+        }
+
+        // the largest unit the magnitude is at least one of
+        let base = self.magnitude * self.scale();
+        let name = ladder.iter().rev()
+            .find(|n| (base / lookup(**n).unwrap().factor).abs() >= 1_f64)
+            .unwrap_or(&ladder[0]);
+        Measure::of(base / lookup(*name).unwrap().factor, *name)
+    }
+
+    /// `self + other`, in the units of `self`.
+    pub fn plus(&self, other: &Measure) -> Result<Measure, CalcError> {
+        let addend = try!(other.in_units_of(self));
+        Ok(Measure { magnitude: self.magnitude + addend, units: self.units.clone() })
+    }
+
+    /// `self * other`, with the powers of like units added.
+    pub fn times(&self, other: &Measure) -> Measure {
+        let mut units = self.units.clone();
+        for &(ref name, power) in other.units.iter() {
+            match units.iter().position(|&(ref n, _)| n == name) {
+                Some(i) => match *units.get_mut(i) {
+                    (_, ref mut existing) => *existing += power,
+                },
+                None => units.push((name.clone(), power)),
+            }
+        }
+        units.retain(|&(_, power)| power != 0);
+        Measure { magnitude: self.magnitude * other.magnitude, units: units }
+    }
+
+    pub fn powi(&self, n: i32) -> Measure {
+        Measure {
+            magnitude: self.magnitude.powi(n),
+            units: self.units.iter()
+                .filter(|_| n != 0)
+                .map(|&(ref name, power)| (name.clone(), power * n))
+                .collect(),
+        }
+    }
+
+    /// How the units are written, such as `km/h`.
+    pub fn unit_text(&self) -> String {
+        let mut text = String::new();
+        let write = |text: &mut String, name: &String, power: i32| {
+            text.push_str(name.as_slice());
+            if power != 1 {
+                text.push_str(format!("^{}", power).as_slice());
+            }
+        };
+
+        for &(ref name, power) in self.units.iter().filter(|&&(_, p)| p > 0) {
+            if !text.is_empty() {
+                text.push_char('*');
+            }
+            write(&mut text, name, power);
+        }
+        let numerator = !text.is_empty();
+        for &(ref name, power) in self.units.iter().filter(|&&(_, p)| p < 0) {
+            if numerator {
+                text.push_char('/');
+                write(&mut text, name, -power);
+            } else {
+                if !text.is_empty() {
+                    text.push_char('*');
+                }
+                write(&mut text, name, power);
+            }
+        }
+        text
+    }
+
+    fn lone_unit(&self) -> Option<&'static Unit> {
+        if self.units.len() != 1 {
+            return None;
+        }
+        let (ref name, power) = *self.units.get(0);
+        if power == 1 { lookup(name.as_slice()) } else { None }
+    }
+}
+
+impl fmt::Show for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.units.is_empty() {
+            write!(f, "{}", self.magnitude)
+        } else {
+            write!(f, "{} {}", self.magnitude, self.unit_text())
+        }
+    }
+}
+
+#[cfg(test, feature = "units")]
+mod test {
+    use std::string::String;
+
+    use super::{Measure, constant};
+
+    fn measure(magnitude: f64, units: &[(&str, i32)]) -> Measure {
+        Measure {
+            magnitude: magnitude,
+            units: units.iter().map(|&(name, power)| (String::from_str(name), power)).collect(),
+        }
+    }
+
+    #[test]
+    fn results_move_to_readable_units() {
+        assert_eq!(Measure::of(1500_f64, "m").nicest(), Measure::of(1.5, "km"));
+        assert_eq!(Measure::of(0.25, "kg").nicest(), Measure::of(250_f64, "g"));
+        assert_eq!(Measure::of(7200_f64, "s").nicest(), Measure::of(2_f64, "h"));
+        // in range, or without kin, a unit stays as written
+        assert_eq!(Measure::of(30_f64, "cm").nicest(), Measure::of(30_f64, "cm"));
+        assert_eq!(Measure::of(5280_f64, "ft").nicest(), Measure::of(5280_f64, "ft"));
+        assert_eq!(Measure::of(100_f64, "f").nicest(), Measure::of(100_f64, "f"));
+    }
+
+    #[test]
+    fn derived_units_are_found() {
+        let work = measure(3_f64, [("kg", 1), ("m", 2), ("s", -2)]);
+        assert_eq!(work.nicest(), Measure::of(3_f64, "j"));
+        let power = measure(2500_f64, [("j", 1), ("s", -1)]);
+        assert_eq!(power.nicest(), Measure::of(2.5, "kw"));
+        assert_eq!(Measure::of(2_f64, "kwh").convert("kJ"), Ok(Measure::of(7200_f64, "kJ")));
+    }
+
+    #[test]
+    fn constants_carry_units() {
+        let energy = Measure::of(1_f64, "kg").times(&constant("c").unwrap().powi(2));
+        assert_eq!(energy.convert("j"), Ok(Measure::of(89875517873681764_f64, "j")));
+        assert!(constant("pi").is_none());
+    }
+}
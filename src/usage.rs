@@ -0,0 +1,127 @@
+use std::cmp::Equal;
+use std::collections::TreeMap;
+use std::string::String;
+
+use builtins;
+use exprtree::{ExprTree, ExprNode, ExprUnary, ExprBinary, ExprCall, ExprUnit};
+
+/// How often each operator and built-in turns up across a corpus of
+/// formulas, for deciding which operations are worth speeding up or
+/// keeping in a minimal build. Calls to user functions are counted
+/// together, as are units, since their names say nothing about the
+/// calculator itself.
+pub struct Usage {
+    counts: TreeMap<String, uint>,
+    formulas: uint,
+    unreadable: uint,
+}
+
+impl Usage {
+    pub fn new() -> Usage {
+        Usage { counts: TreeMap::new(), formulas: 0, unreadable: 0 }
+    }
+
+    /// Counts the operations in `tree`.
+    pub fn add(&mut self, tree: &ExprTree) {
+        self.formulas += 1;
+        match tree.root() {
+            Some(root) => self.count(root),
+            None => {},
+        }
+    }
+
+    /// Notes a formula that could not be parsed, so the report can say how
+    /// much of the corpus it covers.
+    pub fn skip(&mut self) {
+        self.unreadable += 1;
+    }
+
+    /// Each operation with how many times it was used, most used first and
+    /// ties in name order.
+    pub fn ranked(&self) -> Vec<(String, uint)> {
+        let mut ranked: Vec<(String, uint)> = self.counts.iter()
+            .map(|(name, &count)| (name.clone(), count))
+            .collect();
+        ranked.sort_by(|&(ref a, m), &(ref b, n)| match n.cmp(&m) {
+            Equal => a.cmp(b),
+            order => order,
+        });
+        ranked
+    }
+
+    /// A table of the operations, most used first, with each one's share
+    /// of all the operations counted.
+    pub fn report(&self) -> String {
+        let mut lines = format!("{} formulas", self.formulas);
+        if self.unreadable > 0 {
+            lines.push_str(format!(", {} more could not be read", self.unreadable).as_slice());
+        }
+        lines.push_char('\n');
+
+        let ranked = self.ranked();
+        let total = ranked.iter().fold(0, |total, &(_, count)| total + count);
+        if total == 0 {
+            return lines;
+        }
+        lines.push_str(format!("{:>8}  {:>6}  {}\n", "count", "share", "operation").as_slice());
+        for &(ref name, count) in ranked.iter() {
+            let share = 100_f64 * count as f64 / total as f64;
+            lines.push_str(format!("{:>8}  {:>5.1}%  {}\n", count, share, name).as_slice());
+        }
+        lines
+    }
+
+    fn count(&mut self, node: &ExprNode) {
+        let name = match node.kind() {
+            ExprUnary => Some(String::from_str("neg")),
+            ExprBinary => Some(String::from_str(node.token())),
+            ExprCall if builtins::is_builtin(node.token()) => Some(String::from_str(node.token())),
+            ExprCall => Some(String::from_str("(user function)")),
+            ExprUnit => Some(String::from_str("(unit)")),
+            _ => None,
+        };
+        match name {
+            Some(name) => {
+                let count = self.counts.find(&name).map_or(0, |&count| count);
+                self.counts.insert(name, count + 1);
+            },
+            None => {},
+        }
+        for child in node.children().iter() {
+            self.count(child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::string::String;
+
+    use exprtree::ExprTree;
+    use super::Usage;
+
+    #[test]
+    fn operations_are_ranked_by_use() {
+        let mut usage = Usage::new();
+        for formula in ["x^2 + 2*x + 1", "sin(x) + f(x)", "-x * 3"].iter() {
+            usage.add(&ExprTree::build(*formula).unwrap());
+        }
+        usage.skip();
+
+        let ranked: Vec<(String, uint)> = ["+", "*", "(user function)", "^", "neg", "sin"].iter()
+            .zip([3u, 2, 1, 1, 1, 1].iter())
+            .map(|(name, &count)| (String::from_str(*name), count))
+            .collect();
+        assert_eq!(usage.ranked(), ranked);
+
+        let report = usage.report();
+        assert!(report.as_slice().starts_with("3 formulas, 1 more could not be read\n\
+                                               \x20  count   share  operation\n\
+                                               \x20      3   33.3%  +\n"));
+    }
+
+    #[test]
+    fn an_empty_corpus_has_no_table() {
+        assert_eq!(Usage::new().report(), String::from_str("0 formulas\n"));
+    }
+}
@@ -0,0 +1,669 @@
+use std::cmp::{Ordering, Less, Equal, Greater};
+use std::fmt;
+use std::num::{pow, Float, Zero, Signed, ToPrimitive};
+use std::num::{CheckedAdd, CheckedSub, CheckedMul, CheckedDiv};
+use std::string::String;
+
+use num::complex;
+use num::complex::Complex64;
+use num::rational::{BigRational, Ratio};
+
+use decimal::{BigDecimal, DIVISION_PLACES, MAX_EXPONENT};
+use exprtree::ExprNode;
+use fixed::{FixedPoint, QFormat};
+#[cfg(feature = "matrix")]
+use matrix;
+use units::Measure;
+use error::{CalcError, UnknownOperator, DivisionByZero, TypeMismatch, ShapeMismatch};
+use error::IntegerOverflow;
+
+/// How literals are read and arithmetic is carried out.
+#[deriving(Show, Clone, PartialEq)]
+pub enum NumberMode {
+    /// Hardware doubles, the default.
+    FloatMode,
+    /// Arbitrary-precision decimals, selected with `--precision big`.
+    DecimalMode,
+    /// Exact fractions, selected with `--exact`.
+    ExactMode,
+    /// Integers in a word of this many bits, selected with `--int` and
+    /// `--word-width`. Words narrower than 64 bits wrap as a register of
+    /// that width would, so `127 + 1` is `-128` in 8 bits; in 64 bits
+    /// overflow is an error.
+    IntegerMode(uint),
+    /// Q-format fixed point, selected with `--qformat`.
+    FixedMode(QFormat),
+}
+
+/// The result of evaluating an expression. This is an enum rather than a
+/// trait over the tree so values of different kinds can meet in one
+/// expression: anything a decimal or fraction cannot hold exactly, such as
+/// `sin 1` or `2^0.5`, is computed with doubles and stays a `Real`.
+#[deriving(Clone, PartialEq)]
+pub enum Value {
+    Real(f64),
+    Decimal(BigDecimal),
+    Exact(BigRational),
+    Complex(Complex64),
+    Integer(i64),
+    Function(Partial),
+    List(Vec<Value>),
+    Text(String),
+    /// An unevaluated expression, such as the result of `rearrange`.
+    Expression(Box<ExprNode>),
+    /// A number with units, such as `5 km`.
+    Quantity(Measure),
+    /// A fixed-point word, in `FixedMode`.
+    Fixed(FixedPoint),
+}
+
+/// A function used as a value: a named function with some arguments
+/// already supplied. `None` marks a `_` still to be filled, so `mul(2, _)`
+/// holds `[Some(2), None]` and a bare `sin` holds nothing.
+#[deriving(Clone, PartialEq)]
+pub struct Partial {
+    pub name: String,
+    pub args: Vec<Option<Value>>,
+}
+
+impl Partial {
+    pub fn of(name: &str) -> Partial {
+        Partial { name: String::from_str(name), args: vec![] }
+    }
+
+    /// Fills the open slots in order with `args`, appending any left over.
+    pub fn fill(&self, args: Vec<Value>) -> Vec<Option<Value>> {
+        let mut supplied = args.move_iter();
+        let mut filled: Vec<Option<Value>> = self.args.iter().map(|slot| {
+            match *slot {
+                Some(ref v) => Some(v.clone()),
+                None => supplied.next(),
+            }
+        }).collect();
+
+        filled.extend(supplied.map(|v| Some(v)));
+        filled
+    }
+}
+
+impl Value {
+    /// Wraps a double in the representation `mode` calls for.
+    pub fn from_f64(value: f64, mode: NumberMode) -> Value {
+        match mode {
+            DecimalMode => match BigDecimal::from_f64(value) {
+                Some(d) => Decimal(d),
+                None => Real(value),
+            },
+            ExactMode => match BigDecimal::from_f64(value) {
+                Some(d) => Exact(d.to_rational()),
+                None => Real(value),
+            },
+            IntegerMode(bits) if value.fract() == 0_f64 && value.abs() < 9.2e18 =>
+                Integer(word(value as i64, bits)),
+            FixedMode(format) => Fixed(FixedPoint::from_f64(value, format)),
+            _ => Real(value),
+        }
+    }
+
+    /// What sort of value this is, for error messages.
+    pub fn kind_name(&self) -> &'static str {
+        match *self {
+            Real(_) | Decimal(_) | Exact(_) | Fixed(_) => "number",
+            Complex(_) => "complex number",
+            Integer(_) => "integer",
+            Function(_) => "function",
+            List(_) => "list",
+            Text(_) => "text",
+            Expression(_) => "expression",
+            Quantity(_) => "quantity",
+        }
+    }
+
+    /// An integer cut to the word `mode` works in, if it is narrower than
+    /// 64 bits; anything else as it is.
+    pub fn in_word(self, mode: NumberMode) -> Value {
+        match (self, mode) {
+            (Integer(n), IntegerMode(bits)) => Integer(word(n, bits)),
+            (value, _) => value,
+        }
+    }
+
+    /// The value as a double, or a `TypeMismatch` if it is not a number.
+    pub fn number(&self) -> Result<f64, CalcError> {
+        match *self {
+            Real(v) => Ok(v),
+            Decimal(ref d) => Ok(d.to_f64()),
+            Exact(ref r) => Ok(ratio_to_f64(r)),
+            Integer(n) => Ok(n as f64),
+            Fixed(ref x) => Ok(x.to_f64()),
+            _ => Err(TypeMismatch { expected: "number", found: self.kind_name() }),
+        }
+    }
+
+    /// Whether a condition holds: any number other than zero does.
+    pub fn is_truthy(&self) -> Result<bool, CalcError> {
+        match *self {
+            Complex(z) => Ok(z.re != 0_f64 || z.im != 0_f64),
+            Integer(n) => Ok(n != 0),
+            Decimal(ref d) => Ok(!d.is_zero()),
+            Exact(ref r) => Ok(!r.is_zero()),
+            _ => Ok(try!(self.number()) != 0_f64),
+        }
+    }
+
+    /// The value as a complex number; any real number converts.
+    pub fn complex(&self) -> Result<Complex64, CalcError> {
+        match *self {
+            Complex(z) => Ok(z),
+            _ => Ok(complex::Complex::new(try!(self.number()), 0_f64)),
+        }
+    }
+
+    /// The principal square root, which is complex for negative numbers.
+    pub fn sqrt(&self) -> Result<Value, CalcError> {
+        let z = match *self {
+            Complex(z) => z,
+            _ => {
+                let n = try!(self.number());
+                if n >= 0_f64 {
+                    return Ok(Real(n.sqrt()));
+                }
+                // straight onto the imaginary axis; going by the polar form
+                // would leave a rounding error in the real part
+                return Ok(Complex(complex::Complex::new(0_f64, (-n).sqrt())));
+            },
+        };
+
+        let (r, theta) = z.to_polar();
+        Ok(complex_result(complex::Complex::from_polar(&r.sqrt(), &(theta / 2_f64))))
+    }
+
+    pub fn negate(&self) -> Result<Value, CalcError> {
+        match *self {
+            Real(v) => Ok(Real(-v)),
+            Decimal(ref d) => Ok(Decimal(d.neg())),
+            Exact(ref r) => Ok(Exact(-*r)),
+            Complex(z) => Ok(Complex(-z)),
+            Quantity(ref m) => Ok(Quantity(m.times(&Measure::number(-1_f64)))),
+            Fixed(ref x) => Ok(Fixed(x.neg())),
+            List(ref items) => {
+                let mut negated: Vec<Value> = vec![];
+                for item in items.iter() {
+                    negated.push(try!(item.negate()));
+                }
+                Ok(List(negated))
+            },
+            Integer(n) => match n.checked_mul(&-1) {
+                Some(m) => Ok(Integer(m)),
+                None => Err(IntegerOverflow),
+            },
+            _ => Err(TypeMismatch { expected: "number", found: self.kind_name() }),
+        }
+    }
+
+    /// `Value::binary` in the word `mode` works in, if it is narrower than
+    /// 64 bits: the result is cut to the word, and `<<` drops the bits it
+    /// shifts past the word rather than overflowing.
+    pub fn binary_in(operator: &str, lhs: &Value, rhs: &Value,
+                     mode: NumberMode) -> Result<Value, CalcError> {
+        match (operator, lhs, rhs, mode) {
+            ("<<", &Integer(a), &Integer(b), IntegerMode(bits)) if bits < 64 && b >= 0 => {
+                let shifted = if b as uint >= bits { 0 } else { a << b as uint };
+                Ok(Integer(word(shifted, bits)))
+            },
+            _ => Value::binary(operator, lhs, rhs).map(|value| value.in_word(mode)),
+        }
+    }
+
+    /// Applies a binary arithmetic operator. Two decimals or two fractions
+    /// are combined exactly where possible; everything else goes through
+    /// doubles.
+    pub fn binary(operator: &str, lhs: &Value, rhs: &Value) -> Result<Value, CalcError> {
+        match (lhs, rhs) {
+            (&Quantity(_), _) | (_, &Quantity(_)) => return quantity_binary(operator, lhs, rhs),
+            (&List(_), _) | (_, &List(_)) => return list_binary(operator, lhs, rhs),
+            _ => {},
+        }
+
+        match operator {
+            "<"|">"|"<="|">="|"=="|"!=" => return Value::compare(operator, lhs, rhs),
+            _ => {},
+        }
+
+        match (lhs, rhs) {
+            (&Decimal(ref a), &Decimal(ref b)) => match operator {
+                "+" => return Ok(Decimal(a.add(b))),
+                "-" => return Ok(Decimal(a.sub(b))),
+                "*" => return Ok(Decimal(a.mul(b))),
+                "/" => return match a.div(b, DIVISION_PLACES) {
+                    Some(q) => Ok(Decimal(q)),
+                    None => Err(DivisionByZero),
+                },
+                // only integer powers are exact
+                "^" => match b.to_i64().and_then(|n| a.powi(n, DIVISION_PLACES)) {
+                    Some(p) => return Ok(Decimal(p)),
+                    None => {},
+                },
+                _ => {},
+            },
+            (&Complex(_), _) | (_, &Complex(_)) => {
+                let (a, b) = (try!(lhs.complex()), try!(rhs.complex()));
+                return Ok(complex_result(match operator {
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" => a / b,
+                    "^" => complex_pow(a, b),
+                    _ => return Err(UnknownOperator(String::from_str(operator))),
+                }));
+            },
+            (&Integer(a), &Integer(b)) => return integer_binary(operator, a, b),
+            // a plain number meeting a fixed-point one is quantized first
+            (&Fixed(ref a), _) | (_, &Fixed(ref a)) => {
+                let quantize = |value: &Value| match *value {
+                    Fixed(ref x) => Ok(x.clone()),
+                    ref other => other.number().map(|n| FixedPoint::from_f64(n, a.format)),
+                };
+                let (x, y) = (try!(quantize(lhs)), try!(quantize(rhs)));
+                match x.binary(operator, &y) {
+                    Ok(result) => return Ok(Fixed(result)),
+                    // the rest are worked in doubles and quantized below
+                    Err(UnknownOperator(_)) => {
+                        let result = try!(Value::binary(operator, &Real(x.to_f64()),
+                                                        &Real(y.to_f64())));
+                        return Ok(Value::from_f64(try!(result.number()), FixedMode(a.format)));
+                    },
+                    Err(e) => return Err(e),
+                }
+            },
+            (&Exact(ref a), &Exact(ref b)) => match operator {
+                "+" => return Ok(Exact(*a + *b)),
+                "-" => return Ok(Exact(*a - *b)),
+                "*" => return Ok(Exact(*a * *b)),
+                "/" if b.is_zero() => return Err(DivisionByZero),
+                "/" => return Ok(Exact(*a / *b)),
+                "^" => match ratio_powi(a, b) {
+                    Some(p) => return Ok(Exact(p)),
+                    None => {},
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+
+        let (a, b) = (try!(lhs.number()), try!(rhs.number()));
+        Ok(Real(match operator {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" => a / b,
+            "//" => (a / b).floor(),
+            // takes the sign of the divisor, to pair with `//`
+            "%" => a - b * (a / b).floor(),
+            "^" => a.powf(b),
+            // bitwise operators need whole numbers
+            "&"|"|"|"xor"|"<<"|">>" => {
+                let result = try!(integer_binary(operator, try!(whole(a)), try!(whole(b))));
+                try!(result.number())
+            },
+            _ => return Err(UnknownOperator(String::from_str(operator))),
+        }))
+    }
+}
+
+impl Value {
+    /// Applies a comparison, giving 1 when it holds and 0 when it does not.
+    /// Integers compare to an integer result; everything else to a double.
+    pub fn compare(operator: &str, lhs: &Value, rhs: &Value) -> Result<Value, CalcError> {
+        let holds = match (lhs, rhs) {
+            // complex numbers have equality but no order
+            (&Complex(_), _) | (_, &Complex(_)) => {
+                let (a, b) = (try!(lhs.complex()), try!(rhs.complex()));
+                match operator {
+                    "==" => a == b,
+                    "!=" => a != b,
+                    _ => return Err(TypeMismatch {
+                        expected: "real number",
+                        found: "complex number",
+                    }),
+                }
+            },
+            _ => {
+                let order = try!(Value::ordering(lhs, rhs));
+                match (operator, order) {
+                    // NaN is unordered, and unequal to everything
+                    ("!=", None) => true,
+                    (_, None) => false,
+                    ("<", Some(o)) => o == Less,
+                    (">", Some(o)) => o == Greater,
+                    ("<=", Some(o)) => o != Greater,
+                    (">=", Some(o)) => o != Less,
+                    ("==", Some(o)) => o == Equal,
+                    ("!=", Some(o)) => o != Equal,
+                    _ => return Err(UnknownOperator(String::from_str(operator))),
+                }
+            },
+        };
+
+        Ok(match (lhs, rhs) {
+            (&Integer(_), &Integer(_)) => Integer(if holds { 1 } else { 0 }),
+            _ => Real(if holds { 1_f64 } else { 0_f64 }),
+        })
+    }
+
+    // exact where both sides share an exact kind, through doubles otherwise
+    fn ordering(lhs: &Value, rhs: &Value) -> Result<Option<Ordering>, CalcError> {
+        Ok(match (lhs, rhs) {
+            (&Integer(a), &Integer(b)) => Some(a.cmp(&b)),
+            (&Decimal(ref a), &Decimal(ref b)) => {
+                let difference = a.sub(b);
+                Some(if difference.is_zero() {
+                    Equal
+                } else if difference.is_negative() {
+                    Less
+                } else {
+                    Greater
+                })
+            },
+            (&Exact(ref a), &Exact(ref b)) => Some(a.cmp(b)),
+            (&Fixed(ref a), &Fixed(ref b)) => Some(a.raw.cmp(&b.raw)),
+            _ => {
+                let (a, b) = (try!(lhs.number()), try!(rhs.number()));
+                if a < b {
+                    Some(Less)
+                } else if a > b {
+                    Some(Greater)
+                } else if a == b {
+                    Some(Equal)
+                } else {
+                    None
+                }
+            },
+        })
+    }
+}
+
+// numerator and denominator are converted separately where they fit, and
+// through a long division where they do not
+fn ratio_to_f64(ratio: &BigRational) -> f64 {
+    let numer = from_str::<f64>(format!("{}", ratio.numer()).as_slice());
+    let denom = from_str::<f64>(format!("{}", ratio.denom()).as_slice());
+    match (numer, denom) {
+        (Some(n), Some(d)) if n.is_finite() && d.is_finite() => n / d,
+        _ => BigDecimal::from_rational(ratio, DIVISION_PLACES).to_f64(),
+    }
+}
+
+// only whole powers of a fraction are fractions; None leaves the rest,
+// and zero to a negative power, to doubles
+fn ratio_powi(base: &BigRational, exponent: &BigRational) -> Option<BigRational> {
+    if !exponent.is_integer() {
+        return None;
+    }
+    let n = match exponent.numer().to_i64() {
+        Some(n) if n.abs() <= MAX_EXPONENT => n,
+        _ => return None,
+    };
+
+    let numer = pow(base.numer().clone(), n.abs() as uint);
+    let denom = pow(base.denom().clone(), n.abs() as uint);
+    if n >= 0 {
+        Some(Ratio::new(numer, denom))
+    } else if numer.is_zero() {
+        None
+    } else {
+        Some(Ratio::new(denom, numer))
+    }
+}
+
+/// The low `bits` bits of `n`, sign-extended, as a signed word of that
+/// width holds them: `word(255, 8)` is `-1`.
+pub fn word(n: i64, bits: uint) -> i64 {
+    if bits >= 64 {
+        n
+    } else {
+        (n << (64 - bits)) >> (64 - bits)
+    }
+}
+
+fn whole(value: f64) -> Result<i64, CalcError> {
+    if value.fract() == 0_f64 && value.abs() < 9.2e18 {
+        Ok(value as i64)
+    } else {
+        Err(TypeMismatch { expected: "integer", found: "fractional number" })
+    }
+}
+
+// integer arithmetic is checked rather than wrapping; `/` truncates toward
+// zero while `//` and `%` round toward negative infinity
+fn integer_binary(operator: &str, a: i64, b: i64) -> Result<Value, CalcError> {
+    let result = match operator {
+        "+" => a.checked_add(&b),
+        "-" => a.checked_sub(&b),
+        "*" => a.checked_mul(&b),
+        "/"|"//"|"%" if b == 0 => return Err(DivisionByZero),
+        "/" => a.checked_div(&b),
+        "//" => a.checked_div(&b).map(|q| {
+            if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q }
+        }),
+        // i64::MIN % -1 overflows, though the answer is plain
+        "%" if b == -1 => Some(0),
+        "%" => {
+            let r = a % b;
+            Some(if r != 0 && (r < 0) != (b < 0) { r + b } else { r })
+        },
+        "^" if b < 0 => return Ok(Real((a as f64).powf(b as f64))),
+        "^" => checked_pow(a, b),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        "xor" => Some(a ^ b),
+        "<<"|">>" if b < 0 || b >= 64 => None,
+        // shifting out significant bits overflows
+        "<<" => {
+            let r = a << b as uint;
+            if r >> b as uint == a { Some(r) } else { None }
+        },
+        ">>" => Some(a >> b as uint),
+        _ => return Err(UnknownOperator(String::from_str(operator))),
+    };
+
+    match result {
+        Some(n) => Ok(Integer(n)),
+        None => Err(IntegerOverflow),
+    }
+}
+
+// exponentiation by squaring, None on overflow
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+    let mut result = 1i64;
+    let mut base = base;
+    let mut exponent = exponent;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = match result.checked_mul(&base) {
+                Some(r) => r,
+                None => return None,
+            };
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = match base.checked_mul(&base) {
+                Some(b) => b,
+                None => return None,
+            };
+        }
+    }
+    Some(result)
+}
+
+// lists work element by element: two lists of the same length pair up,
+// and a number meets every element; with the matrix feature, `*` with a
+// matrix on either side is a matrix product instead
+fn list_binary(operator: &str, lhs: &Value, rhs: &Value) -> Result<Value, CalcError> {
+    match matrix_product(operator, lhs, rhs) {
+        Some(result) => return result,
+        None => {},
+    }
+
+    let mut results: Vec<Value> = vec![];
+    match (lhs, rhs) {
+        (&List(ref a), &List(ref b)) => {
+            if a.len() != b.len() {
+                return Err(ShapeMismatch {
+                    operation: String::from_str(operator),
+                    left: format!("a list of {}", a.len()),
+                    right: format!("a list of {}", b.len()),
+                });
+            }
+            for (x, y) in a.iter().zip(b.iter()) {
+                results.push(try!(Value::binary(operator, x, y)));
+            }
+        },
+        (&List(ref a), scalar) => for x in a.iter() {
+            results.push(try!(Value::binary(operator, x, scalar)));
+        },
+        (scalar, &List(ref b)) => for y in b.iter() {
+            results.push(try!(Value::binary(operator, scalar, y)));
+        },
+        _ => return Value::binary(operator, lhs, rhs),
+    }
+    Ok(List(results))
+}
+
+#[cfg(feature = "matrix")]
+fn matrix_product(operator: &str, lhs: &Value,
+                  rhs: &Value) -> Option<Result<Value, CalcError>> {
+    matrix::binary(operator, lhs, rhs)
+}
+
+#[cfg(not(feature = "matrix"))]
+fn matrix_product(_: &str, _: &Value, _: &Value) -> Option<Result<Value, CalcError>> {
+    None
+}
+
+// units are carried through the arithmetic: sums convert the right side
+// to the left side's units, products combine them, and `to` or `in`
+// re-expresses the left side in the units on the right
+fn quantity_binary(operator: &str, lhs: &Value, rhs: &Value) -> Result<Value, CalcError> {
+    let measure = |value: &Value| match *value {
+        Quantity(ref m) => Ok(m.clone()),
+        ref other => other.number().map(|n| Measure::number(n)),
+    };
+    let (a, b) = (try!(measure(lhs)), try!(measure(rhs)));
+
+    Ok(match operator {
+        "+" => measure_result(try!(a.plus(&b))),
+        "-" => measure_result(try!(a.plus(&b.times(&Measure::number(-1_f64))))),
+        "*" => measure_result(a.times(&b)),
+        "/" => measure_result(a.times(&b.powi(-1))),
+        "^" if !b.units.is_empty() => return Err(TypeMismatch {
+            expected: "plain number",
+            found: "quantity",
+        }),
+        "^" => {
+            let n = try!(whole(b.magnitude));
+            measure_result(a.powi(n as i32))
+        },
+        "to"|"in" => Quantity(Measure {
+            magnitude: try!(a.in_units_of(&b)) / b.magnitude,
+            units: b.units.clone(),
+        }),
+        "<"|">"|"<="|">="|"=="|"!=" => {
+            let converted = try!(b.in_units_of(&a));
+            return Value::compare(operator, &Real(a.magnitude), &Real(converted));
+        },
+        _ => return Err(UnknownOperator(String::from_str(operator))),
+    })
+}
+
+// a quantity whose units cancel, like `km / m`, is a plain number again;
+// the rest are put in the units that read best
+fn measure_result(m: Measure) -> Value {
+    if m.units.is_empty() {
+        Real(m.magnitude)
+    } else if m.is_dimensionless() {
+        Real(m.magnitude * m.scale())
+    } else {
+        Quantity(m.nicest())
+    }
+}
+
+// a complex result with no imaginary part is an ordinary number again, so
+// `i * i` is -1
+fn complex_result(z: Complex64) -> Value {
+    if z.im == 0_f64 { Real(z.re) } else { Complex(z) }
+}
+
+// z^w = e^(w ln z), with 0^w taken as 0 and 0^0 as 1
+fn complex_pow(z: Complex64, w: Complex64) -> Complex64 {
+    if z.re == 0_f64 && z.im == 0_f64 {
+        let one = if w.re == 0_f64 && w.im == 0_f64 { 1_f64 } else { 0_f64 };
+        return complex::Complex::new(one, 0_f64);
+    }
+
+    let ln_z = complex::Complex::new(z.norm().ln(), z.arg());
+    let exponent = w * ln_z;
+    complex::Complex::from_polar(&exponent.re.exp(), &exponent.im)
+}
+
+impl fmt::Show for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Real(v) => write!(f, "{}", v),
+            Decimal(ref d) => write!(f, "{}", d),
+            Integer(n) => write!(f, "{}", n),
+            Fixed(ref x) => write!(f, "{}", x),
+            Exact(ref r) if r.is_integer() => write!(f, "{}", r.numer()),
+            Exact(ref r) => write!(f, "{}/{}", r.numer(), r.denom()),
+            Complex(z) => {
+                // 2 + 3i, 2 - i, 3i, -i
+                let unit = match z.im.abs() {
+                    1_f64 => String::from_str("i"),
+                    im => format!("{}i", im),
+                };
+                match (z.re, z.im < 0_f64) {
+                    (0_f64, false) => write!(f, "{}", unit),
+                    (0_f64, true) => write!(f, "-{}", unit),
+                    (re, false) => write!(f, "{} + {}", re, unit),
+                    (re, true) => write!(f, "{} - {}", re, unit),
+                }
+            },
+            Function(ref partial) => write!(f, "{}", partial),
+            List(ref items) => {
+                try!(write!(f, "["));
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{}", item));
+                }
+                write!(f, "]")
+            },
+            Text(ref text) => write!(f, "\"{}\"", text),
+            Expression(ref node) => write!(f, "{}", node),
+            Quantity(ref m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl fmt::Show for Partial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.name));
+        if self.args.is_empty() {
+            return Ok(());
+        }
+
+        try!(write!(f, "("));
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                try!(write!(f, ", "));
+            }
+            match *arg {
+                Some(ref v) => try!(write!(f, "{}", v)),
+                None => try!(write!(f, "_")),
+            }
+        }
+        write!(f, ")")
+    }
+}